@@ -0,0 +1,22 @@
+#!/usr/bin/env bash
+
+# compares the hit ratios of the cache policies when point
+# requests on a small hot set are mixed with long scans over
+# a keyspace that is much larger than the cache.
+
+set -euxo pipefail
+
+for policy in lru 2q; do
+    rm -rf default.sled
+    cargo run --release -- \
+        --cache-policy=$policy \
+        --cache-capacity=4000000 \
+        --prefill \
+        --entries=1000000 \
+        --hot-entries=1000 \
+        --get-prop=99 --set-prop=0 --del-prop=0 --cas-prop=0 --merge-prop=0 \
+        --scan-prop=1 --scan-len=50000 \
+        --threads=1 \
+        --duration=20 \
+        | grep "hit ratio"
+done
@@ -1,6 +1,6 @@
 use std::{
     sync::{
-        atomic::{AtomicBool, AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
     thread,
@@ -22,6 +22,8 @@ static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
 static TOTAL: AtomicUsize = AtomicUsize::new(0);
 static SEQ: AtomicUsize = AtomicUsize::new(0);
+static SCAN_HITS: AtomicU64 = AtomicU64::new(0);
+static SCAN_MISSES: AtomicU64 = AtomicU64::new(0);
 
 const USAGE: &'static str = "
 Usage: stress [--threads=<#>] [--burn-in] [--duration=<s>] \
@@ -33,6 +35,11 @@ Usage: stress [--threads=<#>] [--burn-in] [--duration=<s>] \
     [--scan-prop=<p>] \
     [--merge-prop=<p>] \
     [--entries=<n>] \
+    [--hot-entries=<n>] \
+    [--scan-len=<l>] \
+    [--prefill] \
+    [--cache-capacity=<b>] \
+    [--cache-policy=<p>] \
     [--sequential] \
     [--total-ops=<n>]
 
@@ -49,6 +56,11 @@ Options:
     --scan-prop=<p>    The relative proportion of scan requests [default: 1].
     --merge-prop=<p>   The relative proportion of merge requests [default: 1].
     --entries=<n>      The total keyspace [default: 100000].
+    --hot-entries=<n>  Restrict all requests but scans to this many keys.
+    --scan-len=<l>     The bound on the items a scan reads [default: 15].
+    --prefill          Insert every key, and restart, before starting the workload.
+    --cache-capacity=<b>  The page cache size in bytes [default: 1000000000].
+    --cache-policy=<p>    The page cache policy, lru or 2q [default: lru].
     --sequential       Run the test in sequential mode instead of random.
     --total-ops=<n>    Stop test after executing a total number of operations.
 ";
@@ -67,6 +79,11 @@ struct Args {
     flag_scan_prop: usize,
     flag_merge_prop: usize,
     flag_entries: usize,
+    flag_hot_entries: Option<usize>,
+    flag_scan_len: usize,
+    flag_prefill: bool,
+    flag_cache_capacity: u64,
+    flag_cache_policy: String,
     flag_sequential: bool,
     flag_total_ops: Option<usize>,
 }
@@ -85,6 +102,11 @@ static mut ARGS: Args = Args {
     flag_scan_prop: 0,
     flag_merge_prop: 0,
     flag_entries: 0,
+    flag_hot_entries: None,
+    flag_scan_len: 0,
+    flag_prefill: false,
+    flag_cache_capacity: 0,
+    flag_cache_policy: String::new(),
     flag_sequential: false,
     flag_total_ops: None,
 };
@@ -107,7 +129,8 @@ fn concatenate_merge(
     merged_bytes: &[u8],      // the new bytes being merged in
 ) -> Option<Vec<u8>> {
     // set the new value, return None to delete
-    let mut ret = old_value.map(|ov| ov.to_vec()).unwrap_or_else(|| vec![]);
+    let mut ret =
+        old_value.map(|ov| ov.to_vec()).unwrap_or_else(|| vec![]);
 
     ret.extend_from_slice(merged_bytes);
 
@@ -124,24 +147,30 @@ fn run(tree: Arc<sled::Db>, shutdown: Arc<AtomicBool>) {
     let scan_max = cas_max + args.flag_scan_prop;
     let merge_max = scan_max + args.flag_merge_prop;
 
-    let bytes = |len| -> Vec<u8> {
+    let hot_entries =
+        args.flag_hot_entries.unwrap_or(args.flag_entries);
+
+    let bytes_in = |len, entries| -> Vec<u8> {
         let i = if args.flag_sequential {
             SEQ.fetch_add(1, Ordering::Relaxed)
         } else {
             thread_rng().gen::<usize>()
-        } % args.flag_entries;
-
-        let i_bytes: [u8; std::mem::size_of::<usize>()] =
-            unsafe { std::mem::transmute(i) };
+        } % entries;
 
-        i_bytes.into_iter().cycle().take(len).cloned().collect()
+        key_bytes(i, len)
     };
+    let bytes = |len| bytes_in(len, hot_entries);
     let mut rng = thread_rng();
 
     while !shutdown.load(Ordering::Relaxed) {
         TOTAL.fetch_add(1, Ordering::Release);
-        let key = bytes(args.flag_key_len);
         let choice = rng.gen_range(0, merge_max + 1);
+        let key = if choice > cas_max && choice <= scan_max {
+            // scans start anywhere in the keyspace
+            bytes_in(args.flag_key_len, args.flag_entries)
+        } else {
+            bytes(args.flag_key_len)
+        };
 
         match choice {
             v if v <= get_max => {
@@ -173,16 +202,31 @@ fn run(tree: Arc<sled::Db>, shutdown: Arc<AtomicBool>) {
                 }
             }
             v if v > cas_max && v <= scan_max => {
+                let (hits_before, misses_before) =
+                    tree.cache_hits_and_misses();
+
                 let iter = tree.range(key..).map(|res| res.unwrap());
 
+                let len = rng.gen_range(0, args.flag_scan_len);
+
                 if v % 2 == 0 {
-                    let _ = iter.take(rng.gen_range(0, 15)).collect::<Vec<_>>();
+                    let _ = iter.take(len).collect::<Vec<_>>();
                 } else {
-                    let _ = iter
-                        .rev()
-                        .take(rng.gen_range(0, 15))
-                        .collect::<Vec<_>>();
+                    let _ = iter.rev().take(len).collect::<Vec<_>>();
                 }
+
+                // with more than one thread, this also counts the
+                // accesses that other threads made during the scan
+                let (hits_after, misses_after) =
+                    tree.cache_hits_and_misses();
+                SCAN_HITS.fetch_add(
+                    hits_after - hits_before,
+                    Ordering::Relaxed,
+                );
+                SCAN_MISSES.fetch_add(
+                    misses_after - misses_before,
+                    Ordering::Relaxed,
+                );
             }
             _ => {
                 tree.merge(&key, bytes(args.flag_val_len)).unwrap();
@@ -191,27 +235,61 @@ fn run(tree: Arc<sled::Db>, shutdown: Arc<AtomicBool>) {
     }
 }
 
+// big-endian, so that the hot entries are adjacent in the tree
+fn key_bytes(i: usize, len: usize) -> Vec<u8> {
+    i.to_be_bytes().iter().cycle().take(len).cloned().collect()
+}
+
+fn hit_ratio(hits: u64, misses: u64) -> f64 {
+    if hits + misses == 0 {
+        0.
+    } else {
+        hits as f64 / (hits + misses) as f64
+    }
+}
+
 fn main() {
     setup_logger();
 
     let args = unsafe {
         ARGS = Docopt::new(USAGE)
-            .and_then(|d| d.argv(std::env::args().into_iter()).deserialize())
+            .and_then(|d| {
+                d.argv(std::env::args().into_iter()).deserialize()
+            })
             .unwrap_or_else(|e| e.exit());
         ARGS.clone()
     };
 
     let shutdown = Arc::new(AtomicBool::new(false));
 
+    let cache_policy = match &*args.flag_cache_policy {
+        "lru" => sled::CachePolicy::Lru,
+        "2q" => sled::CachePolicy::TwoQueue,
+        other => panic!("unknown cache policy {}", other),
+    };
+
     let config = sled::ConfigBuilder::new()
         .io_buf_size(8_000_000)
         .page_consolidation_threshold(10)
-        .cache_capacity(1_000_000_000)
+        .cache_capacity(args.flag_cache_capacity)
+        .cache_policy(cache_policy)
         .flush_every_ms(Some(200))
         .snapshot_after_ops(100_000_000_000)
         .print_profile_on_drop(true)
         .build();
 
+    if args.flag_prefill {
+        let tree = sled::Db::start(config.clone()).unwrap();
+        for i in 0..args.flag_entries {
+            let key = key_bytes(i, args.flag_key_len);
+            tree.insert(key, vec![0; args.flag_val_len]).unwrap();
+        }
+        tree.flush().unwrap();
+
+        // written pages stay in memory until they are first
+        // read, so restart to begin the workload with a cold cache
+    }
+
     let tree = Arc::new(sled::Db::start(config).unwrap());
     tree.set_merge_operator(concatenate_merge);
 
@@ -266,6 +344,21 @@ fn main() {
         time,
         (ops * 1_000) / (time * 1_000)
     );
+
+    let (hits, misses) = tree.cache_hits_and_misses();
+    let scan_hits = SCAN_HITS.load(Ordering::SeqCst);
+    let scan_misses = SCAN_MISSES.load(Ordering::SeqCst);
+
+    println!(
+        "cache hit ratio: {:.3} overall, {:.3} for scans, {:.3} for \
+         other requests",
+        hit_ratio(hits, misses),
+        hit_ratio(scan_hits, scan_misses),
+        hit_ratio(
+            hits.saturating_sub(scan_hits),
+            misses.saturating_sub(scan_misses)
+        ),
+    );
 }
 
 pub fn setup_logger() {
@@ -288,7 +381,12 @@ pub fn setup_logger() {
                 "{:05} {:25} {:10} {}",
                 record.level(),
                 tn(),
-                record.module_path().unwrap().split("::").last().unwrap(),
+                record
+                    .module_path()
+                    .unwrap()
+                    .split("::")
+                    .last()
+                    .unwrap(),
                 record.args()
             )
         })
@@ -0,0 +1,288 @@
+//! Online backups of the files that make up a `PageCache`.
+//!
+//! A backup is a copy of the data file, the configuration,
+//! the latest usable snapshot and the blobs directory that
+//! recovers to the state of the log at a single stable Lsn,
+//! just as if the original system had crashed right after
+//! that Lsn was made durable.
+//!
+//! Writers continue while the copy is being made, so:
+//!
+//! 1. the caller must pause segment rewriting for the whole
+//!    copy, which prevents segments that are live as of the
+//!    stable Lsn from being reused. An epoch `Guard` is only
+//!    pinned while each segment is read, so that a long
+//!    backup does not hold up the reclamation of memory.
+//! 2. segments that are rewritten during the copy, or that
+//!    only contain data above the stable Lsn, have their
+//!    headers corrupted in the copy, exactly like torn
+//!    segments are handled during recovery.
+//! 3. the segment that contains the stable Lsn has every
+//!    byte above it corrupted, so recovery stops there.
+//!
+//! An incremental backup only rewrites the segments of an
+//! existing backup that may contain data above the Lsn
+//! returned by the previous backup.
+use std::{fs, path::Path};
+
+use super::*;
+
+/// Copies the files of the system described by `config`
+/// into the directory at `path`, as of the `stable` Lsn.
+/// If `since` is provided, `path` must contain a backup
+/// that was previously taken at that Lsn.
+pub(crate) fn backup(
+    config: &Config,
+    stable: Lsn,
+    path: &Path,
+    since: Option<Lsn>,
+) -> Result<Lsn> {
+    let source_dir = config.get_path();
+
+    if path.exists()
+        && source_dir.exists()
+        && path.canonicalize()? == source_dir.canonicalize()?
+    {
+        return Err(Error::Unsupported(
+            "cannot back up a database into its own directory".to_owned(),
+        ));
+    }
+
+    let db_path = path.join("db");
+
+    match (since, db_path.exists()) {
+        (None, true) => {
+            return Err(Error::Unsupported(format!(
+                "refusing to overwrite existing database at {:?}, \
+                 use an incremental backup to update it",
+                path
+            )));
+        }
+        (Some(since), false) => {
+            return Err(Error::Unsupported(format!(
+                "no previous backup found at {:?} to apply an \
+                 incremental backup since lsn {} to",
+                path, since
+            )));
+        }
+        _ => {}
+    }
+
+    debug!(
+        "backing up {:?} to {:?} at stable lsn {}, since {:?}",
+        source_dir, path, stable, since
+    );
+
+    fs::create_dir_all(path.join("blobs"))?;
+
+    copy_file(&config.config_path(), &path.join("conf"))?;
+
+    backup_log(config, stable, &db_path, since)?;
+
+    backup_snapshot(config, stable, path)?;
+
+    backup_blobs(config, stable, path)?;
+
+    Ok(stable)
+}
+
+fn backup_log(
+    config: &Config,
+    stable: Lsn,
+    db_path: &Path,
+    since: Option<Lsn>,
+) -> Result<()> {
+    let segment_len = config.io_buf_size as LogId;
+
+    let source = &config.file;
+    // an incremental backup keeps the segments that did
+    // not change since the previous one.
+    let target = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(since.is_none())
+        .open(db_path)?;
+
+    let file_len = source.metadata()?.len();
+    let mut buf = vec![0; config.io_buf_size];
+    let mut copied = 0;
+
+    for idx in 0..file_len.div_ceil(segment_len) {
+        let _guard = pin();
+
+        let lid = idx * segment_len;
+        let len = usize::try_from(std::cmp::min(segment_len, file_len - lid))
+            .unwrap();
+
+        if len < SEG_HEADER_LEN {
+            // too small to contain anything recoverable
+            continue;
+        }
+
+        let mut header_before = [0; SEG_HEADER_LEN];
+        source.pread_exact(&mut header_before, lid)?;
+        let header = SegmentHeader::from(header_before);
+
+        if let Some(since) = since {
+            // this segment was completely stable when the last
+            // backup was taken, and it has not been reused
+            // since then, so the backup already has it.
+            if header.ok && header.lsn + segment_len as Lsn - 1 <= since {
+                continue;
+            }
+        }
+
+        let buf = &mut buf[..len];
+        source.pread_exact(buf, lid)?;
+
+        let mut header_after = [0; SEG_HEADER_LEN];
+        source.pread_exact(&mut header_after, lid)?;
+
+        let reused = header_before != header_after;
+        let beyond_stable = header.ok && header.lsn > stable;
+
+        if reused || beyond_stable {
+            trace!(
+                "corrupting header of segment at lid {} in backup, \
+                 reused: {} lsn: {} stable: {}",
+                lid,
+                reused,
+                header.lsn,
+                stable
+            );
+            for byte in &mut buf[..SEG_HEADER_LEN] {
+                *byte = MessageKind::Corrupted as u8;
+            }
+        } else if header.ok && header.lsn + segment_len as Lsn - 1 > stable {
+            // this is the segment that contains the stable
+            // tip, so anything written after it is dropped.
+            let tip = usize::try_from(stable + 1 - header.lsn).unwrap();
+            trace!(
+                "truncating segment at lid {} in backup after offset {}",
+                lid,
+                tip
+            );
+            if tip < len {
+                for byte in &mut buf[tip..] {
+                    *byte = MessageKind::Corrupted as u8;
+                }
+            }
+        }
+
+        target.pwrite_all(buf, lid)?;
+        copied += 1;
+    }
+
+    target.set_len(file_len)?;
+    target.sync_all()?;
+
+    debug!("copied {} segments to backup at {:?}", copied, db_path);
+
+    Ok(())
+}
+
+fn backup_snapshot(config: &Config, stable: Lsn, path: &Path) -> Result<()> {
+    let mut candidates: Vec<(Lsn, std::path::PathBuf)> = config
+        .get_snapshot_files()?
+        .into_iter()
+        .filter_map(|snapshot_path| {
+            let name = snapshot_path.file_name()?.to_str()?;
+            let lsn = Lsn::from_str_radix(name.get(5..)?, 16).ok()?;
+            Some((lsn, snapshot_path))
+        })
+        .filter(|(lsn, _)| *lsn <= stable)
+        .collect();
+
+    candidates.sort();
+
+    // snapshots from a previous backup may be newer than
+    // the one we are about to copy, so they must go.
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with("snap.") {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    while let Some((lsn, snapshot_path)) = candidates.pop() {
+        let name = format!("snap.{:016X}", lsn);
+        if copy_file(&snapshot_path, &path.join(name))? {
+            return Ok(());
+        }
+        // the snapshot was replaced by a newer one while we
+        // were working, so try the next older candidate.
+    }
+
+    // without a snapshot, recovery will scan the whole log
+    debug!("no snapshot at or below lsn {} could be backed up", stable);
+
+    Ok(())
+}
+
+fn backup_blobs(config: &Config, stable: Lsn, path: &Path) -> Result<()> {
+    let source_dir = config.get_path().join("blobs");
+    let target_dir = path.join("blobs");
+
+    let mut wanted = FastSet8::default();
+
+    for entry in fs::read_dir(&source_dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let lsn: Lsn = match name.to_string_lossy().parse() {
+            Ok(lsn) => lsn,
+            Err(_) => continue,
+        };
+
+        if lsn > stable {
+            continue;
+        }
+
+        wanted.insert(name.clone());
+
+        let target = target_dir.join(&name);
+        if target.exists() {
+            // blobs are never modified after being written
+            continue;
+        }
+
+        if !copy_file(&entry.path(), &target)? {
+            // the log up to the stable Lsn may still refer to
+            // this blob, so the backup would not be usable.
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "blob {} was removed before it could be backed up, \
+                     the backup should be retried",
+                    lsn
+                ),
+            )));
+        }
+    }
+
+    for entry in fs::read_dir(&target_dir)? {
+        let entry = entry?;
+        if !wanted.contains(&entry.file_name()) {
+            trace!("removing stale blob {:?} from backup", entry.path());
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies a file and syncs the copy, returning `false`
+/// if the source was removed before it could be read.
+fn copy_file(from: &Path, to: &Path) -> Result<bool> {
+    match fs::copy(from, to) {
+        Ok(_) => {}
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(false);
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    fs::OpenOptions::new().write(true).open(to)?.sync_all()?;
+
+    Ok(true)
+}
@@ -4,6 +4,7 @@ use super::*;
 
 pub(crate) fn read_blob(
     blob_ptr: Lsn,
+    pid: PageId,
     config: &Config,
 ) -> Result<(MessageKind, Vec<u8>)> {
     let path = config.blob_path(blob_ptr);
@@ -52,12 +53,24 @@ pub(crate) fn read_blob(
     let crc_actual = hasher.finalize();
 
     if crc_expected == crc_actual {
-        let buf = if config.use_compression {
+        let toggles_compression = kind_byte[0] & COMPRESSION_TOGGLE != 0;
+        let kind = MessageKind::from(kind_byte[0] & !COMPRESSION_TOGGLE);
+        let aad = message_aad(blob_ptr, pid, kind, toggles_compression);
+        let scope = KeyScope::Blob(blob_ptr);
+        let buf = if let Some(buf) = maybe_decrypt(config, scope, buf, &aad) {
+            buf
+        } else {
+            warn!("blob {} failed authentication!", blob_ptr);
+            return Err(Error::Corruption {
+                at: DiskPtr::Blob(0, blob_ptr),
+            });
+        };
+        let buf = if config.use_compression != toggles_compression {
             maybe_decompress(buf)?
         } else {
             buf
         };
-        Ok((MessageKind::from(kind_byte[0]), buf))
+        Ok((kind, buf))
     } else {
         warn!("blob {} failed crc check!", blob_ptr);
 
@@ -70,6 +83,7 @@ pub(crate) fn read_blob(
 pub(crate) fn write_blob(
     config: &Config,
     kind: MessageKind,
+    toggles_compression: bool,
     id: Lsn,
     data: &[u8],
 ) -> Result<()> {
@@ -79,7 +93,12 @@ pub(crate) fn write_blob(
         .create_new(true)
         .open(&path)?;
 
-    let kind_buf = &[kind.into()];
+    let kind_byte = if toggles_compression {
+        kind.into() | COMPRESSION_TOGGLE
+    } else {
+        kind.into()
+    };
+    let kind_buf = &[kind_byte];
 
     let mut hasher = crc32fast::Hasher::new();
     hasher.update(kind_buf);
@@ -21,6 +21,61 @@ use super::{LogReader, *};
 
 const DEFAULT_PATH: &str = "default.sled";
 
+// written before the serialized configuration, followed by the
+// pagecache version that wrote it. The layout of the configuration
+// may change between versions, so the version is checked before
+// trying to deserialize the rest.
+const CONFIG_MAGIC: [u8; 8] = *b"pcconfig";
+
+/// The layout of the configuration written by pagecache 0.18
+/// before the conf file started with `CONFIG_MAGIC`. Such a
+/// file is rewritten in the current format on the next open.
+#[derive(Deserialize)]
+struct LegacyConfig {
+    cache_capacity: u64,
+    flush_every_ms: Option<u64>,
+    io_buf_size: usize,
+    page_consolidation_threshold: usize,
+    path: PathBuf,
+    read_only: bool,
+    segment_cleanup_threshold: f64,
+    segment_cleanup_skew: usize,
+    segment_mode: SegmentMode,
+    snapshot_after_ops: u64,
+    snapshot_path: Option<PathBuf>,
+    temporary: bool,
+    use_compression: bool,
+    compression_factor: i32,
+    print_profile_on_drop: bool,
+    idgen_persist_interval: u64,
+    version: (usize, usize),
+}
+
+impl From<LegacyConfig> for ConfigBuilder {
+    fn from(legacy: LegacyConfig) -> Self {
+        Self {
+            cache_capacity: legacy.cache_capacity,
+            flush_every_ms: legacy.flush_every_ms,
+            io_buf_size: legacy.io_buf_size,
+            page_consolidation_threshold: legacy.page_consolidation_threshold,
+            path: legacy.path,
+            read_only: legacy.read_only,
+            segment_cleanup_threshold: legacy.segment_cleanup_threshold,
+            segment_cleanup_skew: legacy.segment_cleanup_skew,
+            segment_mode: legacy.segment_mode,
+            snapshot_after_ops: legacy.snapshot_after_ops,
+            snapshot_path: legacy.snapshot_path,
+            temporary: legacy.temporary,
+            use_compression: legacy.use_compression,
+            compression_factor: legacy.compression_factor,
+            print_profile_on_drop: legacy.print_profile_on_drop,
+            idgen_persist_interval: legacy.idgen_persist_interval,
+            version: legacy.version,
+            ..Self::default()
+        }
+    }
+}
+
 /// A persisted configuration about high-level
 /// storage file information
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
@@ -62,10 +117,14 @@ pub struct ConfigBuilder {
     #[doc(hidden)]
     pub cache_capacity: u64,
     #[doc(hidden)]
+    pub cache_policy: CachePolicy,
+    #[doc(hidden)]
     pub flush_every_ms: Option<u64>,
     #[doc(hidden)]
     pub io_buf_size: usize,
     #[doc(hidden)]
+    pub io_mode: IoMode,
+    #[doc(hidden)]
     pub page_consolidation_threshold: usize,
     #[doc(hidden)]
     pub path: PathBuf,
@@ -93,6 +152,13 @@ pub struct ConfigBuilder {
     pub idgen_persist_interval: u64,
     #[doc(hidden)]
     pub version: (usize, usize),
+    #[doc(hidden)]
+    pub change_feed: bool,
+    #[doc(hidden)]
+    #[serde(skip)]
+    pub encryption_key: Option<EncryptionKey>,
+    #[doc(hidden)]
+    pub encryption_check: Option<Vec<u8>>,
 }
 
 unsafe impl Send for ConfigBuilder {}
@@ -101,10 +167,12 @@ impl Default for ConfigBuilder {
     fn default() -> Self {
         Self {
             io_buf_size: 2 << 22, // 8mb
+            io_mode: IoMode::Buffered,
             page_consolidation_threshold: 10,
             path: PathBuf::from(DEFAULT_PATH),
             read_only: false,
             cache_capacity: 1024 * 1024 * 1024, // 1gb
+            cache_policy: CachePolicy::Lru,
             use_compression: false,
             compression_factor: 5,
             flush_every_ms: Some(500),
@@ -117,6 +185,9 @@ impl Default for ConfigBuilder {
             print_profile_on_drop: false,
             idgen_persist_interval: 1_000_000,
             version: pagecache_crate_version(),
+            change_feed: false,
+            encryption_key: None,
+            encryption_check: None,
         }
     }
 }
@@ -152,6 +223,19 @@ impl ConfigBuilder {
         self
     }
 
+    /// Adopts the settings that can not change across restarts,
+    /// `io_buf_size` and `use_compression`, from the database
+    /// that already exists at the configured path, if any. This
+    /// lets a database, such as a backup or a repaired copy, be
+    /// opened without knowing which settings it was created with.
+    pub fn use_persisted_format(mut self) -> Result<Self> {
+        if let Some(old) = self.read_config()? {
+            self.io_buf_size = old.io_buf_size;
+            self.use_compression = old.use_compression;
+        }
+        Ok(self)
+    }
+
     /// Finalize the configuration.
     ///
     /// # Panics
@@ -206,10 +290,42 @@ impl ConfigBuilder {
             );
         });
 
+        let log_file = if self.io_mode == IoMode::Buffered || self.read_only {
+            None
+        } else {
+            let log_file = LogFile::open(&self.db_path(), self.io_mode)
+                .unwrap_or_else(|e| {
+                    panic!(
+                        "should be able to open configured file at {:?} \
+                         in {:?} mode; {}",
+                        self.db_path(),
+                        self.io_mode,
+                        e,
+                    );
+                });
+            Some(log_file)
+        };
+
+        #[cfg(all(target_os = "linux", feature = "io_uring"))]
+        let uring = match Uring::new(&file, log_file.as_ref()) {
+            Ok(uring) => Some(uring),
+            Err(e) => {
+                warn!(
+                    "failed to set up io_uring, so the log will be \
+                     accessed with blocking calls instead; {}",
+                    e
+                );
+                None
+            }
+        };
+
         // seal config in a Config
         Config(Arc::new(ConfigInner {
             inner: self,
+            #[cfg(all(target_os = "linux", feature = "io_uring"))]
+            uring,
             file,
+            log_file,
             global_error: Atomic::default(),
             #[cfg(feature = "event_log")]
             event_log: crate::event_log::EventLog::default(),
@@ -218,10 +334,12 @@ impl ConfigBuilder {
 
     builder!(
         (io_buf_size, usize, "size of each io flush buffer. MUST be multiple of 512!"),
+        (io_mode, IoMode, "how the log is written to its file, which may bypass the page cache of the OS"),
         (page_consolidation_threshold, usize, "page consolidation threshold"),
         (temporary, bool, "deletes the database after drop. if no path is set, uses /dev/shm on linux"),
-        (read_only, bool, "whether to run in read-only mode"),
+        (read_only, bool, "whether to run in read-only mode, following the writes of another process with refresh"),
         (cache_capacity, u64, "maximum size for the system page cache"),
+        (cache_policy, CachePolicy, "the policy that chooses which pages to page out of a full cache"),
         (use_compression, bool, "whether to use zstd compression"),
         (compression_factor, i32, "the compression factor to use with zstd compression"),
         (flush_every_ms, Option<u64>, "number of ms between IO buffer flushes"),
@@ -231,7 +349,9 @@ impl ConfigBuilder {
         (segment_mode, SegmentMode, "the file segment selection mode"),
         (snapshot_path, Option<PathBuf>, "snapshot file location"),
         (print_profile_on_drop, bool, "print a performance profile when the Config is dropped"),
-        (idgen_persist_interval, u64, "generated IDs are persisted at this interval. during recovery we skip twice this number")
+        (idgen_persist_interval, u64, "generated IDs are persisted at this interval. during recovery we skip twice this number"),
+        (change_feed, bool, "record every write in a durable feed that sled can replay with Db::changes_since"),
+        (encryption_key, Option<EncryptionKey>, "encrypt and authenticate the log, blobs and snapshots with this key. it must be provided every time the database is opened")
     );
 
     // panics if config options are outside of advised range
@@ -282,6 +402,22 @@ impl ConfigBuilder {
             self.idgen_persist_interval > 0,
             "idgen_persist_interval must be above 0"
         );
+        if self.encryption_key.is_some() {
+            supported!(
+                cfg!(feature = "encryption"),
+                "the encryption feature must be enabled"
+            );
+        }
+        if self.io_mode != IoMode::Buffered {
+            supported!(
+                self.io_buf_size % DIRECT_IO_ALIGNMENT == 0,
+                format!(
+                    "io_buf_size must be a multiple of {} \
+                     to write the log with O_DIRECT",
+                    DIRECT_IO_ALIGNMENT
+                )
+            );
+        }
         Ok(())
     }
 
@@ -317,21 +453,23 @@ impl ConfigBuilder {
 
         // open the data file
         let mut options = fs::OpenOptions::new();
-        options.create(true);
         options.read(true);
         if !self.read_only {
+            options.create(true);
             options.write(true);
         }
 
         match options.open(&path) {
             Ok(file) => {
-                // try to exclusively lock the file
+                // try to lock the file, exclusively for the single
+                // process that writes and shared between read-only
+                // processes.
                 #[cfg(any(windows, target_os = "linux", target_os = "macos"))]
                 {
                     let lock_res = if self.read_only {
-                        file.try_lock_shared()
+                        try_lock_shared(&file)
                     } else {
-                        file.try_lock_exclusive()
+                        try_lock(&file)
                     };
                     if lock_res.is_err() {
                         return Err(Error::Io(std::io::Error::new(
@@ -351,8 +489,8 @@ impl ConfigBuilder {
     }
 
     fn verify_config_changes_ok(&self) -> Result<()> {
-        match self.read_config() {
-            Ok(Some(old)) => {
+        match self.read_config_file() {
+            Ok(Some((old, versioned))) => {
                 supported!(
                     self.use_compression == old.use_compression,
                     format!(
@@ -372,29 +510,65 @@ impl ConfigBuilder {
                     )
                 );
 
-                supported!(
-                    self.version == old.version,
-                    format!(
-                        "This database was created using \
-                         pagecache version {}.{}, but our pagecache \
-                         version is {}.{}. Please perform an upgrade \
-                         using the sled::Db::export and sled::Db::import \
-                         methods.",
-                        old.version.0,
-                        old.version.1,
-                        self.version.0,
-                        self.version.1,
-                    )
-                );
+                if !versioned && !self.read_only {
+                    self.write_config()?;
+                }
                 Ok(())
             }
+            Ok(None) if self.read_only => Ok(()),
             Ok(None) => self.write_config(),
-            Err(e) => Err(e.into()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Checks that the configured `encryption_key` is the one
+    /// that the database was created with. This happens when
+    /// the system is started rather than in `build`, so that a
+    /// wrong key is reported as an error instead of a panic.
+    pub(crate) fn verify_encryption_key(&self) -> Result<()> {
+        let old = if let Some(old) = self.read_config()? {
+            old
+        } else {
+            return Ok(());
+        };
+
+        match (&old.encryption_check, &self.encryption_key) {
+            (Some(check), Some(key)) => supported!(
+                key.matches(check),
+                "the provided encryption key is not the one \
+                 this database was created with"
+            ),
+            (Some(_), None) => {
+                return Err(Error::Unsupported(
+                    "this database is encrypted, so an \
+                     encryption_key must be provided"
+                        .to_owned(),
+                ));
+            }
+            (None, Some(_)) => {
+                return Err(Error::Unsupported(
+                    "this database was created without encryption, \
+                     which cannot be enabled across restarts"
+                        .to_owned(),
+                ));
+            }
+            (None, None) => {}
         }
+
+        Ok(())
     }
 
     fn write_config(&self) -> Result<()> {
-        let bytes = serialize(&*self).unwrap();
+        // the key itself is never persisted, only a value
+        // sealed with it that later opens can check.
+        let mut persisted = self.clone();
+        persisted.encryption_check =
+            self.encryption_key.as_ref().map(EncryptionKey::key_check);
+
+        let mut bytes = CONFIG_MAGIC.to_vec();
+        bytes.extend_from_slice(&u64_to_arr(self.version.0 as u64));
+        bytes.extend_from_slice(&u64_to_arr(self.version.1 as u64));
+        bytes.extend_from_slice(&serialize(&persisted).unwrap());
         let crc: u32 = crc32(&*bytes);
         let crc_arr = u32_to_arr(crc);
 
@@ -403,6 +577,7 @@ impl ConfigBuilder {
         let mut f = std::fs::OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .open(path)?;
 
         maybe_fail!("write_config bytes");
@@ -413,7 +588,13 @@ impl ConfigBuilder {
         Ok(())
     }
 
-    fn read_config(&self) -> std::io::Result<Option<Self>> {
+    pub(crate) fn read_config(&self) -> Result<Option<Self>> {
+        Ok(self.read_config_file()?.map(|(config, _versioned)| config))
+    }
+
+    /// Reads the persisted configuration, along with whether
+    /// it was written with the version header.
+    fn read_config_file(&self) -> Result<Option<(Self, bool)>> {
         let path = self.config_path();
 
         let f_res = std::fs::OpenOptions::new().read(true).open(&path);
@@ -423,7 +604,7 @@ impl ConfigBuilder {
                 return Ok(None);
             }
             Err(other) => {
-                return Err(other);
+                return Err(other.into());
             }
             Ok(f) => f,
         };
@@ -453,7 +634,46 @@ impl ConfigBuilder {
             );
         }
 
-        Ok(deserialize::<Self>(&*buf).ok())
+        let header_len = CONFIG_MAGIC.len() + 16;
+        let versioned = buf.len() >= header_len
+            && buf[..CONFIG_MAGIC.len()] == CONFIG_MAGIC;
+
+        let parsed = if versioned {
+            let major = arr_to_u64(&buf[8..16]) as usize;
+            let minor = arr_to_u64(&buf[16..24]) as usize;
+            self.check_version((major, minor))?;
+            deserialize::<Self>(&buf[header_len..])
+        } else {
+            deserialize::<LegacyConfig>(&*buf).map(Self::from)
+        };
+
+        match parsed {
+            Ok(config) => {
+                if !versioned {
+                    self.check_version(config.version)?;
+                }
+                Ok(Some((config, versioned)))
+            }
+            Err(e) => Err(Error::Unsupported(format!(
+                "failed to parse the configuration file {:?}: {}",
+                path, e
+            ))),
+        }
+    }
+
+    fn check_version(&self, (major, minor): (usize, usize)) -> Result<()> {
+        supported!(
+            (major, minor) == self.version,
+            format!(
+                "This database was created using \
+                 pagecache version {}.{}, but our pagecache \
+                 version is {}.{}. Please perform an upgrade \
+                 using the sled::Db::export and sled::Db::import \
+                 methods.",
+                major, minor, self.version.0, self.version.1,
+            )
+        );
+        Ok(())
     }
 
     // Get the path of the database
@@ -469,13 +689,13 @@ impl ConfigBuilder {
         path
     }
 
-    fn db_path(&self) -> PathBuf {
+    pub(crate) fn db_path(&self) -> PathBuf {
         let mut path = self.get_path();
         path.push("db");
         path
     }
 
-    fn config_path(&self) -> PathBuf {
+    pub(crate) fn config_path(&self) -> PathBuf {
         let mut path = self.get_path();
         path.push("conf");
         path
@@ -498,7 +718,15 @@ impl Deref for Config {
 #[derive(Debug)]
 pub struct ConfigInner {
     inner: ConfigBuilder,
+    #[cfg(all(target_os = "linux", feature = "io_uring"))]
+    /// The ring that the log is accessed through, which is
+    /// declared before the files to be dropped before them.
+    pub(crate) uring: Option<Uring>,
     pub(crate) file: fs::File,
+    /// The log is written through this file instead, if the
+    /// `io_mode` asks for other flags than `file` was opened
+    /// with.
+    pub(crate) log_file: Option<LogFile>,
     pub(crate) global_error: Atomic<Error>,
     #[cfg(feature = "event_log")]
     /// an event log for concurrent debugging
@@ -508,6 +736,46 @@ pub struct ConfigInner {
 unsafe impl Send for Config {}
 unsafe impl Sync for Config {}
 
+/// Takes a shared lock of the database file, which keeps a
+/// writer from starting, and recovering the log, underneath
+/// read-only processes. If a writer already holds the
+/// exclusive lock then it has completed its recovery, and the
+/// log is followed without a lock.
+#[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+fn try_lock_shared(file: &fs::File) -> std::io::Result<()> {
+    match FileExt::try_lock_shared(file) {
+        Err(ref e) if e.kind() == fs2::lock_contended_error().kind() => {
+            debug!("following the log of the process that writes it");
+            Ok(())
+        }
+        res => res,
+    }
+}
+
+/// Takes the exclusive lock of the database file.
+#[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+fn try_lock(file: &fs::File) -> std::io::Result<()> {
+    // the kernel finishes the io_uring operations of a process
+    // after it is killed, and the file stays locked until then,
+    // so that they can't overwrite the log of the next process.
+    #[cfg(all(target_os = "linux", feature = "io_uring"))]
+    {
+        let deadline =
+            std::time::Instant::now() + std::time::Duration::from_secs(1);
+        loop {
+            match file.try_lock_exclusive() {
+                Err(_) if std::time::Instant::now() < deadline => {
+                    std::thread::sleep(std::time::Duration::from_millis(10))
+                }
+                res => return res,
+            }
+        }
+    }
+
+    #[cfg(not(all(target_os = "linux", feature = "io_uring")))]
+    file.try_lock_exclusive()
+}
+
 impl Drop for ConfigInner {
     fn drop(&mut self) {
         if self.print_profile_on_drop {
@@ -528,6 +796,15 @@ impl Drop for ConfigInner {
 }
 
 impl Config {
+    /// Returns `true` if the log is written with `O_DIRECT`,
+    /// so that every write must end on a multiple of
+    /// `DIRECT_IO_ALIGNMENT`.
+    pub(crate) fn direct_io(&self) -> bool {
+        self.log_file
+            .as_ref()
+            .map_or(false, |log_file| log_file.direct)
+    }
+
     /// Return the global error if one was encountered during
     /// an asynchronous IO operation.
     pub fn global_error(&self) -> Result<()> {
@@ -619,6 +896,13 @@ impl Config {
 
     #[doc(hidden)]
     pub fn verify_snapshot(&self) -> Result<()> {
+        self.verify_encryption_key()?;
+
+        if self.read_only {
+            // the snapshots belong to the process that writes
+            return Ok(());
+        }
+
         debug!("generating incremental snapshot");
 
         let incremental = read_snapshot_or_default(&self)?;
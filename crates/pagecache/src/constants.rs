@@ -1,6 +1,6 @@
 use super::*;
 
-// kind: u8 1
+// kind: u8 1 (the high bit is COMPRESSION_TOGGLE)
 // pid: u64 8
 // lsn: i64 8
 // len: u32 4
@@ -20,6 +20,10 @@ pub const BLOB_INLINE_LEN: usize = std::mem::size_of::<Lsn>();
 /// needed to recover a writebatch atomically.
 pub const BATCH_MANIFEST_INLINE_LEN: usize = std::mem::size_of::<Lsn>();
 
+/// With `IoMode::Direct`, the log is written in blocks of
+/// this length, from buffers that start at a multiple of it.
+pub const DIRECT_IO_ALIGNMENT: usize = 4096;
+
 /// The minimum number of items per segment.
 /// Items larger than this fraction of an `io_buf`
 /// will be stored as an off-log blob.
@@ -28,6 +32,12 @@ pub const MINIMUM_ITEMS_PER_SEGMENT: usize = 4;
 /// During testing, this should never be exceeded.
 pub const MAX_SPACE_AMPLIFICATION: f64 = 30.;
 
+/// Set in the kind byte of messages and blobs that are
+/// compressed while `use_compression` is disabled, or that
+/// are not compressed while it is enabled, because the
+/// settings of their page override the `Config`.
+pub(crate) const COMPRESSION_TOGGLE: u8 = 0b1000_0000;
+
 pub(crate) const META_PID: PageId = 0;
 pub(crate) const COUNTER_PID: PageId = 1;
 pub(crate) const CONFIG_PID: PageId = 2;
@@ -97,8 +97,7 @@ impl Dll {
         ptr
     }
 
-    #[cfg(test)]
-    pub(crate) fn push_tail(&mut self, item: PageId) {
+    pub(crate) fn push_tail(&mut self, item: PageId) -> *mut Node {
         self.len += 1;
 
         let node = Node {
@@ -120,6 +119,8 @@ impl Dll {
         }
 
         self.tail = ptr;
+
+        ptr
     }
 
     pub(crate) fn promote(&mut self, ptr: *mut Node) -> *mut Node {
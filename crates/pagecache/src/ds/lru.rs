@@ -3,7 +3,29 @@ use std::ptr;
 
 use super::*;
 
-/// A simple Lru cache.
+/// The policy that the `Lru` uses to choose which pages
+/// to page out once the cache is full.
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize,
+)]
+pub enum CachePolicy {
+    /// Pages out the least recently used page first. A single
+    /// scan over more pages than fit in the cache pages out
+    /// every other page. This is the default.
+    #[default]
+    Lru,
+    /// The 2Q policy. Pages that are accessed for the first
+    /// time are put on probation, in a FIFO queue that holds
+    /// up to a quarter of the cache. Pages paged out of it are
+    /// remembered for a while without their data, and move to
+    /// the main LRU list if they are accessed again in that
+    /// time. A scan only pages out the other pages on
+    /// probation, and leaves the main list alone.
+    TwoQueue,
+}
+
+/// A sharded page cache that chooses which pages to page out
+/// according to a `CachePolicy`.
 pub struct Lru {
     shards: Vec<Mutex<Shard>>,
 }
@@ -11,8 +33,15 @@ pub struct Lru {
 unsafe impl Sync for Lru {}
 
 impl Lru {
-    /// Instantiates a new `Lru` cache.
+    /// Instantiates a new `Lru` cache with the default
+    /// `CachePolicy`.
     pub fn new(cache_capacity: u64) -> Self {
+        Self::with_policy(cache_capacity, CachePolicy::default())
+    }
+
+    /// Instantiates a new `Lru` cache that uses the provided
+    /// `CachePolicy`.
+    pub fn with_policy(cache_capacity: u64, policy: CachePolicy) -> Self {
         assert!(
             cache_capacity >= 256,
             "Please configure the cache \
@@ -22,7 +51,9 @@ impl Lru {
         let shard_capacity = cache_capacity / n_shards as u64;
 
         let mut shards = Vec::with_capacity(n_shards);
-        shards.resize_with(n_shards, || Mutex::new(Shard::new(shard_capacity)));
+        shards.resize_with(n_shards, || {
+            Mutex::new(Shard::new(shard_capacity, policy))
+        });
 
         Self { shards }
     }
@@ -31,11 +62,22 @@ impl Lru {
     /// try to page-out. For each one of these, the caller is expected
     /// to call `page_out_succeeded` if the page-out succeeded.
     pub fn accessed(&self, pid: PageId, sz: u64) -> Vec<PageId> {
+        self.accessed_with_priority(pid, sz, CachePriority::Normal)
+    }
+
+    /// Like `accessed`, for a page whose collection has a
+    /// `CachePriority` other than the default.
+    pub fn accessed_with_priority(
+        &self,
+        pid: PageId,
+        sz: u64,
+        priority: CachePriority,
+    ) -> Vec<PageId> {
         let shard_idx = pid % self.shards.len() as u64;
         let rel_idx = pid / self.shards.len() as u64;
         let shard_mu = &self.shards[usize::try_from(shard_idx).unwrap()];
         let mut shard = shard_mu.lock();
-        let mut rel_ids = shard.accessed(rel_idx, sz);
+        let mut rel_ids = shard.accessed(rel_idx, sz, priority);
 
         for rel_id in &mut rel_ids {
             let real_id = (*rel_id * self.shards.len() as u64) + shard_idx;
@@ -44,12 +86,40 @@ impl Lru {
 
         rel_ids
     }
+
+    /// Returns the number of accesses that found the page
+    /// still resident, and the number that did not.
+    pub fn hits_and_misses(&self) -> (u64, u64) {
+        self.shards.iter().fold((0, 0), |(hits, misses), shard| {
+            let shard = shard.lock();
+            (hits + shard.hits, misses + shard.misses)
+        })
+    }
+}
+
+/// The list of a `Shard` that a page is on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Queue {
+    /// The page is not tracked.
+    Absent,
+    /// The page is resident, on the main list.
+    Main,
+    /// The page is resident, on probation.
+    Probation,
+    /// The page was paged out from probation recently,
+    /// and is only remembered.
+    Ghost,
 }
 
 #[derive(Clone)]
 struct Entry {
     ptr: *mut dll::Node,
     sz: u64,
+    queue: Queue,
+    priority: CachePriority,
+    /// Set when a `High` priority page was skipped by
+    /// eviction, and cleared when it is accessed again.
+    spared: bool,
 }
 
 impl Default for Entry {
@@ -57,30 +127,52 @@ impl Default for Entry {
         Self {
             ptr: ptr::null_mut(),
             sz: 0,
+            queue: Queue::Absent,
+            priority: CachePriority::Normal,
+            spared: false,
         }
     }
 }
 
 struct Shard {
-    list: Dll,
+    policy: CachePolicy,
+    main: Dll,
+    probation: Dll,
+    ghosts: Dll,
     entries: Vec<Entry>,
     capacity: u64,
     sz: u64,
+    probation_capacity: u64,
+    probation_sz: u64,
+    hits: u64,
+    misses: u64,
 }
 
 impl Shard {
-    fn new(capacity: u64) -> Self {
+    fn new(capacity: u64, policy: CachePolicy) -> Self {
         assert!(capacity > 0, "shard capacity must be non-zero");
 
         Self {
-            list: Dll::default(),
+            policy,
+            main: Dll::default(),
+            probation: Dll::default(),
+            ghosts: Dll::default(),
             entries: vec![],
             capacity,
             sz: 0,
+            probation_capacity: capacity / 4,
+            probation_sz: 0,
+            hits: 0,
+            misses: 0,
         }
     }
 
-    fn accessed(&mut self, rel_idx: PageId, sz: u64) -> Vec<PageId> {
+    fn accessed(
+        &mut self,
+        rel_idx: PageId,
+        sz: u64,
+        priority: CachePriority,
+    ) -> Vec<PageId> {
         if PageId::try_from(self.entries.len()).unwrap() <= rel_idx {
             self.entries.resize(
                 usize::try_from(rel_idx).unwrap() + 1,
@@ -91,34 +183,172 @@ impl Shard {
         {
             let entry = &mut self.entries[usize::try_from(rel_idx).unwrap()];
 
-            self.sz -= entry.sz;
-            entry.sz = sz;
-            self.sz += sz;
+            let target = match (self.policy, entry.queue, priority) {
+                (CachePolicy::Lru, _, _) | (_, Queue::Main, _) => Queue::Main,
+                (_, _, CachePriority::Low) => Queue::Probation,
+                (_, _, CachePriority::High) | (_, Queue::Ghost, _) => {
+                    Queue::Main
+                }
+                (_, Queue::Probation, _) | (_, Queue::Absent, _) => {
+                    Queue::Probation
+                }
+            };
+
+            match entry.queue {
+                Queue::Main => {
+                    self.hits += 1;
+                    self.sz -= entry.sz;
+                }
+                Queue::Probation => {
+                    self.hits += 1;
+                    self.sz -= entry.sz;
+                    self.probation_sz -= entry.sz;
+                }
+                Queue::Ghost | Queue::Absent => self.misses += 1,
+            }
 
-            if entry.ptr.is_null() {
-                entry.ptr = self.list.push_head(rel_idx);
+            if entry.queue == target {
+                // pages on probation keep their place in the queue
+                if target == Queue::Main && priority != CachePriority::Low {
+                    entry.ptr = self.main.promote(entry.ptr);
+                }
             } else {
-                entry.ptr = self.list.promote(entry.ptr);
+                match entry.queue {
+                    Queue::Main => unsafe {
+                        self.main.pop_ptr(entry.ptr);
+                    },
+                    Queue::Probation => unsafe {
+                        self.probation.pop_ptr(entry.ptr);
+                    },
+                    Queue::Ghost => unsafe {
+                        self.ghosts.pop_ptr(entry.ptr);
+                    },
+                    Queue::Absent => {}
+                }
+
+                let list = if target == Queue::Main {
+                    &mut self.main
+                } else {
+                    &mut self.probation
+                };
+                entry.ptr = if priority == CachePriority::Low {
+                    list.push_tail(rel_idx)
+                } else {
+                    list.push_head(rel_idx)
+                };
             }
+
+            entry.sz = sz;
+            self.sz += sz;
+            if target == Queue::Probation {
+                self.probation_sz += sz;
+            }
+            entry.queue = target;
+            entry.priority = priority;
+            entry.spared = false;
         }
 
         let mut to_evict = vec![];
         while self.sz > self.capacity {
-            if self.list.len() == 1 {
+            if self.main.len() + self.probation.len() == 1 {
                 // don't evict what we just added
                 break;
             }
 
-            let min_pid = self.list.pop_tail().unwrap();
-            self.entries[usize::try_from(min_pid).unwrap()].ptr =
-                ptr::null_mut();
+            let from_probation = self.probation.len() > 0
+                && (self.probation_sz > self.probation_capacity
+                    || self.main.len() == 0);
+
+            let min_pid = if from_probation {
+                self.probation.pop_tail().unwrap()
+            } else {
+                self.main.pop_tail().unwrap()
+            };
+            let entry = &mut self.entries[usize::try_from(min_pid).unwrap()];
+
+            if from_probation {
+                self.probation_sz -= entry.sz;
+            }
+
+            if min_pid != rel_idx
+                && entry.priority == CachePriority::High
+                && !entry.spared
+            {
+                // give it another pass through the main list
+                entry.spared = true;
+                entry.queue = Queue::Main;
+                entry.ptr = self.main.push_head(min_pid);
+                continue;
+            }
 
             to_evict.push(min_pid);
 
-            self.sz -= self.entries[usize::try_from(min_pid).unwrap()].sz;
-            self.entries[usize::try_from(min_pid).unwrap()].sz = 0;
+            self.sz -= entry.sz;
+            entry.sz = 0;
+
+            if from_probation {
+                entry.queue = Queue::Ghost;
+                entry.ptr = self.ghosts.push_head(min_pid);
+            } else {
+                entry.queue = Queue::Absent;
+                entry.ptr = ptr::null_mut();
+            }
+        }
+
+        // remember about as many pages as are resident
+        let max_ghosts = self.main.len() + self.probation.len();
+        while self.ghosts.len() > max_ghosts {
+            let ghost = self.ghosts.pop_tail().unwrap();
+            let entry = &mut self.entries[usize::try_from(ghost).unwrap()];
+            entry.queue = Queue::Absent;
+            entry.ptr = ptr::null_mut();
         }
 
         to_evict
     }
 }
+
+#[test]
+fn test_lru_priorities() {
+    let mut shard = Shard::new(3, CachePolicy::Lru);
+
+    // a low priority page is the first to go
+    assert!(shard.accessed(0, 1, CachePriority::Normal).is_empty());
+    assert!(shard.accessed(1, 1, CachePriority::Low).is_empty());
+    assert!(shard.accessed(2, 1, CachePriority::Normal).is_empty());
+    assert_eq!(shard.accessed(3, 1, CachePriority::Normal), vec![1]);
+
+    // a high priority page is skipped once
+    assert_eq!(shard.accessed(1, 1, CachePriority::High), vec![0]);
+    assert_eq!(shard.accessed(4, 1, CachePriority::Normal), vec![2]);
+    assert_eq!(shard.accessed(5, 1, CachePriority::Normal), vec![3]);
+    assert_eq!(shard.accessed(6, 1, CachePriority::Normal), vec![4]);
+}
+
+#[test]
+fn test_two_queue_scan_resistance() {
+    fn accessed(shard: &mut Shard, pids: std::ops::Range<PageId>) -> Vec<u64> {
+        pids.flat_map(|pid| shard.accessed(pid, 1, CachePriority::Normal))
+            .collect()
+    }
+
+    let mut shard = Shard::new(8, CachePolicy::TwoQueue);
+    assert!(accessed(&mut shard, 0..8).is_empty());
+    assert_eq!(accessed(&mut shard, 8..16), (0..8).collect::<Vec<_>>());
+
+    // pages that come back soon after being paged out are hot
+    assert_eq!(accessed(&mut shard, 0..4), (8..12).collect::<Vec<_>>());
+
+    // a scan only pages out the pages on probation
+    let evicted = accessed(&mut shard, 100..200);
+    assert_eq!(evicted.len(), 100);
+    assert!(evicted.iter().all(|pid| *pid >= 12));
+    assert!(accessed(&mut shard, 0..4).is_empty());
+
+    // while the same scan pages out everything from an Lru
+    let mut shard = Shard::new(8, CachePolicy::Lru);
+    assert!(accessed(&mut shard, 0..4).is_empty());
+    assert!(accessed(&mut shard, 0..4).is_empty());
+    let evicted = accessed(&mut shard, 100..200);
+    assert_eq!(&evicted[..4], &[0, 1, 2, 3]);
+}
@@ -7,7 +7,7 @@ mod stack;
 mod vecset;
 
 pub use self::dll::Dll;
-pub use self::lru::Lru;
+pub use self::lru::{CachePolicy, Lru};
 pub use self::pagetable::{PageTable, PAGETABLE_NODE_SZ};
 pub use self::stack::{node_from_frag_vec, Node, Stack, StackIter};
 pub use self::vecset::VecSet;
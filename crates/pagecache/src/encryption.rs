@@ -0,0 +1,276 @@
+//! Authenticated encryption of data at rest.
+//!
+//! When `ConfigBuilder::encryption_key` is set, the body of
+//! every log message (after compression), every blob and
+//! every snapshot is sealed with XChaCha20-Poly1305 before
+//! it is written. Each sealed buffer carries its own random
+//! 192-bit nonce, so no nonce is reused across segments,
+//! blobs or restarts:
+//!
+//! `nonce (24 bytes) ++ ciphertext ++ tag (16 bytes)`
+//!
+//! Buffers are not sealed with the configured key itself,
+//! but with a subkey that HKDF-SHA256 derives from it for the
+//! file being written: the segment, by the lsn it starts at,
+//! the blob or the snapshot, by their lsn, or the key check.
+//! This limits how much is sealed under any one key, and a
+//! buffer copied into another file fails to authenticate.
+//!
+//! The tag also authenticates some associated data that says
+//! where the buffer belongs. It starts with an id derived from
+//! the configured key, so that buffers left behind by another
+//! key are recognized as such. For log messages and blobs, it
+//! is followed by the lsn, page and kind of the message, so
+//! that a sealed body can not be replayed at another position
+//! in the log or for another page. Snapshots are bound to the
+//! lsn in their file name, and the key check to its own label.
+//!
+//! Checksums are still computed over the sealed bytes, so
+//! torn writes are detected during recovery before any
+//! decryption is attempted. Batch manifests are left in the
+//! clear, because they only contain an Lsn that is filled in
+//! after the message has been reserved.
+//!
+//! The configuration file never contains the key. It stores
+//! a sealed known value instead, which is used to reject a
+//! wrong key when the system is started.
+use super::*;
+
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+
+/// How many bytes `seal` adds to a buffer.
+pub(crate) const SEAL_OVERHEAD: usize = NONCE_LEN + TAG_LEN;
+
+/// The value that is sealed in the configuration file.
+const KEY_CHECK: &[u8] = b"pagecache encryption key check";
+
+/// The HKDF info of the id that starts the associated data.
+#[cfg(feature = "encryption")]
+const KEY_ID_INFO: &[u8] = b"pagecache key id";
+
+const KEY_ID_LEN: usize = 8;
+
+/// The file that a buffer is sealed for, which selects the
+/// subkey that it is sealed with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum KeyScope {
+    /// A log message in the segment that starts at this lsn.
+    Segment(Lsn),
+    /// The blob written at this lsn.
+    Blob(Lsn),
+    /// The snapshot taken at this lsn.
+    Snapshot(Lsn),
+    /// The key check stored in the configuration file.
+    KeyCheck,
+}
+
+impl KeyScope {
+    /// The scope of a log message written at `lsn`.
+    pub(crate) fn segment(config: &Config, lsn: Lsn) -> KeyScope {
+        KeyScope::Segment(lsn - lsn % config.io_buf_size as Lsn)
+    }
+
+    /// The HKDF info that the subkey of this scope is derived
+    /// with.
+    #[cfg(feature = "encryption")]
+    fn info(self) -> Vec<u8> {
+        let (label, lsn): (&[u8], Option<Lsn>) = match self {
+            KeyScope::Segment(lsn) => (b"pagecache segment", Some(lsn)),
+            KeyScope::Blob(lsn) => (b"pagecache blob", Some(lsn)),
+            KeyScope::Snapshot(lsn) => (b"pagecache snapshot", Some(lsn)),
+            KeyScope::KeyCheck => (b"pagecache key check", None),
+        };
+        let mut info = label.to_vec();
+        if let Some(lsn) = lsn {
+            info.extend_from_slice(&u64_to_arr(lsn as u64));
+        }
+        info
+    }
+}
+
+/// The associated data of a log message or blob: its lsn,
+/// page and kind, as written to disk.
+pub(crate) fn message_aad(
+    lsn: Lsn,
+    pid: PageId,
+    kind: MessageKind,
+    toggles_compression: bool,
+) -> [u8; 17] {
+    let mut aad = [0_u8; 17];
+    aad[..8].copy_from_slice(&u64_to_arr(lsn as u64));
+    aad[8..16].copy_from_slice(&u64_to_arr(pid));
+    aad[16] = kind.into();
+    if toggles_compression {
+        aad[16] |= COMPRESSION_TOGGLE;
+    }
+    aad
+}
+
+/// The associated data of the snapshot taken at `last_lsn`.
+pub(crate) fn snapshot_aad(last_lsn: Lsn) -> Vec<u8> {
+    let mut aad = b"snapshot".to_vec();
+    aad.extend_from_slice(&u64_to_arr(last_lsn as u64));
+    aad
+}
+
+/// A 256-bit key used to encrypt everything written to disk.
+#[derive(Clone, PartialEq)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    /// Wraps the raw bytes of a key. Keys should come from a
+    /// cryptographically secure source, such as a key
+    /// management service, and not be derived from a password
+    /// without a proper key derivation function.
+    pub fn new(key: [u8; 32]) -> Self {
+        EncryptionKey(key)
+    }
+
+    /// Derives the subkey of a scope, along with the
+    /// associated data that starts with the key id.
+    #[cfg(feature = "encryption")]
+    fn derive(
+        &self,
+        scope: KeyScope,
+        aad: &[u8],
+    ) -> (chacha20poly1305::XChaCha20Poly1305, Vec<u8>) {
+        use chacha20poly1305::{aead::NewAead, Key, XChaCha20Poly1305};
+
+        let hkdf = hkdf::Hkdf::<sha2::Sha256>::new(None, &self.0);
+
+        let mut subkey = [0_u8; 32];
+        hkdf.expand(&scope.info(), &mut subkey)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+
+        let mut full_aad = vec![0_u8; KEY_ID_LEN];
+        hkdf.expand(KEY_ID_INFO, &mut full_aad)
+            .expect("8 bytes is a valid HKDF-SHA256 output length");
+        full_aad.extend_from_slice(aad);
+
+        (XChaCha20Poly1305::new(&Key::from(subkey)), full_aad)
+    }
+
+    /// Seals a buffer for a scope along with its associated
+    /// data, returning the nonce, ciphertext and tag.
+    #[cfg(feature = "encryption")]
+    pub(crate) fn seal(
+        &self,
+        scope: KeyScope,
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> Vec<u8> {
+        use chacha20poly1305::{
+            aead::{Aead, Payload},
+            XNonce,
+        };
+        use rand::RngCore;
+
+        let _measure = Measure::new(&M.encrypt);
+
+        let mut nonce = [0_u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let (cipher, aad) = self.derive(scope, aad);
+        let payload = Payload {
+            msg: plaintext,
+            aad: &aad,
+        };
+        let sealed = cipher
+            .encrypt(&XNonce::from(nonce), payload)
+            .expect("failed to encrypt an in-memory buffer");
+
+        let mut ret = Vec::with_capacity(NONCE_LEN + sealed.len());
+        ret.extend_from_slice(&nonce);
+        ret.extend_from_slice(&sealed);
+        ret
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    pub(crate) fn seal(
+        &self,
+        _scope: KeyScope,
+        _plaintext: &[u8],
+        _aad: &[u8],
+    ) -> Vec<u8> {
+        panic!("the encryption feature must be enabled");
+    }
+
+    /// Authenticates and decrypts a buffer produced by `seal`,
+    /// returning `None` if it was not sealed with this key for
+    /// the scope and associated data, or has been tampered with.
+    #[cfg(feature = "encryption")]
+    pub(crate) fn open(
+        &self,
+        scope: KeyScope,
+        sealed: &[u8],
+        aad: &[u8],
+    ) -> Option<Vec<u8>> {
+        use chacha20poly1305::{
+            aead::{Aead, Payload},
+            XNonce,
+        };
+
+        let _measure = Measure::new(&M.decrypt);
+
+        if sealed.len() < NONCE_LEN + TAG_LEN {
+            return None;
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let mut nonce_arr = [0_u8; NONCE_LEN];
+        nonce_arr.copy_from_slice(nonce);
+
+        let (cipher, aad) = self.derive(scope, aad);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: &aad,
+        };
+        cipher.decrypt(&XNonce::from(nonce_arr), payload).ok()
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    pub(crate) fn open(
+        &self,
+        _scope: KeyScope,
+        _sealed: &[u8],
+        _aad: &[u8],
+    ) -> Option<Vec<u8>> {
+        panic!("the encryption feature must be enabled");
+    }
+
+    /// The value stored in the configuration file.
+    pub(crate) fn key_check(&self) -> Vec<u8> {
+        self.seal(KeyScope::KeyCheck, KEY_CHECK, KEY_CHECK)
+    }
+
+    /// Returns `true` if the value stored in the configuration
+    /// file was produced by `key_check` with this key.
+    pub(crate) fn matches(&self, key_check: &[u8]) -> bool {
+        self.open(KeyScope::KeyCheck, key_check, KEY_CHECK)
+            .is_some_and(|check| check == KEY_CHECK)
+    }
+}
+
+impl Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // never print the key into logs
+        write!(f, "EncryptionKey(..)")
+    }
+}
+
+/// Authenticates and decrypts a buffer read from disk if
+/// encryption is enabled, returning `None` if it fails
+/// authentication.
+pub(crate) fn maybe_decrypt(
+    config: &Config,
+    scope: KeyScope,
+    buf: Vec<u8>,
+    aad: &[u8],
+) -> Option<Vec<u8>> {
+    if let Some(ref key) = config.encryption_key {
+        key.open(scope, &buf, aad)
+    } else {
+        Some(buf)
+    }
+}
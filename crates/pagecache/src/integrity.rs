@@ -0,0 +1,163 @@
+//! Online verification of the data that the page table
+//! refers to.
+//!
+//! `PageCache::verify_integrity` walks every page, reads
+//! each of its fragments back from the log or its blob,
+//! which checks their CRCs (and authenticates them if
+//! encryption is enabled), and deserializes them. Blob files
+//! that no page refers to are reported as orphans. Writers
+//! are not blocked, so a problem is only reported if the
+//! page still refers to the same fragment after the failed
+//! read, and blobs written after the walk started are never
+//! considered orphans.
+use std::fs;
+
+use super::*;
+
+/// The problems found by `PageCache::verify_integrity`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct IntegrityReport {
+    /// The number of pages that were checked.
+    pub pages_checked: usize,
+    /// The number of page fragments that were read back.
+    pub fragments_checked: usize,
+    /// Fragments that failed their checksum or authentication,
+    /// or whose message is not where the page expects it.
+    pub corrupt_messages: Vec<(PageId, DiskPtr)>,
+    /// Fragments stored in a blob whose file does not exist.
+    pub missing_blobs: Vec<(PageId, DiskPtr)>,
+    /// Fragments that were read back intact, but could not
+    /// be deserialized.
+    pub undeserializable: Vec<(PageId, DiskPtr)>,
+    /// Blob files that no page refers to, by their Lsn.
+    pub orphaned_blobs: Vec<BlobPointer>,
+}
+
+impl IntegrityReport {
+    /// Returns `true` if no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.corrupt_messages.is_empty()
+            && self.missing_blobs.is_empty()
+            && self.undeserializable.is_empty()
+            && self.orphaned_blobs.is_empty()
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        problem: Problem,
+        pid: PageId,
+        ptr: DiskPtr,
+    ) {
+        warn!(
+            "integrity check found {:?} for pid {} at {}",
+            problem, pid, ptr
+        );
+        let list = match problem {
+            Problem::Corrupt => &mut self.corrupt_messages,
+            Problem::MissingBlob => &mut self.missing_blobs,
+            Problem::Undeserializable => &mut self.undeserializable,
+        };
+        list.push((pid, ptr));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Problem {
+    Corrupt,
+    MissingBlob,
+    Undeserializable,
+}
+
+/// Reads a page fragment back and checks that it deserializes
+/// into what its message kind says it is.
+pub(crate) fn check_fragment<P: DeserializeOwned>(
+    log: &Log,
+    pid: PageId,
+    lsn: Lsn,
+    ptr: DiskPtr,
+) -> Result<Option<Problem>> {
+    let (header, buf) = match log.read(pid, lsn, ptr) {
+        Ok(LogRead::Inline(header, buf, _len)) => (header, buf),
+        Ok(LogRead::Blob(header, buf, _blob_ptr)) => (header, buf),
+        Ok(LogRead::DanglingBlob(..)) => return Ok(Some(Problem::MissingBlob)),
+        Ok(other) => {
+            debug!("read unexpected message {:?} at {}", other, ptr);
+            return Ok(Some(Problem::Corrupt));
+        }
+        Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(Some(Problem::MissingBlob));
+        }
+        Err(Error::Corruption { .. }) => return Ok(Some(Problem::Corrupt)),
+        Err(other) => return Err(other),
+    };
+
+    if header.pid != pid || header.lsn != lsn {
+        debug!(
+            "expected pid {} lsn {} at {}, but found pid {} lsn {}",
+            pid, lsn, ptr, header.pid, header.lsn
+        );
+        return Ok(Some(Problem::Corrupt));
+    }
+
+    let deserializes = {
+        use MessageKind::*;
+
+        match header.kind {
+            Counter => deserialize::<u64>(&buf).is_ok(),
+            BlobMeta | InlineMeta => Meta::from_bytes(&buf).is_ok(),
+            BlobConfig | InlineConfig => {
+                deserialize::<PersistedConfig>(&buf).is_ok()
+            }
+            BlobAppend | InlineAppend | BlobReplace | InlineReplace => {
+                deserialize::<P>(&buf).is_ok()
+            }
+            Free => true,
+            _ => false,
+        }
+    };
+
+    if deserializes {
+        Ok(None)
+    } else {
+        Ok(Some(Problem::Undeserializable))
+    }
+}
+
+/// Lists the blob files written before `before` that are
+/// neither referenced by a page nor waiting to be removed.
+pub(crate) fn orphaned_blobs(
+    config: &Config,
+    referenced: &FastSet8<BlobPointer>,
+    pending_removal: &FastSet8<BlobPointer>,
+    before: Lsn,
+) -> Result<Vec<BlobPointer>> {
+    let mut ret = vec![];
+
+    for entry in fs::read_dir(config.get_path().join("blobs"))? {
+        let entry = entry?;
+        let lsn: BlobPointer = match entry.file_name().to_string_lossy().parse()
+        {
+            Ok(lsn) => lsn,
+            Err(_) => {
+                warn!(
+                    "ignoring unexpected file {:?} among blobs",
+                    entry.path()
+                );
+                continue;
+            }
+        };
+
+        if lsn < before
+            && !referenced.contains(&lsn)
+            && !pending_removal.contains(&lsn)
+            // it may have been removed since we listed it
+            && entry.path().exists()
+        {
+            ret.push(lsn);
+        }
+    }
+
+    ret.sort();
+
+    Ok(ret)
+}
@@ -30,7 +30,7 @@ macro_rules! io_fail {
 }
 
 pub(crate) struct IoBuf {
-    pub(crate) buf: UnsafeCell<Vec<u8>>,
+    pub(crate) buf: UnsafeCell<AlignedBuf>,
     header: CachePadded<AtomicU64>,
     pub(super) lid: LogId,
     pub(super) lsn: Lsn,
@@ -156,8 +156,12 @@ impl IoBufs {
             );
         }
 
-        // remove all blob files larger than our stable offset
-        gc_blobs(&config, stable)?;
+        // remove all blob files larger than our stable offset,
+        // unless they may belong to writes that the process
+        // which owns the log has not finished yet.
+        if !config.read_only {
+            gc_blobs(&config, stable)?;
+        }
 
         Ok(Self {
             config,
@@ -233,9 +237,7 @@ impl IoBufs {
         &self,
         in_buf: &[u8],
         out_buf: &mut [u8],
-        kind: MessageKind,
-        pid: PageId,
-        lsn: Lsn,
+        mut header: MessageHeader,
         over_blob_threshold: bool,
     ) -> Result<()> {
         let blob_ptr;
@@ -243,9 +245,15 @@ impl IoBufs {
         let to_reserve = if over_blob_threshold {
             // write blob to file
             io_fail!(self, "blob blob write");
-            write_blob(&self.config, kind, lsn, in_buf)?;
+            write_blob(
+                &self.config,
+                header.kind,
+                header.toggles_compression,
+                header.lsn,
+                in_buf,
+            )?;
 
-            let lsn_buf = u64_to_arr(lsn as u64);
+            let lsn_buf = u64_to_arr(header.lsn as u64);
 
             blob_ptr = lsn_buf;
             &blob_ptr
@@ -257,13 +265,7 @@ impl IoBufs {
 
         assert_eq!(out_buf.len(), to_reserve.len() + MSG_HEADER_LEN);
 
-        let header = MessageHeader {
-            kind,
-            pid,
-            lsn,
-            len: u32::try_from(to_reserve.len()).unwrap(),
-            crc32: 0,
-        };
+        header.len = u32::try_from(to_reserve.len()).unwrap();
 
         let header_bytes: [u8; MSG_HEADER_LEN] = header.into();
 
@@ -287,6 +289,31 @@ impl IoBufs {
     // next IO buffer for writing.
     pub(crate) fn write_to_log(&self, iobuf: &IoBuf) -> Result<()> {
         let _measure = Measure::new(&M.write_to_log);
+        let total_len = self.prepare_write(iobuf);
+        let lid = iobuf.lid;
+        let data = unsafe { &(&*iobuf.buf.get())[..total_len] };
+
+        io_fail!(self, "buffer write");
+        if let Some(ref log_file) = self.config.log_file {
+            log_file.pwrite_all(data, lid)?;
+            if !self.config.temporary {
+                log_file.sync()?;
+            }
+        } else {
+            let f = &self.config.file;
+            f.pwrite_all(data, lid)?;
+            if !self.config.temporary {
+                f.sync_all()?;
+            }
+        }
+        io_fail!(self, "buffer write post");
+
+        self.complete_write(iobuf, total_len)
+    }
+
+    // Pad the end of a sealed IO buffer, returning the
+    // number of bytes to write from it.
+    fn prepare_write(&self, iobuf: &IoBuf) -> usize {
         let header = iobuf.get_header();
         let lid = iobuf.lid;
         let base_lsn = iobuf.lsn;
@@ -320,68 +347,64 @@ impl IoBufs {
         let unused_space = capacity - bytes_to_write;
         let should_pad = maxed && unused_space >= MSG_HEADER_LEN;
 
+        let data = unsafe { (*iobuf.buf.get()).as_mut_slice() };
+
         // a pad is a null message written to the end of a buffer
         // to signify that nothing else will be written into it
         if should_pad {
-            let data = unsafe { (*iobuf.buf.get()).as_mut_slice() };
-            let pad_len = capacity - bytes_to_write - MSG_HEADER_LEN;
-
-            // take the crc of the random bytes already after where we
-            // would place our header.
-            let padding_bytes = vec![MessageKind::Corrupted.into(); pad_len];
-
-            let header = MessageHeader {
-                kind: MessageKind::Pad,
-                pid: PageId::max_value(),
-                lsn: base_lsn + bytes_to_write as Lsn,
-                len: u32::try_from(pad_len).unwrap(),
-                crc32: 0,
-            };
-
-            let header_bytes: [u8; MSG_HEADER_LEN] = header.into();
-
-            unsafe {
-                std::ptr::copy_nonoverlapping(
-                    header_bytes.as_ptr(),
-                    data.as_mut_ptr().add(bytes_to_write),
-                    MSG_HEADER_LEN,
-                );
-                std::ptr::copy_nonoverlapping(
-                    padding_bytes.as_ptr(),
-                    data.as_mut_ptr().add(bytes_to_write + MSG_HEADER_LEN),
-                    pad_len,
-                );
-            }
-
-            let mut hasher = crc32fast::Hasher::new();
-            hasher.update(&padding_bytes);
-            hasher.update(&header_bytes);
-            let crc32 = hasher.finalize();
-            let crc32_arr = u32_to_arr(crc32 ^ 0xFFFF_FFFF);
-
-            unsafe {
-                std::ptr::copy_nonoverlapping(
-                    crc32_arr.as_ptr(),
-                    data.as_mut_ptr().add(
-                        bytes_to_write + MSG_HEADER_LEN
-                            - std::mem::size_of::<u32>(),
-                    ),
-                    std::mem::size_of::<u32>(),
-                );
-            }
+            store_filler(
+                data,
+                bytes_to_write,
+                unused_space,
+                MessageKind::Pad,
+                base_lsn + bytes_to_write as Lsn,
+            );
         }
 
-        let total_len = if maxed { capacity } else { bytes_to_write };
-
-        let data = unsafe { (*iobuf.buf.get()).as_mut_slice() };
+        // direct writes are padded out to the next block with a
+        // message that recovery skips, like an aborted reservation
+        let gap = if maxed || !self.config.direct_io() {
+            0
+        } else {
+            alignment_gap(lid, bytes_to_write)
+        };
+        if gap > 0 {
+            store_filler(
+                data,
+                bytes_to_write,
+                gap,
+                MessageKind::Cancelled,
+                base_lsn + bytes_to_write as Lsn,
+            );
+        }
 
-        let f = &self.config.file;
-        io_fail!(self, "buffer write");
-        f.pwrite_all(&data[..total_len], lid)?;
-        if !self.config.temporary {
-            f.sync_all()?;
+        if maxed {
+            capacity
+        } else {
+            bytes_to_write + gap
         }
+    }
+
+    // Complete a write that was submitted to io_uring.
+    #[cfg(all(target_os = "linux", feature = "io_uring"))]
+    fn complete_uring_write(
+        &self,
+        iobuf: &IoBuf,
+        total_len: usize,
+        res: io::Result<usize>,
+    ) -> Result<()> {
+        res?;
         io_fail!(self, "buffer write post");
+        self.complete_write(iobuf, total_len)
+    }
+
+    // Mark the data of an IO buffer as stable, once it
+    // was written.
+    fn complete_write(&self, iobuf: &IoBuf, total_len: usize) -> Result<()> {
+        let lid = iobuf.lid;
+        let base_lsn = iobuf.lsn;
+        let io_buf_size = self.config.io_buf_size;
+        let maxed = iobuf.linearized(|| iobuf.get_maxed());
 
         if total_len > 0 {
             let complete_len = if maxed {
@@ -539,6 +562,10 @@ pub(crate) fn make_stable(iobufs: &Arc<IoBufs>, lsn: Lsn) -> Result<usize> {
         // block until another thread updates the stable lsn
         let mut waiter = iobufs.intervals.lock();
 
+        // a failed write sets the error before notifying
+        // under this lock, so check it again before waiting
+        iobufs.config.global_error()?;
+
         stable = iobufs.stable();
         if stable < lsn {
             trace!("waiting on cond var for make_stable({})", lsn);
@@ -584,6 +611,165 @@ pub(super) fn flush(iobufs: &Arc<IoBufs>) -> Result<usize> {
     make_stable(iobufs, max_reserved_lsn)
 }
 
+/// Writes a sealed IO buffer that has no writers left,
+/// without blocking the calling thread.
+pub(crate) fn write_in_background(iobufs: &Arc<IoBufs>, iobuf: &Arc<IoBuf>) {
+    let lsn = iobuf.lsn;
+
+    #[cfg(all(target_os = "linux", feature = "io_uring"))]
+    {
+        if let Some(ref uring) = iobufs.config.uring {
+            match submit_to_log(iobufs, iobuf, uring) {
+                Ok(_promise) => {
+                    #[cfg(any(test, feature = "check_snapshot_integrity"))]
+                    _promise.unwrap();
+                }
+                Err(e) => write_failed(iobufs, lsn, e),
+            }
+            return;
+        }
+    }
+
+    let iobufs = iobufs.clone();
+    let iobuf = iobuf.clone();
+    let _result = threadpool::spawn(move || {
+        if let Err(e) = iobufs.write_to_log(&iobuf) {
+            write_failed(&iobufs, lsn, e);
+        }
+    });
+
+    #[cfg(any(test, feature = "check_snapshot_integrity"))]
+    _result.unwrap();
+}
+
+/// Writes an IO buffer through io_uring. The write is completed
+/// by the thread that reaps the ring, which fills the returned
+/// `Promise` afterwards.
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+fn submit_to_log(
+    iobufs: &Arc<IoBufs>,
+    iobuf: &Arc<IoBuf>,
+    uring: &Uring,
+) -> Result<Promise<()>> {
+    let measure = Measure::new(&M.write_to_log);
+    let total_len = iobufs.prepare_write(iobuf);
+    let lid = iobuf.lid;
+    let data = unsafe { &(&*iobuf.buf.get())[..total_len] };
+
+    io_fail!(iobufs, "buffer write");
+
+    // with O_DIRECT, the first write after a restart may
+    // have to be copied to start on a block boundary
+    let aligned = if let Some(ref log_file) = iobufs.config.log_file {
+        log_file.align(data, lid)?
+    } else {
+        None
+    };
+    let (buf, offset) = match aligned {
+        Some((ref aligned, start)) => (
+            unsafe {
+                std::slice::from_raw_parts(aligned.as_ptr(), aligned.len())
+            },
+            start,
+        ),
+        None => (data, lid),
+    };
+
+    let (filler, promise) = Promise::pair();
+    let callback = {
+        let iobufs = iobufs.clone();
+        let iobuf = iobuf.clone();
+        move |res: io::Result<usize>| {
+            if let Err(e) = iobufs.complete_uring_write(&iobuf, total_len, res)
+            {
+                write_failed(&iobufs, iobuf.lsn, e);
+            }
+
+            // the kernel is done with the buffers now
+            drop(aligned);
+            drop(iobuf);
+            drop(measure);
+
+            filler.fill(());
+        }
+    };
+
+    // the callback keeps both buffers alive until the write completes
+    unsafe {
+        uring.write_log(
+            buf,
+            offset,
+            !iobufs.config.temporary,
+            Box::new(callback),
+        );
+    }
+
+    Ok(promise)
+}
+
+fn write_failed(iobufs: &IoBufs, lsn: Lsn, e: Error) {
+    error!("hit error while writing iobuf with lsn {}: {:?}", lsn, e);
+    iobufs.config.set_global_error(e);
+    // wake up any waiting threads so they don't stall forever.
+    // the lock is held while notifying, so that a thread can
+    // not miss the error between checking it and waiting.
+    let _intervals = iobufs.intervals.lock();
+    iobufs.interval_updated.notify_all();
+}
+
+/// Writes a message of `len` bytes, including its header, at
+/// `offset` in `data`, that only fills space in the log.
+fn store_filler(
+    data: &mut [u8],
+    offset: usize,
+    len: usize,
+    kind: MessageKind,
+    lsn: Lsn,
+) {
+    let filler_len = len - MSG_HEADER_LEN;
+
+    // take the crc of the random bytes already after where we
+    // would place our header.
+    let filler_bytes = vec![MessageKind::Corrupted.into(); filler_len];
+
+    let header = MessageHeader {
+        kind,
+        toggles_compression: false,
+        pid: PageId::max_value(),
+        lsn,
+        len: u32::try_from(filler_len).unwrap(),
+        crc32: 0,
+    };
+
+    let header_bytes: [u8; MSG_HEADER_LEN] = header.into();
+
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&filler_bytes);
+    hasher.update(&header_bytes);
+    let crc32 = hasher.finalize();
+    let crc32_arr = u32_to_arr(crc32 ^ 0xFFFF_FFFF);
+
+    let message = &mut data[offset..offset + len];
+    message[..MSG_HEADER_LEN].copy_from_slice(&header_bytes);
+    message[MSG_HEADER_LEN..].copy_from_slice(&filler_bytes);
+    message[MSG_HEADER_LEN - std::mem::size_of::<u32>()..MSG_HEADER_LEN]
+        .copy_from_slice(&crc32_arr);
+}
+
+/// Returns how many bytes must follow `len` bytes that are
+/// written at `lid` for the write to end on a multiple of
+/// `DIRECT_IO_ALIGNMENT`, leaving room for the header of a
+/// message to fill them.
+fn alignment_gap(lid: LogId, len: usize) -> usize {
+    let end = usize::try_from(lid).unwrap() + len;
+    let mut gap =
+        (DIRECT_IO_ALIGNMENT - end % DIRECT_IO_ALIGNMENT) % DIRECT_IO_ALIGNMENT;
+    if gap != 0 && gap < MSG_HEADER_LEN {
+        gap += DIRECT_IO_ALIGNMENT;
+    }
+    gap
+}
+
 /// Attempt to seal the current IO buffer, possibly
 /// writing it to disk if there are no other writers
 /// operating on it.
@@ -613,7 +799,14 @@ pub(crate) fn maybe_seal_and_write_iobuf(
     let sealed = mk_sealed(header);
     let res_len = offset(sealed);
 
-    let maxed = from_reserve || capacity - res_len < MSG_HEADER_LEN;
+    // with O_DIRECT, the next buffer starts at the next block
+    let gap = if iobufs.config.direct_io() {
+        alignment_gap(lid, res_len)
+    } else {
+        0
+    };
+
+    let maxed = from_reserve || capacity < res_len + gap + MSG_HEADER_LEN;
 
     let worked = iobuf.linearized(|| {
         if iobuf.cas_header(header, sealed).is_err() {
@@ -684,11 +877,11 @@ pub(crate) fn maybe_seal_and_write_iobuf(
         debug!(
             "advancing offset within the current segment from {} to {}",
             lid,
-            lid + res_len as LogId
+            lid + (res_len + gap) as LogId
         );
-        next_lsn += res_len as Lsn;
+        next_lsn += (res_len + gap) as Lsn;
 
-        lid + res_len as LogId
+        lid + (res_len + gap) as LogId
     };
 
     let mut next_iobuf = IoBuf::new(io_buf_size);
@@ -702,7 +895,7 @@ pub(crate) fn maybe_seal_and_write_iobuf(
         next_iobuf.capacity = io_buf_size;
         next_iobuf.store_segment_header(sealed, next_lsn, iobufs.stable());
     } else {
-        let new_cap = capacity - res_len;
+        let new_cap = capacity - res_len - gap;
         assert_ne!(new_cap, 0);
         next_iobuf.capacity = new_cap;
         next_iobuf.lsn = next_lsn;
@@ -731,22 +924,7 @@ pub(crate) fn maybe_seal_and_write_iobuf(
             "asynchronously writing iobuf with lsn {} to log from maybe_seal",
             lsn
         );
-        let iobufs = iobufs.clone();
-        let iobuf = iobuf.clone();
-        let _result = threadpool::spawn(move || {
-            if let Err(e) = iobufs.write_to_log(&iobuf) {
-                error!(
-                    "hit error while writing iobuf with lsn {}: {:?}",
-                    lsn, e
-                );
-                let _ = iobufs.intervals.lock();
-                iobufs.interval_updated.notify_all();
-                iobufs.config.set_global_error(e);
-            }
-        });
-
-        #[cfg(any(test, feature = "check_snapshot_integrity"))]
-        _result.unwrap();
+        write_in_background(iobufs, iobuf);
 
         Ok(())
     } else {
@@ -783,7 +961,7 @@ impl Debug for IoBuf {
 impl IoBuf {
     pub(crate) fn new(buf_size: usize) -> Self {
         Self {
-            buf: UnsafeCell::new(vec![0; buf_size]),
+            buf: UnsafeCell::new(AlignedBuf::new(buf_size)),
             header: CachePadded::new(AtomicU64::new(0)),
             lid: LogId::max_value(),
             lsn: 0,
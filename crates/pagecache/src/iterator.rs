@@ -392,6 +392,13 @@ fn clean_tail_tears(
     for (lsn, lid) in ordering
         .range((std::ops::Bound::Excluded(tip.0), std::ops::Bound::Unbounded))
     {
+        if config.read_only {
+            // the segment may still be in the middle of being
+            // written by the process that owns the log.
+            debug!("ignoring torn segment with lsn {} at lid {}", lsn, lid);
+            continue;
+        }
+
         debug!("zeroing torn segment with lsn {} at lid {}", lsn, lid);
 
         // NB we intentionally corrupt this header to prevent any segment
@@ -18,11 +18,14 @@ macro_rules! maybe_fail {
     };
 }
 
+mod backup;
 mod blob_io;
 mod config;
 mod constants;
 mod diskptr;
 mod ds;
+mod encryption;
+mod integrity;
 mod iobuf;
 mod iterator;
 mod lazy;
@@ -36,10 +39,16 @@ mod promise;
 mod reader;
 mod reservation;
 mod result;
+mod salvage;
 mod segment;
+mod settings;
 mod snapshot;
 mod threadpool;
 mod util;
+mod versions;
+
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+mod uring;
 
 #[cfg(feature = "measure_allocs")]
 mod measure_allocs;
@@ -76,22 +85,36 @@ use self::logger::{MessageHeader, SegmentHeader};
 use self::{
     blob_io::{gc_blobs, read_blob, remove_blob, write_blob},
     config::PersistedConfig,
-    constants::{BATCH_MANIFEST_PID, CONFIG_PID, COUNTER_PID, META_PID},
+    constants::{
+        BATCH_MANIFEST_PID, COMPRESSION_TOGGLE, CONFIG_PID, COUNTER_PID,
+        META_PID,
+    },
+    encryption::{
+        maybe_decrypt, message_aad, snapshot_aad, KeyScope, SEAL_OVERHEAD,
+    },
     iobuf::{IoBuf, IoBufs},
     iterator::{raw_segment_iter_from, LogIter},
     metrics::{clock, measure},
     pagecache::Update,
-    parallel_io::Pio,
+    parallel_io::{AlignedBuf, LogFile, Pio},
     reader::LogReader,
     segment::SegmentAccountant,
-    snapshot::{advance_snapshot, PageState},
+    snapshot::{advance_snapshot, follow_snapshot, PageState},
     util::{arr_to_u32, arr_to_u64, maybe_decompress, u32_to_arr, u64_to_arr},
 };
 
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+use self::uring::Uring;
+
 pub use self::{
     config::{Config, ConfigBuilder},
     diskptr::DiskPtr,
-    ds::{node_from_frag_vec, Lru, Node, PageTable, Stack, StackIter, VecSet},
+    ds::{
+        node_from_frag_vec, CachePolicy, Lru, Node, PageTable, Stack,
+        StackIter, VecSet,
+    },
+    encryption::EncryptionKey,
+    integrity::IntegrityReport,
     lazy::Lazy,
     logger::{Log, LogRead},
     map::{FastMap1, FastMap4, FastMap8, FastSet1, FastSet4, FastSet8},
@@ -99,17 +122,23 @@ pub use self::{
     meta::Meta,
     metrics::M,
     pagecache::{PageCache, PagePtr, RecoveryGuard},
+    parallel_io::IoMode,
     promise::{Promise, PromiseFiller},
     reservation::Reservation,
     result::{CasResult, Error, Result},
+    salvage::{salvage, Salvage},
     segment::SegmentMode,
+    settings::{CachePriority, PageSettings},
+    threadpool::{spawn, spawn_or_run},
+    versions::PageVersions,
 };
 
 #[doc(hidden)]
 pub use self::{
     constants::{
-        BATCH_MANIFEST_INLINE_LEN, BLOB_INLINE_LEN, MAX_SPACE_AMPLIFICATION,
-        MINIMUM_ITEMS_PER_SEGMENT, MSG_HEADER_LEN, SEG_HEADER_LEN,
+        BATCH_MANIFEST_INLINE_LEN, BLOB_INLINE_LEN, DIRECT_IO_ALIGNMENT,
+        MAX_SPACE_AMPLIFICATION, MINIMUM_ITEMS_PER_SEGMENT, MSG_HEADER_LEN,
+        SEG_HEADER_LEN,
     },
     ds::PAGETABLE_NODE_SZ,
     metrics::Measure,
@@ -254,7 +283,9 @@ impl From<MessageKind> for LogKind {
     }
 }
 
-pub(crate) fn crc32(buf: &[u8]) -> u32 {
+/// Calculates the crc32 checksum of the provided buffer.
+#[doc(hidden)]
+pub fn crc32(buf: &[u8]) -> u32 {
     let mut hasher = crc32fast::Hasher::new();
     hasher.update(&buf);
     hasher.finalize()
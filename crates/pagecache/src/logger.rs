@@ -87,6 +87,14 @@ impl Log {
 
         if ptr.is_inline() {
             let lid = ptr.lid();
+
+            #[cfg(all(target_os = "linux", feature = "io_uring"))]
+            {
+                if let Some(ref uring) = self.config.uring {
+                    return uring.read_message(lid, lsn, &self.config);
+                }
+            }
+
             let f = &self.config.file;
 
             f.read_message(lid, lsn, &self.config)
@@ -95,10 +103,11 @@ impl Log {
             // here because it might not still
             // exist in the inline log.
             let (_lid, blob_ptr) = ptr.blob();
-            read_blob(blob_ptr, &self.config).map(|(kind, buf)| {
+            read_blob(blob_ptr, pid, &self.config).map(|(kind, buf)| {
                 let sz = MSG_HEADER_LEN + BLOB_INLINE_LEN;
                 let header = MessageHeader {
                     kind,
+                    toggles_compression: false,
                     pid,
                     lsn,
                     crc32: 0,
@@ -140,7 +149,7 @@ impl Log {
         let lsn_buf: [u8; std::mem::size_of::<BlobPointer>()] =
             u64_to_arr(blob_ptr as u64);
 
-        self.reserve_inner(LogKind::Replace, pid, &lsn_buf, true)
+        self.reserve_inner(LogKind::Replace, pid, &lsn_buf, true, false)
     }
 
     /// Tries to claim a reservation for writing a buffer to a
@@ -154,26 +163,41 @@ impl Log {
         log_kind: LogKind,
         pid: PageId,
         raw_buf: &[u8],
+    ) -> Result<Reservation> {
+        self.reserve_with(log_kind, pid, raw_buf, &PageSettings::default())
+    }
+
+    /// Like `reserve`, but compresses the buffer according to
+    /// the settings of the page it belongs to.
+    pub(crate) fn reserve_with(
+        &self,
+        log_kind: LogKind,
+        pid: PageId,
+        raw_buf: &[u8],
+        settings: &PageSettings,
     ) -> Result<Reservation> {
         let mut _compressed: Option<Vec<u8>> = None;
         let mut buf = raw_buf;
 
+        let compression = settings.compression(&self.config);
+        let toggles_compression =
+            compression.is_some() != self.config.use_compression;
+
         #[cfg(feature = "compression")]
         {
-            if self.config.use_compression {
+            if let Some(compression_factor) = compression {
                 use zstd::block::compress;
 
                 let _measure = Measure::new(&M.compress);
 
-                let compressed_buf =
-                    compress(buf, self.config.compression_factor).unwrap();
+                let compressed_buf = compress(buf, compression_factor).unwrap();
                 _compressed = Some(compressed_buf);
 
                 buf = _compressed.as_ref().unwrap();
             }
         }
 
-        self.reserve_inner(log_kind, pid, buf, false)
+        self.reserve_inner(log_kind, pid, buf, false, toggles_compression)
     }
 
     fn reserve_inner(
@@ -182,10 +206,25 @@ impl Log {
         pid: PageId,
         buf: &[u8],
         is_blob_rewrite: bool,
+        toggles_compression: bool,
     ) -> Result<Reservation> {
         let _measure = Measure::new(&M.reserve_lat);
 
-        let total_buf_len = MSG_HEADER_LEN + buf.len();
+        // the body is sealed once its lsn is known, because the
+        // lsn is part of its associated data. batch manifests are
+        // filled in after being reserved, and blob rewrites only
+        // contain a pointer to a blob that is already sealed.
+        let encrypt = self.config.encryption_key.is_some()
+            && pid != BATCH_MANIFEST_PID
+            && !is_blob_rewrite;
+
+        let sealed_len = if encrypt {
+            buf.len() + SEAL_OVERHEAD
+        } else {
+            buf.len()
+        };
+
+        let total_buf_len = MSG_HEADER_LEN + sealed_len;
 
         M.reserve_sz.measure(total_buf_len as f64);
 
@@ -347,12 +386,39 @@ impl Log {
 
             bump_atomic_lsn(&self.iobufs.max_reserved_lsn, reservation_lsn);
 
-            self.iobufs.encapsulate(
-                &*buf,
-                destination,
+            let _sealed: Option<Vec<u8>>;
+            let body = if encrypt {
+                let aad = message_aad(
+                    reservation_lsn,
+                    pid,
+                    kind,
+                    toggles_compression,
+                );
+                let scope = if over_blob_threshold {
+                    KeyScope::Blob(reservation_lsn)
+                } else {
+                    KeyScope::segment(&self.config, reservation_lsn)
+                };
+                let key = self.config.encryption_key.as_ref().unwrap();
+                _sealed = Some(key.seal(scope, buf, &aad));
+                _sealed.as_ref().unwrap()
+            } else {
+                buf
+            };
+
+            let header = MessageHeader {
                 kind,
+                toggles_compression,
                 pid,
-                reservation_lsn,
+                lsn: reservation_lsn,
+                len: 0,
+                crc32: 0,
+            };
+
+            self.iobufs.encapsulate(
+                body,
+                destination,
+                header,
                 over_blob_threshold,
             )?;
 
@@ -415,20 +481,7 @@ impl Log {
                  to log from exit_reservation",
                 lsn
             );
-            let iobufs = self.iobufs.clone();
-            let iobuf = iobuf.clone();
-            let _result = threadpool::spawn(move || {
-                if let Err(e) = iobufs.write_to_log(&iobuf) {
-                    error!(
-                        "hit error while writing iobuf with lsn {}: {:?}",
-                        lsn, e
-                    );
-                    iobufs.config.set_global_error(e);
-                }
-            });
-
-            #[cfg(any(test, feature = "check_snapshot_integrity"))]
-            _result.unwrap();
+            iobuf::write_in_background(&self.iobufs, iobuf);
 
             Ok(())
         } else {
@@ -460,6 +513,9 @@ impl Drop for Log {
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct MessageHeader {
     pub(crate) kind: MessageKind,
+    /// Whether the compression of this message differs
+    /// from what `use_compression` implies.
+    pub(crate) toggles_compression: bool,
     pub(crate) lsn: Lsn,
     pub(crate) pid: PageId,
     pub(crate) len: u32,
@@ -550,7 +606,8 @@ impl LogRead {
 
 impl From<[u8; MSG_HEADER_LEN]> for MessageHeader {
     fn from(buf: [u8; MSG_HEADER_LEN]) -> Self {
-        let kind = MessageKind::from(buf[0]);
+        let kind = MessageKind::from(buf[0] & !COMPRESSION_TOGGLE);
+        let toggles_compression = buf[0] & COMPRESSION_TOGGLE != 0;
 
         unsafe {
             let pid = arr_to_u64(buf.get_unchecked(1..9));
@@ -560,6 +617,7 @@ impl From<[u8; MSG_HEADER_LEN]> for MessageHeader {
 
             Self {
                 kind,
+                toggles_compression,
                 pid,
                 lsn,
                 len,
@@ -573,6 +631,9 @@ impl Into<[u8; MSG_HEADER_LEN]> for MessageHeader {
     fn into(self) -> [u8; MSG_HEADER_LEN] {
         let mut buf = [0; MSG_HEADER_LEN];
         buf[0] = self.kind.into();
+        if self.toggles_compression {
+            buf[0] |= COMPRESSION_TOGGLE;
+        }
 
         let pid_arr = u64_to_arr(self.pid);
         let lsn_arr = u64_to_arr(self.lsn as u64);
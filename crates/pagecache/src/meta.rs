@@ -2,11 +2,19 @@ use std::collections::BTreeMap;
 
 use super::*;
 
+/// The version of what follows the roots of a serialized
+/// `Meta`. Versions before it only held the roots, and a
+/// `Meta` without comparators or settings is still written
+/// that way, so both can be read by any version.
+const META_FORMAT_VERSION: u64 = 2;
+
 /// A simple map that can be used to store metadata
 /// for the pagecache tenant.
 #[derive(Clone, Debug, Eq, PartialEq, Default, Serialize, Deserialize)]
 pub struct Meta {
     inner: BTreeMap<Vec<u8>, PageId>,
+    comparators: BTreeMap<Vec<u8>, String>,
+    settings: BTreeMap<Vec<u8>, PageSettings>,
 }
 
 impl Meta {
@@ -20,22 +28,102 @@ impl Meta {
         self.inner.insert(name, pid);
     }
 
-    /// Remove the page mapping for a given identifier
+    /// Remove the page mapping for a given identifier,
+    /// along with the name of its comparator and its settings
     pub fn del_root(&mut self, name: &[u8]) -> Option<PageId> {
+        self.comparators.remove(name);
+        self.settings.remove(name);
         self.inner.remove(name)
     }
 
+    /// Retrieve the name of the comparator that orders the
+    /// keys of a collection, if it does not use byte order
+    pub fn get_comparator(&self, table: &[u8]) -> Option<&str> {
+        self.comparators.get(table).map(String::as_str)
+    }
+
+    /// Set the name of the comparator for an identifier
+    pub fn set_comparator(&mut self, name: Vec<u8>, comparator: String) {
+        self.comparators.insert(name, comparator);
+    }
+
+    /// Retrieve the overrides of the `Config` for the pages
+    /// of a collection, which are the defaults if none were set
+    pub fn get_settings(&self, table: &[u8]) -> PageSettings {
+        self.settings.get(table).cloned().unwrap_or_default()
+    }
+
+    /// Set the overrides of the `Config` for an identifier
+    pub fn set_settings(&mut self, name: Vec<u8>, settings: PageSettings) {
+        if settings == PageSettings::default() {
+            self.settings.remove(&name);
+        } else {
+            self.settings.insert(name, settings);
+        }
+    }
+
     /// Return the current rooted tenants in Meta
     pub fn tenants(&self) -> BTreeMap<Vec<u8>, PageId> {
         self.inner.clone()
     }
 
+    /// Serializes the `Meta` for the log: the roots in the
+    /// layout that every version starts with, followed by the
+    /// comparators and settings if there are any.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = serialize(&self.inner).unwrap();
+        if !self.comparators.is_empty() || !self.settings.is_empty() {
+            let rest = (META_FORMAT_VERSION, &self.comparators, &self.settings);
+            buf.extend(serialize(&rest).unwrap());
+        }
+        buf
+    }
+
+    /// Reads a `Meta` written by `to_bytes`, or by a version
+    /// that only stored the roots.
+    pub(crate) fn from_bytes(mut buf: &[u8]) -> bincode::Result<Meta> {
+        let inner = bincode::deserialize_from(&mut buf)?;
+        if buf.is_empty() {
+            return Ok(Meta {
+                inner,
+                ..Meta::default()
+            });
+        }
+
+        let version: u64 = bincode::deserialize_from(&mut buf)?;
+        if version != META_FORMAT_VERSION {
+            return Err(Box::new(bincode::ErrorKind::Custom(format!(
+                "unknown Meta format version {}",
+                version
+            ))));
+        }
+
+        let (comparators, settings) = deserialize(buf)?;
+        Ok(Meta {
+            inner,
+            comparators,
+            settings,
+        })
+    }
+
     pub(crate) fn size_in_bytes(&self) -> u64 {
-        self.inner
+        let roots: u64 = self
+            .inner
             .iter()
             .map(|(k, _pid)| {
                 k.len() as u64 + std::mem::size_of::<PageId>() as u64
             })
-            .sum()
+            .sum();
+        let comparators: u64 = self
+            .comparators
+            .iter()
+            .map(|(k, c)| k.len() as u64 + c.len() as u64)
+            .sum();
+        let settings: u64 = self
+            .settings
+            .iter()
+            .map(|(k, s)| k.len() as u64 + s.size_in_bytes())
+            .sum();
+        roots + comparators + settings
     }
 }
@@ -105,7 +105,12 @@ pub struct Metrics {
     pub deserialize: Histo,
     pub compress: Histo,
     pub decompress: Histo,
+    pub encrypt: Histo,
+    pub decrypt: Histo,
     pub make_stable: Histo,
+    pub backup: Histo,
+    pub verify_integrity: Histo,
+    pub refresh: Histo,
     pub assign_offset: Histo,
     pub assign_spinloop: Histo,
     pub reserve_lat: Histo,
@@ -279,12 +284,17 @@ impl Metrics {
             lat("deserialize", &self.deserialize),
             lat("compress", &self.compress),
             lat("decompress", &self.decompress),
+            lat("encrypt", &self.encrypt),
+            lat("decrypt", &self.decrypt),
         ]);
 
         println!("{}", std::iter::repeat("-").take(134).collect::<String>());
         println!("log:");
         p(vec![
             lat("make_stable", &self.make_stable),
+            lat("backup", &self.backup),
+            lat("verify integrity", &self.verify_integrity),
+            lat("refresh", &self.refresh),
             lat("read", &self.read),
             lat("write", &self.write_to_log),
             sz("written bytes", &self.written_bytes),
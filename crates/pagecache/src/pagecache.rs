@@ -1,4 +1,10 @@
-use std::{borrow::Cow, collections::BinaryHeap, ops::Deref, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::BinaryHeap,
+    ops::Deref,
+    sync::{atomic::AtomicUsize, Arc, Weak},
+    time::Duration,
+};
 
 use parking_lot::Mutex;
 
@@ -196,6 +202,8 @@ where
     idgen_persists: Arc<AtomicU64>,
     idgen_persist_mu: Arc<Mutex<()>>,
     was_recovered: bool,
+    versions: Mutex<Vec<Weak<PageVersions<P>>>>,
+    versions_held: Arc<AtomicUsize>,
 }
 
 unsafe impl<P> Send for PageCache<P> where P: Materializer {}
@@ -270,13 +278,15 @@ where
 
         config.reset_global_error();
 
+        config.verify_encryption_key()?;
+
         // try to pull any existing snapshot off disk, and
         // apply any new data to it to "catch-up" the
         // snapshot before loading it.
         let snapshot = read_snapshot_or_default(&config)?;
 
         let cache_capacity = config.cache_capacity;
-        let lru = Lru::new(cache_capacity);
+        let lru = Lru::with_policy(cache_capacity, config.cache_policy);
 
         let mut pc = Self {
             config: config.clone(),
@@ -288,6 +298,8 @@ where
             updates: AtomicU64::new(0),
             last_snapshot: Arc::new(Mutex::new(Some(snapshot))),
             idgen_persist_mu: Arc::new(Mutex::new(())),
+            versions: Mutex::new(vec![]),
+            versions_held: Arc::new(AtomicUsize::new(0)),
             idgen: Arc::new(AtomicU64::new(0)),
             idgen_persists: Arc::new(AtomicU64::new(0)),
             was_recovered: false,
@@ -328,6 +340,7 @@ where
 
             if let Err(Error::ReportableBug(..)) = pc.get_meta(&guard) {
                 // set up meta
+                pc.verify_can_initialize()?;
                 was_recovered = false;
 
                 let meta_update = Update::Meta(Meta::default());
@@ -345,6 +358,7 @@ where
 
             if let Err(Error::ReportableBug(..)) = pc.get_idgen(&guard) {
                 // set up idgen
+                pc.verify_can_initialize()?;
                 was_recovered = false;
 
                 let counter_update = Update::Counter(0);
@@ -365,6 +379,7 @@ where
                 pc.get_persisted_config(&guard)
             {
                 // set up idgen
+                pc.verify_can_initialize()?;
                 was_recovered = false;
 
                 let config_update = Update::Config(PersistedConfig);
@@ -427,7 +442,63 @@ where
         new: P,
         guard: &'g Guard,
     ) -> Result<(PageId, PagePtr<'g, P>)> {
-        self.allocate_inner(Update::Compact(new), guard)
+        self.allocate_with(new, &PageSettings::default(), guard)
+    }
+
+    /// Like `allocate`, but writes the page according to the
+    /// overrides of the `Config` for the collection it is in.
+    pub fn allocate_with<'g>(
+        &self,
+        new: P,
+        settings: &PageSettings,
+        guard: &'g Guard,
+    ) -> Result<(PageId, PagePtr<'g, P>)> {
+        let pid = self.reserve_pid(guard);
+        let new_ptr =
+            self.install_new_page(pid, Update::Compact(new), settings, guard)?;
+
+        Ok((pid, new_ptr))
+    }
+
+    /// Takes the ID of a new page without writing anything to
+    /// the log, so that pages which point to each other can
+    /// each be written once, with `allocate_reserved`. The
+    /// page reads as missing until then. Every reserved page
+    /// must be written, because recovery expects each page
+    /// below the highest written one to exist.
+    pub fn reserve_pid(&self, guard: &Guard) -> PageId {
+        if let Some(pid) = self.free.lock().pop() {
+            trace!("re-allocating pid {}", pid);
+            return pid;
+        }
+
+        let pid = self.next_pid_to_allocate.fetch_add(1, Relaxed);
+
+        trace!("allocating pid {} for the first time", pid);
+
+        let new_stack = Stack::default();
+
+        let head_ptr = Owned::new(new_stack).into_shared(guard);
+
+        self.inner.cas(pid, Shared::null(), head_ptr, guard).expect(
+            "allocating a fresh new page should \
+                 never conflict on existing data",
+        );
+
+        pid
+    }
+
+    /// Writes the first state of a page that was taken with
+    /// `reserve_pid`, returning its pointer for use in future
+    /// atomic `replace` and `link` operations.
+    pub fn allocate_reserved<'g>(
+        &self,
+        pid: PageId,
+        new: P,
+        settings: &PageSettings,
+        guard: &'g Guard,
+    ) -> Result<PagePtr<'g, P>> {
+        self.install_new_page(pid, Update::Compact(new), settings, guard)
     }
 
     /// Attempt to opportunistically rewrite data from a Draining
@@ -490,63 +561,53 @@ where
         new: Update<P>,
         guard: &'g Guard,
     ) -> Result<(PageId, PagePtr<'g, P>)> {
-        let (pid, key) = if let Some(pid) = self.free.lock().pop() {
-            trace!("re-allocating pid {}", pid);
+        let pid = self.reserve_pid(guard);
+        let new_ptr =
+            self.install_new_page(pid, new, &PageSettings::default(), guard)?;
 
-            let head_ptr = match self.inner.get(pid, &guard) {
-                None => panic!(
-                    "expected to find existing stack \
-                     for re-allocated pid {}",
-                    pid
-                ),
-                Some(p) => p,
-            };
-
-            let head = unsafe { head_ptr.deref().head(&guard) };
-
-            let mut stack_iter = StackIter::from_ptr(head, &guard);
-
-            match stack_iter.next() {
-                Some((Some(Update::Free), cache_info)) => (
-                    pid,
-                    PagePtr {
-                        cached_ptr: head,
-                        ts: cache_info.ts,
-                    },
-                ),
-                other => panic!(
-                    "failed to re-allocate pid {} which \
-                     contained unexpected state {:?}",
-                    pid, other
-                ),
-            }
-        } else {
-            let pid = self.next_pid_to_allocate.fetch_add(1, Relaxed);
-
-            trace!("allocating pid {} for the first time", pid);
+        Ok((pid, new_ptr))
+    }
 
-            let new_stack = Stack::default();
+    // installs the first state of a page ID from `reserve_pid`,
+    // which is either empty or freed.
+    fn install_new_page<'g>(
+        &self,
+        pid: PageId,
+        new: Update<P>,
+        settings: &PageSettings,
+        guard: &'g Guard,
+    ) -> Result<PagePtr<'g, P>> {
+        let head_ptr = match self.inner.get(pid, guard) {
+            None => panic!(
+                "expected to find existing stack \
+                 for allocated pid {}",
+                pid
+            ),
+            Some(p) => p,
+        };
 
-            let head_ptr = Owned::new(new_stack).into_shared(&guard);
+        let head = unsafe { head_ptr.deref().head(guard) };
 
-            self.inner
-                .cas(pid, Shared::null(), head_ptr, &guard)
-                .expect(
-                    "allocating a fresh new page should \
-                     never conflict on existing data",
-                );
+        let mut stack_iter = StackIter::from_ptr(head, guard);
 
-            (
-                pid,
-                PagePtr {
-                    cached_ptr: Shared::null(),
-                    ts: 0,
-                },
-            )
+        let key = match stack_iter.next() {
+            None => PagePtr {
+                cached_ptr: Shared::null(),
+                ts: 0,
+            },
+            Some((Some(Update::Free), cache_info)) => PagePtr {
+                cached_ptr: head,
+                ts: cache_info.ts,
+            },
+            other => panic!(
+                "failed to allocate pid {} which \
+                 contained unexpected state {:?}",
+                pid, other
+            ),
         };
 
         let new_ptr = self
-            .cas_page(pid, key, new, false, guard)?
+            .cas_page(pid, key, new, false, settings, guard)?
             .unwrap_or_else(|e| {
                 panic!(
                     "should always be able to install \
@@ -556,7 +617,7 @@ where
                 )
             });
 
-        Ok((pid, new_ptr))
+        Ok(new_ptr)
     }
 
     /// Free a particular page.
@@ -581,7 +642,16 @@ where
             ));
         }
 
-        let new_ptr = self.cas_page(pid, old, Update::Free, false, guard)?;
+        self.preserve(pid, guard)?;
+
+        let new_ptr = self.cas_page(
+            pid,
+            old,
+            Update::Free,
+            false,
+            &PageSettings::default(),
+            guard,
+        )?;
 
         if new_ptr.is_ok() {
             let free = self.free.clone();
@@ -604,10 +674,23 @@ where
     /// `Err(None)` if the page no longer exists. Returns `Err(Some(actual_key))`
     /// if the atomic append fails.
     pub fn link<'g>(
+        &'g self,
+        pid: PageId,
+        old: PagePtr<'g, P>,
+        new: P,
+        guard: &'g Guard,
+    ) -> Result<CasResult<'g, P, P>> {
+        self.link_with(pid, old, new, &PageSettings::default(), guard)
+    }
+
+    /// Like `link`, but writes the page according to the
+    /// overrides of the `Config` for the collection it is in.
+    pub fn link_with<'g>(
         &'g self,
         pid: PageId,
         mut old: PagePtr<'g, P>,
         new: P,
+        settings: &PageSettings,
         guard: &'g Guard,
     ) -> Result<CasResult<'g, P, P>> {
         let _measure = Measure::new(&M.link_page);
@@ -618,25 +701,28 @@ where
             Some(p) => p,
         };
 
+        self.preserve(pid, guard)?;
+
         // see if we should short-circuit replace
         let head = unsafe { head_ptr.deref().head(&guard) };
         let stack_iter = StackIter::from_ptr(head, &guard);
         let stack_len = stack_iter.size_hint().1.unwrap();
-        if stack_len >= self.config.page_consolidation_threshold {
-            let current_frag =
-                if let Some((current_ptr, frag, _sz)) = self.get(pid, guard)? {
-                    if old.ts != current_ptr.ts
-                        && old.cached_ptr != current_ptr.cached_ptr
-                    {
-                        // the page has changed in the mean time,
-                        // and merging frags may violate correctness
-                        // invariants
-                        return Ok(Err(Some((current_ptr, new))));
-                    }
-                    frag
-                } else {
-                    return Ok(Err(None));
-                };
+        if stack_len >= settings.consolidation_threshold(&self.config) {
+            let current_frag = if let Some((current_ptr, frag, _sz)) =
+                self.get_with(pid, settings, guard)?
+            {
+                if old.ts != current_ptr.ts
+                    && old.cached_ptr != current_ptr.cached_ptr
+                {
+                    // the page has changed in the mean time,
+                    // and merging frags may violate correctness
+                    // invariants
+                    return Ok(Err(Some((current_ptr, new))));
+                }
+                frag
+            } else {
+                return Ok(Err(None));
+            };
 
             let update: P = {
                 let _measure = Measure::new(&M.merge_page);
@@ -646,7 +732,7 @@ where
                 update
             };
 
-            return self.replace(pid, old, update, guard);
+            return self.replace_with(pid, old, update, settings, guard);
         }
 
         let bytes = measure(&M.serialize, || serialize(&new).unwrap());
@@ -674,8 +760,12 @@ where
         };
 
         loop {
-            let log_reservation =
-                self.log.reserve(LogKind::Append, pid, &bytes)?;
+            let log_reservation = self.log.reserve_with(
+                LogKind::Append,
+                pid,
+                &bytes,
+                settings,
+            )?;
 
             let lsn = log_reservation.lsn();
             let ptr = log_reservation.ptr();
@@ -800,13 +890,34 @@ where
         old: PagePtr<'g, P>,
         new: P,
         guard: &'g Guard,
+    ) -> Result<CasResult<'g, P, P>> {
+        self.replace_with(pid, old, new, &PageSettings::default(), guard)
+    }
+
+    /// Like `replace`, but writes the page according to the
+    /// overrides of the `Config` for the collection it is in.
+    pub fn replace_with<'g>(
+        &self,
+        pid: PageId,
+        old: PagePtr<'g, P>,
+        new: P,
+        settings: &PageSettings,
+        guard: &'g Guard,
     ) -> Result<CasResult<'g, P, P>> {
         let _measure = Measure::new(&M.replace_page);
 
         trace!("replacing pid {} with {:?}", pid, new);
 
-        let result =
-            self.cas_page(pid, old, Update::Compact(new), false, guard)?;
+        self.preserve(pid, guard)?;
+
+        let result = self.cas_page(
+            pid,
+            old,
+            Update::Compact(new),
+            false,
+            settings,
+            guard,
+        )?;
 
         let to_clean = self.log.with_sa(|sa| sa.clean(pid));
 
@@ -945,9 +1056,15 @@ where
                 }
             };
 
-            self.cas_page(pid, key, update, true, guard).map(|res| {
-                trace!("rewriting pid {} success: {}", pid, res.is_ok());
-            })
+            // the collection that the page belongs to is not known
+            // here, so it is written according to the `Config` until
+            // the next time that its collection writes it.
+            let settings = PageSettings::default();
+
+            self.cas_page(pid, key, update, true, &settings, guard)
+                .map(|res| {
+                    trace!("rewriting pid {} success: {}", pid, res.is_ok());
+                })
         }
     }
 
@@ -1001,6 +1118,7 @@ where
         mut old: PagePtr<'g, P>,
         update: Update<P>,
         is_rewrite: bool,
+        settings: &PageSettings,
         guard: &'g Guard,
     ) -> Result<CasResult<'g, P, Update<P>>> {
         trace!(
@@ -1021,7 +1139,7 @@ where
         let serialize_latency = Measure::new(&M.serialize);
         let bytes = match &update {
             Update::Counter(c) => serialize(&c).unwrap(),
-            Update::Meta(m) => serialize(&m).unwrap(),
+            Update::Meta(m) => m.to_bytes(),
             Update::Config(c) => serialize(&c).unwrap(),
             Update::Free => vec![],
             other => serialize(other.as_frag()).unwrap(),
@@ -1030,7 +1148,8 @@ where
         let mut update_opt = Some(update);
 
         loop {
-            let log_reservation = self.log.reserve(log_kind, pid, &bytes)?;
+            let log_reservation =
+                self.log.reserve_with(log_kind, pid, &bytes, settings)?;
             let lsn = log_reservation.lsn();
             let new_ptr = log_reservation.ptr();
 
@@ -1117,6 +1236,60 @@ where
         } // loop
     }
 
+    /// Caches an internal page that was pulled from disk. It is
+    /// normally logged again at the same time, which a read-only
+    /// process must not do, so there it is only swapped into
+    /// the page table.
+    fn install_pulled<'g>(
+        &self,
+        pid: PageId,
+        head: PagePtrInner<'g, P>,
+        update: Update<P>,
+        cache_info: CacheInfo,
+        guard: &'g Guard,
+    ) -> Result<()> {
+        if !self.config.read_only {
+            let ptr = PagePtr {
+                cached_ptr: head,
+                ts: cache_info.ts,
+            };
+            let _ = self.cas_page(
+                pid,
+                ptr,
+                update,
+                false,
+                &PageSettings::default(),
+                guard,
+            )?;
+            return Ok(());
+        }
+
+        let head_ptr = match self.inner.get(pid, &guard) {
+            None => return Ok(()),
+            Some(p) => p,
+        };
+
+        let node = node_from_frag_vec(vec![(Some(update), cache_info)]);
+
+        debug_delay();
+        let _ = unsafe { head_ptr.deref().cas(head, node, &guard) };
+
+        Ok(())
+    }
+
+    /// Returns an error if this process may not set up the
+    /// internal pages of a system that was never written to.
+    fn verify_can_initialize(&self) -> Result<()> {
+        if self.config.read_only {
+            return Err(Error::Unsupported(
+                "the system has not been initialized yet, \
+                 which can not be done in read-only mode"
+                    .to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
     /// Retrieve the current meta page
     pub(crate) fn get_meta<'g>(
         &self,
@@ -1148,11 +1321,13 @@ where
             Some((None, cache_info)) => {
                 let update =
                     self.pull(META_PID, cache_info.lsn, cache_info.ptr)?;
-                let ptr = PagePtr {
-                    cached_ptr: head,
-                    ts: cache_info.ts,
-                };
-                let _ = self.cas_page(META_PID, ptr, update, false, guard)?;
+                self.install_pulled(
+                    META_PID,
+                    head,
+                    update,
+                    *cache_info,
+                    guard,
+                )?;
                 self.get_meta(guard)
             }
             _ => Err(Error::ReportableBug(
@@ -1194,11 +1369,13 @@ where
             Some((None, cache_info)) => {
                 let update =
                     self.pull(CONFIG_PID, cache_info.lsn, cache_info.ptr)?;
-                let ptr = PagePtr {
-                    cached_ptr: head,
-                    ts: cache_info.ts,
-                };
-                let _ = self.cas_page(CONFIG_PID, ptr, update, false, guard)?;
+                self.install_pulled(
+                    CONFIG_PID,
+                    head,
+                    update,
+                    *cache_info,
+                    guard,
+                )?;
                 self.get_persisted_config(guard)
             }
             _ => Err(Error::ReportableBug(
@@ -1240,12 +1417,13 @@ where
             Some((None, cache_info)) => {
                 let update =
                     self.pull(COUNTER_PID, cache_info.lsn, cache_info.ptr)?;
-                let ptr = PagePtr {
-                    cached_ptr: head,
-                    ts: cache_info.ts,
-                };
-                let _ =
-                    self.cas_page(COUNTER_PID, ptr, update, false, guard)?;
+                self.install_pulled(
+                    COUNTER_PID,
+                    head,
+                    update,
+                    *cache_info,
+                    guard,
+                )?;
                 self.get_idgen(guard)
             }
             _ => Err(Error::ReportableBug(
@@ -1261,6 +1439,18 @@ where
         &self,
         pid: PageId,
         guard: &'g Guard,
+    ) -> Result<Option<(PagePtr<'g, P>, &'g P, u64)>> {
+        self.get_with(pid, &PageSettings::default(), guard)
+    }
+
+    /// Like `get`, but lets the cache know how reluctantly to
+    /// evict the page, according to the overrides of the
+    /// `Config` for the collection it is in.
+    pub fn get_with<'g>(
+        &self,
+        pid: PageId,
+        settings: &PageSettings,
+        guard: &'g Guard,
     ) -> Result<Option<(PagePtr<'g, P>, &'g P, u64)>> {
         trace!("getting page iterator for pid {}", pid);
         let _measure = Measure::new(&M.get_page);
@@ -1305,7 +1495,17 @@ where
 
         let initial_base = match entries[0] {
             (Some(Update::Compact(compact)), cache_info) => {
-                // short circuit
+                // short circuit, after letting the cache
+                // policy know that the page is still in use
+                let to_evict = self.lru.accessed_with_priority(
+                    pid,
+                    total_page_size,
+                    settings.cache_priority,
+                );
+                if !to_evict.is_empty() {
+                    self.page_out(to_evict, guard)?;
+                }
+
                 return Ok(Some((
                     PagePtr {
                         cached_ptr: head,
@@ -1401,7 +1601,11 @@ where
             trace!("fix-up for pid {} succeeded", pid);
 
             // possibly evict an item now that our cache has grown
-            let to_evict = self.lru.accessed(pid, total_page_size);
+            let to_evict = self.lru.accessed_with_priority(
+                pid,
+                total_page_size,
+                settings.cache_priority,
+            );
             trace!("accessed pid {} -> paging out pids {:?}", pid, to_evict);
             if !to_evict.is_empty() {
                 self.page_out(to_evict, guard)?;
@@ -1425,7 +1629,547 @@ where
         } else {
             trace!("fix-up for pid {} failed", pid);
 
-            self.get(pid, guard)
+            self.get_with(pid, settings, guard)
+        }
+    }
+
+    /// Like `get`, but a page that has been paged out is read
+    /// from disk without being installed in the page table, and
+    /// no page is marked as accessed in the cache. A page that had
+    /// to be materialized is returned owned, so that it is freed
+    /// as soon as the caller drops it rather than when the `Guard`
+    /// is unpinned. Meant for bulk scans, so that they do not page
+    /// out the pages that other readers keep using.
+    pub fn get_uncached<'g>(
+        &self,
+        pid: PageId,
+        guard: &'g Guard,
+    ) -> Result<Option<(PagePtr<'g, P>, Cow<'g, P>, u64)>> {
+        trace!("getting uncached page iterator for pid {}", pid);
+
+        if pid == COUNTER_PID
+            || pid == META_PID
+            || pid == CONFIG_PID
+            || pid == BATCH_MANIFEST_PID
+        {
+            return Err(Error::Unsupported(
+                "you are not able to iterate over \
+                 the first couple pages, which are \
+                 reserved for storing metadata and \
+                 monotonic ID generator info"
+                    .into(),
+            ));
+        }
+
+        let head_ptr = match self.inner.get(pid, guard) {
+            None => return Ok(None),
+            Some(p) => p,
+        };
+
+        let head = unsafe { head_ptr.deref().head(guard) };
+
+        let entries: Vec<_> = StackIter::from_ptr(head, guard).collect();
+
+        let ts = match entries.first() {
+            None | Some((Some(Update::Free), _)) => return Ok(None),
+            Some((_, cache_info)) => cache_info.ts,
+        };
+
+        let total_page_size = entries
+            .iter()
+            .map(|(_, cache_info)| cache_info.log_size as u64)
+            .sum();
+
+        let ptr = PagePtr {
+            cached_ptr: head,
+            ts,
+        };
+
+        let page = match self.get_at(pid, ptr.clone(), guard)? {
+            None => return Ok(None),
+            Some(page) => page,
+        };
+
+        Ok(Some((ptr, page, total_page_size)))
+    }
+
+    /// Like `get`, but never reads from disk. `None` is
+    /// returned if the page does not exist, or if any of its
+    /// fragments has been paged out. A page that is made of
+    /// several resident fragments is merged into an owned copy,
+    /// without consolidating its stack. The `settings` are
+    /// used as with `get_with`.
+    pub fn get_cached<'g>(
+        &self,
+        pid: PageId,
+        settings: &PageSettings,
+        guard: &'g Guard,
+    ) -> Result<Option<(PagePtr<'g, P>, Cow<'g, P>, u64)>> {
+        trace!("getting cached page iterator for pid {}", pid);
+
+        if pid == COUNTER_PID
+            || pid == META_PID
+            || pid == CONFIG_PID
+            || pid == BATCH_MANIFEST_PID
+        {
+            return Err(Error::Unsupported(
+                "you are not able to iterate over \
+                 the first couple pages, which are \
+                 reserved for storing metadata and \
+                 monotonic ID generator info"
+                    .into(),
+            ));
+        }
+
+        let head_ptr = match self.inner.get(pid, guard) {
+            None => return Ok(None),
+            Some(p) => p,
+        };
+
+        let head = unsafe { head_ptr.deref().head(guard) };
+
+        let entries: Vec<_> = StackIter::from_ptr(head, guard).collect();
+
+        let ts = match entries.first() {
+            None | Some((Some(Update::Free), _)) => return Ok(None),
+            Some((_, cache_info)) => cache_info.ts,
+        };
+
+        let mut appends = vec![];
+        let mut base = None;
+        for (update, _) in &entries {
+            match update {
+                Some(Update::Compact(compact)) => {
+                    base = Some(compact);
+                    break;
+                }
+                Some(Update::Append(append)) => appends.push(append),
+                _ => return Ok(None),
+            }
+        }
+
+        let page = match base {
+            None => return Ok(None),
+            Some(base) if appends.is_empty() => Cow::Borrowed(base),
+            Some(base) => {
+                let mut base = base.clone();
+                for append in appends.into_iter().rev() {
+                    base.merge(append);
+                }
+                Cow::Owned(base)
+            }
+        };
+
+        let total_page_size = entries
+            .iter()
+            .map(|(_, cache_info)| cache_info.log_size as u64)
+            .sum();
+
+        let to_evict = self.lru.accessed_with_priority(
+            pid,
+            total_page_size,
+            settings.cache_priority,
+        );
+        if !to_evict.is_empty() {
+            self.page_out(to_evict, guard)?;
+        }
+
+        let ptr = PagePtr {
+            cached_ptr: head,
+            ts,
+        };
+
+        Ok(Some((ptr, page, total_page_size)))
+    }
+
+    /// Returns the number of page accesses that found the
+    /// page in the cache, and the number that did not.
+    pub fn cache_hits_and_misses(&self) -> (u64, u64) {
+        self.lru.hits_and_misses()
+    }
+
+    /// Materialize a page as of the provided head, without
+    /// modifying the page or the cache. Any fragments that have
+    /// been paged out since are read from disk, which is safe
+    /// because segments are not reused while the `Guard` used
+    /// to acquire the head remains pinned.
+    fn get_at<'g>(
+        &self,
+        pid: PageId,
+        head: PagePtr<'g, P>,
+        guard: &'g Guard,
+    ) -> Result<Option<Cow<'g, P>>> {
+        trace!("getting historical state for pid {}", pid);
+        let _measure = Measure::new(&M.get_page);
+
+        let entries: Vec<_> =
+            StackIter::from_ptr(head.cached_ptr, &guard).collect();
+
+        match entries.first().copied() {
+            None | Some((Some(Update::Free), _)) => return Ok(None),
+            Some((Some(Update::Compact(compact)), _)) => {
+                // short circuit
+                return Ok(Some(Cow::Borrowed(compact)));
+            }
+            _ => {}
+        }
+
+        let mut frags: Vec<Cow<'g, P>> = Vec::with_capacity(entries.len());
+
+        for entry in entries {
+            match entry {
+                (Some(Update::Compact(compact)), _) => {
+                    frags.push(Cow::Borrowed(compact));
+                    break;
+                }
+                (Some(Update::Append(append)), _) => {
+                    frags.push(Cow::Borrowed(append));
+                }
+                (None, cache_info) => {
+                    let pulled = self.pull(pid, cache_info.lsn, cache_info.ptr);
+                    let update = match pulled {
+                        Ok(update) => update,
+                        Err(Error::Io(ref error))
+                            if error.kind() == std::io::ErrorKind::NotFound =>
+                        {
+                            // blob has been removed
+                            return Ok(None);
+                        }
+                        Err(error) => return Err(error),
+                    };
+                    let is_compact = update.is_compact();
+                    frags.push(Cow::Owned(update.into_frag()));
+                    if is_compact {
+                        break;
+                    }
+                }
+                other => {
+                    panic!("iterating over unexpected update: {:?}", other);
+                }
+            }
+        }
+
+        let mut base = frags.pop().unwrap().into_owned();
+
+        while let Some(frag) = frags.pop() {
+            base.merge(&frag);
+        }
+
+        Ok(Some(Cow::Owned(base)))
+    }
+
+    /// Starts recording the state that pages have now, just
+    /// before they are next changed, for as long as the
+    /// returned `PageVersions` is held.
+    pub fn preserve_versions(&self) -> Arc<PageVersions<P>> {
+        let versions = Arc::new(PageVersions::new(self.versions_held.clone()));
+
+        let mut all = self.versions.lock();
+        all.retain(|v| v.strong_count() > 0);
+        all.push(Arc::downgrade(&versions));
+
+        versions
+    }
+
+    // records the current state of a page in every held
+    // `PageVersions` that does not have one yet. Must be
+    // called before the page is changed.
+    fn preserve<'g>(&self, pid: PageId, guard: &'g Guard) -> Result<()> {
+        if self.versions_held.load(SeqCst) == 0 {
+            return Ok(());
+        }
+
+        let holders: Vec<Arc<PageVersions<P>>> = self
+            .versions
+            .lock()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter(|v| !v.contains(pid))
+            .collect();
+
+        if holders.is_empty() {
+            return Ok(());
+        }
+
+        let page = if let Some(head_ptr) = self.inner.get(pid, guard) {
+            let head = unsafe { head_ptr.deref().head(guard) };
+            let ts = StackIter::from_ptr(head, guard)
+                .next()
+                .map_or(0, |(_, cache_info)| cache_info.ts);
+            let ptr = PagePtr {
+                cached_ptr: head,
+                ts,
+            };
+            self.get_at(pid, ptr, guard)?
+                .map(|page| Arc::new(page.into_owned()))
+        } else {
+            None
+        };
+
+        for holder in holders {
+            holder.record(pid, page.clone());
+        }
+
+        Ok(())
+    }
+
+    /// The highest Lsn that has been reserved in the log.
+    pub fn max_reserved_lsn(&self) -> Lsn {
+        self.log.iobufs.max_reserved_lsn.load(Acquire)
+    }
+
+    /// Writes a crash-consistent copy of the data file, the
+    /// latest snapshot and any blobs into the directory at
+    /// `path`, as of the Lsn that is returned. Concurrent
+    /// writers are not blocked. If `since` is the Lsn returned
+    /// by a previous backup into the same directory, only the
+    /// segments written after it are copied.
+    pub fn backup_to<Pa: AsRef<std::path::Path>>(
+        &self,
+        path: Pa,
+        since: Option<Lsn>,
+    ) -> Result<Lsn> {
+        let _measure = Measure::new(&M.backup);
+
+        // NB new segments are appended to the end of the file
+        // while rewriting is paused, so segments that are live
+        // as of the stable Lsn below are not reused until the
+        // copy has been completed. Holding the snapshot mutex
+        // keeps a snapshot from resuming rewriting meanwhile.
+        let _snapshot_mu = self.last_snapshot.lock();
+        self.log.with_sa(SegmentAccountant::pause_rewriting);
+
+        let ret = self.flush().and_then(|_| {
+            let stable = self.stable_lsn();
+            backup::backup(&self.config, stable, path.as_ref(), since)
+        });
+
+        self.log.with_sa(SegmentAccountant::resume_rewriting);
+
+        ret
+    }
+
+    /// Reads back every fragment of every page, checking that it
+    /// is intact and deserializes, and looks for blob files that
+    /// no page refers to. Concurrent readers and writers are not
+    /// blocked. If `pause` is set, the check sleeps for that long
+    /// after each page, to limit its impact on foreground work.
+    pub fn verify_integrity(
+        &self,
+        pause: Option<Duration>,
+    ) -> Result<IntegrityReport> {
+        let _measure = Measure::new(&M.verify_integrity);
+
+        let started_at = self.max_reserved_lsn();
+        let mut report = IntegrityReport::default();
+        let mut referenced_blobs = FastSet8::default();
+
+        for pid in 0..self.next_pid_to_allocate.load(Acquire) {
+            let guard = pin();
+
+            let head_ptr = match self.inner.get(pid, &guard) {
+                None => continue,
+                Some(p) => p,
+            };
+            let head = unsafe { head_ptr.deref().head(&guard) };
+
+            let fragments: Vec<(Lsn, DiskPtr)> =
+                StackIter::from_ptr(head, &guard)
+                    .map(|(_, cache_info)| (cache_info.lsn, cache_info.ptr))
+                    .collect();
+
+            if fragments.is_empty() {
+                continue;
+            }
+
+            report.pages_checked += 1;
+
+            for &(lsn, ptr) in &fragments {
+                if ptr.is_blob() {
+                    referenced_blobs.insert(ptr.blob().1);
+                }
+
+                report.fragments_checked += 1;
+
+                let problem = match integrity::check_fragment::<P>(
+                    &self.log, pid, lsn, ptr,
+                )? {
+                    None => continue,
+                    Some(problem) => problem,
+                };
+
+                // the page may have been rewritten, and the
+                // fragment reclaimed, while we were reading it
+                let head = unsafe { head_ptr.deref().head(&guard) };
+                let still_referenced =
+                    StackIter::from_ptr(head, &guard).any(|(_, cache_info)| {
+                        cache_info.lsn == lsn && cache_info.ptr == ptr
+                    });
+
+                if still_referenced {
+                    report.record(problem, pid, ptr);
+                }
+            }
+
+            drop(guard);
+
+            if let Some(pause) = pause {
+                std::thread::sleep(pause);
+            }
+        }
+
+        // blobs may have been written just before we started, but
+        // only linked into their pages after we checked them
+        let guard = pin();
+        for pid in 0..self.next_pid_to_allocate.load(Acquire) {
+            if let Some(head_ptr) = self.inner.get(pid, &guard) {
+                let head = unsafe { head_ptr.deref().head(&guard) };
+                for (_, cache_info) in StackIter::from_ptr(head, &guard) {
+                    if cache_info.ptr.is_blob() {
+                        referenced_blobs.insert(cache_info.ptr.blob().1);
+                    }
+                }
+            }
+        }
+        drop(guard);
+
+        let pending_removal = self.log.with_sa(|sa| sa.pending_blob_removals());
+
+        report.orphaned_blobs = integrity::orphaned_blobs(
+            &self.config,
+            &referenced_blobs,
+            &pending_removal,
+            started_at,
+        )?;
+
+        Ok(report)
+    }
+
+    /// Catches a read-only `PageCache` up with the writes that
+    /// the process which owns the log made since this one was
+    /// started or last refreshed, and returns the number of
+    /// pages that changed. Only the writes that recovery would
+    /// find after a crash at the same moment are followed, so
+    /// a batch becomes visible all at once.
+    ///
+    /// The owner of the log eventually reuses the segments and
+    /// removes the blobs that an out-of-date page points to,
+    /// after which reading that page fails until the next call
+    /// to `refresh`.
+    pub fn refresh(&self) -> Result<usize> {
+        if !self.config.read_only {
+            return Err(Error::Unsupported(
+                "only a read-only PageCache can follow the \
+                 writes of another process"
+                    .to_owned(),
+            ));
+        }
+
+        let _measure = Measure::new(&M.refresh);
+
+        let mut snapshot_opt = self.last_snapshot.lock();
+        let snapshot = snapshot_opt
+            .as_mut()
+            .expect("PageCache::refresh called before recovery");
+
+        let (iter, max_header_stable_lsn) =
+            raw_segment_iter_from(snapshot.last_lsn, &self.config)?;
+        snapshot.max_header_stable_lsn = max_header_stable_lsn;
+
+        let changed = follow_snapshot(iter, snapshot);
+
+        // reads first wait for the log to be stable up to the
+        // message they read, which is up to the other process.
+        let iobufs = &self.log.iobufs;
+        bump_atomic_lsn(&iobufs.max_reserved_lsn, snapshot.last_lsn);
+        bump_atomic_lsn(&iobufs.stable_lsn, snapshot.last_lsn);
+
+        let guard = pin();
+
+        for &pid in &changed {
+            if let Some(state) = snapshot.pt.get(&pid) {
+                self.install_followed(pid, state, &guard);
+            }
+        }
+
+        debug!(
+            "refreshed {} pages up to lsn {}",
+            changed.len(),
+            snapshot.last_lsn
+        );
+
+        Ok(changed.len())
+    }
+
+    /// Points a page at the fragments that `refresh` found for
+    /// it, dropping anything that was cached for it before.
+    fn install_followed<'g>(
+        &self,
+        pid: PageId,
+        state: &PageState,
+        guard: &'g Guard,
+    ) {
+        let frags = |ts| -> Vec<(Option<Update<P>>, CacheInfo)> {
+            match *state {
+                PageState::Present(ref ptrs) => ptrs
+                    .iter()
+                    .rev()
+                    .map(|&(lsn, ptr, log_size)| {
+                        let cache_info = CacheInfo {
+                            ts,
+                            lsn,
+                            ptr,
+                            log_size,
+                        };
+                        (None, cache_info)
+                    })
+                    .collect(),
+                PageState::Free(lsn, ptr) => {
+                    let cache_info = CacheInfo {
+                        ts,
+                        lsn,
+                        ptr,
+                        log_size: MSG_HEADER_LEN,
+                    };
+                    vec![(Some(Update::Free), cache_info)]
+                }
+            }
+        };
+
+        loop {
+            let head_ptr = if let Some(head_ptr) = self.inner.get(pid, guard) {
+                head_ptr
+            } else {
+                // the page was allocated since we last looked
+                let stack = Stack::default();
+                for frag in frags(0).into_iter().rev() {
+                    stack.push(frag);
+                }
+                let new_stack = Owned::new(stack).into_shared(guard);
+
+                self.inner
+                    .cas(pid, Shared::null(), new_stack, guard)
+                    .expect("only refresh installs new pages");
+
+                if pid >= self.next_pid_to_allocate.load(Acquire) {
+                    self.next_pid_to_allocate.store(pid + 1, Release);
+                }
+                return;
+            };
+
+            let head = unsafe { head_ptr.deref().head(guard) };
+            let ts = StackIter::from_ptr(head, guard)
+                .next()
+                .map_or(0, |(_, cache_info)| cache_info.ts + 1);
+
+            let node = node_from_frag_vec(frags(ts));
+
+            // this races with the page being pulled in or paged
+            // out by concurrent readers, so retry until it sticks.
+            debug_delay();
+            if unsafe { head_ptr.deref().cas(head, node, guard) }.is_ok() {
+                return;
+            }
         }
     }
 
@@ -1442,6 +2186,22 @@ where
         self.log.make_stable(lsn)
     }
 
+    /// Returns a `Promise` that is filled once the provided Lsn
+    /// is stable on disk. Any flushes this requires happen on
+    /// the background threadpool, so the calling thread never
+    /// blocks, and the `Promise` may be awaited from any async
+    /// executor.
+    pub fn make_stable_async(&self, lsn: Lsn) -> Promise<Result<usize>> {
+        if lsn <= self.stable_lsn() {
+            let (filler, promise) = Promise::pair();
+            filler.fill(Ok(0));
+            return promise;
+        }
+
+        let iobufs = self.log.iobufs.clone();
+        threadpool::spawn(move || iobuf::make_stable(&iobufs, lsn))
+    }
+
     /// Returns `true` if the database was
     /// recovered from a previous process.
     /// Note that database state is only
@@ -1491,6 +2251,7 @@ where
                     key.clone(),
                     counter_update,
                     false,
+                    &PageSettings::default(),
                     &guard,
                 );
 
@@ -1552,6 +2313,58 @@ where
         old: Option<PageId>,
         new: Option<PageId>,
         guard: &'g Guard,
+    ) -> Result<std::result::Result<(), Option<PageId>>> {
+        self.cas_meta(name, old, new, None, None, guard)
+    }
+
+    /// Install the root of a new collection in the `Meta`,
+    /// along with the name of the comparator that orders
+    /// its keys, if any, and the overrides of the `Config`
+    /// for its pages. Fails if the identifier is taken.
+    pub fn create_root_in_meta<'g>(
+        &self,
+        name: Vec<u8>,
+        root: PageId,
+        comparator: Option<String>,
+        settings: PageSettings,
+        guard: &'g Guard,
+    ) -> Result<std::result::Result<(), Option<PageId>>> {
+        self.cas_meta(name, None, Some(root), comparator, Some(settings), guard)
+    }
+
+    /// Replace the overrides of the `Config` that are
+    /// persisted for an existing collection. Fails with
+    /// `CollectionNotFound` if it does not exist.
+    pub fn set_settings_in_meta<'g>(
+        &self,
+        name: Vec<u8>,
+        settings: PageSettings,
+        guard: &'g Guard,
+    ) -> Result<()> {
+        loop {
+            let root = self.meta_pid_for_name(&name, guard)?;
+            let res = self.cas_meta(
+                name.clone(),
+                Some(root),
+                Some(root),
+                None,
+                Some(settings),
+                guard,
+            )?;
+            if res.is_ok() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn cas_meta<'g>(
+        &self,
+        name: Vec<u8>,
+        old: Option<PageId>,
+        new: Option<PageId>,
+        comparator: Option<String>,
+        settings: Option<PageSettings>,
+        guard: &'g Guard,
     ) -> Result<std::result::Result<(), Option<PageId>>> {
         loop {
             let (meta_key, meta) = self.get_meta(guard)?;
@@ -1564,6 +2377,12 @@ where
             let mut new_meta = (*meta).clone();
             if let Some(new) = new {
                 new_meta.set_root(name.clone(), new);
+                if let Some(ref comparator) = comparator {
+                    new_meta.set_comparator(name.clone(), comparator.clone());
+                }
+                if let Some(settings) = settings {
+                    new_meta.set_settings(name.clone(), settings);
+                }
             } else {
                 new_meta.del_root(&name);
             }
@@ -1575,6 +2394,7 @@ where
                 meta_key.clone(),
                 new_meta_frag,
                 false,
+                &PageSettings::default(),
                 &guard,
             )?;
 
@@ -1695,7 +2515,7 @@ where
         let update_res = match header.kind {
             Counter => deserialize::<u64>(&bytes).map(Update::Counter),
             BlobMeta | InlineMeta => {
-                deserialize::<Meta>(&bytes).map(Update::Meta)
+                Meta::from_bytes(&bytes).map(Update::Meta)
             }
             BlobConfig | InlineConfig => {
                 deserialize::<PersistedConfig>(&bytes).map(Update::Config)
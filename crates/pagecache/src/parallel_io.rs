@@ -96,3 +96,253 @@ impl Pio for std::fs::File {
         Ok(())
     }
 }
+
+/// How the log is written to its file.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum IoMode {
+    /// Write through the page cache of the operating system,
+    /// and sync the file after every write.
+    Buffered,
+    /// On Linux, open the file with `O_DIRECT` for writing the
+    /// log, bypassing the page cache, and sync it after every
+    /// write. Every write of the log is padded out to a multiple
+    /// of 4096 bytes, which `io_buf_size` must also be a
+    /// multiple of. Falls back to `Buffered` if the
+    /// file system rejects `O_DIRECT`, as tmpfs does.
+    Direct,
+    /// Like `Direct`, but also open the file with `O_DSYNC`,
+    /// so that every write is durable by the time it returns,
+    /// without a separate sync. Falls back to `O_DSYNC` alone
+    /// if the file system rejects `O_DIRECT`.
+    DirectSync,
+}
+
+/// The separate handle that the log is written through
+/// when the `IoMode` is not `Buffered`.
+#[derive(Debug)]
+pub(crate) struct LogFile {
+    file: std::fs::File,
+    /// Set if the file was opened with `O_DIRECT`, so that
+    /// writes must end on a multiple of `DIRECT_IO_ALIGNMENT`.
+    pub(crate) direct: bool,
+    /// Set if the file was opened with `O_DSYNC`, so that
+    /// writes need no separate sync.
+    pub(crate) dsync: bool,
+}
+
+impl LogFile {
+    /// Opens the file at `path` for writing in the given mode,
+    /// dropping `O_DIRECT` if the file system rejects it.
+    #[cfg(unix)]
+    pub(crate) fn open(
+        path: &std::path::Path,
+        mode: IoMode,
+    ) -> io::Result<LogFile> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let dsync = mode == IoMode::DirectSync;
+        let direct = mode != IoMode::Buffered && cfg!(target_os = "linux");
+
+        let open = |direct: bool| {
+            let mut flags = 0;
+            #[cfg(target_os = "linux")]
+            {
+                if direct {
+                    flags |= libc::O_DIRECT;
+                }
+            }
+            if dsync {
+                flags |= libc::O_DSYNC;
+            }
+            std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .custom_flags(flags)
+                .open(path)
+        };
+
+        match open(direct) {
+            Ok(file) => Ok(LogFile {
+                file,
+                direct,
+                dsync,
+            }),
+            Err(ref e) if direct && e.raw_os_error() == Some(libc::EINVAL) => {
+                warn!(
+                    "the file system of {:?} does not support O_DIRECT, \
+                     so the log will be written through the page cache",
+                    path
+                );
+                let file = open(false)?;
+                Ok(LogFile {
+                    file,
+                    direct: false,
+                    dsync,
+                })
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Other systems only support the `Buffered` mode, which
+    /// the log is written in if the file is opened here.
+    #[cfg(not(unix))]
+    pub(crate) fn open(
+        path: &std::path::Path,
+        mode: IoMode,
+    ) -> io::Result<LogFile> {
+        warn!(
+            "{:?} is only supported on unix, so the log will be \
+             written through the page cache",
+            mode
+        );
+        let file = std::fs::OpenOptions::new().write(true).open(path)?;
+        Ok(LogFile {
+            file,
+            direct: false,
+            dsync: false,
+        })
+    }
+
+    /// Writes `buf` at `offset`. With `O_DIRECT`, the write must
+    /// end on a multiple of `DIRECT_IO_ALIGNMENT`.
+    pub(crate) fn pwrite_all(
+        &self,
+        buf: &[u8],
+        offset: LogId,
+    ) -> io::Result<()> {
+        if let Some((aligned, start)) = self.align(buf, offset)? {
+            self.file.pwrite_all(&aligned, start)
+        } else {
+            self.file.pwrite_all(buf, offset)
+        }
+    }
+
+    /// With `O_DIRECT`, returns a copy of `buf` that starts on
+    /// a multiple of `DIRECT_IO_ALIGNMENT`, and the offset to
+    /// write it at, if `buf` itself may not be written at
+    /// `offset`. The bytes before `offset` in its first block
+    /// are read back, to be written again along with it.
+    pub(crate) fn align(
+        &self,
+        buf: &[u8],
+        offset: LogId,
+    ) -> io::Result<Option<(AlignedBuf, LogId)>> {
+        if !self.direct {
+            return Ok(None);
+        }
+
+        let align = DIRECT_IO_ALIGNMENT as LogId;
+        assert_eq!(
+            (offset + buf.len() as LogId) % align,
+            0,
+            "direct writes must end on a multiple of {}",
+            align
+        );
+
+        let head = usize::try_from(offset % align).unwrap();
+        if head == 0
+            && (buf.as_ptr() as usize).is_multiple_of(DIRECT_IO_ALIGNMENT)
+        {
+            return Ok(None);
+        }
+
+        let start = offset - head as LogId;
+        let mut aligned = AlignedBuf::new(head + buf.len());
+        if head > 0 {
+            self.read_block(&mut aligned[..DIRECT_IO_ALIGNMENT], start)?;
+        }
+        aligned[head..].copy_from_slice(buf);
+
+        Ok(Some((aligned, start)))
+    }
+
+    /// Reads a block, which may extend past the end of the file.
+    #[cfg(unix)]
+    fn read_block(&self, block: &mut [u8], offset: LogId) -> io::Result<()> {
+        let mut read = 0;
+        while read < block.len() {
+            match self
+                .file
+                .read_at(&mut block[read..], offset + read as LogId)
+            {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn read_block(&self, _block: &mut [u8], _offset: LogId) -> io::Result<()> {
+        unreachable!("O_DIRECT is only used on linux")
+    }
+
+    /// Makes the written data durable, unless every write
+    /// already is.
+    pub(crate) fn sync(&self) -> io::Result<()> {
+        if self.dsync {
+            Ok(())
+        } else {
+            self.file.sync_all()
+        }
+    }
+}
+
+#[cfg(unix)]
+impl std::os::unix::io::AsRawFd for LogFile {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+/// A zeroed buffer whose address is a multiple of
+/// `DIRECT_IO_ALIGNMENT`, so that it can be written to a
+/// file that was opened with `O_DIRECT`.
+pub(crate) struct AlignedBuf {
+    ptr: *mut u8,
+    layout: std::alloc::Layout,
+}
+
+unsafe impl Send for AlignedBuf {}
+unsafe impl Sync for AlignedBuf {}
+
+impl AlignedBuf {
+    pub(crate) fn new(len: usize) -> AlignedBuf {
+        assert_ne!(len, 0);
+        let layout =
+            std::alloc::Layout::from_size_align(len, DIRECT_IO_ALIGNMENT)
+                .unwrap();
+        let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            std::alloc::handle_alloc_error(layout);
+        }
+        AlignedBuf { ptr, layout }
+    }
+
+    pub(crate) fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut *self
+    }
+}
+
+impl std::ops::Deref for AlignedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.layout.size()) }
+    }
+}
+
+impl std::ops::DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.layout.size()) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.ptr, self.layout) }
+    }
+}
@@ -1,17 +1,42 @@
-use std::sync::Arc;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
 
 use parking_lot::{Condvar, Mutex};
 
-/// A Future value which may or may not be filled
+#[derive(Debug)]
+struct Inner<T> {
+    done: bool,
+    item: Option<T>,
+    waker: Option<Waker>,
+}
+
+impl<T> Inner<T> {
+    fn complete(&mut self, item: Option<T>) {
+        self.done = true;
+        self.item = item;
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A Future value which may or may not be filled.
+/// It may be blocked on with `wait`, or awaited
+/// from any async executor.
 #[derive(Debug)]
 pub struct Promise<T> {
-    mu: Arc<Mutex<(bool, Option<T>)>>,
+    mu: Arc<Mutex<Inner<T>>>,
     cv: Arc<Condvar>,
 }
 
 /// The completer side of the Future
 pub struct PromiseFiller<T> {
-    mu: Arc<Mutex<(bool, Option<T>)>>,
+    mu: Arc<Mutex<Inner<T>>>,
     cv: Arc<Condvar>,
     completed: bool,
 }
@@ -20,7 +45,11 @@ impl<T> Promise<T> {
     /// Create a new PromiseFiller and the Promise
     /// that will be filled by its completion.
     pub fn pair() -> (PromiseFiller<T>, Self) {
-        let mu = Arc::new(Mutex::new((false, None)));
+        let mu = Arc::new(Mutex::new(Inner {
+            done: false,
+            item: None,
+            waker: None,
+        }));
         let cv = Arc::new(Condvar::new());
         let future = Self {
             mu: mu.clone(),
@@ -39,10 +68,33 @@ impl<T> Promise<T> {
     /// or dropping of the PromiseFiller
     pub fn wait(self) -> Option<T> {
         let mut inner = self.mu.lock();
-        while !inner.0 {
+        while !inner.done {
             self.cv.wait(&mut inner);
         }
-        inner.1.take()
+        inner.item.take()
+    }
+
+    /// Block on the Promise's completion or dropping of
+    /// the PromiseFiller for at most `timeout`. If neither
+    /// happened in time, the Promise is handed back in
+    /// the `Err` so that it may be waited on again.
+    pub fn wait_timeout(
+        self,
+        timeout: Duration,
+    ) -> std::result::Result<Option<T>, Self> {
+        let deadline = Instant::now() + timeout;
+        let mut inner = self.mu.lock();
+        while !inner.done {
+            if self.cv.wait_until(&mut inner, deadline).timed_out() {
+                break;
+            }
+        }
+        if inner.done {
+            Ok(inner.item.take())
+        } else {
+            drop(inner);
+            Err(self)
+        }
     }
 
     /// Block on the Promise's completion
@@ -60,7 +112,7 @@ impl<T> PromiseFiller<T> {
     /// Complete the Promise
     pub fn fill(mut self, inner: T) {
         let mut mu = self.mu.lock();
-        *mu = (true, Some(inner));
+        mu.complete(Some(inner));
         self.cv.notify_all();
         self.completed = true;
     }
@@ -72,7 +124,23 @@ impl<T> Drop for PromiseFiller<T> {
             return;
         }
         let mut mu = self.mu.lock();
-        *mu = (true, None);
+        mu.complete(None);
         self.cv.notify_all();
     }
 }
+
+impl<T> Future for Promise<T> {
+    /// `None` if the `PromiseFiller` was
+    /// dropped without completing it.
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut inner = self.mu.lock();
+        if inner.done {
+            Poll::Ready(inner.item.take())
+        } else {
+            inner.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
@@ -1,5 +1,3 @@
-use std::fs::File;
-
 use super::Pio;
 
 use super::*;
@@ -15,7 +13,7 @@ pub(crate) trait LogReader {
     ) -> Result<LogRead>;
 }
 
-impl LogReader for File {
+impl<P: Pio> LogReader for P {
     fn read_segment_header(&self, lid: LogId) -> Result<SegmentHeader> {
         trace!("reading segment header at {}", lid);
 
@@ -154,7 +152,7 @@ impl LogReader for File {
             | MessageKind::BlobConfig => {
                 let id = arr_to_u64(&buf) as Lsn;
 
-                match read_blob(id, config) {
+                match read_blob(id, header.pid, config) {
                     Ok((kind, buf)) => {
                         assert_eq!(header.kind, kind);
                         trace!(
@@ -187,11 +185,32 @@ impl LogReader for File {
             | MessageKind::Free
             | MessageKind::Counter => {
                 trace!("read a successful inline message");
-                let buf = if config.use_compression {
-                    maybe_decompress(buf)?
-                } else {
+                let aad = message_aad(
+                    header.lsn,
+                    header.pid,
+                    header.kind,
+                    header.toggles_compression,
+                );
+                let scope = KeyScope::segment(config, header.lsn);
+                let buf = if let Some(buf) =
+                    maybe_decrypt(config, scope, buf, &aad)
+                {
                     buf
+                } else {
+                    warn!(
+                        "failed to authenticate message at lsn {}",
+                        header.lsn
+                    );
+                    return Err(Error::Corruption {
+                        at: DiskPtr::Inline(lid),
+                    });
                 };
+                let buf =
+                    if config.use_compression != header.toggles_compression {
+                        maybe_decompress(buf)?
+                    } else {
+                        buf
+                    };
 
                 Ok(LogRead::Inline(header, buf, header.len))
             }
@@ -20,7 +20,12 @@ impl<'a> Drop for Reservation<'a> {
     fn drop(&mut self) {
         // We auto-abort if the user never uses a reservation.
         if !self.flushed {
-            self.flush(false).unwrap();
+            if let Err(e) = self.flush(false) {
+                // the log only fails to take the abort once it
+                // has hit an error that every later operation
+                // returns, so there is nothing left to do here.
+                error!("failed to abort dropped reservation: {:?}", e);
+            }
         }
     }
 }
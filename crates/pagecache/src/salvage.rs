@@ -0,0 +1,331 @@
+//! Offline recovery of the pages that survive in a damaged log.
+//!
+//! Normal recovery, including `Log::start_raw_log`, reads the
+//! log with a `LogIter`, which stops at the first message
+//! that fails its checksum and treats everything after it as
+//! a torn tail. That is the right thing to do after a crash,
+//! but when a stable segment in the middle of the log is
+//! damaged it throws away everything written after it. It
+//! also zeroes torn segments on disk, which a repair tool
+//! must not do to the files it is trying to recover.
+//!
+//! `salvage` instead reads the segments of a log that is not
+//! in use, without writing to it, in Lsn order:
+//!
+//! 1. a message whose header is intact, but whose body fails
+//!    its checksum or authentication, or whose blob is
+//!    missing or corrupt, is dropped, and the rest of its
+//!    segment is still read.
+//! 2. a message whose header is damaged can not be skipped,
+//!    because its length is unknown, so the rest of its
+//!    segment is lost. This is only reported for segments
+//!    that were stable, because the unstable tail of the log
+//!    is expected to end with a torn write after a crash.
+//!
+//! Each page is then rebuilt from its last readable base and
+//! the fragments appended after it. Pages that are missing
+//! a fragment are reported as damaged.
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::Path,
+};
+
+use super::*;
+
+/// The pages that `salvage` read back from a log.
+#[derive(Debug)]
+pub struct Salvage<P> {
+    /// The configuration that the log was read with.
+    pub config: Config,
+    /// The last readable version of the meta page.
+    pub meta: Option<Meta>,
+    /// Whether an update to the meta page was lost, in which
+    /// case `meta` may be out of date.
+    pub meta_damaged: bool,
+    /// The pages that could be rebuilt, other than the
+    /// meta page and other internal pages.
+    pub pages: FastMap8<PageId, P>,
+    /// Pages that lost a fragment, or their base. Pages that
+    /// lost their base are missing from `pages`, and the
+    /// others may be missing updates.
+    pub damaged: FastSet8<PageId>,
+    /// The offsets of stable segments that could not be read
+    /// to their end. The messages in the rest of them were
+    /// lost, along with the knowledge of which pages they
+    /// belonged to.
+    pub unreadable_segments: Vec<LogId>,
+    /// The number of page fragments that were read.
+    pub messages_read: usize,
+    /// The number of messages that were dropped, because they
+    /// were corrupt or their blob could not be read.
+    pub messages_dropped: usize,
+}
+
+enum Entry {
+    Frag(MessageKind, Vec<u8>),
+    Lost,
+}
+
+/// Reads every page that can still be recovered from the
+/// system stored at `path`, without modifying it. The system
+/// must not be running. The configuration it was created
+/// with is read from disk, so only an `encryption_key` is
+/// needed if it is encrypted.
+pub fn salvage<P: Materializer, Pa: AsRef<Path>>(
+    path: Pa,
+    encryption_key: Option<EncryptionKey>,
+) -> Result<Salvage<P>> {
+    let path = path.as_ref();
+
+    let persisted = if let Some(persisted) =
+        ConfigBuilder::new().path(path).read_config()?
+    {
+        persisted
+    } else {
+        return Err(Error::Unsupported(format!(
+            "could not read the configuration of the system at {:?}",
+            path
+        )));
+    };
+
+    let config = persisted
+        .path(path)
+        .temporary(false)
+        .read_only(true)
+        .encryption_key(encryption_key)
+        .build();
+
+    config.verify_encryption_key()?;
+
+    let mut chains: FastMap8<PageId, Vec<Entry>> = FastMap8::default();
+    let mut unreadable_segments = vec![];
+    let mut messages_read = 0;
+    let mut messages_dropped = 0;
+
+    let (segments, max_stable_lsn) = readable_segments(&config)?;
+    let segment_len = config.io_buf_size as LogId;
+
+    for (segment_lsn, base) in segments {
+        let mut offset = SEG_HEADER_LEN as LogId;
+
+        while offset + MSG_HEADER_LEN as LogId <= segment_len {
+            let lid = base + offset;
+            let lsn = segment_lsn + offset as Lsn;
+
+            let (header, entry, len) =
+                match config.file.read_message(lid, lsn, &config) {
+                    Ok(LogRead::Inline(header, buf, len)) => (
+                        header,
+                        Entry::Frag(header.kind, buf),
+                        MSG_HEADER_LEN + len as usize,
+                    ),
+                    Ok(LogRead::Blob(header, buf, _blob_ptr)) => (
+                        header,
+                        Entry::Frag(header.kind, buf),
+                        MSG_HEADER_LEN + BLOB_INLINE_LEN,
+                    ),
+                    Ok(LogRead::DanglingBlob(header, blob_ptr)) => {
+                        warn!(
+                            "dropping message at lsn {} for pid {}, \
+                             because its blob {} is missing",
+                            lsn, header.pid, blob_ptr
+                        );
+                        (header, Entry::Lost, MSG_HEADER_LEN + BLOB_INLINE_LEN)
+                    }
+                    Ok(LogRead::Failed(_, len)) => {
+                        offset += (MSG_HEADER_LEN + len as usize) as LogId;
+                        continue;
+                    }
+                    Ok(LogRead::BatchManifest(_)) => {
+                        offset += (MSG_HEADER_LEN + BATCH_MANIFEST_INLINE_LEN)
+                            as LogId;
+                        continue;
+                    }
+                    Ok(LogRead::Pad(_)) => break,
+                    Ok(LogRead::Corrupted(_)) | Err(_) => {
+                        match skippable_header(&config, lid, lsn)? {
+                            Some(header) => {
+                                warn!(
+                                    "dropping corrupt message at lsn {} \
+                                     for pid {}",
+                                    lsn, header.pid
+                                );
+                                let len = MSG_HEADER_LEN + header.len as usize;
+                                (header, Entry::Lost, len)
+                            }
+                            None => {
+                                if segment_lsn + (segment_len as Lsn)
+                                    <= max_stable_lsn
+                                {
+                                    warn!(
+                                        "could not read past lsn {} in the \
+                                         stable segment at lid {}",
+                                        lsn, base
+                                    );
+                                    unreadable_segments.push(base);
+                                }
+                                break;
+                            }
+                        }
+                    }
+                };
+
+            offset += len as LogId;
+
+            let chain = chains.entry(header.pid).or_default();
+
+            match entry {
+                Entry::Lost => messages_dropped += 1,
+                Entry::Frag(kind, _) => {
+                    messages_read += 1;
+                    if LogKind::from(kind) != LogKind::Append {
+                        // a replacement or free supersedes everything
+                        // that was written to the page before it
+                        chain.clear();
+                    }
+                }
+            }
+
+            chain.push(entry);
+        }
+    }
+
+    let mut meta = None;
+    let mut meta_damaged = false;
+    let mut pages = FastMap8::default();
+    let mut damaged = FastSet8::default();
+
+    for (pid, chain) in chains {
+        if pid == COUNTER_PID || pid == CONFIG_PID || pid == BATCH_MANIFEST_PID
+        {
+            continue;
+        }
+
+        let lost_fragment =
+            chain.iter().any(|entry| matches!(entry, Entry::Lost));
+
+        if pid == META_PID {
+            meta_damaged = lost_fragment;
+            meta = chain.iter().find_map(|entry| match entry {
+                Entry::Frag(MessageKind::InlineMeta, buf)
+                | Entry::Frag(MessageKind::BlobMeta, buf) => {
+                    Meta::from_bytes(buf).ok()
+                }
+                _ => None,
+            });
+            continue;
+        }
+
+        if lost_fragment {
+            damaged.insert(pid);
+        }
+
+        match materialize::<P>(&chain) {
+            Ok(Some(page)) => {
+                pages.insert(pid, page);
+            }
+            Ok(None) => {}
+            Err(()) => {
+                warn!("could not rebuild pid {} from its fragments", pid);
+                damaged.insert(pid);
+            }
+        }
+    }
+
+    Ok(Salvage {
+        config,
+        meta,
+        meta_damaged,
+        pages,
+        damaged,
+        unreadable_segments,
+        messages_read,
+        messages_dropped,
+    })
+}
+
+/// Returns the Lsn and offset of every segment with an intact
+/// header, ordered by Lsn, along with the highest stable Lsn
+/// recorded in any of them.
+fn readable_segments(config: &Config) -> Result<(Vec<(Lsn, LogId)>, Lsn)> {
+    let segment_len = config.io_buf_size as LogId;
+    let file_len = config.file.metadata()?.len();
+
+    let mut segments = vec![];
+    let mut max_stable_lsn = 0;
+    let mut base = 0;
+
+    while base + SEG_HEADER_LEN as LogId <= file_len {
+        let header = config.file.read_segment_header(base)?;
+        if header.ok && header.lsn % segment_len as Lsn == 0 {
+            segments.push((header.lsn, base));
+            max_stable_lsn =
+                std::cmp::max(max_stable_lsn, header.max_stable_lsn);
+        } else {
+            debug!("skipping segment at lid {} with header {:?}", base, header);
+        }
+        base += segment_len;
+    }
+
+    segments.sort();
+
+    Ok((segments, max_stable_lsn))
+}
+
+/// Returns the header of a message that could not be read, if
+/// it is intact enough to skip over the message.
+fn skippable_header(
+    config: &Config,
+    lid: LogId,
+    lsn: Lsn,
+) -> Result<Option<MessageHeader>> {
+    let mut buf = [0; MSG_HEADER_LEN];
+    if config.file.pread_exact(&mut buf, lid).is_err() {
+        return Ok(None);
+    }
+    let header = MessageHeader::from(buf);
+
+    let segment_len = config.io_buf_size as LogId;
+    let ceiling = lid / segment_len * segment_len + segment_len;
+    let fits =
+        lid + (MSG_HEADER_LEN as LogId) + LogId::from(header.len) <= ceiling;
+
+    let skippable = header.lsn == lsn
+        && fits
+        && header.kind != MessageKind::Corrupted
+        && LogKind::from(header.kind) != LogKind::Corrupted;
+
+    Ok(if skippable { Some(header) } else { None })
+}
+
+/// Rebuilds a page from a base and the fragments appended to
+/// it, returning `None` if it was freed, or an error if its
+/// base was lost or the fragments do not fit together.
+fn materialize<P: Materializer>(
+    chain: &[Entry],
+) -> std::result::Result<Option<P>, ()> {
+    let mut base: P = match chain.first() {
+        Some(Entry::Frag(MessageKind::Free, _)) => return Ok(None),
+        Some(Entry::Frag(kind, buf))
+            if LogKind::from(*kind) == LogKind::Replace =>
+        {
+            deserialize(buf).map_err(|_| ())?
+        }
+        _ => return Err(()),
+    };
+
+    let appended = chain[1..].iter().filter_map(|entry| match entry {
+        Entry::Frag(_kind, buf) => Some(buf),
+        Entry::Lost => None,
+    });
+
+    for buf in appended {
+        let frag: P = deserialize(buf).map_err(|_| ())?;
+
+        // a fragment that was written to a different version
+        // of the page may not apply cleanly
+        catch_unwind(AssertUnwindSafe(|| base.merge(&frag))).map_err(|_| ())?;
+    }
+
+    Ok(Some(base))
+}
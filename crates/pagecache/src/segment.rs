@@ -543,17 +543,22 @@ impl SegmentAccountant {
                     // we would need to ensure through other means
                     // that empty segments with a written segment header
                     // but no other data get reused.
-                    trace!(
-                        "zeroing segment with lid {} during SA initialization",
-                        segment_base
-                    );
-                    maybe_fail!("segment initial free zero");
-                    self.config.file.pwrite_all(
-                        &*vec![MessageKind::Corrupted.into(); SEG_HEADER_LEN],
-                        segment_base,
-                    )?;
-                    if !self.config.temporary {
-                        self.config.file.sync_all()?;
+                    if !self.config.read_only {
+                        trace!(
+                            "zeroing segment with lid {} during SA initialization",
+                            segment_base
+                        );
+                        maybe_fail!("segment initial free zero");
+                        self.config.file.pwrite_all(
+                            &*vec![
+                                MessageKind::Corrupted.into();
+                                SEG_HEADER_LEN
+                            ],
+                            segment_base,
+                        )?;
+                        if !self.config.temporary {
+                            self.config.file.sync_all()?;
+                        }
                     }
                 } else if segment_sizes[idx] <= drain_sz {
                     trace!(
@@ -651,6 +656,16 @@ impl SegmentAccountant {
         }
     }
 
+    /// Returns the blobs that are no longer referenced by any
+    /// page, but whose removal is deferred until the segment
+    /// that replaced them is deactivated.
+    pub(super) fn pending_blob_removals(&self) -> FastSet8<BlobPointer> {
+        self.segments
+            .iter()
+            .flat_map(|segment| segment.deferred_rm_blob.iter().cloned())
+            .collect()
+    }
+
     /// Called by the `PageCache` when a page has been rewritten completely.
     /// We mark all of the old segments that contained the previous state
     /// from the page, and if the old segments are empty or clear enough to
@@ -1048,8 +1063,9 @@ impl SegmentAccountant {
 
         trace!("evaluating free list {:?} in SA::next", free);
 
-        // truncate if possible
-        while self.tip != 0 && self.free.len() > 1 {
+        // truncate if possible. read-only processes leave the
+        // file to the process that writes it.
+        while !self.config.read_only && self.tip != 0 && self.free.len() > 1 {
             let last_segment = self.tip - self.config.io_buf_size as LogId;
             if free.contains(&last_segment) {
                 self.free.remove(&last_segment);
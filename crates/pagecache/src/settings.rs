@@ -0,0 +1,87 @@
+use super::*;
+
+/// How reluctantly the `Lru` evicts the pages of a collection.
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize,
+)]
+pub enum CachePriority {
+    /// Pages enter the cache as the next to be evicted,
+    /// and are not promoted when they are accessed again.
+    Low,
+    /// Pages are evicted in least-recently-used order.
+    #[default]
+    Normal,
+    /// Pages are skipped once when they would be evicted,
+    /// so they survive a pass over a larger working set.
+    High,
+}
+
+/// Overrides of the `Config` for the pages of a single
+/// collection, which are persisted in the `Meta` along
+/// with its root. Unset fields fall back to the `Config`.
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize,
+)]
+pub struct PageSettings {
+    /// Overrides `use_compression`
+    pub use_compression: Option<bool>,
+    /// Overrides `compression_factor`
+    pub compression_factor: Option<i32>,
+    /// Overrides `page_consolidation_threshold`
+    pub page_consolidation_threshold: Option<usize>,
+    /// How reluctantly pages are evicted from the cache
+    pub cache_priority: CachePriority,
+}
+
+impl PageSettings {
+    /// Fails if an override is outside of the range
+    /// that the `Config` accepts for the same setting.
+    pub fn validate(&self) -> Result<()> {
+        if self.use_compression == Some(true) && !cfg!(feature = "compression")
+        {
+            return Err(Error::Unsupported(
+                "the compression feature must be enabled".into(),
+            ));
+        }
+        if let Some(factor) = self.compression_factor {
+            if !(1..=22).contains(&factor) {
+                return Err(Error::Unsupported(
+                    "compression_factor must be between 1 and 22".into(),
+                ));
+            }
+        }
+        if let Some(threshold) = self.page_consolidation_threshold {
+            if !(1..1 << 20).contains(&threshold) {
+                return Err(Error::Unsupported(
+                    "must consolidate pages after between 1 and \
+                     1 million updates"
+                        .into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the zstd compression factor to write pages
+    /// with, or `None` if they are not compressed.
+    pub(crate) fn compression(&self, config: &Config) -> Option<i32> {
+        let use_compression =
+            self.use_compression.unwrap_or(config.use_compression);
+        if use_compression && cfg!(feature = "compression") {
+            Some(self.compression_factor.unwrap_or(config.compression_factor))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of fragments that a page may
+    /// accumulate before it is consolidated.
+    pub(crate) fn consolidation_threshold(&self, config: &Config) -> usize {
+        self.page_consolidation_threshold
+            .unwrap_or(config.page_consolidation_threshold)
+    }
+
+    pub(crate) fn size_in_bytes(&self) -> u64 {
+        std::mem::size_of::<Self>() as u64
+    }
+}
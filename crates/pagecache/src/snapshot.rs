@@ -155,7 +155,7 @@ pub(super) fn advance_snapshot(
         snapshot.apply(log_kind, pid, lsn, ptr, sz);
     }
 
-    if snapshot.last_lsn != old_lsn {
+    if snapshot.last_lsn != old_lsn && !config.read_only {
         write_snapshot(config, &snapshot)?;
     }
 
@@ -164,6 +164,30 @@ pub(super) fn advance_snapshot(
     Ok(snapshot)
 }
 
+/// Applies the messages that a read-only process has not seen
+/// yet to its `Snapshot`, without persisting it, and returns
+/// the pages that they changed.
+pub(super) fn follow_snapshot(
+    iter: LogIter,
+    snapshot: &mut Snapshot,
+) -> FastSet8<PageId> {
+    let mut changed = FastSet8::default();
+
+    for (log_kind, pid, lsn, ptr, sz) in iter {
+        if lsn <= snapshot.last_lsn {
+            continue;
+        }
+
+        snapshot.last_lsn = lsn;
+        snapshot.last_lid = ptr.lid();
+
+        snapshot.apply(log_kind, pid, lsn, ptr, sz);
+        changed.insert(pid);
+    }
+
+    changed
+}
+
 /// Read a `Snapshot` or generate a default, then advance it to
 /// the tip of the data file, if present.
 pub fn read_snapshot_or_default(config: &Config) -> Result<Snapshot> {
@@ -180,7 +204,7 @@ pub fn read_snapshot_or_default(config: &Config) -> Result<Snapshot> {
 
 /// Read a `Snapshot` from disk.
 fn read_snapshot(config: &Config) -> std::io::Result<Option<Snapshot>> {
-    let mut f = loop {
+    let (mut f, path) = loop {
         let mut candidates = config.get_snapshot_files()?;
         if candidates.is_empty() {
             debug!("no previous snapshot found");
@@ -192,7 +216,7 @@ fn read_snapshot(config: &Config) -> std::io::Result<Option<Snapshot>> {
         let path = candidates.pop().unwrap();
 
         match std::fs::OpenOptions::new().read(true).open(&path) {
-            Ok(f) => break f,
+            Ok(f) => break (f, path),
             Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
                 // this can happen if there's a race
                 continue;
@@ -224,6 +248,28 @@ fn read_snapshot(config: &Config) -> std::io::Result<Option<Snapshot>> {
         return Ok(None);
     }
 
+    // the lsn in the file name is authenticated along with
+    // an encrypted snapshot
+    let name = path.file_name().and_then(std::ffi::OsStr::to_str);
+    let last_lsn = if let Some(lsn) =
+        name.and_then(|name| Lsn::from_str_radix(name.get(5..21)?, 16).ok())
+    {
+        lsn
+    } else {
+        warn!("snapshot file name {:?} has no lsn, ignoring it", path);
+        return Ok(None);
+    };
+
+    let scope = KeyScope::Snapshot(last_lsn);
+    let buf = if let Some(buf) =
+        maybe_decrypt(config, scope, buf, &snapshot_aad(last_lsn))
+    {
+        buf
+    } else {
+        warn!("snapshot failed authentication, ignoring it");
+        return Ok(None);
+    };
+
     #[cfg(feature = "zstd")]
     let bytes = if config.use_compression {
         let len_expected: u64 = arr_to_u64(&len_expected_bytes);
@@ -252,6 +298,13 @@ fn write_snapshot(config: &Config, snapshot: &Snapshot) -> Result<()> {
     #[cfg(not(feature = "zstd"))]
     let bytes = raw_bytes;
 
+    let bytes = if let Some(ref key) = config.encryption_key {
+        let scope = KeyScope::Snapshot(snapshot.last_lsn);
+        key.seal(scope, &bytes, &snapshot_aad(snapshot.last_lsn))
+    } else {
+        bytes
+    };
+
     let crc32: [u8; 4] = u32_to_arr(crc32(&bytes));
     let len_bytes: [u8; 8] = u64_to_arr(decompressed_len as u64);
 
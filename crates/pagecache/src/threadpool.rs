@@ -100,3 +100,33 @@ where
     }
     promise
 }
+
+/// Like `spawn`, but if no thread of the pool is idle, the
+/// function runs on the calling thread instead of waiting
+/// for one, and the returned `Promise` is already filled.
+pub fn spawn_or_run<F, R>(work: F) -> Promise<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let (promise_filler, promise) = Promise::pair();
+    let task = move || {
+        let result = (work)();
+        promise_filler.fill(result);
+    };
+    match POOL.sender.try_send(Box::new(task)) {
+        Ok(()) => {}
+        Err(crossbeam_channel::TrySendError::Full(task)) => {
+            // leave a thread waiting for the next caller
+            maybe_create_another_blocking_thread();
+            (task)();
+        }
+        Err(crossbeam_channel::TrySendError::Disconnected(_)) => {
+            panic!(
+                "unable to send to blocking threadpool \
+                 due to receiver disconnection"
+            );
+        }
+    }
+    promise
+}
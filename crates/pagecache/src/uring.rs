@@ -0,0 +1,381 @@
+//! An io_uring backend for the log, used on linux when the
+//! `io_uring` feature is enabled.
+//!
+//! A sealed IO buffer is written and synced by a pair of
+//! linked submissions, instead of by a call that blocks a
+//! thread of the threadpool until the sync returns. The rest
+//! of the write, which marks the buffer as stable and wakes
+//! up threads in `make_stable`, runs on the single thread that
+//! reaps completions from the ring. Reads of pages go through
+//! the same ring, so that reads issued by several threads at
+//! once are handed to the kernel together.
+use std::{
+    os::unix::io::{AsRawFd, RawFd},
+    sync::{atomic::AtomicBool, Arc},
+    thread,
+};
+
+use io_uring::{cqueue, opcode, squeue, types, IoUring};
+use parking_lot::Mutex;
+
+use super::*;
+
+/// The number of submissions that fit in the ring.
+const RING_ENTRIES: u32 = 256;
+
+/// The `user_data` of the no-op that wakes up the reaper
+/// when the ring is dropped.
+const WAKE: u64 = u64::max_value();
+
+/// The operations of a chain share the high bits of their
+/// `user_data`, and keep their position in the low bits.
+const CHAIN_SHIFT: u32 = 4;
+
+type Callback = Box<dyn FnOnce(io::Result<usize>) + Send + 'static>;
+
+/// Operations that were submitted together, each of which is
+/// only started once the one before it succeeded.
+struct Chain {
+    /// The number of bytes that each operation must transfer
+    /// to succeed.
+    lens: Vec<usize>,
+    remaining: usize,
+    /// The first error of the chain, or the number of bytes
+    /// transferred by its last operation.
+    result: io::Result<usize>,
+    callback: Callback,
+}
+
+struct Shared {
+    ring: IoUring,
+    /// Serializes pushes to the submission queue.
+    sq: Mutex<()>,
+    chains: Mutex<FastMap8<u64, Chain>>,
+    next_chain: AtomicU64,
+    shutdown: AtomicBool,
+}
+
+/// A ring that the files of a `Config` are accessed through.
+pub(crate) struct Uring {
+    shared: Arc<Shared>,
+    reaper: Option<thread::JoinHandle<()>>,
+    /// The file that pages are read from.
+    file: RawFd,
+    /// The file that the log is written to, which differs
+    /// from `file` if it was opened with other flags.
+    log_file: RawFd,
+    /// Whether writes of the log must be followed by a sync.
+    log_sync: bool,
+}
+
+impl Debug for Uring {
+    fn fmt(
+        &self,
+        formatter: &mut fmt::Formatter<'_>,
+    ) -> std::result::Result<(), fmt::Error> {
+        formatter.write_fmt(format_args!(
+            "Uring {{ file: {}, log_file: {} }}",
+            self.file, self.log_file
+        ))
+    }
+}
+
+impl Uring {
+    /// Sets up a ring and the thread that reaps it, failing
+    /// if the kernel does not support io_uring or forbids it.
+    pub(crate) fn new(
+        file: &std::fs::File,
+        log_file: Option<&LogFile>,
+    ) -> io::Result<Uring> {
+        let shared = Arc::new(Shared {
+            ring: IoUring::new(RING_ENTRIES)?,
+            sq: Mutex::new(()),
+            chains: Mutex::new(FastMap8::default()),
+            next_chain: AtomicU64::new(0),
+            shutdown: AtomicBool::new(false),
+        });
+
+        let reaper = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("sled-uring".to_string())
+                .spawn(move || shared.reap())?
+        };
+
+        Ok(Uring {
+            shared,
+            reaper: Some(reaper),
+            file: file.as_raw_fd(),
+            log_file: log_file.map_or(file.as_raw_fd(), AsRawFd::as_raw_fd),
+            log_sync: log_file.map_or(true, |log_file| !log_file.dsync),
+        })
+    }
+
+    /// Writes `buf` to the log at `offset`, followed by a sync
+    /// if `sync` is set and the log file needs one, and calls
+    /// `callback` with the result on the reaper thread.
+    ///
+    /// # Safety
+    ///
+    /// `buf` must stay valid until `callback` is called.
+    pub(crate) unsafe fn write_log(
+        &self,
+        buf: &[u8],
+        offset: LogId,
+        sync: bool,
+        callback: Callback,
+    ) {
+        let write = opcode::Write::new(
+            types::Fd(self.log_file),
+            buf.as_ptr(),
+            u32::try_from(buf.len()).unwrap(),
+        )
+        .offset64(i64::try_from(offset).unwrap())
+        .build();
+
+        if sync && self.log_sync {
+            let fsync = opcode::Fsync::new(types::Fd(self.log_file)).build();
+            self.shared
+                .submit(vec![(write, buf.len()), (fsync, 0)], callback);
+        } else {
+            self.shared.submit(vec![(write, buf.len())], callback);
+        }
+    }
+
+    /// Submits a single operation, and blocks until it
+    /// completes.
+    ///
+    /// # Safety
+    ///
+    /// The buffer of `op` must stay valid until this returns.
+    unsafe fn wait(&self, op: squeue::Entry) -> io::Result<usize> {
+        let (filler, promise) = Promise::pair();
+        self.shared
+            .submit(vec![(op, 0)], Box::new(move |res| filler.fill(res)));
+        promise
+            .wait()
+            .expect("io_uring operations are always completed")
+    }
+}
+
+impl Pio for Uring {
+    fn pread_exact(
+        &self,
+        mut buf: &mut [u8],
+        mut offset: LogId,
+    ) -> io::Result<()> {
+        while !buf.is_empty() {
+            let read = opcode::Read::new(
+                types::Fd(self.file),
+                buf.as_mut_ptr(),
+                u32::try_from(buf.len()).unwrap(),
+            )
+            .offset64(i64::try_from(offset).unwrap())
+            .build();
+
+            match unsafe { self.wait(read) } {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "failed to fill whole buffer",
+                    ));
+                }
+                Ok(n) => {
+                    let tmp = buf;
+                    buf = &mut tmp[n..];
+                    offset += n as LogId;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn pwrite_all(&self, mut buf: &[u8], mut offset: LogId) -> io::Result<()> {
+        while !buf.is_empty() {
+            let write = opcode::Write::new(
+                types::Fd(self.file),
+                buf.as_ptr(),
+                u32::try_from(buf.len()).unwrap(),
+            )
+            .offset64(i64::try_from(offset).unwrap())
+            .build();
+
+            match unsafe { self.wait(write) } {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    ));
+                }
+                Ok(n) => {
+                    buf = &buf[n..];
+                    offset += n as LogId;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Uring {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, SeqCst);
+
+        // the reaper may be waiting with nothing in flight
+        let wake = opcode::Nop::new().build().user_data(WAKE);
+        unsafe { self.shared.push(&[wake]) };
+        self.shared.enter();
+
+        let reaper = self.reaper.take().unwrap();
+
+        // the last reference to the `Config` may be dropped by
+        // a callback, in which case the reaper exits on its own
+        if reaper.thread().id() != thread::current().id() {
+            reaper.join().expect("the io_uring reaper panicked");
+        }
+    }
+}
+
+impl Shared {
+    /// Submits `ops` as one chain, along with the number of bytes
+    /// that each of them must transfer to succeed.
+    ///
+    /// # Safety
+    ///
+    /// The buffers of `ops` must stay valid until `callback`
+    /// is called.
+    unsafe fn submit(
+        &self,
+        ops: Vec<(squeue::Entry, usize)>,
+        callback: Callback,
+    ) {
+        assert!(ops.len() < 1 << CHAIN_SHIFT);
+
+        let id = self.next_chain.fetch_add(1, Relaxed);
+        let last = ops.len() - 1;
+
+        let mut lens = Vec::with_capacity(ops.len());
+        let mut entries = Vec::with_capacity(ops.len());
+        for (i, (op, len)) in ops.into_iter().enumerate() {
+            let op = op.user_data(id << CHAIN_SHIFT | i as u64);
+            if i == last {
+                entries.push(op);
+            } else {
+                entries.push(op.flags(squeue::Flags::IO_LINK));
+            }
+            lens.push(len);
+        }
+
+        self.chains.lock().insert(
+            id,
+            Chain {
+                remaining: lens.len(),
+                lens,
+                result: Ok(0),
+                callback,
+            },
+        );
+
+        self.push(&entries);
+        self.enter();
+    }
+
+    /// Pushes `entries` next to each other in the submission
+    /// queue, handing earlier ones to the kernel while it is
+    /// full.
+    unsafe fn push(&self, entries: &[squeue::Entry]) {
+        let _sq = self.sq.lock();
+        while self
+            .ring
+            .submission_shared()
+            .push_multiple(entries)
+            .is_err()
+        {
+            self.enter();
+        }
+    }
+
+    /// Hands the queued submissions to the kernel.
+    fn enter(&self) {
+        loop {
+            match self.ring.submit() {
+                Ok(_) => return,
+                Err(ref e) if is_transient(e) => thread::yield_now(),
+                Err(e) => panic!("failed to submit to io_uring: {}", e),
+            }
+        }
+    }
+
+    /// Waits for completions, and calls the callbacks of
+    /// the chains that they complete, until the ring is
+    /// dropped and nothing is in flight anymore.
+    fn reap(&self) {
+        loop {
+            match self.ring.submit_and_wait(1) {
+                Ok(_) => {}
+                // the completion queue may need to be drained
+                // before the kernel accepts more submissions
+                Err(ref e) if is_transient(e) => {}
+                Err(e) => panic!("failed to wait for io_uring: {}", e),
+            }
+
+            // only this thread reads the completion queue
+            let cqes: Vec<cqueue::Entry> =
+                unsafe { self.ring.completion_shared() }.collect();
+
+            for cqe in cqes {
+                if cqe.user_data() != WAKE {
+                    self.complete(cqe.user_data(), cqe.result());
+                }
+            }
+
+            if self.shutdown.load(SeqCst) && self.chains.lock().is_empty() {
+                return;
+            }
+        }
+    }
+
+    fn complete(&self, user_data: u64, res: i32) {
+        let id = user_data >> CHAIN_SHIFT;
+        let idx =
+            usize::try_from(user_data & ((1 << CHAIN_SHIFT) - 1)).unwrap();
+
+        let mut chains = self.chains.lock();
+        let chain = chains
+            .get_mut(&id)
+            .expect("completed an operation of an unknown chain");
+
+        // once an operation fails, the ones linked after it
+        // are cancelled, so only its own error is kept
+        if chain.result.is_ok() {
+            chain.result = if res < 0 {
+                Err(io::Error::from_raw_os_error(-res))
+            } else if usize::try_from(res).unwrap() < chain.lens[idx] {
+                Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "io_uring transferred fewer bytes than requested",
+                ))
+            } else {
+                Ok(usize::try_from(res).unwrap())
+            };
+        }
+
+        chain.remaining -= 1;
+        if chain.remaining == 0 {
+            let chain = chains.remove(&id).unwrap();
+            drop(chains);
+            (chain.callback)(chain.result);
+        }
+    }
+}
+
+fn is_transient(e: &io::Error) -> bool {
+    match e.raw_os_error() {
+        Some(libc::EINTR) | Some(libc::EAGAIN) | Some(libc::EBUSY) => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,55 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering::SeqCst},
+    Arc,
+};
+
+use parking_lot::Mutex;
+
+use super::*;
+
+/// The states that pages had when this was created by
+/// `PageCache::preserve_versions`. Nothing is copied up
+/// front: while this is held, the state of a page is
+/// recorded just before the first `link`, `replace` or
+/// `free` of it. Memory use is proportional to the
+/// number of pages changed while this is held.
+pub struct PageVersions<P> {
+    pages: Mutex<FastMap8<PageId, Option<Arc<P>>>>,
+    held: Arc<AtomicUsize>,
+}
+
+impl<P> PageVersions<P> {
+    pub(crate) fn new(held: Arc<AtomicUsize>) -> Self {
+        held.fetch_add(1, SeqCst);
+        Self {
+            pages: Mutex::new(FastMap8::default()),
+            held,
+        }
+    }
+
+    /// Returns the state of a page as of the creation of
+    /// this `PageVersions`, if the page has been changed
+    /// since. `Some(None)` means that the page did not
+    /// exist at that time. To read a page consistently,
+    /// read the current state of the page first, and only
+    /// use it if this returns `None` afterwards.
+    pub fn get(&self, pid: PageId) -> Option<Option<Arc<P>>> {
+        self.pages.lock().get(&pid).cloned()
+    }
+
+    pub(crate) fn contains(&self, pid: PageId) -> bool {
+        self.pages.lock().contains_key(&pid)
+    }
+
+    /// Records the state of a page, unless a state that was
+    /// recorded earlier, and is therefore older, exists.
+    pub(crate) fn record(&self, pid: PageId, page: Option<Arc<P>>) {
+        self.pages.lock().entry(pid).or_insert(page);
+    }
+}
+
+impl<P> Drop for PageVersions<P> {
+    fn drop(&mut self) {
+        self.held.fetch_sub(1, SeqCst);
+    }
+}
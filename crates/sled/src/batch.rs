@@ -33,6 +33,7 @@ impl<'a> Batch<'a> {
         let peg = self.tree.context.pin_log()?;
         let cc = self.tree.concurrency_control.write();
         for (k, v_opt) in self.writes {
+            self.tree.clear_ttl(&k)?;
             if let Some(v) = v_opt {
                 self.tree.insert_inner(k, v)?;
             } else {
@@ -0,0 +1,97 @@
+//! Writes a dump of a sled database, which can be restored
+//! with `sled-restore`, possibly by a later version of sled.
+use std::{
+    fs::OpenOptions,
+    io::{self, Seek, SeekFrom, Write},
+    process,
+};
+
+use sled::{Db, DumpCheckpoint};
+
+const USAGE: &str = "
+Usage: sled-dump [--resume] <db-path> [<dump-file>]
+
+Writes a dump of the database at <db-path> to <dump-file>,
+or to stdout if no dump file is given.
+
+Options:
+    --resume    Continue a dump file that was interrupted,
+                instead of refusing to overwrite it.
+";
+
+fn main() {
+    let mut resume = false;
+    let mut paths = vec![];
+
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--resume" => resume = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    if paths.is_empty() || paths.len() > 2 || (resume && paths.len() != 2) {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    if let Err(e) = run(&paths[0], paths.get(1), resume) {
+        eprintln!("sled-dump: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(
+    db_path: &str,
+    dump_path: Option<&String>,
+    resume: bool,
+) -> sled::Result<()> {
+    let db = Db::open(db_path)?;
+
+    let entries = match dump_path {
+        None => {
+            let stdout = io::stdout();
+            let writer = io::BufWriter::new(stdout.lock());
+            db.export_to(writer)?
+        }
+        Some(dump_path) if resume => {
+            let mut file =
+                OpenOptions::new().read(true).write(true).open(dump_path)?;
+
+            let checkpoint = DumpCheckpoint::scan(io::BufReader::new(&file))?;
+            if checkpoint.complete {
+                eprintln!("sled-dump: {} is already complete", dump_path);
+                return Ok(());
+            }
+
+            file.set_len(checkpoint.offset)?;
+            file.seek(SeekFrom::Start(checkpoint.offset))?;
+
+            let mut writer = io::BufWriter::new(file);
+            let entries = db.resume_export_to(&mut writer, &checkpoint)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+            entries
+        }
+        Some(dump_path) => {
+            let file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(dump_path)?;
+
+            let mut writer = io::BufWriter::new(file);
+            let entries = db.export_to(&mut writer)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+            entries
+        }
+    };
+
+    eprintln!("sled-dump: wrote {} entries", entries);
+
+    Ok(())
+}
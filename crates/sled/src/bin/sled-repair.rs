@@ -0,0 +1,109 @@
+//! Salvages what can still be read from a damaged sled
+//! database into a new one, and reports what was lost.
+use std::{convert::TryInto, fs, process};
+
+use sled::{Db, EncryptionKey, Error};
+
+const USAGE: &str = "
+Usage: sled-repair [--key-file <key-file>] <damaged-db-path> <new-db-path>
+
+Reads every intact page of the database at <damaged-db-path>,
+which must not be open, without modifying it, and writes the
+trees it contains into a new database at <new-db-path>. The
+trees and key ranges that may have lost data are printed.
+
+Exits with status 3 if any data may have been lost.
+
+Options:
+    --key-file <key-file>   The file containing the 32 byte
+                            key of an encrypted database.
+";
+
+fn main() {
+    let mut key_file = None;
+    let mut paths = vec![];
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--key-file" => key_file = args.next(),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    if paths.len() != 2 {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    match run(&paths[0], &paths[1], key_file) {
+        Ok(true) => {}
+        Ok(false) => process::exit(3),
+        Err(e) => {
+            eprintln!("sled-repair: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn run(from: &str, to: &str, key_file: Option<String>) -> sled::Result<bool> {
+    let encryption_key = match key_file {
+        None => None,
+        Some(key_file) => {
+            let raw = fs::read(&key_file)?;
+            let key: [u8; 32] = raw.as_slice().try_into().map_err(|_| {
+                Error::Unsupported(format!(
+                    "expected {} to contain a 32 byte key, \
+                     but it contains {} bytes",
+                    key_file,
+                    raw.len()
+                ))
+            })?;
+            Some(EncryptionKey::new(key))
+        }
+    };
+
+    let report = Db::repair(from, to, encryption_key)?;
+
+    eprintln!(
+        "sled-repair: recovered {} keys in {} trees from {} log messages, \
+         dropped {} corrupt messages",
+        report.keys_recovered,
+        report.trees.len(),
+        report.messages_read,
+        report.messages_dropped,
+    );
+
+    for range in &report.lost_ranges {
+        println!(
+            "lost: tree {} keys {:?} to {}",
+            String::from_utf8_lossy(&range.tree_name),
+            range.lo,
+            range
+                .hi
+                .as_ref()
+                .map_or_else(|| "the end".to_owned(), |hi| format!("{:?}", hi)),
+        );
+    }
+
+    for lid in &report.unreadable_segments {
+        println!(
+            "lost: the end of the log segment at offset {}, \
+             which may have contained writes to any tree",
+            lid
+        );
+    }
+
+    if report.trees_may_be_missing {
+        println!(
+            "lost: the latest record of which trees exist, \
+             so recently created trees may be missing"
+        );
+    }
+
+    Ok(report.is_complete())
+}
@@ -0,0 +1,59 @@
+//! Restores a dump written by `sled-dump` into a
+//! sled database, creating it if it does not exist.
+use std::{fs::File, io, process};
+
+use sled::{Db, DumpReader};
+
+const USAGE: &str = "
+Usage: sled-restore <dump-file> <db-path>
+
+Restores the dump in <dump-file> into the database at
+<db-path>. Use - as the dump file to read it from stdin.
+Existing keys are overwritten, so an interrupted restore
+may simply be run again.
+";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+
+    if args.len() != 2 {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    if let Err(e) = run(&args[0], &args[1]) {
+        eprintln!("sled-restore: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(dump_path: &str, db_path: &str) -> sled::Result<()> {
+    let db = Db::open(db_path)?;
+
+    let entries = if dump_path == "-" {
+        let stdin = io::stdin();
+        let reader = io::BufReader::new(stdin.lock());
+        db.import_from(reader)?
+    } else {
+        let file = File::open(dump_path)?;
+        let header =
+            DumpReader::new(io::BufReader::new(&file))?.header().clone();
+        eprintln!(
+            "sled-restore: restoring dump written by sled {} \
+             (format version {})",
+            header.sled_version, header.format_version
+        );
+        db.import_from(io::BufReader::new(File::open(dump_path)?))?
+    };
+
+    db.flush()?;
+
+    eprintln!("sled-restore: restored {} entries", entries);
+
+    Ok(())
+}
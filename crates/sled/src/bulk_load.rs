@@ -0,0 +1,340 @@
+//! Building a `Tree` from sorted input, for `Tree::bulk_load`.
+//!
+//! Inserting keys one by one appends a fragment for every key,
+//! and splits every node on the way to its final size. Instead,
+//! the leaves are filled from left to right with as many items
+//! as `Node::should_split` allows, and every level of index
+//! nodes is filled in the same way with the low keys of the
+//! nodes below it, as they are completed.
+//!
+//! A node points to its right sibling, so as soon as a node is
+//! full, the page ID of the node after it is reserved without
+//! writing anything. The full node is then written once, as a
+//! single `Frag::Base` that is the first state of its page.
+//!
+//! None of the new pages are reachable until the new root is
+//! swapped into the `Meta`, so readers either see the empty
+//! tree or all of the loaded keys. Every write of the load is
+//! part of one atomic batch of the log, so a crash before the
+//! swap does not leave any of the new pages behind.
+use std::sync::atomic::Ordering::SeqCst;
+
+use super::*;
+
+/// The node that is being filled on one level of the tree.
+struct Pending {
+    pid: PageId,
+    lo: IVec,
+    data: Data,
+}
+
+struct Loader<'a> {
+    tree: &'a Tree,
+    /// The nodes being filled, from the leaves up.
+    levels: Vec<Pending>,
+    /// The first node of each level.
+    leftmost: Vec<PageId>,
+    /// Every page reserved so far, freed if loading fails.
+    reserved: Vec<PageId>,
+    stats: TreeStats,
+}
+
+/// Replaces the contents of an empty tree with the pairs of
+/// `iter`, which must be sorted in the order of the tree's
+/// keys. Must be called while holding the write side of the
+/// tree's `concurrency_control`.
+pub(crate) fn load<I, K, V>(tree: &Tree, iter: I) -> Result<usize>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<[u8]>,
+    IVec: From<V>,
+{
+    let old_root = tree.root.load(SeqCst);
+    let old_pages = empty_tree_pages(tree, old_root)?;
+
+    let peg = tree.context.pin_log()?;
+
+    let mut loader = Loader {
+        tree,
+        levels: vec![],
+        leftmost: vec![],
+        reserved: vec![],
+        stats: TreeStats::default(),
+    };
+
+    let new_root = match loader.build(iter) {
+        Ok(new_root) => new_root,
+        Err(e) => {
+            loader.free_reserved()?;
+            return Err(e);
+        }
+    };
+
+    tree.mark_stats_dirty()?;
+
+    let guard = pin();
+    let cas = tree.context.pagecache.cas_root_in_meta(
+        tree.tree_id.clone(),
+        Some(old_root),
+        Some(new_root),
+        &guard,
+    )?;
+    if let Err(actual) = cas {
+        loader.free_reserved()?;
+        return Err(Error::ReportableBug(format!(
+            "the root of tree {:?} changed from {} to {:?} while \
+             it was being bulk loaded",
+            String::from_utf8_lossy(&tree.tree_id),
+            old_root,
+            actual,
+        )));
+    }
+
+    debug!(
+        "bulk load installed root {} in place of {}",
+        new_root, old_root
+    );
+    tree.root.store(new_root, SeqCst);
+    tree.stats.store(loader.stats);
+
+    free_pages(tree, &old_pages)?;
+
+    peg.seal_batch()?;
+
+    Ok(loader.stats.len as usize)
+}
+
+/// Returns the pages of a tree, failing if any of its leaves
+/// hold a key.
+fn empty_tree_pages(tree: &Tree, root: PageId) -> Result<Vec<PageId>> {
+    let guard = pin();
+
+    let mut pages = vec![];
+    let mut leftmost = Some(root);
+
+    while let Some(pid) = leftmost.take() {
+        let mut cursor = Some(pid);
+
+        while let Some(pid) = cursor {
+            let view = if let Some(view) = tree.view_for_pid(pid, &guard)? {
+                view
+            } else {
+                // merged away while we were walking
+                break;
+            };
+
+            if let Some(index) = view.data.index_ref() {
+                if leftmost.is_none() {
+                    leftmost = index.first().map(|(_, child)| *child);
+                }
+            } else if view.data.len() > 0 {
+                return Err(Error::Unsupported(
+                    "bulk_load requires the tree to be empty".to_owned(),
+                ));
+            }
+
+            pages.push(pid);
+            cursor = view.next;
+        }
+    }
+
+    Ok(pages)
+}
+
+fn free_pages(tree: &Tree, pages: &[PageId]) -> Result<()> {
+    for &pid in pages {
+        loop {
+            let guard = pin();
+            let view = if let Some(view) = tree.view_for_pid(pid, &guard)? {
+                view
+            } else {
+                break;
+            };
+
+            let free = tree.context.pagecache.free(pid, view.ptr, &guard)?;
+            if free.is_ok() {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl<'a> Loader<'a> {
+    /// Writes every node, returning the page of the root.
+    fn build<I, K, V>(&mut self, iter: I) -> Result<PageId>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<[u8]>,
+        IVec: From<V>,
+    {
+        self.add_level(Data::Leaf(vec![]))?;
+
+        let mut last_key: Option<IVec> = None;
+
+        for (key, value) in iter {
+            let key = key.as_ref();
+            let value = IVec::from(value);
+
+            if let Some(ref last_key) = last_key {
+                if self.tree.order.cmp(last_key, key)
+                    != std::cmp::Ordering::Less
+                {
+                    return Err(Error::Unsupported(format!(
+                        "bulk_load requires strictly ascending keys, \
+                         but {:?} came after {:?}",
+                        key, last_key,
+                    )));
+                }
+            }
+
+            self.stats.len += 1;
+            self.stats.key_bytes += key.len() as u64;
+            self.stats.value_bytes += value.len() as u64;
+
+            if self.levels[0].data.len() == Node::split_threshold(false) {
+                self.start_node(0, key)?;
+            }
+
+            let leaf = &mut self.levels[0];
+            let encoded = self.tree.order.encode(&leaf.lo, key);
+            if let Data::Leaf(ref mut items) = leaf.data {
+                items.push((encoded, value));
+            }
+
+            last_key = Some(key.into());
+        }
+
+        // the root is always an index node
+        if self.levels.len() == 1 {
+            let leaf = self.levels[0].pid;
+            self.add_level(Data::Index(vec![(vec![0].into(), leaf)]))?;
+        }
+
+        for level in 0..self.levels.len() {
+            self.write(level, vec![].into(), None)?;
+        }
+
+        let root = self.levels.last().unwrap().pid;
+
+        self.stats.nodes = self.reserved.len() as u64;
+        self.stats.depth = self.levels.len() as u64;
+
+        Ok(root)
+    }
+
+    /// Adds a level above the highest one so far, which starts
+    /// at the lowest key and holds `data`.
+    fn add_level(&mut self, data: Data) -> Result<()> {
+        let pid = self.reserve();
+        self.leftmost.push(pid);
+        self.levels.push(Pending {
+            pid,
+            lo: vec![].into(),
+            data,
+        });
+        Ok(())
+    }
+
+    /// Writes the full node of a level, and starts the next one
+    /// at `lo`, pointing to it from the level above.
+    fn start_node(&mut self, level: usize, lo: &[u8]) -> Result<()> {
+        let pid = self.reserve();
+        let lo = IVec::from(lo);
+
+        let data = if self.levels[level].data.is_index() {
+            Data::Index(vec![])
+        } else {
+            Data::Leaf(vec![])
+        };
+
+        self.write(level, lo.clone(), Some(pid))?;
+        self.levels[level] = Pending {
+            pid,
+            lo: lo.clone(),
+            data,
+        };
+
+        if level + 1 == self.levels.len() {
+            // vec![0] represents a prefix-encoded empty prefix
+            let first = (vec![0].into(), self.leftmost[level]);
+            self.add_level(Data::Index(vec![first]))?;
+        }
+
+        if self.levels[level + 1].data.len() == Node::split_threshold(true) {
+            self.start_node(level + 1, &lo)?;
+        }
+
+        let parent = &mut self.levels[level + 1];
+        let encoded = self.tree.order.encode(&parent.lo, &lo);
+        if let Data::Index(ref mut children) = parent.data {
+            children.push((encoded, pid));
+        }
+
+        Ok(())
+    }
+
+    /// Writes the node that is being filled on a level.
+    fn write(
+        &mut self,
+        level: usize,
+        hi: IVec,
+        next: Option<PageId>,
+    ) -> Result<()> {
+        let pending = &mut self.levels[level];
+        let data = std::mem::replace(&mut pending.data, Data::Leaf(vec![]));
+
+        let node = Node {
+            data,
+            next,
+            lo: pending.lo.clone(),
+            hi,
+            merging_child: None,
+            merging: false,
+        };
+
+        let guard = pin();
+        self.tree.context.pagecache.allocate_reserved(
+            pending.pid,
+            Frag::Base(node),
+            &self.tree.page_settings(),
+            &guard,
+        )?;
+
+        Ok(())
+    }
+
+    fn reserve(&mut self) -> PageId {
+        let guard = pin();
+        let pid = self.tree.context.pagecache.reserve_pid(&guard);
+        self.reserved.push(pid);
+        pid
+    }
+
+    /// Frees every reserved page, first writing the ones that
+    /// were not written yet, which read as missing.
+    fn free_reserved(&self) -> Result<()> {
+        let guard = pin();
+        for &pid in &self.reserved {
+            if self.tree.view_for_pid(pid, &guard)?.is_none() {
+                let placeholder = Frag::Base(Node {
+                    data: Data::Leaf(vec![]),
+                    next: None,
+                    lo: vec![].into(),
+                    hi: vec![].into(),
+                    merging_child: None,
+                    merging: false,
+                });
+                self.tree.context.pagecache.allocate_reserved(
+                    pid,
+                    placeholder,
+                    &self.tree.page_settings(),
+                    &guard,
+                )?;
+            }
+        }
+        drop(guard);
+
+        free_pages(self.tree, &self.reserved)
+    }
+}
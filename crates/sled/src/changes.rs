@@ -0,0 +1,236 @@
+//! A durable feed of every write, for change-data-capture.
+//!
+//! The log itself can not be replayed for this purpose: the
+//! keys in `Frag::Set` and `Frag::Del` are prefix-encoded
+//! relative to the low key of the node they were written to,
+//! and log messages only know the page they belong to, not
+//! the tree. Instead, when `ConfigBuilder::change_feed` is
+//! enabled, each write is also recorded in an internal tree,
+//! keyed by the big-endian Lsn of the log message that
+//! made the write:
+//!
+//! `lsn -> kind ++ len(tree id) ++ tree id ++ len(key) ++ key ++ value`
+//!
+//! where lengths are big-endian u64s and the value is only
+//! present for `SET` records. `DEL_RANGE` records store the
+//! first removed key as their key, followed by `1 ++ end`
+//! if the range has an end. The empty key maps to the Lsn
+//! below which history is no longer complete, either because
+//! it was truncated, or because the feed was disabled at the
+//! time. Merges are recorded as the values they produced.
+//!
+//! A write and its record are written within a single pinned
+//! log batch, so recovery restores either both or neither.
+use std::convert::TryInto;
+
+use pagecache::Lsn;
+
+use super::*;
+
+const SET: u8 = 0;
+const DEL: u8 = 1;
+const EXPIRED: u8 = 2;
+const DEL_RANGE: u8 = 3;
+
+/// The key that stores the Lsn at which history starts.
+const START_KEY: &[u8] = &[];
+
+/// Marks history as incomplete while the feed is disabled,
+/// so that it is restarted when the feed is enabled again.
+const DISABLED: u64 = u64::max_value();
+
+fn lsn_key(lsn: Lsn) -> [u8; 8] {
+    (lsn.max(0) as u64).to_be_bytes()
+}
+
+fn parse_u64(buf: &[u8]) -> Option<u64> {
+    let array: [u8; 8] = buf.get(..8)?.try_into().ok()?;
+    Some(u64::from_be_bytes(array))
+}
+
+/// Splits a length-prefixed field off the front of `buf`.
+fn parse_field(buf: &[u8]) -> Option<(&[u8], &[u8])> {
+    let len: usize = parse_u64(buf)?.try_into().ok()?;
+    let rest = buf.get(8..)?;
+    if rest.len() < len {
+        return None;
+    }
+    Some(rest.split_at(len))
+}
+
+fn push_field(buf: &mut Vec<u8>, field: &[u8]) {
+    buf.extend_from_slice(&(field.len() as u64).to_be_bytes());
+    buf.extend_from_slice(field);
+}
+
+/// A single write that was read back from the change feed
+/// with `Db::changes_since`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    /// The Lsn of the write, which may be passed to
+    /// `Db::changes_since` after adding 1 to resume
+    /// reading right after this `Change`.
+    pub lsn: Lsn,
+    /// The name of the tree that was written to.
+    pub tree_name: Vec<u8>,
+    /// The write itself, which is an `Event::Set`,
+    /// `Event::Del`, `Event::Expired` or `Event::DelRange`.
+    pub event: Event,
+}
+
+/// An iterator over the `Change`s returned by `Db::changes_since`,
+/// ordered by Lsn.
+pub struct Changes<'a> {
+    pub(crate) iter: Iter<'a>,
+}
+
+impl<'a> Iterator for Changes<'a> {
+    type Item = Result<Change>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v) = match self.iter.next()? {
+            Ok(kv) => kv,
+            Err(e) => return Some(Err(e)),
+        };
+
+        Some(decode(&k, &v).ok_or_else(|| {
+            Error::ReportableBug(format!(
+                "malformed change feed record at key {:?}",
+                k
+            ))
+        }))
+    }
+}
+
+fn decode(raw_lsn: &[u8], raw: &[u8]) -> Option<Change> {
+    let lsn = parse_u64(raw_lsn)?.try_into().ok()?;
+    let (kind, rest) = raw.split_first()?;
+    let (tree_name, rest) = parse_field(rest)?;
+    let (key, value) = parse_field(rest)?;
+    let key = key.to_vec();
+
+    let event = match *kind {
+        SET => Event::Set(key, IVec::from(value)),
+        DEL => Event::Del(key),
+        EXPIRED => Event::Expired(key),
+        DEL_RANGE => match value.split_first() {
+            None => Event::DelRange(key, None),
+            Some((1, end)) => Event::DelRange(key, Some(end.to_vec())),
+            Some(_) => return None,
+        },
+        _ => return None,
+    };
+
+    Some(Change {
+        lsn,
+        tree_name: tree_name.to_vec(),
+        event,
+    })
+}
+
+/// Appends a write to the feed.
+pub(crate) fn record(
+    feed: &Tree,
+    lsn: Lsn,
+    tree_id: &[u8],
+    event: &Event,
+) -> Result<()> {
+    let end;
+    let (kind, key, value): (u8, &[u8], &[u8]) = match event {
+        Event::Set(k, v) => (SET, k.as_slice(), &**v),
+        Event::Del(k) => (DEL, k.as_slice(), &[]),
+        Event::Expired(k) => (EXPIRED, k.as_slice(), &[]),
+        Event::DelRange(lo, hi) => {
+            end = hi.as_ref().map_or(vec![], |hi| {
+                let mut end = vec![1];
+                end.extend_from_slice(hi);
+                end
+            });
+            (DEL_RANGE, lo.as_slice(), &end)
+        }
+        Event::Merge(..) | Event::Lagged(_) => {
+            return Err(Error::ReportableBug(format!(
+                "tried to record {:?} in the change feed",
+                event
+            )));
+        }
+    };
+
+    let mut raw =
+        Vec::with_capacity(17 + tree_id.len() + key.len() + value.len());
+    raw.push(kind);
+    push_field(&mut raw, tree_id);
+    push_field(&mut raw, key);
+    raw.extend_from_slice(value);
+
+    feed.insert_inner(lsn_key(lsn), raw)?;
+    Ok(())
+}
+
+/// Returns the Lsn at which the history in the feed starts.
+pub(crate) fn start(feed: &Tree) -> Result<Lsn> {
+    let raw = feed.get_inner(START_KEY)?;
+    let start = raw.and_then(|raw| parse_u64(&raw)).unwrap_or(DISABLED);
+    Ok(start.try_into().unwrap_or(Lsn::max_value()))
+}
+
+/// Restarts history at `lsn` if the feed was just created,
+/// or if writes may have been missed while it was disabled.
+pub(crate) fn enable(feed: &Tree, lsn: Lsn) -> Result<()> {
+    let raw = feed.get_inner(START_KEY)?;
+    let current = raw.and_then(|raw| parse_u64(&raw)).unwrap_or(DISABLED);
+    if current == DISABLED {
+        debug!("starting change feed history at lsn {}", lsn);
+        feed.insert_inner(START_KEY, lsn_key(lsn).to_vec())?;
+    }
+    Ok(())
+}
+
+/// Marks the history in the feed as incomplete, because
+/// the `Db` was opened without recording writes.
+pub(crate) fn disable(feed: &Tree) -> Result<()> {
+    feed.insert_inner(START_KEY, DISABLED.to_be_bytes().to_vec())?;
+    Ok(())
+}
+
+/// Returns the changes that were recorded at or after `since`,
+/// up to and including `until`.
+pub(crate) fn since(
+    feed: &Tree,
+    since: Lsn,
+    until: Lsn,
+) -> Result<Changes<'_>> {
+    let start = start(feed)?;
+    if since < start {
+        return Err(Error::Unsupported(format!(
+            "history truncated: the change feed starts at lsn {}, \
+             but changes since lsn {} were requested",
+            start, since
+        )));
+    }
+
+    let lo = lsn_key(since);
+    let hi = lsn_key(until.max(since));
+
+    Ok(Changes {
+        iter: feed.range(lo..=hi),
+    })
+}
+
+/// Removes the changes recorded before `before`, returning
+/// how many were removed.
+pub(crate) fn truncate(feed: &Tree, before: Lsn) -> Result<usize> {
+    if start(feed)? < before {
+        feed.insert_inner(START_KEY, lsn_key(before).to_vec())?;
+    }
+
+    let hi = lsn_key(before);
+    let stale: Vec<IVec> =
+        feed.range(lsn_key(0)..hi).keys().collect::<Result<_>>()?;
+
+    for key in &stale {
+        feed.remove_inner(key)?;
+    }
+
+    Ok(stale.len())
+}
@@ -0,0 +1,938 @@
+//! Serde-based codecs that `TypedTree` uses to turn keys and
+//! values into bytes.
+//!
+//! `OrderedCodec` writes everything so that byte order
+//! matches the natural order of the encoded values, which
+//! lets typed ranges be answered by plain byte ranges:
+//!
+//! * unsigned integers are big-endian, and signed integers
+//!   are big-endian with their sign bit flipped.
+//! * floats are big-endian, with their sign bit flipped if
+//!   they are positive, and every bit flipped if they are
+//!   negative, so that `-1.0 < -0.0 < 0.0 < 1.0`.
+//! * strings and byte strings escape `0` as `0 255`, and are
+//!   terminated by `0 0`, so that they sort before any of
+//!   their extensions even when they are followed by more
+//!   fields.
+//! * options, sequences and maps mark each present element
+//!   with a `1`, and sequences and maps end with a `0`.
+//! * tuples and structs are their fields, one after the
+//!   other, so they sort field by field.
+//! * enums are their big-endian u32 variant index, followed
+//!   by their fields.
+//!
+//! The format is not self-describing, so values have to be
+//! decoded into the same type they were encoded from.
+use std::fmt;
+
+use serde::{
+    de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor},
+    ser,
+};
+
+use super::*;
+
+/// Turns keys and values into bytes and back for a
+/// `TypedTree`. Ranges over a `TypedTree` are answered
+/// in the byte order of the encoded keys, so key
+/// encodings should preserve the order of the keys.
+/// Encodings should also be deterministic, because
+/// `TypedTree::cas` compares encoded values.
+pub trait Codec {
+    /// Encodes `value` into bytes.
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>>;
+
+    /// Decodes bytes that were produced by `encode`.
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T>;
+}
+
+/// The default `Codec` of a `TypedTree`, which preserves the
+/// order of integers, floats, strings, and of tuples, structs,
+/// sequences and enums made of them.
+///
+/// # Examples
+///
+/// ```
+/// use sled::{Codec, OrderedCodec};
+///
+/// let a = OrderedCodec::encode(&(1_u64, "b")).unwrap();
+/// let b = OrderedCodec::encode(&(2_u64, "a")).unwrap();
+/// assert!(a < b);
+///
+/// let c = OrderedCodec::encode(&-5_i32).unwrap();
+/// let d = OrderedCodec::encode(&3_i32).unwrap();
+/// assert!(c < d);
+///
+/// assert_eq!(OrderedCodec::decode::<(u64, String)>(&a).unwrap(), (1, "b".into()));
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OrderedCodec;
+
+impl Codec for OrderedCodec {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+        let mut encoder = Encoder { buf: vec![] };
+        value.serialize(&mut encoder).map_err(|e| {
+            Error::Unsupported(format!("failed to encode value: {}", e))
+        })?;
+        Ok(encoder.buf)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        let mut decoder = Decoder { input: bytes };
+        T::deserialize(&mut decoder)
+            .and_then(|value| {
+                if decoder.input.is_empty() {
+                    Ok(value)
+                } else {
+                    Err(CodecError(format!(
+                        "{} trailing bytes",
+                        decoder.input.len()
+                    )))
+                }
+            })
+            .map_err(|e| {
+                Error::Unsupported(format!("failed to decode value: {}", e))
+            })
+    }
+}
+
+/// The error of our `Serializer` and `Deserializer`, which
+/// is turned into an `Error::Unsupported` before it is
+/// returned to users.
+#[derive(Debug)]
+struct CodecError(String);
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+impl ser::Error for CodecError {
+    fn custom<T: fmt::Display>(msg: T) -> CodecError {
+        CodecError(msg.to_string())
+    }
+}
+
+impl de::Error for CodecError {
+    fn custom<T: fmt::Display>(msg: T) -> CodecError {
+        CodecError(msg.to_string())
+    }
+}
+
+type CodecResult<T> = std::result::Result<T, CodecError>;
+
+const END: u8 = 0;
+const ELEMENT: u8 = 1;
+const ESCAPE: u8 = 255;
+
+struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    fn push_escaped(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.buf.push(*byte);
+            if *byte == 0 {
+                self.buf.push(ESCAPE);
+            }
+        }
+        self.buf.extend_from_slice(&[0, 0]);
+    }
+}
+
+impl ser::Serializer for &mut Encoder {
+    type Ok = ();
+    type Error = CodecError;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn serialize_bool(self, v: bool) -> CodecResult<()> {
+        self.buf.push(v as u8);
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> CodecResult<()> {
+        self.serialize_u8(v as u8 ^ (1 << 7))
+    }
+
+    fn serialize_i16(self, v: i16) -> CodecResult<()> {
+        self.serialize_u16(v as u16 ^ (1 << 15))
+    }
+
+    fn serialize_i32(self, v: i32) -> CodecResult<()> {
+        self.serialize_u32(v as u32 ^ (1 << 31))
+    }
+
+    fn serialize_i64(self, v: i64) -> CodecResult<()> {
+        self.serialize_u64(v as u64 ^ (1 << 63))
+    }
+
+    fn serialize_i128(self, v: i128) -> CodecResult<()> {
+        self.serialize_u128(v as u128 ^ (1 << 127))
+    }
+
+    fn serialize_u8(self, v: u8) -> CodecResult<()> {
+        self.buf.push(v);
+        Ok(())
+    }
+
+    fn serialize_u16(self, v: u16) -> CodecResult<()> {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+        Ok(())
+    }
+
+    fn serialize_u32(self, v: u32) -> CodecResult<()> {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+        Ok(())
+    }
+
+    fn serialize_u64(self, v: u64) -> CodecResult<()> {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+        Ok(())
+    }
+
+    fn serialize_u128(self, v: u128) -> CodecResult<()> {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+        Ok(())
+    }
+
+    fn serialize_f32(self, v: f32) -> CodecResult<()> {
+        let bits = v.to_bits();
+        let sign = 1 << 31;
+        self.serialize_u32(if bits & sign == 0 { bits | sign } else { !bits })
+    }
+
+    fn serialize_f64(self, v: f64) -> CodecResult<()> {
+        let bits = v.to_bits();
+        let sign = 1 << 63;
+        self.serialize_u64(if bits & sign == 0 { bits | sign } else { !bits })
+    }
+
+    fn serialize_char(self, v: char) -> CodecResult<()> {
+        self.serialize_u32(v as u32)
+    }
+
+    fn serialize_str(self, v: &str) -> CodecResult<()> {
+        self.push_escaped(v.as_bytes());
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> CodecResult<()> {
+        self.push_escaped(v);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> CodecResult<()> {
+        self.buf.push(END);
+        Ok(())
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(
+        self,
+        value: &T,
+    ) -> CodecResult<()> {
+        self.buf.push(ELEMENT);
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> CodecResult<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> CodecResult<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> CodecResult<()> {
+        self.serialize_u32(variant_index)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> CodecResult<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> CodecResult<()> {
+        self.buf.extend_from_slice(&variant_index.to_be_bytes());
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> CodecResult<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> CodecResult<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> CodecResult<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> CodecResult<Self> {
+        self.buf.extend_from_slice(&variant_index.to_be_bytes());
+        Ok(self)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> CodecResult<Self> {
+        Ok(self)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> CodecResult<Self> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> CodecResult<Self> {
+        self.buf.extend_from_slice(&variant_index.to_be_bytes());
+        Ok(self)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+impl ser::SerializeSeq for &mut Encoder {
+    type Ok = ();
+    type Error = CodecError;
+
+    fn serialize_element<T: ?Sized + Serialize>(
+        &mut self,
+        value: &T,
+    ) -> CodecResult<()> {
+        self.buf.push(ELEMENT);
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> CodecResult<()> {
+        self.buf.push(END);
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for &mut Encoder {
+    type Ok = ();
+    type Error = CodecError;
+
+    fn serialize_key<T: ?Sized + Serialize>(
+        &mut self,
+        key: &T,
+    ) -> CodecResult<()> {
+        self.buf.push(ELEMENT);
+        key.serialize(&mut **self)
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(
+        &mut self,
+        value: &T,
+    ) -> CodecResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> CodecResult<()> {
+        self.buf.push(END);
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for &mut Encoder {
+    type Ok = ();
+    type Error = CodecError;
+
+    fn serialize_element<T: ?Sized + Serialize>(
+        &mut self,
+        value: &T,
+    ) -> CodecResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> CodecResult<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for &mut Encoder {
+    type Ok = ();
+    type Error = CodecError;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        value: &T,
+    ) -> CodecResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> CodecResult<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleVariant for &mut Encoder {
+    type Ok = ();
+    type Error = CodecError;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        value: &T,
+    ) -> CodecResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> CodecResult<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for &mut Encoder {
+    type Ok = ();
+    type Error = CodecError;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> CodecResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> CodecResult<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStructVariant for &mut Encoder {
+    type Ok = ();
+    type Error = CodecError;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> CodecResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> CodecResult<()> {
+        Ok(())
+    }
+}
+
+struct Decoder<'de> {
+    input: &'de [u8],
+}
+
+/// Reads a big-endian integer of the given type.
+macro_rules! take_be {
+    ($decoder:expr, $t:ty) => {{
+        const LEN: usize = std::mem::size_of::<$t>();
+        let mut array = [0; LEN];
+        array.copy_from_slice($decoder.take(LEN)?);
+        <$t>::from_be_bytes(array)
+    }};
+}
+
+impl<'de> Decoder<'de> {
+    fn take(&mut self, len: usize) -> CodecResult<&'de [u8]> {
+        if self.input.len() < len {
+            return Err(CodecError("unexpected end of input".to_owned()));
+        }
+        let (taken, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(taken)
+    }
+
+    /// Reads the marker in front of an optional or repeated
+    /// element, returning `true` if an element follows.
+    fn take_marker(&mut self) -> CodecResult<bool> {
+        match self.take(1)?[0] {
+            END => Ok(false),
+            ELEMENT => Ok(true),
+            other => Err(CodecError(format!("invalid marker {}", other))),
+        }
+    }
+
+    fn take_escaped(&mut self) -> CodecResult<Vec<u8>> {
+        let mut bytes = vec![];
+        loop {
+            let byte = self.take(1)?[0];
+            if byte != 0 {
+                bytes.push(byte);
+                continue;
+            }
+            match self.take(1)?[0] {
+                ESCAPE => bytes.push(0),
+                0 => return Ok(bytes),
+                other => {
+                    return Err(CodecError(format!(
+                        "invalid escape {} in string",
+                        other
+                    )))
+                }
+            }
+        }
+    }
+
+    fn take_string(&mut self) -> CodecResult<String> {
+        String::from_utf8(self.take_escaped()?)
+            .map_err(|e| CodecError(e.to_string()))
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut Decoder<'de> {
+    type Error = CodecError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> CodecResult<V::Value> {
+        Err(CodecError(
+            "OrderedCodec is not self-describing, so values must be \
+             decoded into a concrete type"
+                .to_owned(),
+        ))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        match self.take(1)?[0] {
+            0 => visitor.visit_bool(false),
+            1 => visitor.visit_bool(true),
+            other => Err(CodecError(format!("invalid bool {}", other))),
+        }
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_i8((take_be!(self, u8) ^ (1 << 7)) as i8)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_i16((take_be!(self, u16) ^ (1 << 15)) as i16)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_i32((take_be!(self, u32) ^ (1 << 31)) as i32)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_i64((take_be!(self, u64) ^ (1 << 63)) as i64)
+    }
+
+    fn deserialize_i128<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_i128((take_be!(self, u128) ^ (1 << 127)) as i128)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_u8(take_be!(self, u8))
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_u16(take_be!(self, u16))
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_u32(take_be!(self, u32))
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_u64(take_be!(self, u64))
+    }
+
+    fn deserialize_u128<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_u128(take_be!(self, u128))
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        let bits = take_be!(self, u32);
+        let sign = 1 << 31;
+        let bits = if bits & sign == 0 { !bits } else { bits ^ sign };
+        visitor.visit_f32(f32::from_bits(bits))
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        let bits = take_be!(self, u64);
+        let sign = 1 << 63;
+        let bits = if bits & sign == 0 { !bits } else { bits ^ sign };
+        visitor.visit_f64(f64::from_bits(bits))
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        let raw = take_be!(self, u32);
+        match std::char::from_u32(raw) {
+            Some(c) => visitor.visit_char(c),
+            None => Err(CodecError(format!("invalid char {}", raw))),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_string(self.take_string()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_string(self.take_string()?)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_byte_buf(self.take_escaped()?)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_byte_buf(self.take_escaped()?)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        if self.take_marker()? {
+            visitor.visit_some(self)
+        } else {
+            visitor.visit_none()
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_seq(Marked { decoder: self })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_seq(Counted {
+            decoder: self,
+            remaining: len,
+        })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_map(Marked { decoder: self })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        self.deserialize_u32(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        self.deserialize_any(visitor)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Elements of a sequence or map, which are each
+/// preceded by a marker.
+struct Marked<'a, 'de> {
+    decoder: &'a mut Decoder<'de>,
+}
+
+impl<'a, 'de> de::SeqAccess<'de> for Marked<'a, 'de> {
+    type Error = CodecError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> CodecResult<Option<T::Value>> {
+        if self.decoder.take_marker()? {
+            seed.deserialize(&mut *self.decoder).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<'a, 'de> de::MapAccess<'de> for Marked<'a, 'de> {
+    type Error = CodecError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> CodecResult<Option<K::Value>> {
+        if self.decoder.take_marker()? {
+            seed.deserialize(&mut *self.decoder).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> CodecResult<V::Value> {
+        seed.deserialize(&mut *self.decoder)
+    }
+}
+
+/// The fields of a tuple or struct, whose number is
+/// known up front.
+struct Counted<'a, 'de> {
+    decoder: &'a mut Decoder<'de>,
+    remaining: usize,
+}
+
+impl<'a, 'de> de::SeqAccess<'de> for Counted<'a, 'de> {
+    type Error = CodecError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> CodecResult<Option<T::Value>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(&mut *self.decoder).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+impl<'de> de::EnumAccess<'de> for &mut Decoder<'de> {
+    type Error = CodecError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> CodecResult<(V::Value, Self)> {
+        let index: u32 = take_be!(self, u32);
+        let index: de::value::U32Deserializer<CodecError> =
+            index.into_deserializer();
+        Ok((seed.deserialize(index)?, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for &mut Decoder<'de> {
+    type Error = CodecError;
+
+    fn unit_variant(self) -> CodecResult<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> CodecResult<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> CodecResult<V::Value> {
+        de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
+
+#[test]
+fn test_ordered_codec_roundtrip() {
+    fn roundtrip<T>(value: T)
+    where
+        T: Serialize + DeserializeOwned + PartialEq + fmt::Debug,
+    {
+        let encoded = OrderedCodec::encode(&value).unwrap();
+        assert_eq!(OrderedCodec::decode::<T>(&encoded).unwrap(), value);
+    }
+
+    roundtrip(true);
+    roundtrip(-3_i8);
+    roundtrip(i64::min_value());
+    roundtrip(u128::max_value());
+    roundtrip(-0.5_f64);
+    roundtrip('ß');
+    roundtrip("a\0b".to_owned());
+    roundtrip(Some((1_u16, vec!["x".to_owned(), String::new()])));
+    roundtrip(None::<u8>);
+    roundtrip(Ok::<u8, String>(5));
+    roundtrip(Err::<u8, String>("e".into()));
+
+    assert!(OrderedCodec::decode::<u32>(&[0; 5]).is_err());
+    assert!(OrderedCodec::decode::<u32>(&[0; 3]).is_err());
+}
+
+#[test]
+fn test_ordered_codec_order() {
+    fn assert_sorted<T: Serialize + fmt::Debug>(values: &[T]) {
+        for pair in values.windows(2) {
+            let a = OrderedCodec::encode(&pair[0]).unwrap();
+            let b = OrderedCodec::encode(&pair[1]).unwrap();
+            assert!(a < b, "{:?} should sort before {:?}", pair[0], pair[1]);
+        }
+    }
+
+    assert_sorted(&[i32::min_value(), -256, -1, 0, 1, 255, i32::max_value()]);
+    assert_sorted(&[0_u64, 1, 255, 256, u64::max_value()]);
+    assert_sorted(&[std::f64::NEG_INFINITY, -1.5, -0.0, 0.0, 1e-9, 2.0]);
+    assert_sorted(&["", "\0", "\0\0", "a", "a\0", "ab", "b"]);
+    assert_sorted(&[(1_u8, "b"), (1, "ba"), (2, ""), (2, "a")]);
+    assert_sorted(&[None, Some(0_u8), Some(1)]);
+    assert_sorted(&[vec![], vec![0_u32], vec![0, 0], vec![1]]);
+}
@@ -56,6 +56,8 @@ impl Context {
             Ok(_) => {}
             #[cfg(feature = "failpoints")]
             Ok(_) | Err(Error::FailPoint) => {}
+            // e.g. the wrong encryption key was provided
+            Err(Error::Unsupported(e)) => return Err(Error::Unsupported(e)),
             other => panic!("failed to verify snapshot: {:?}", other),
         }
 
@@ -94,7 +96,34 @@ impl Context {
         self.pagecache.generate_id()
     }
 
-    pub(crate) fn pin_log(&self) -> Result<RecoveryGuard> {
-        self.pagecache.pin_log()
+    pub(crate) fn pin_log(&self) -> Result<Peg<'_>> {
+        let backpressure = subscription::defer_backpressure();
+        let guard = self.pagecache.pin_log()?;
+        Ok(Peg {
+            guard: Some(guard),
+            _backpressure: backpressure,
+        })
+    }
+}
+
+/// A `RecoveryGuard` that keeps the writers of this thread
+/// from waiting for subscribers until it is released.
+pub(crate) struct Peg<'a> {
+    guard: Option<RecoveryGuard<'a>>,
+    _backpressure: subscription::DeferBackpressure,
+}
+
+impl<'a> Peg<'a> {
+    /// Completes the atomic batch, see `RecoveryGuard::seal_batch`.
+    pub(crate) fn seal_batch(mut self) -> Result<()> {
+        self.guard.take().unwrap().seal_batch()
+    }
+}
+
+impl<'a> Drop for Peg<'a> {
+    fn drop(&mut self) {
+        // release the log reservation before waiting, which
+        // happens when `_backpressure` is dropped after this
+        drop(self.guard.take());
     }
 }
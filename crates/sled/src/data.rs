@@ -41,7 +41,11 @@ impl Data {
         where
             T: Clone + Ord,
         {
-            let (_lhs, rhs) = xs.split_at(xs.len() / 2 + 1);
+            // neither half may be full, or a node that keeps
+            // receiving inserts, like the leftmost one when keys
+            // are descending, splits again with each of them and
+            // grows its tree by a level every time.
+            let (_lhs, rhs) = xs.split_at(xs.len() / 2);
             let split = prefix_decode(lhs_prefix, &rhs[0].0);
 
             let mut rhs_data = Vec::with_capacity(rhs.len());
@@ -119,14 +123,10 @@ impl Data {
         }
     }
 
-    pub(crate) fn drop_gte(&mut self, bound: &[u8], prefix: &[u8]) {
+    pub(crate) fn truncate(&mut self, len: usize) {
         match *self {
-            Data::Index(ref mut ptrs) => ptrs.retain(|&(ref k, _)| {
-                prefix_cmp_encoded(k, bound, prefix) == std::cmp::Ordering::Less
-            }),
-            Data::Leaf(ref mut items) => items.retain(|&(ref k, _)| {
-                prefix_cmp_encoded(k, bound, prefix) == std::cmp::Ordering::Less
-            }),
+            Data::Index(ref mut ptrs) => ptrs.truncate(len),
+            Data::Leaf(ref mut items) => items.truncate(len),
         }
     }
 
@@ -1,11 +1,15 @@
 use std::{
+    io::{Read, Write},
     ops::Deref,
-    sync::{atomic::AtomicU64, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering::SeqCst},
+        Arc,
+    },
 };
 
-use pagecache::FastMap8;
+use pagecache::{FastMap8, Guard, Lsn};
 
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 
 use super::*;
 
@@ -15,6 +19,10 @@ pub struct Db {
     context: Context,
     default: Arc<Tree>,
     tenants: Arc<RwLock<FastMap8<Vec<u8>, Arc<Tree>>>>,
+    expirations: Arc<Tree>,
+    changes: Option<Arc<Tree>>,
+    stats_tree: Option<Arc<Tree>>,
+    reaper_closed: Arc<Mutex<bool>>,
 }
 
 unsafe impl Send for Db {}
@@ -47,8 +55,49 @@ impl std::fmt::Debug for Db {
     }
 }
 
+impl Drop for Db {
+    fn drop(&mut self) {
+        // the reaper only holds references to our trees while
+        // it holds this lock, so they can be counted under it.
+        // the last handle closes it, because a reference the
+        // reaper released last would tear the `Db` down after
+        // it is reopened.
+        {
+            let mut reaper_closed = self.reaper_closed.lock();
+            if Arc::strong_count(&self.tenants) != 1 {
+                return;
+            }
+            *reaper_closed = true;
+        }
+
+        // only the last handle persists the statistics,
+        // because others may keep writing.
+        if self.context.read_only {
+            return;
+        }
+
+        let stats_tree = if let Some(ref stats_tree) = self.stats_tree {
+            stats_tree
+        } else {
+            return;
+        };
+
+        let tenants = self.tenants.read();
+        for tree in tenants.values() {
+            if let Err(e) = stats::persist(stats_tree, tree) {
+                error!(
+                    "failed to persist the statistics of tree {:?}: {:?}",
+                    tree.tree_id, e
+                );
+            }
+        }
+    }
+}
+
 impl Db {
     /// Load existing or create a new `Db` with a default configuration.
+    /// An existing `Db` keeps the io buffer size and compression
+    /// setting that it was created with.
     ///
     /// # Examples
     ///
@@ -58,7 +107,10 @@ impl Db {
     /// let t = Db::open("my_db").unwrap();
     /// ```
     pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
-        let config = ConfigBuilder::new().path(path).build();
+        let config = ConfigBuilder::new()
+            .path(path)
+            .use_persisted_format()?
+            .build();
         Self::start(config)
     }
 
@@ -74,23 +126,30 @@ impl Db {
 
         let context = Context::start(config)?;
 
-        if !context.read_only {
-            let flusher_pagecache = context.pagecache.clone();
-            let flusher = context.flush_every_ms.map(move |fem| {
-                flusher::Flusher::new(
-                    "log flusher".to_owned(),
-                    flusher_pagecache,
-                    fem,
-                )
-            });
-            *context._flusher.lock() = flusher;
-        }
+        let guard = pin();
+
+        // open the deadlines of keys written with a ttl
+        let expirations = Arc::new(meta::open_tree(
+            context.clone(),
+            EXPIRATIONS_TREE_ID.to_vec(),
+            TreeOptions::default(),
+            &guard,
+        )?);
+
+        let changes = Self::open_changes(&context, &guard)?;
+
+        let stats_tree = Self::open_stats(&context, &guard)?;
 
         // create or open the default tree
-        let guard = pin();
         let default = Arc::new(meta::open_tree(
             context.clone(),
             DEFAULT_TREE_ID.to_vec(),
+            TreeOptions {
+                expirations: Some(expirations.clone()),
+                changes: changes.clone(),
+                stats_tree: stats_tree.clone(),
+                ..TreeOptions::default()
+            },
             &guard,
         )?);
 
@@ -98,34 +157,290 @@ impl Db {
             context: context.clone(),
             default,
             tenants: Arc::new(RwLock::new(FastMap8::default())),
+            expirations: expirations.clone(),
+            changes: changes.clone(),
+            stats_tree,
+            reaper_closed: Arc::new(Mutex::new(false)),
         };
 
         let mut tenants = ret.tenants.write();
 
-        for (id, root) in context.pagecache.meta(&guard)?.tenants()
-        {
-            let tree = Tree {
-                tree_id: id.clone(),
-                subscriptions: Arc::new(Subscriptions::default()),
-                context: context.clone(),
-                root: Arc::new(AtomicU64::new(root)),
-                concurrency_control: Arc::new(RwLock::new(())),
-                merge_operator: Arc::new(RwLock::new(None)),
-            };
+        for (id, root) in context.pagecache.meta(&guard)?.tenants() {
+            if id == EXPIRATIONS_TREE_ID
+                || id == CHANGES_TREE_ID
+                || id == STATS_TREE_ID
+                || indexes::is_index_tree(&id)
+            {
+                continue;
+            }
+            if id == DEFAULT_TREE_ID {
+                // share the counters of the default tree
+                tenants.insert(id, ret.default.clone());
+                continue;
+            }
+            let tree = ret.tenant(id.clone(), root)?;
             tenants.insert(id, Arc::new(tree));
         }
 
         drop(tenants);
 
+        if !context.read_only {
+            let flusher_pagecache = context.pagecache.clone();
+            let reaper = ttl::Reaper {
+                index: Arc::downgrade(&ret.expirations),
+                default: Arc::downgrade(&ret.default),
+                tenants: Arc::downgrade(&ret.tenants),
+                closed: ret.reaper_closed.clone(),
+            };
+            let flusher = context.flush_every_ms.map(move |fem| {
+                flusher::Flusher::new(
+                    "log flusher".to_owned(),
+                    flusher_pagecache,
+                    fem,
+                    reaper,
+                )
+            });
+            *context._flusher.lock() = flusher;
+        }
+
         Ok(ret)
     }
 
+    /// Returns a handle to an existing tree with the provided
+    /// root. Fails if the tree uses a comparator that has not
+    /// been registered.
+    fn tenant(&self, id: Vec<u8>, root: PageId) -> Result<Tree> {
+        let (order, indexes, settings) = {
+            let guard = pin();
+            let meta = self.context.pagecache.meta(&guard)?;
+            let order =
+                KeyOrder::named(meta.get_comparator(&id).map(str::as_bytes))?;
+            (order, Indexes::load(meta, &id), meta.get_settings(&id))
+        };
+        let has_expirations = ttl::has_deadlines(&self.expirations, &id)?;
+        let stats = stats::load(self.stats_tree.as_ref().map(|t| &**t), &id)?;
+        Ok(Tree {
+            tree_id: id,
+            subscriptions: Arc::new(Subscriptions::default()),
+            context: self.context.clone(),
+            root: Arc::new(AtomicU64::new(root)),
+            concurrency_control: Arc::new(RwLock::new(())),
+            merge_operator: Arc::new(RwLock::new(None)),
+            expirations: Some(self.expirations.clone()),
+            has_expirations: Arc::new(AtomicBool::new(has_expirations)),
+            changes: self.changes.clone(),
+            stats: Arc::new(stats),
+            stats_tree: self.stats_tree.clone(),
+            order,
+            indexes: Arc::new(RwLock::new(indexes)),
+            settings: Arc::new(RwLock::new(settings)),
+        })
+    }
+
+    /// Opens the change feed if `ConfigBuilder::change_feed` is
+    /// enabled, or marks its history as incomplete otherwise.
+    fn open_changes(
+        context: &Context,
+        guard: &Guard,
+    ) -> Result<Option<Arc<Tree>>> {
+        let exists = context
+            .pagecache
+            .meta(guard)?
+            .tenants()
+            .contains_key(CHANGES_TREE_ID);
+
+        if !exists && (!context.change_feed || context.read_only) {
+            return Ok(None);
+        }
+
+        let feed = meta::open_tree(
+            context.clone(),
+            CHANGES_TREE_ID.to_vec(),
+            TreeOptions::default(),
+            guard,
+        )?;
+
+        if context.read_only {
+            // the feed can still be read, nothing is written
+        } else if context.change_feed {
+            // history starts after everything written so far
+            let start = context.pagecache.max_reserved_lsn() + 1;
+            changes::enable(&feed, start)?;
+        } else {
+            // writes made while the feed is disabled are not
+            // recorded, so it must restart when re-enabled.
+            changes::disable(&feed)?;
+            return Ok(None);
+        }
+
+        Ok(Some(Arc::new(feed)))
+    }
+
+    /// Opens the tree that the statistics of other trees are
+    /// persisted in, unless it does not exist yet and the
+    /// database is read-only.
+    fn open_stats(
+        context: &Context,
+        guard: &Guard,
+    ) -> Result<Option<Arc<Tree>>> {
+        let exists = context
+            .pagecache
+            .meta(guard)?
+            .tenants()
+            .contains_key(STATS_TREE_ID);
+
+        if !exists && context.read_only {
+            return Ok(None);
+        }
+
+        let stats_tree = meta::open_tree(
+            context.clone(),
+            STATS_TREE_ID.to_vec(),
+            TreeOptions::default(),
+            guard,
+        )?;
+
+        Ok(Some(Arc::new(stats_tree)))
+    }
+
     /// Open or create a new disk-backed Tree with its own keyspace,
     /// accessible from the `Db` via the provided identifier.
     pub fn open_tree<V: AsRef<[u8]>>(&self, name: V) -> Result<Arc<Tree>> {
+        self.open_tree_ordered(name.as_ref(), KeyOrder::default(), None)
+    }
+
+    /// Open or create a Tree like `open_tree`, overriding parts
+    /// of the global `Config` for its pages. The overrides are
+    /// persisted along with the tree, so that `open_tree` keeps
+    /// using them, and replace any that it was opened with
+    /// before.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::{CachePriority, ConfigBuilder, Db, TreeConfig};
+    ///
+    /// let config = ConfigBuilder::new().temporary(true).build();
+    /// let db = Db::start(config).unwrap();
+    ///
+    /// let blobs = db
+    ///     .open_tree_with(
+    ///         b"blobs",
+    ///         TreeConfig::new().cache_priority(CachePriority::Low),
+    ///     )
+    ///     .unwrap();
+    /// blobs.insert(b"a", vec![0; 4096]).unwrap();
+    ///
+    /// let reopened = db.open_tree(b"blobs").unwrap();
+    /// let priority = reopened.config().get_cache_priority();
+    /// assert_eq!(priority, CachePriority::Low);
+    /// ```
+    pub fn open_tree_with<V: AsRef<[u8]>>(
+        &self,
+        name: V,
+        config: TreeConfig,
+    ) -> Result<Arc<Tree>> {
+        config.settings.validate()?;
         let name = name.as_ref();
+        let order = self.order_of(name)?;
+        self.open_tree_ordered(name, order, Some(config.settings))
+    }
+
+    /// Open or create a Tree like `open_tree`, whose keys are
+    /// sorted by `comparator` instead of by their bytes. This
+    /// order is used to search and split its nodes, and by
+    /// its iterators.
+    ///
+    /// The comparator is registered for this process under
+    /// `comparator_name`, which is persisted along with the
+    /// tree. Opening it with a different comparator, or with
+    /// `open_tree`, fails. A `Db` that contains such a tree
+    /// can only be started once its comparator is registered,
+    /// either by an earlier call to this method in the same
+    /// process, or with `sled::register_comparator`.
+    ///
+    /// The empty key always sorts first. `scan_prefix` returns
+    /// the keys between the prefix and the first byte string
+    /// after all keys that start with it, according to the
+    /// comparator, so it only returns all of those keys if
+    /// the comparator keeps them together.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::{cmp::Ordering, convert::TryInto};
+    ///
+    /// use sled::{ConfigBuilder, Db};
+    ///
+    /// fn little_endian_u32(a: &[u8], b: &[u8]) -> Ordering {
+    ///     let a = u32::from_le_bytes(a.try_into().unwrap());
+    ///     let b = u32::from_le_bytes(b.try_into().unwrap());
+    ///     a.cmp(&b)
+    /// }
+    ///
+    /// let config = ConfigBuilder::new().temporary(true).build();
+    /// let db = Db::start(config).unwrap();
+    /// let tree = db
+    ///     .open_tree_with_comparator(b"numbers", "le_u32", little_endian_u32)
+    ///     .unwrap();
+    ///
+    /// for i in &[256_u32, 1, 2] {
+    ///     tree.insert(i.to_le_bytes(), vec![0]).unwrap();
+    /// }
+    ///
+    /// let keys: Vec<_> = tree.iter().keys().map(|k| k.unwrap()).collect();
+    /// assert_eq!(&*keys[0], &1_u32.to_le_bytes()[..]);
+    /// assert_eq!(&*keys[2], &256_u32.to_le_bytes()[..]);
+    ///
+    /// assert!(db.open_tree(b"numbers").is_err());
+    /// ```
+    pub fn open_tree_with_comparator<V: AsRef<[u8]>>(
+        &self,
+        name: V,
+        comparator_name: &str,
+        comparator: Comparator,
+    ) -> Result<Arc<Tree>> {
+        register_comparator(comparator_name, comparator)?;
+        let order = KeyOrder::named(Some(comparator_name.as_bytes()))?;
+        self.open_tree_ordered(name.as_ref(), order, None)
+    }
+
+    /// Returns the order that an existing tree was created
+    /// with, or byte order for a new one.
+    fn order_of(&self, name: &[u8]) -> Result<KeyOrder> {
+        let guard = pin();
+        let meta = self.context.pagecache.meta(&guard)?;
+        KeyOrder::named(meta.get_comparator(name).map(str::as_bytes))
+    }
+
+    /// Opens a tree, failing if it exists with another order.
+    /// If `settings` is set, it replaces the overrides of the
+    /// `Config` that the tree was created with.
+    pub(crate) fn open_tree_ordered(
+        &self,
+        name: &[u8],
+        order: KeyOrder,
+        settings: Option<PageSettings>,
+    ) -> Result<Arc<Tree>> {
+        if name == EXPIRATIONS_TREE_ID
+            || name == CHANGES_TREE_ID
+            || name == STATS_TREE_ID
+            || indexes::is_index_tree(name)
+        {
+            return Err(Error::Unsupported(
+                "cannot open the core structures".into(),
+            ));
+        }
         let tenants = self.tenants.read();
         if let Some(tree) = tenants.get(name) {
+            check_comparator(
+                name,
+                tree.order.name().map(|n| &**n),
+                order.name().map(|n| &**n),
+            )?;
+            if let Some(settings) = settings {
+                tree.set_settings(settings)?;
+            }
             return Ok(tree.clone());
         }
         drop(tenants);
@@ -136,6 +451,13 @@ impl Db {
         let tree = Arc::new(meta::open_tree(
             self.context.clone(),
             name.to_vec(),
+            TreeOptions {
+                order,
+                settings,
+                expirations: Some(self.expirations.clone()),
+                changes: self.changes.clone(),
+                stats_tree: self.stats_tree.clone(),
+            },
             &guard,
         )?);
         tenants.insert(name.to_vec(), tree.clone());
@@ -145,7 +467,12 @@ impl Db {
 
     /// Remove a disk-backed collection.
     pub fn drop_tree(&self, name: &[u8]) -> Result<bool> {
-        if name == DEFAULT_TREE_ID {
+        if name == DEFAULT_TREE_ID
+            || name == EXPIRATIONS_TREE_ID
+            || name == CHANGES_TREE_ID
+            || name == STATS_TREE_ID
+            || indexes::is_index_tree(name)
+        {
             return Err(Error::Unsupported(
                 "cannot remove the core structures".into(),
             ));
@@ -162,47 +489,351 @@ impl Db {
 
         let guard = pin();
 
-        let mut root_id =
-            Some(self.context.pagecache.meta_pid_for_name(&name, &guard)?);
+        // a tree that is later created with the same name
+        // must start counting from scratch.
+        if let Some(ref stats_tree) = self.stats_tree {
+            stats::remove_tree(stats_tree, name)?;
+        }
+
+        let leftmost_chain = tree.detach(&guard)?;
+
+        // a tree that is later created with the same name
+        // must not inherit the deadlines or indexes of these keys.
+        ttl::remove_tree(&self.expirations, name)?;
+        indexes::remove_tree(&tree)?;
+
+        // drop writer lock
+        drop(tenants);
+
+        tree.gc_pages(leftmost_chain)?;
+
+        guard.flush();
+
+        Ok(true)
+    }
+
+    /// Take a read-only, point-in-time view of every
+    /// collection in this `Db`. The returned `Snapshot`
+    /// reads from the default tree, and others may be
+    /// opened from it with `Snapshot::open_tree`. All of
+    /// them reflect the same instant, so a snapshot will
+    /// never observe part of a transaction or batch.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::{ConfigBuilder, Db, IVec};
+    /// let config = ConfigBuilder::new().temporary(true).build();
+    /// let db = Db::start(config).unwrap();
+    /// let users = db.open_tree(b"users").unwrap();
+    ///
+    /// users.insert(b"alice", vec![1]).unwrap();
+    ///
+    /// let snapshot = db.snapshot().unwrap();
+    ///
+    /// users.remove(b"alice").unwrap();
+    ///
+    /// let old_users = snapshot.open_tree(b"users").unwrap();
+    /// assert_eq!(old_users.get(b"alice"), Ok(Some(IVec::from(vec![1]))));
+    /// assert_eq!(users.get(b"alice"), Ok(None));
+    /// ```
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        let tenants = self.tenants.read();
+
+        // the default tree is also tracked in the tenants
+        // map, but its writers only use `self.default`.
+        let mut trees: Vec<&Tree> = tenants
+            .iter()
+            .filter(|(name, _)| name.as_slice() != DEFAULT_TREE_ID)
+            .map(|(_, tree)| &**tree)
+            .collect();
+        trees.push(&self.default);
+
+        Snapshot::capture(&self.context, &trees, DEFAULT_TREE_ID)
+    }
+
+    /// Write a crash-consistent copy of this `Db` into the
+    /// directory at `path`, which must not already contain
+    /// a database. Writers are not blocked while the copy
+    /// is made, and the copy can be opened with `Db::open`.
+    ///
+    /// Returns the Lsn that the copy reflects, which may be
+    /// passed to `backup_incremental` to later bring the
+    /// same copy up to date.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::{ConfigBuilder, Db, IVec};
+    /// let config = ConfigBuilder::new().temporary(true).build();
+    /// let db = Db::start(config).unwrap();
+    ///
+    /// let path = std::env::temp_dir().join("sled_backup_to_doctest");
+    /// let _ = std::fs::remove_dir_all(&path);
+    ///
+    /// db.insert(b"a", vec![1]).unwrap();
+    /// let lsn = db.backup_to(&path).unwrap();
+    ///
+    /// db.insert(b"b", vec![2]).unwrap();
+    /// db.backup_incremental(&path, lsn).unwrap();
+    ///
+    /// let copy = Db::open(&path).unwrap();
+    /// assert_eq!(copy.get(b"a"), Ok(Some(IVec::from(vec![1]))));
+    /// assert_eq!(copy.get(b"b"), Ok(Some(IVec::from(vec![2]))));
+    /// # drop(copy);
+    /// # std::fs::remove_dir_all(&path).unwrap();
+    /// ```
+    pub fn backup_to<P: AsRef<std::path::Path>>(&self, path: P) -> Result<Lsn> {
+        self.context.pagecache.backup_to(path, None)
+    }
+
+    /// Bring a copy previously made by `backup_to` up to
+    /// date, by only copying the parts of the log that were
+    /// written after the Lsn returned by the last backup
+    /// into the same directory. Returns the Lsn that the
+    /// updated copy reflects.
+    pub fn backup_incremental<P: AsRef<std::path::Path>>(
+        &self,
+        path: P,
+        since: Lsn,
+    ) -> Result<Lsn> {
+        self.context.pagecache.backup_to(path, Some(since))
+    }
+
+    /// Salvage what can still be read from a database that
+    /// fails to open with `Error::Corruption`, and write it
+    /// into a new database at `to`, which must not exist yet
+    /// or be empty. The damaged database at `from` is only
+    /// read, and must not be open in any process. The
+    /// returned `RepairReport` lists the trees and key ranges
+    /// that may have lost data.
+    ///
+    /// The `encryption_key` is only needed if the damaged
+    /// database is encrypted, in which case the new one is
+    /// encrypted with the same key. All other settings are
+    /// read from the damaged database. The comparators of
+    /// trees opened with `open_tree_with_comparator` must be
+    /// registered beforehand.
+    pub fn repair<P: AsRef<std::path::Path>, Q: AsRef<std::path::Path>>(
+        from: P,
+        to: Q,
+        encryption_key: Option<EncryptionKey>,
+    ) -> Result<RepairReport> {
+        repair::repair(from.as_ref(), to.as_ref(), encryption_key)
+    }
+
+    /// Check that everything the database refers to on disk is
+    /// intact: every fragment of every page is read back, which
+    /// verifies its checksum, and deserialized. Blob files that
+    /// are missing, and blob files that nothing refers to, are
+    /// reported as well. Concurrent readers and writers are not
+    /// blocked, so this may be run periodically against a live
+    /// database.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let config = sled::ConfigBuilder::new().temporary(true).build();
+    /// let db = sled::Db::start(config).unwrap();
+    /// db.insert(b"a", vec![1]).unwrap();
+    ///
+    /// let report = db.verify_integrity().unwrap();
+    /// assert!(report.is_ok(), "found problems: {:?}", report);
+    /// ```
+    pub fn verify_integrity(&self) -> Result<IntegrityReport> {
+        self.context.pagecache.verify_integrity(None)
+    }
+
+    /// Run `verify_integrity` on a background thread, sleeping
+    /// for `pause` after each page that is checked, to limit the
+    /// IO and CPU it takes away from foreground work. The report
+    /// is returned when the thread is joined.
+    pub fn verify_integrity_in_background(
+        &self,
+        pause: std::time::Duration,
+    ) -> Result<std::thread::JoinHandle<Result<IntegrityReport>>> {
+        let pagecache = self.context.pagecache.clone();
+        let join_handle = std::thread::Builder::new()
+            .name("sled-integrity-check".into())
+            .spawn(move || pagecache.verify_integrity(Some(pause)))?;
+        Ok(join_handle)
+    }
+
+    /// Catches a `Db` opened with `ConfigBuilder::read_only` up
+    /// with the writes that the process which has it open for
+    /// writing made durable since it was opened or last
+    /// refreshed, including the trees opened or dropped there.
+    /// Returns the number of pages that changed.
+    ///
+    /// Any number of read-only processes may follow the one
+    /// that writes. They should refresh regularly, because
+    /// reading a page that the writer has since moved elsewhere
+    /// fails until the next refresh. Subscribers are not told
+    /// about the writes that are found, and the change feed is
+    /// only visible if it was enabled when the `Db` was opened.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::{ConfigBuilder, Db};
+    ///
+    /// let path = std::env::temp_dir().join("sled_refresh_doctest");
+    /// let _ = std::fs::remove_dir_all(&path);
+    ///
+    /// let writer = Db::open(&path).unwrap();
+    /// writer.insert(b"a", vec![1]).unwrap();
+    /// writer.flush().unwrap();
+    ///
+    /// let config = ConfigBuilder::new().path(&path).read_only(true).build();
+    /// let reader = Db::start(config).unwrap();
+    /// assert_eq!(reader.get(b"a").unwrap(), Some(vec![1].into()));
+    ///
+    /// writer.insert(b"b", vec![2]).unwrap();
+    /// writer.flush().unwrap();
+    /// assert_eq!(reader.get(b"b").unwrap(), None);
+    ///
+    /// reader.refresh().unwrap();
+    /// assert_eq!(reader.get(b"b").unwrap(), Some(vec![2].into()));
+    /// # drop(reader);
+    /// # drop(writer);
+    /// # std::fs::remove_dir_all(&path).unwrap();
+    /// ```
+    pub fn refresh(&self) -> Result<usize> {
+        let changed = self.context.pagecache.refresh()?;
+
+        let guard = pin();
+        let roots = self.context.pagecache.meta(&guard)?.tenants();
+
+        let mut tenants = self.tenants.write();
 
-        let mut leftmost_chain: Vec<PageId> = vec![root_id.unwrap()];
-        let mut cursor = root_id.unwrap();
-        while let Some(view) = self.view_for_pid(cursor, &guard)? {
-            if let Some(index) = view.data.index_ref() {
-                let leftmost_child = index[0].1;
-                leftmost_chain.push(leftmost_child);
-                cursor = leftmost_child;
+        tenants.retain(|id, tree| {
+            if roots.contains_key(id) {
+                true
             } else {
-                break;
+                tree.root.store(u64::max_value(), SeqCst);
+                false
             }
-        }
+        });
 
-        loop {
-            let res = self.context.pagecache.cas_root_in_meta(
-                name.to_vec(),
-                root_id,
-                None,
-                &guard,
-            )?;
+        for (id, root) in roots {
+            if id == EXPIRATIONS_TREE_ID {
+                self.expirations.root.store(root, SeqCst);
+                continue;
+            }
+            if id == CHANGES_TREE_ID {
+                if let Some(ref changes) = self.changes {
+                    changes.root.store(root, SeqCst);
+                }
+                continue;
+            }
+            if id == STATS_TREE_ID {
+                if let Some(ref stats_tree) = self.stats_tree {
+                    stats_tree.root.store(root, SeqCst);
+                }
+                continue;
+            }
+
+            let has_expirations = ttl::has_deadlines(&self.expirations, &id)?;
+
+            if id == DEFAULT_TREE_ID {
+                self.default.root.store(root, SeqCst);
+                self.default.has_expirations.store(has_expirations, SeqCst);
+            }
 
-            if let Err(actual_root) = res {
-                root_id = actual_root;
+            if let Some(tree) = tenants.get(&id) {
+                tree.root.store(root, SeqCst);
+                tree.has_expirations.store(has_expirations, SeqCst);
+                // the writer's changes bypassed our counters
+                tree.stats.forget();
             } else {
-                break;
+                let tree = self.tenant(id.clone(), root)?;
+                tenants.insert(id, Arc::new(tree));
             }
         }
 
-        tree.root
-            .store(u64::max_value(), std::sync::atomic::Ordering::SeqCst);
+        Ok(changed)
+    }
 
-        // drop writer lock
-        drop(tenants);
+    /// Read the writes made to every tree at or after the
+    /// provided Lsn, in the order they were made, from the
+    /// durable feed that is kept when `ConfigBuilder::change_feed`
+    /// is enabled. Only writes that are durable at the time of
+    /// the call are returned, so a consumer can checkpoint the
+    /// Lsn of the last `Change` it processed and resume after
+    /// it with `changes_since(lsn + 1)`, even across crashes.
+    ///
+    /// Returns an error if changes before the Lsn have been
+    /// removed with `truncate_changes`, or if the feed was
+    /// disabled at the time. `change_feed_start` returns the
+    /// Lsn that a new consumer should start from.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::{ConfigBuilder, Db, Event, IVec};
+    /// let config = ConfigBuilder::new()
+    ///     .temporary(true)
+    ///     .change_feed(true)
+    ///     .build();
+    /// let db = Db::start(config).unwrap();
+    /// let start = db.change_feed_start().unwrap();
+    ///
+    /// let users = db.open_tree(b"users").unwrap();
+    /// users.insert(b"alice", vec![1]).unwrap();
+    ///
+    /// let mut changes = db.changes_since(start).unwrap();
+    /// let change = changes.next().unwrap().unwrap();
+    /// assert_eq!(change.tree_name, b"users".to_vec());
+    /// assert_eq!(change.event, Event::Set(b"alice".to_vec(), IVec::from(vec![1])));
+    /// assert!(changes.next().is_none());
+    ///
+    /// // resume right after the last change we saw
+    /// assert!(db.changes_since(change.lsn + 1).unwrap().next().is_none());
+    /// ```
+    pub fn changes_since(&self, lsn: Lsn) -> Result<Changes<'_>> {
+        let feed = self.change_feed()?;
 
-        tree.gc_pages(leftmost_chain)?;
+        // writers hold the read side from before their Lsn is
+        // reserved until their change is recorded, so every
+        // change up to this Lsn is in the feed once we have it.
+        let cc = feed.concurrency_control.write();
+        let until = self.context.pagecache.max_reserved_lsn();
+        drop(cc);
 
-        guard.flush();
+        self.context.pagecache.make_stable(until)?;
 
-        Ok(true)
+        changes::since(feed, lsn, until)
+    }
+
+    /// Returns the Lsn of the oldest change that is still
+    /// retained by the change feed, which is where a consumer
+    /// without a checkpoint should start `changes_since` from.
+    pub fn change_feed_start(&self) -> Result<Lsn> {
+        changes::start(self.change_feed()?)
+    }
+
+    /// Removes the changes before the provided Lsn from the
+    /// change feed, which should be the lowest Lsn that any
+    /// consumer still needs. Returns the number of changes
+    /// that were removed. Later calls to `changes_since` with
+    /// an earlier Lsn return an error.
+    pub fn truncate_changes(&self, before: Lsn) -> Result<usize> {
+        if self.context.read_only {
+            return Err(Error::Unsupported(
+                "the database is in read-only mode".to_owned(),
+            ));
+        }
+        changes::truncate(self.change_feed()?, before)
+    }
+
+    fn change_feed(&self) -> Result<&Tree> {
+        self.changes.as_ref().map(|feed| &**feed).ok_or_else(|| {
+            Error::Unsupported(
+                "the change feed must be enabled with \
+                 ConfigBuilder::change_feed"
+                    .to_owned(),
+            )
+        })
     }
 
     /// Returns the trees names saved in this Db.
@@ -240,7 +871,9 @@ impl Db {
     /// A database export method for all collections in the `Db`,
     /// for use in sled version upgrades. Can be used in combination
     /// with the `import` method below on a database running a later
-    /// version.
+    /// version. To move data through a file or between processes,
+    /// or to handle errors instead of panicking, use `export_to`
+    /// and `import_from` instead.
     ///
     /// # Panics
     ///
@@ -286,7 +919,10 @@ impl Db {
             match collection_type {
                 ref t if t == b"tree" => {
                     let tree = self
-                        .open_tree(collection_name)
+                        .order_of(&collection_name)
+                        .and_then(|order| {
+                            self.open_collection(&collection_name, order, None)
+                        })
                         .expect("failed to open new tree during import");
                     for mut kv in collection_iter {
                         let v = kv
@@ -305,6 +941,190 @@ impl Db {
         }
     }
 
+    /// Write a dump of every collection in this `Db` to the
+    /// provided writer, using the format documented in the
+    /// format described in the `dump` module of the sled
+    /// source. Each collection is streamed from an iterator
+    /// over it, along with the name of its comparator and
+    /// its `TreeConfig`, without blocking writers, so the
+    /// dump does not reflect a single point in time if the
+    /// `Db` is written to meanwhile. Returns the number of
+    /// entries written.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::{ConfigBuilder, Db, IVec};
+    /// let config = ConfigBuilder::new().temporary(true).build();
+    /// let db = Db::start(config).unwrap();
+    /// db.open_tree(b"users").unwrap().insert(b"alice", vec![1]).unwrap();
+    ///
+    /// let mut dump = vec![];
+    /// assert_eq!(db.export_to(&mut dump), Ok(1));
+    ///
+    /// let config = ConfigBuilder::new().temporary(true).build();
+    /// let restored = Db::start(config).unwrap();
+    /// assert_eq!(restored.import_from(&*dump), Ok(1));
+    ///
+    /// let users = restored.open_tree(b"users").unwrap();
+    /// assert_eq!(users.get(b"alice"), Ok(Some(IVec::from(vec![1]))));
+    /// ```
+    pub fn export_to<W: Write>(&self, writer: W) -> Result<u64> {
+        let writer = DumpWriter::new(writer)?;
+        self.export_with(writer, None)
+    }
+
+    /// Continue writing a dump that was interrupted. The
+    /// writer must append to the dump after truncating it
+    /// to `checkpoint.offset`. Entries written after the
+    /// checkpoint reflect the current state of the `Db`,
+    /// rather than the state at the time of the original
+    /// export, so the `Db` should not be written to in the
+    /// meantime if the combined dump must be consistent.
+    pub fn resume_export_to<W: Write>(
+        &self,
+        writer: W,
+        checkpoint: &DumpCheckpoint,
+    ) -> Result<u64> {
+        if checkpoint.complete {
+            return Ok(checkpoint.entries);
+        }
+        let writer = DumpWriter::resume(writer, checkpoint);
+        self.export_with(writer, Some(checkpoint))
+    }
+
+    fn export_with<W: Write>(
+        &self,
+        mut writer: DumpWriter<W>,
+        checkpoint: Option<&DumpCheckpoint>,
+    ) -> Result<u64> {
+        let mut trees: Vec<Arc<Tree>> =
+            self.tenants.read().values().cloned().collect();
+        trees.sort_by(|a, b| a.tree_id.cmp(&b.tree_id));
+
+        for tree in trees {
+            let name = &tree.tree_id;
+            let order = &tree.order;
+            let has_merge_operator = tree.merge_operator.read().is_some();
+
+            let resuming_collection =
+                checkpoint.map_or(false, |c| c.covers(name, None, order));
+
+            if !resuming_collection {
+                writer.write_collection(
+                    b"tree",
+                    name,
+                    has_merge_operator,
+                    order.name().map(|n| &**n),
+                    &tree.config(),
+                )?;
+            }
+
+            for kv_res in tree.iter() {
+                let (k, v) = kv_res?;
+                if checkpoint.map_or(false, |c| c.covers(name, Some(&k), order))
+                {
+                    continue;
+                }
+                writer.write_entry(&k, &v)?;
+            }
+        }
+
+        writer.finish()
+    }
+
+    /// Restore the collections in a dump, as written by
+    /// `export_to`, into this `Db`. Collections are created
+    /// with the comparator and `TreeConfig` that they were
+    /// dumped with, so the comparators that they use must
+    /// have been registered with `sled::register_comparator`.
+    /// Existing keys are overwritten, so an import that fails
+    /// partway may be retried from the start. Returns the
+    /// number of entries that were imported.
+    pub fn import_from<R: Read>(&self, reader: R) -> Result<u64> {
+        let reader = DumpReader::new(reader)?;
+
+        debug!(
+            "importing dump written by sled {} in format version {}",
+            reader.header().sled_version,
+            reader.header().format_version
+        );
+
+        let mut tree: Option<Arc<Tree>> = None;
+        let mut imported = 0;
+
+        for record in reader {
+            match record? {
+                DumpRecord::Collection {
+                    collection_type,
+                    name,
+                    has_merge_operator,
+                    comparator,
+                    config,
+                } => {
+                    if collection_type != b"tree" {
+                        return Err(Error::Unsupported(format!(
+                            "unknown collection type {:?} in dump",
+                            collection_type
+                        )));
+                    }
+
+                    let order = KeyOrder::named(comparator.as_deref())?;
+                    config.settings.validate()?;
+                    let next = self.open_collection(
+                        &name,
+                        order,
+                        Some(config.settings),
+                    )?;
+
+                    if has_merge_operator
+                        && next.merge_operator.read().is_none()
+                    {
+                        warn!(
+                            "collection {:?} had a merge operator set when \
+                             it was dumped, remember to set it again",
+                            name
+                        );
+                    }
+
+                    tree = Some(next);
+                }
+                DumpRecord::Entry { key, value } => {
+                    let tree = tree.as_ref().expect(
+                        "DumpReader should reject entries before a collection",
+                    );
+                    tree.insert(key, value)?;
+                    imported += 1;
+                }
+                DumpRecord::End { .. } => {}
+            }
+        }
+
+        Ok(imported)
+    }
+
+    /// Returns the tree that imports into the named collection
+    /// should be applied to, like `open_tree_ordered`, but
+    /// also accepting the default tree.
+    pub(crate) fn open_collection(
+        &self,
+        name: &[u8],
+        order: KeyOrder,
+        settings: Option<PageSettings>,
+    ) -> Result<Arc<Tree>> {
+        if name != DEFAULT_TREE_ID {
+            return self.open_tree_ordered(name, order, settings);
+        }
+
+        // the default tree is also tracked in the tenants
+        // map, but its writers only use `self.default`.
+        check_comparator(name, None, order.name().map(|n| &**n))?;
+        if let Some(settings) = settings {
+            self.default.set_settings(settings)?;
+        }
+        Ok(self.default.clone())
+    }
+
     /// Traverses all files and calculates their total physical
     /// size, then traverses all pages and calculates their
     /// total logical size, then divides the physical size
@@ -313,6 +1133,14 @@ impl Db {
     pub fn space_amplification(&self) -> Result<f64> {
         self.context.pagecache.space_amplification()
     }
+
+    /// Returns the number of page accesses that found the
+    /// page in the cache, and the number that did not, under
+    /// the configured `CachePolicy`.
+    #[doc(hidden)]
+    pub fn cache_hits_and_misses(&self) -> (u64, u64) {
+        self.context.pagecache.cache_hits_and_misses()
+    }
 }
 
 /// These types provide the information that allows an entire
@@ -0,0 +1,635 @@
+//! A streaming, checksummed file format for moving the
+//! contents of a `Db` between processes, machines, and
+//! incompatible versions of sled.
+//!
+//! A dump begins with a header:
+//!
+//! | bytes | contents                                      |
+//! |-------|-----------------------------------------------|
+//! | 8     | the magic bytes `SLEDDUMP`                    |
+//! | 4     | the dump format version                       |
+//! | 4     | the length of the sled version string         |
+//! | n     | the version of sled that wrote the dump       |
+//! | 4     | the crc32 of all of the above header bytes    |
+//!
+//! Which is followed by any number of records:
+//!
+//! | bytes | contents                                      |
+//! |-------|-----------------------------------------------|
+//! | 1     | the record kind                               |
+//! | 4     | the length of the payload                     |
+//! | n     | the payload                                   |
+//! | 4     | the crc32 of the kind, length and payload     |
+//!
+//! The payload of each record kind is:
+//!
+//! * `1`, a collection: the `u32` length of the collection
+//!   type, the collection type, the `u32` length of the
+//!   collection name, the collection name, a byte that is
+//!   `1` if a merge operator was set on the collection, a
+//!   byte that is `1` if its keys are ordered by a
+//!   comparator, in which case the `u32` length of the
+//!   comparator name and the name follow, and then the
+//!   overrides of the `Config` that it was opened with.
+//!   Each override is a byte that is `1` if it is set,
+//!   followed by its value: a byte for `use_compression`,
+//!   an `i32` for `compression_factor`, and a `u64` for
+//!   `page_consolidation_threshold`. The cache priority
+//!   comes last, as a byte that is `0` for `Low`, `1` for
+//!   `Normal` and `2` for `High`.
+//! * `2`, an entry in the most recent collection: the `u32`
+//!   length of the key, the key, and then the value, which
+//!   takes up the rest of the payload.
+//! * `3`, the end of the dump: the total number of entries
+//!   in the dump, as a `u64`.
+//!
+//! All integers are little-endian. Entries of a collection
+//! are written in key order, and collections are written in
+//! name order, which allows an interrupted dump to be
+//! continued with `Db::resume_export_to` from the
+//! `DumpCheckpoint` of the intact part of the file.
+use std::io::{self, Read, Write};
+
+use super::*;
+
+const MAGIC: &[u8; 8] = b"SLEDDUMP";
+
+/// The version of the dump format that is
+/// written by this version of sled.
+pub const DUMP_FORMAT_VERSION: u32 = 1;
+
+/// Version strings are short, so a longer length in a
+/// header means that it is corrupted.
+const MAX_VERSION_LEN: usize = 256;
+
+const COLLECTION: u8 = 1;
+const ENTRY: u8 = 2;
+const END: u8 = 3;
+
+/// The header of a dump file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpHeader {
+    /// The version of the dump format.
+    pub format_version: u32,
+    /// The version of sled that wrote the dump.
+    pub sled_version: String,
+}
+
+/// A single record read from a dump file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DumpRecord {
+    /// The start of a new collection. All following
+    /// entries belong to it.
+    Collection {
+        /// The type of the collection, such as `tree`.
+        collection_type: Vec<u8>,
+        /// The name of the collection.
+        name: Vec<u8>,
+        /// Whether the collection had a merge operator
+        /// set when the dump was written. Merge operators
+        /// are functions, so they can not be dumped, and
+        /// must be set again after restoring.
+        has_merge_operator: bool,
+        /// The name of the comparator that ordered the keys
+        /// of the collection, if any. It must be registered
+        /// before the collection can be restored.
+        comparator: Option<Vec<u8>>,
+        /// The overrides of the `Config` that applied to
+        /// the collection.
+        config: TreeConfig,
+    },
+    /// A key and value in the current collection.
+    Entry {
+        /// The key.
+        key: IVec,
+        /// The value.
+        value: IVec,
+    },
+    /// The end of a complete dump.
+    End {
+        /// The total number of entries in the dump.
+        entries: u64,
+    },
+}
+
+/// The position of the last intact record in a
+/// possibly-interrupted dump, used for resuming it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpCheckpoint {
+    /// The number of intact bytes at the start of the
+    /// dump. Anything after this should be truncated
+    /// before resuming the dump.
+    pub offset: u64,
+    /// Whether the dump was completely written.
+    pub complete: bool,
+    collection: Option<(Vec<u8>, Vec<u8>)>,
+    last_key: Option<IVec>,
+    pub(crate) entries: u64,
+}
+
+impl DumpCheckpoint {
+    /// Reads a dump until its end, or until the first
+    /// truncated or corrupted record, and returns the
+    /// checkpoint of the intact part.
+    pub fn scan<R: Read>(reader: R) -> Result<Self> {
+        let mut reader = DumpReader::new(reader)?;
+
+        loop {
+            match reader.read_record() {
+                Ok(Some(DumpRecord::End { .. })) | Ok(None) => break,
+                Ok(Some(_)) => continue,
+                Err(Error::Io(ref e))
+                    if e.kind() == io::ErrorKind::UnexpectedEof
+                        || e.kind() == io::ErrorKind::InvalidData =>
+                {
+                    debug!("stopping dump scan at torn record: {}", e);
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(reader.checkpoint())
+    }
+
+    /// Returns `true` if the provided entry was already
+    /// written before this checkpoint, given the order of
+    /// the keys in its collection.
+    pub(crate) fn covers(
+        &self,
+        collection: &[u8],
+        key: Option<&[u8]>,
+        order: &KeyOrder,
+    ) -> bool {
+        if self.complete {
+            return true;
+        }
+
+        let (collection_type, name) = if let Some(c) = &self.collection {
+            c
+        } else {
+            return false;
+        };
+
+        let collection_written: &[u8] = name;
+
+        if collection < collection_written {
+            return true;
+        }
+
+        if collection > collection_written || collection_type != b"tree" {
+            return false;
+        }
+
+        match (key, &self.last_key) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(key), Some(last_key)) => {
+                order.cmp(key, last_key) != std::cmp::Ordering::Greater
+            }
+        }
+    }
+}
+
+/// Writes dump records to an underlying writer.
+pub(crate) struct DumpWriter<W: Write> {
+    writer: W,
+    entries: u64,
+}
+
+impl<W: Write> DumpWriter<W> {
+    /// Begins a new dump by writing its header.
+    pub(crate) fn new(mut writer: W) -> Result<Self> {
+        let version = env!("CARGO_PKG_VERSION").as_bytes();
+
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&DUMP_FORMAT_VERSION.to_le_bytes());
+        header.extend_from_slice(&(version.len() as u32).to_le_bytes());
+        header.extend_from_slice(version);
+        let crc = pagecache::crc32(&header);
+        header.extend_from_slice(&crc.to_le_bytes());
+
+        writer.write_all(&header)?;
+
+        Ok(Self { writer, entries: 0 })
+    }
+
+    /// Continues writing a dump after the provided
+    /// checkpoint, without writing another header.
+    pub(crate) fn resume(writer: W, checkpoint: &DumpCheckpoint) -> Self {
+        Self {
+            writer,
+            entries: checkpoint.entries,
+        }
+    }
+
+    pub(crate) fn write_collection(
+        &mut self,
+        collection_type: &[u8],
+        name: &[u8],
+        has_merge_operator: bool,
+        comparator: Option<&[u8]>,
+        config: &TreeConfig,
+    ) -> Result<()> {
+        let mut payload =
+            Vec::with_capacity(collection_type.len() + name.len() + 32);
+        payload
+            .extend_from_slice(&(collection_type.len() as u32).to_le_bytes());
+        payload.extend_from_slice(collection_type);
+        payload.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        payload.extend_from_slice(name);
+        payload.push(has_merge_operator as u8);
+
+        if let Some(comparator) = comparator {
+            payload.push(1);
+            payload.extend_from_slice(&(comparator.len() as u32).to_le_bytes());
+            payload.extend_from_slice(comparator);
+        } else {
+            payload.push(0);
+        }
+
+        push_settings(&mut payload, &config.settings);
+
+        self.write_record(COLLECTION, &payload)
+    }
+
+    pub(crate) fn write_entry(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<()> {
+        let mut payload = Vec::with_capacity(key.len() + value.len() + 4);
+        payload.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        payload.extend_from_slice(key);
+        payload.extend_from_slice(value);
+
+        self.entries += 1;
+
+        self.write_record(ENTRY, &payload)
+    }
+
+    /// Marks the dump as complete, and returns the total
+    /// number of entries that it contains.
+    pub(crate) fn finish(mut self) -> Result<u64> {
+        let entries = self.entries;
+        self.write_record(END, &entries.to_le_bytes())?;
+        self.writer.flush()?;
+        Ok(entries)
+    }
+
+    fn write_record(&mut self, kind: u8, payload: &[u8]) -> Result<()> {
+        let mut buf = Vec::with_capacity(payload.len() + 9);
+        buf.push(kind);
+        buf.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        buf.extend_from_slice(payload);
+        let crc = pagecache::crc32(&buf);
+        buf.extend_from_slice(&crc.to_le_bytes());
+
+        self.writer.write_all(&buf)?;
+
+        Ok(())
+    }
+}
+
+/// Reads the header and records of a dump.
+///
+/// # Examples
+///
+/// ```
+/// use sled::{ConfigBuilder, Db, DumpReader, DumpRecord};
+/// let config = ConfigBuilder::new().temporary(true).build();
+/// let db = Db::start(config).unwrap();
+///
+/// db.insert(b"k", b"v".to_vec()).unwrap();
+///
+/// let mut dump = vec![];
+/// db.export_to(&mut dump).unwrap();
+///
+/// let reader = DumpReader::new(&*dump).unwrap();
+/// let entries = reader
+///     .filter(|record| match record {
+///         Ok(DumpRecord::Entry { .. }) => true,
+///         _ => false,
+///     })
+///     .count();
+/// assert_eq!(entries, 1);
+/// ```
+pub struct DumpReader<R: Read> {
+    reader: R,
+    header: DumpHeader,
+    offset: u64,
+    collection: Option<(Vec<u8>, Vec<u8>)>,
+    last_key: Option<IVec>,
+    entries: u64,
+    complete: bool,
+    failed: bool,
+}
+
+impl<R: Read> DumpReader<R> {
+    /// Reads and verifies the header of a dump.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut fixed = [0; 16];
+        reader.read_exact(&mut fixed)?;
+
+        if &fixed[..8] != MAGIC {
+            return Err(Error::Unsupported(
+                "the provided file is not a sled dump".to_owned(),
+            ));
+        }
+
+        let format_version = read_u32(&fixed[8..12]);
+        let version_len = read_u32(&fixed[12..16]) as usize;
+        if version_len > MAX_VERSION_LEN {
+            return Err(invalid_data("the dump header is corrupted"));
+        }
+
+        let version = read_len(&mut reader, version_len)?;
+
+        let mut crc = [0; 4];
+        reader.read_exact(&mut crc)?;
+
+        let mut header = fixed.to_vec();
+        header.extend_from_slice(&version);
+        if pagecache::crc32(&header) != read_u32(&crc) {
+            return Err(invalid_data("the dump header is corrupted"));
+        }
+
+        if format_version > DUMP_FORMAT_VERSION {
+            return Err(Error::Unsupported(format!(
+                "this dump uses format version {}, but this version \
+                 of sled only supports versions up to {}",
+                format_version, DUMP_FORMAT_VERSION
+            )));
+        }
+
+        let sled_version = String::from_utf8_lossy(&version).into_owned();
+
+        Ok(Self {
+            reader,
+            header: DumpHeader {
+                format_version,
+                sled_version,
+            },
+            offset: (header.len() + 4) as u64,
+            collection: None,
+            last_key: None,
+            entries: 0,
+            complete: false,
+            failed: false,
+        })
+    }
+
+    /// Returns the header of this dump.
+    pub fn header(&self) -> &DumpHeader {
+        &self.header
+    }
+
+    /// Returns the position after the last record
+    /// that was successfully read.
+    pub fn checkpoint(&self) -> DumpCheckpoint {
+        DumpCheckpoint {
+            offset: self.offset,
+            complete: self.complete,
+            collection: self.collection.clone(),
+            last_key: self.last_key.clone(),
+            entries: self.entries,
+        }
+    }
+
+    /// Reads the next record, returning `None` if the
+    /// underlying reader ended cleanly between records.
+    fn read_record(&mut self) -> Result<Option<DumpRecord>> {
+        if self.complete {
+            return Ok(None);
+        }
+
+        let mut prefix = [0; 5];
+        let mut read = 0;
+        while read < prefix.len() {
+            match self.reader.read(&mut prefix[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => {
+                    return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+                }
+                Ok(n) => read += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let kind = prefix[0];
+        let len = read_u32(&prefix[1..5]) as usize;
+
+        let mut buf = read_len(&mut self.reader, len + 4)?;
+        let crc = read_u32(&buf[len..]);
+        buf.truncate(len);
+
+        let mut checked = prefix.to_vec();
+        checked.extend_from_slice(&buf);
+        if pagecache::crc32(&checked) != crc {
+            return Err(invalid_data("dump record failed its checksum"));
+        }
+
+        let record = parse_record(kind, &buf)?;
+
+        match &record {
+            DumpRecord::Collection {
+                collection_type,
+                name,
+                ..
+            } => {
+                self.collection = Some((collection_type.clone(), name.clone()));
+                self.last_key = None;
+            }
+            DumpRecord::Entry { key, .. } => {
+                if self.collection.is_none() {
+                    return Err(invalid_data(
+                        "dump contains an entry before any collection",
+                    ));
+                }
+                self.last_key = Some(key.clone());
+                self.entries += 1;
+            }
+            DumpRecord::End { entries } => {
+                if *entries != self.entries {
+                    return Err(invalid_data(
+                        "dump ended with an unexpected number of entries",
+                    ));
+                }
+                self.complete = true;
+            }
+        }
+
+        self.offset += (len + 9) as u64;
+
+        Ok(Some(record))
+    }
+}
+
+impl<R: Read> Iterator for DumpReader<R> {
+    type Item = Result<DumpRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let ret = match self.read_record() {
+            Ok(Some(record)) => return Some(Ok(record)),
+            Ok(None) if self.complete => return None,
+            Ok(None) => Error::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the dump ended before it was complete",
+            )),
+            Err(e) => e,
+        };
+
+        // stop after the first error
+        self.failed = true;
+
+        Some(Err(ret))
+    }
+}
+
+fn parse_record(kind: u8, buf: &[u8]) -> Result<DumpRecord> {
+    match kind {
+        COLLECTION => {
+            let (collection_type, rest) = split_prefixed(buf)?;
+            let (name, rest) = split_prefixed(rest)?;
+            let (has_merge_operator, rest) =
+                if let Some(split) = rest.split_first() {
+                    split
+                } else {
+                    return Err(invalid_data("malformed collection record"));
+                };
+
+            let (comparator, rest) = match rest.split_first() {
+                Some((0, rest)) => (None, rest),
+                Some((1, rest)) => {
+                    let (comparator, rest) = split_prefixed(rest)?;
+                    (Some(comparator.to_vec()), rest)
+                }
+                _ => {
+                    return Err(invalid_data("malformed collection record"));
+                }
+            };
+            let settings = parse_settings(rest)?;
+            let config = TreeConfig { settings };
+
+            Ok(DumpRecord::Collection {
+                collection_type: collection_type.to_vec(),
+                name: name.to_vec(),
+                has_merge_operator: *has_merge_operator == 1,
+                comparator,
+                config,
+            })
+        }
+        ENTRY => {
+            let (key, value) = split_prefixed(buf)?;
+            Ok(DumpRecord::Entry {
+                key: IVec::from(key),
+                value: IVec::from(value),
+            })
+        }
+        END if buf.len() == 8 => {
+            let mut entries = [0; 8];
+            entries.copy_from_slice(buf);
+            Ok(DumpRecord::End {
+                entries: u64::from_le_bytes(entries),
+            })
+        }
+        other => {
+            Err(invalid_data(&format!("unknown dump record kind {}", other)))
+        }
+    }
+}
+
+fn push_settings(buf: &mut Vec<u8>, settings: &PageSettings) {
+    buf.push(settings.use_compression.is_some() as u8);
+    buf.push(settings.use_compression.unwrap_or(false) as u8);
+    buf.push(settings.compression_factor.is_some() as u8);
+    buf.extend_from_slice(
+        &settings.compression_factor.unwrap_or(0).to_le_bytes(),
+    );
+    buf.push(settings.page_consolidation_threshold.is_some() as u8);
+    buf.extend_from_slice(
+        &(settings.page_consolidation_threshold.unwrap_or(0) as u64)
+            .to_le_bytes(),
+    );
+    buf.push(match settings.cache_priority {
+        CachePriority::Low => 0,
+        CachePriority::Normal => 1,
+        CachePriority::High => 2,
+    });
+}
+
+/// The number of bytes that `push_settings` writes.
+const SETTINGS_LEN: usize = 2 + 5 + 9 + 1;
+
+fn parse_settings(buf: &[u8]) -> Result<PageSettings> {
+    if buf.len() != SETTINGS_LEN {
+        return Err(invalid_data("malformed collection record"));
+    }
+
+    let set = |at: usize| buf[at] == 1;
+
+    let mut compression_factor = [0; 4];
+    compression_factor.copy_from_slice(&buf[3..7]);
+    let mut page_consolidation_threshold = [0; 8];
+    page_consolidation_threshold.copy_from_slice(&buf[8..16]);
+
+    let cache_priority = match buf[16] {
+        0 => CachePriority::Low,
+        1 => CachePriority::Normal,
+        2 => CachePriority::High,
+        _ => return Err(invalid_data("malformed collection record")),
+    };
+
+    Ok(PageSettings {
+        use_compression: if set(0) { Some(buf[1] == 1) } else { None },
+        compression_factor: if set(2) {
+            Some(i32::from_le_bytes(compression_factor))
+        } else {
+            None
+        },
+        page_consolidation_threshold: if set(7) {
+            Some(u64::from_le_bytes(page_consolidation_threshold) as usize)
+        } else {
+            None
+        },
+        cache_priority,
+    })
+}
+
+fn split_prefixed(buf: &[u8]) -> Result<(&[u8], &[u8])> {
+    if buf.len() < 4 {
+        return Err(invalid_data("truncated length prefix in dump record"));
+    }
+    let len = read_u32(&buf[..4]) as usize;
+    if buf.len() - 4 < len {
+        return Err(invalid_data("length prefix exceeds dump record"));
+    }
+    Ok((&buf[4..4 + len], &buf[4 + len..]))
+}
+
+/// Reads exactly `len` bytes. The buffer only grows as data
+/// arrives, so a corrupted length can not make us allocate
+/// much more memory than the reader actually holds.
+fn read_len<R: Read>(reader: &mut R, len: usize) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    reader.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() < len {
+        return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+    }
+    Ok(buf)
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    let mut arr = [0; 4];
+    arr.copy_from_slice(buf);
+    u32::from_le_bytes(arr)
+}
+
+fn invalid_data(msg: &str) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
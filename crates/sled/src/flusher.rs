@@ -2,7 +2,7 @@ use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
-use parking_lot::{Condvar, Mutex};
+use parking_lot::{Condvar, Mutex, MutexGuard};
 
 use super::*;
 
@@ -39,11 +39,13 @@ pub(crate) struct Flusher {
 }
 
 impl Flusher {
-    /// Spawns a thread that periodically calls `callback` until dropped.
+    /// Spawns a thread that periodically flushes the `PageCache`
+    /// and removes expired keys using `reaper` until dropped.
     pub(crate) fn new(
         name: String,
         pagecache: Arc<PageCache<Frag>>,
         flush_every_ms: u64,
+        reaper: ttl::Reaper,
     ) -> Self {
         #[allow(clippy::mutex_atomic)] // mutex used in CondVar below
         let shutdown = Arc::new(Mutex::new(ShutdownState::Running));
@@ -54,7 +56,7 @@ impl Flusher {
             .spawn({
                 let shutdown = shutdown.clone();
                 let sc = sc.clone();
-                move || run(shutdown, sc, pagecache, flush_every_ms)
+                move || run(shutdown, sc, pagecache, flush_every_ms, reaper)
             })
             .unwrap();
 
@@ -71,12 +73,26 @@ fn run(
     sc: Arc<Condvar>,
     pagecache: Arc<PageCache<Frag>>,
     flush_every_ms: u64,
+    reaper: ttl::Reaper,
 ) {
     let flush_every = Duration::from_millis(flush_every_ms);
     let mut shutdown = shutdown.lock();
     let mut wrote_data = false;
     while shutdown.is_running() || wrote_data {
         let before = std::time::Instant::now();
+
+        if shutdown.is_running() {
+            // reaping writes to trees and may wait for their
+            // locks, so it does not hold up a shutdown.
+            match MutexGuard::unlocked(&mut shutdown, || reaper.reap()) {
+                Ok(0) => {}
+                Ok(reaped) => debug!("reaped {} expired keys", reaped),
+                Err(e) => {
+                    error!("failed to reap expired keys: {}", e);
+                }
+            }
+        }
+
         match pagecache.flush() {
             Ok(0) => {
                 wrote_data = false;
@@ -4,9 +4,6 @@ use super::*;
 // Merged
 // LeftMerge(head: Raw, rhs: PageId, hi: Bound)
 // ParentMerge(lhs: PageId, rhs: PageId)
-// TxBegin(TxID), // in-mem
-// TxCommit(TxID), // in-mem
-// TxAbort(TxID), // in-mem
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub(crate) enum Frag {
@@ -16,4 +13,10 @@ pub(crate) enum Frag {
     ParentMergeIntention(PageId),
     ParentMergeConfirm,
     ChildMergeCap,
+    /// A `Set` in a tree with a custom order, along with
+    /// the position of the key in the node.
+    SetAt(IVec, IVec, usize),
+    /// A `Del` in a tree with a custom order, along with
+    /// the position of the key in the node.
+    DelAt(IVec, usize),
 }
@@ -0,0 +1,367 @@
+//! Secondary indexes, maintained by `Tree::add_index`.
+//!
+//! Each index lives in an internal tree with the id
+//!
+//! `__sled__index ++ len(tree id) ++ tree id ++ index name`
+//!
+//! where the length is a big-endian u64, so that all indexes
+//! of a tree can be found in the `Meta`. For every index key
+//! that the extractor returns for a key and value of the
+//! indexed tree, the index tree stores
+//!
+//! `len(index key) ++ index key ++ primary key -> []`
+//!
+//! so that the primary keys of an index key are found with a
+//! prefix scan. Index entries are written while holding the
+//! write side of the indexed tree's `concurrency_control`,
+//! in the same pinned log batch as the write they follow,
+//! so they are recovered atomically with it.
+//!
+//! The empty key marks an index whose backfill completed. An
+//! index whose backfill was interrupted by a crash is rebuilt
+//! from scratch the next time it is added. Extractors are
+//! plain functions that can not be persisted, so a tree
+//! refuses writes while it has an index that was not added
+//! again in this process, because that index would silently
+//! fall out of date.
+use std::sync::Arc;
+
+use pagecache::Meta;
+
+use super::*;
+
+/// Returns the index keys of a key and its value, for an index
+/// added with `Tree::add_index`.
+pub type IndexExtractor = fn(key: &[u8], value: &[u8]) -> Vec<Vec<u8>>;
+
+/// Marks an index tree whose backfill completed. Entries
+/// are never empty, because they start with a length.
+const BACKFILLED: &[u8] = &[];
+
+fn push_field(buf: &mut Vec<u8>, field: &[u8]) {
+    buf.extend_from_slice(&(field.len() as u64).to_be_bytes());
+    buf.extend_from_slice(field);
+}
+
+/// The prefix of the ids of all index trees of a tree.
+fn tree_prefix(tree_id: &[u8]) -> Vec<u8> {
+    let mut prefix = INDEX_TREE_PREFIX.to_vec();
+    push_field(&mut prefix, tree_id);
+    prefix
+}
+
+fn index_tree_id(tree_id: &[u8], name: &[u8]) -> Vec<u8> {
+    let mut id = tree_prefix(tree_id);
+    id.extend_from_slice(name);
+    id
+}
+
+fn entry(index_key: &[u8], primary_key: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(8 + index_key.len() + primary_key.len());
+    push_field(&mut entry, index_key);
+    entry.extend_from_slice(primary_key);
+    entry
+}
+
+/// Returns `true` for the ids of index trees, which are
+/// opened by the tree they index rather than by the `Db`.
+pub(crate) fn is_index_tree(id: &[u8]) -> bool {
+    id.starts_with(INDEX_TREE_PREFIX)
+}
+
+struct Active {
+    name: Vec<u8>,
+    extractor: IndexExtractor,
+    tree: Arc<Tree>,
+}
+
+/// The secondary indexes of a `Tree`.
+#[derive(Default)]
+pub(crate) struct Indexes {
+    active: Vec<Active>,
+    /// The names of persisted indexes that were not
+    /// added in this process yet.
+    missing: Vec<Vec<u8>>,
+}
+
+impl Indexes {
+    /// Finds the persisted indexes of a tree, which must all
+    /// be added again before the tree may be written to.
+    pub(crate) fn load(meta: &Meta, tree_id: &[u8]) -> Indexes {
+        let prefix = tree_prefix(tree_id);
+        let missing = meta
+            .tenants()
+            .into_iter()
+            .filter(|(id, _)| id.starts_with(&prefix))
+            .map(|(id, _)| id[prefix.len()..].to_vec())
+            .collect();
+        Indexes {
+            active: vec![],
+            missing,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.active.is_empty() && self.missing.is_empty()
+    }
+
+    /// Fails if writing to the tree would leave an index
+    /// that was not added in this process out of date.
+    pub(crate) fn check_writable(&self, tree_id: &[u8]) -> Result<()> {
+        match self.missing.first() {
+            None => Ok(()),
+            Some(name) => Err(Error::Unsupported(format!(
+                "tree {:?} has the index {:?}, which must be added with \
+                 `Tree::add_index` or removed with `Tree::drop_index` \
+                 before the tree is written to",
+                String::from_utf8_lossy(tree_id),
+                String::from_utf8_lossy(name),
+            ))),
+        }
+    }
+
+    /// Brings every index up to date with a write that replaced
+    /// `old` with `new`. Must be called while holding the write
+    /// side of the indexed tree's `concurrency_control`, and
+    /// within a pinned log batch.
+    pub(crate) fn update(
+        &self,
+        key: &[u8],
+        old: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<()> {
+        for index in &self.active {
+            let old_keys =
+                old.map_or(vec![], |old| (index.extractor)(key, old));
+            let new_keys =
+                new.map_or(vec![], |new| (index.extractor)(key, new));
+
+            for index_key in &old_keys {
+                if !new_keys.contains(index_key) {
+                    index.tree.remove_inner(entry(index_key, key))?;
+                }
+            }
+            for index_key in &new_keys {
+                if !old_keys.contains(index_key) {
+                    index.tree.insert_inner(entry(index_key, key), vec![])?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A secondary index of a `Tree`, returned by `Tree::add_index`.
+/// Cloning it is cheap.
+#[derive(Clone)]
+pub struct Index {
+    name: Vec<u8>,
+    primary: Tree,
+    tree: Arc<Tree>,
+}
+
+impl Index {
+    /// Returns the name of the index.
+    pub fn name(&self) -> &[u8] {
+        &self.name
+    }
+
+    /// Returns the keys of the indexed `Tree` that `index_key`
+    /// was extracted from, in byte order.
+    pub fn get<K: AsRef<[u8]>>(&self, index_key: K) -> Result<Vec<IVec>> {
+        let _cc = self.primary.concurrency_control.read();
+        self.primary_keys(index_key.as_ref())
+    }
+
+    /// Returns the keys and values of the indexed `Tree` that
+    /// `index_key` was extracted from, in byte order of their
+    /// keys. The returned records reflect a single point in
+    /// time, because writes to indexed trees are exclusive.
+    pub fn get_records<K: AsRef<[u8]>>(
+        &self,
+        index_key: K,
+    ) -> Result<Vec<(IVec, IVec)>> {
+        let _cc = self.primary.concurrency_control.read();
+        let mut records = vec![];
+        for key in self.primary_keys(index_key.as_ref())? {
+            if let Some(value) = self.primary.get_inner(&key)? {
+                records.push((key, value));
+            }
+        }
+        Ok(records)
+    }
+
+    /// Must be called while holding the read side of the
+    /// indexed tree's `concurrency_control`.
+    fn primary_keys(&self, index_key: &[u8]) -> Result<Vec<IVec>> {
+        let prefix = entry(index_key, &[]);
+        let mut keys = vec![];
+        for entry in self.tree.scan_prefix(&prefix).keys() {
+            let key = IVec::from(&entry?[prefix.len()..]);
+            if !self.primary.is_expired(&key)? {
+                keys.push(key);
+            }
+        }
+        Ok(keys)
+    }
+}
+
+impl std::fmt::Debug for Index {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::result::Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "Index({:?} of tree {:?})",
+            String::from_utf8_lossy(&self.name),
+            String::from_utf8_lossy(&self.primary.tree_id)
+        )
+    }
+}
+
+/// Opens the tree of an index, creating it if needed.
+fn open(tree: &Tree, name: &[u8]) -> Result<Tree> {
+    let guard = pin();
+    meta::open_tree(
+        tree.context.clone(),
+        index_tree_id(&tree.tree_id, name),
+        TreeOptions::default(),
+        &guard,
+    )
+}
+
+/// Adds an index to `tree`, backfilling it if it is new.
+pub(crate) fn add(
+    tree: &Tree,
+    name: &[u8],
+    extractor: IndexExtractor,
+) -> Result<Index> {
+    let _cc = tree.concurrency_control.write();
+    let mut indexes = tree.indexes.write();
+
+    if let Some(active) = indexes.active.iter().find(|a| a.name == name) {
+        if active.extractor as usize != extractor as usize {
+            return Err(Error::Unsupported(format!(
+                "a different extractor was already added as the index {:?}",
+                String::from_utf8_lossy(name)
+            )));
+        }
+        return Ok(Index {
+            name: name.to_vec(),
+            primary: tree.clone(),
+            tree: active.tree.clone(),
+        });
+    }
+
+    let index_tree = Arc::new(open(tree, name)?);
+
+    if index_tree.get_inner(BACKFILLED)?.is_none() {
+        backfill(tree, &index_tree, extractor)?;
+    }
+
+    indexes.missing.retain(|missing| missing != name);
+    indexes.active.push(Active {
+        name: name.to_vec(),
+        extractor,
+        tree: index_tree.clone(),
+    });
+
+    Ok(Index {
+        name: name.to_vec(),
+        primary: tree.clone(),
+        tree: index_tree,
+    })
+}
+
+/// Rebuilds an index from every key of `tree`. Must be called
+/// while holding the write side of its `concurrency_control`.
+fn backfill(
+    tree: &Tree,
+    index_tree: &Tree,
+    extractor: IndexExtractor,
+) -> Result<()> {
+    debug!(
+        "backfilling index tree {:?}",
+        String::from_utf8_lossy(&index_tree.tree_id)
+    );
+
+    // start over if a previous backfill was interrupted
+    index_tree.clear()?;
+
+    let mut cursor = vec![];
+    loop {
+        let guard = pin();
+        let view = tree.node_for_key(&cursor, &guard)?;
+
+        let items = view
+            .data
+            .leaf_ref()
+            .expect("node_for_key should always return a leaf");
+        for (k, v) in items {
+            let key = prefix_decode(&view.lo, k);
+            for index_key in extractor(&key, v) {
+                index_tree.insert_inner(entry(&index_key, &key), vec![])?;
+            }
+        }
+
+        if view.hi.is_empty() {
+            break;
+        }
+        cursor = view.hi.to_vec();
+    }
+
+    index_tree.insert_inner(BACKFILLED, vec![])?;
+
+    Ok(())
+}
+
+/// Removes an index of `tree`, returning `false` if it had
+/// no index with that name.
+pub(crate) fn remove(tree: &Tree, name: &[u8]) -> Result<bool> {
+    let _cc = tree.concurrency_control.write();
+    let mut indexes = tree.indexes.write();
+
+    indexes.active.retain(|active| active.name != name);
+    indexes.missing.retain(|missing| missing != name);
+
+    let index_tree_id = index_tree_id(&tree.tree_id, name);
+    remove_index_trees(tree, |id| id == &index_tree_id[..])
+}
+
+/// Removes every index of a tree that is being dropped.
+pub(crate) fn remove_tree(tree: &Tree) -> Result<()> {
+    let prefix = tree_prefix(&tree.tree_id);
+    remove_index_trees(tree, |id| id.starts_with(&prefix))?;
+    Ok(())
+}
+
+fn remove_index_trees<F>(tree: &Tree, matches: F) -> Result<bool>
+where
+    F: Fn(&[u8]) -> bool,
+{
+    let guard = pin();
+    let ids: Vec<Vec<u8>> = tree
+        .context
+        .pagecache
+        .meta(&guard)?
+        .tenants()
+        .into_iter()
+        .map(|(id, _)| id)
+        .filter(|id| matches(id))
+        .collect();
+
+    for id in &ids {
+        trace!("dropping index tree {:?}", id);
+        let index_tree = meta::open_tree(
+            tree.context.clone(),
+            id.clone(),
+            TreeOptions::default(),
+            &guard,
+        )?;
+        let leftmost_chain = index_tree.detach(&guard)?;
+        index_tree.gc_pages(leftmost_chain)?;
+    }
+
+    Ok(!ids.is_empty())
+}
@@ -1,4 +1,8 @@
-use std::ops::Bound;
+use std::{
+    borrow::Cow,
+    cmp::Ordering::{self, Greater, Less},
+    ops::Bound,
+};
 
 use pagecache::{Guard, Measure, M};
 
@@ -10,21 +14,6 @@ const MAX_LOOPS: usize = usize::max_value();
 #[cfg(not(feature = "lock_free_delays"))]
 const MAX_LOOPS: usize = 1_000_000;
 
-fn possible_predecessor(s: &[u8]) -> Option<Vec<u8>> {
-    let mut ret = s.to_vec();
-    match ret.pop() {
-        None => None,
-        Some(i) if i == 0 => Some(ret),
-        Some(i) => {
-            ret.push(i - 1);
-            for _ in 0..4 {
-                ret.push(255);
-            }
-            Some(ret)
-        }
-    }
-}
-
 macro_rules! iter_try {
     ($e:expr) => {
         match $e {
@@ -39,12 +28,46 @@ pub struct Iter<'a> {
     pub(super) tree: &'a Tree,
     pub(super) hi: Bound<IVec>,
     pub(super) lo: Bound<IVec>,
-    pub(super) cached_node: Option<(PageId, &'a Node)>,
+    // owned if it was read by a `no_cache` scan, so that it
+    // is freed as soon as the scan moves on to the next leaf
+    pub(super) cached_node: Option<(PageId, Cow<'a, Node>)>,
     pub(super) guard: Guard,
     pub(super) going_forward: bool,
+    pub(super) cache: bool,
+    // the leaf that `any_due` was last computed for
+    pub(super) expiry_leaf: Option<PageId>,
+    // whether any deadline in the `Db` had passed without
+    // having been reaped when `expiry_leaf` was reached
+    pub(super) any_due: bool,
 }
 
 impl<'a> Iter<'a> {
+    /// Reads the pages that have been paged out from disk
+    /// without adding them to the page cache, so that a large
+    /// scan does not page out the pages that other readers keep
+    /// using. The pages that are already cached are still used.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::{ConfigBuilder, Db, IVec};
+    /// let config = ConfigBuilder::new().temporary(true).build();
+    /// let t = Db::start(config).unwrap();
+    ///
+    /// t.insert(&[1], vec![10]).unwrap();
+    /// t.insert(&[2], vec![20]).unwrap();
+    ///
+    /// let start: &[u8] = &[1];
+    /// let mut r = t.range(start..).no_cache();
+    /// assert_eq!(r.next().unwrap(), Ok((IVec::from(&[1]), IVec::from(&[10]))));
+    /// assert_eq!(r.next().unwrap(), Ok((IVec::from(&[2]), IVec::from(&[20]))));
+    /// assert_eq!(r.next(), None);
+    /// ```
+    pub fn no_cache(mut self) -> Self {
+        self.cache = false;
+        self
+    }
+
     /// Iterate over the keys of this Tree
     pub fn keys(self) -> impl 'a + DoubleEndedIterator<Item = Result<IVec>> {
         self.map(|r| r.map(|(k, _v)| k))
@@ -61,12 +84,37 @@ impl<'a> Iter<'a> {
             | (Bound::Included(ref start), Bound::Excluded(ref end))
             | (Bound::Excluded(ref start), Bound::Included(ref end))
             | (Bound::Excluded(ref start), Bound::Excluded(ref end)) => {
-                start > end
+                self.tree.order.cmp(start, end) == Ordering::Greater
             }
             _ => false,
         }
     }
 
+    /// Returns `true` if the key that was just returned from
+    /// the current leaf has a deadline that has passed. The
+    /// deadlines of individual keys are only looked up if some
+    /// deadline had passed without having been reaped when the
+    /// scan reached this leaf, which is decided once per leaf.
+    fn is_expired(&mut self, key: &[u8]) -> Result<bool> {
+        let index = if let Some(index) = self.tree.expirations() {
+            index
+        } else {
+            return Ok(false);
+        };
+
+        let leaf = self.cached_node.as_ref().map(|(pid, _)| *pid);
+        if leaf.is_none() || leaf != self.expiry_leaf {
+            self.expiry_leaf = leaf;
+            self.any_due = ttl::any_due(index)?;
+        }
+
+        if self.any_due {
+            self.tree.is_expired(key)
+        } else {
+            Ok(false)
+        }
+    }
+
     fn low_key(&self) -> &[u8] {
         match self.lo {
             Bound::Unbounded => &[],
@@ -74,11 +122,12 @@ impl<'a> Iter<'a> {
         }
     }
 
-    fn high_key(&self) -> &[u8] {
-        const MAX_KEY: &[u8] = &[255; 1024 * 1024];
+    fn high_seek(&self) -> Seek<'_> {
         match self.hi {
-            Bound::Unbounded => MAX_KEY,
-            Bound::Excluded(ref hi) | Bound::Included(ref hi) => hi.as_ref(),
+            Bound::Unbounded => Seek::Last,
+            Bound::Excluded(ref hi) | Bound::Included(ref hi) => {
+                Seek::Key(hi.as_ref())
+            }
         }
     }
 }
@@ -87,8 +136,36 @@ impl<'a> Iterator for Iter<'a> {
     type Item = Result<(IVec, IVec)>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, value) = iter_try!(self.next_inner()?);
+            if !iter_try!(self.is_expired(&key)) {
+                return Some(Ok((key, value)));
+            }
+        }
+    }
+
+    fn last(mut self) -> Option<Self::Item> {
+        self.next_back()
+    }
+}
+
+impl<'a> DoubleEndedIterator for Iter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, value) = iter_try!(self.next_back_inner()?);
+            if !iter_try!(self.is_expired(&key)) {
+                return Some(Ok((key, value)));
+            }
+        }
+    }
+}
+
+impl<'a> Iter<'a> {
+    /// Returns the next pair, including keys whose
+    /// time-to-live has already elapsed.
+    fn next_inner(&mut self) -> Option<Result<(IVec, IVec)>> {
         let _measure = Measure::new(&M.tree_scan);
-        let _ = self.tree.concurrency_control.read();
+        let _cc = self.tree.concurrency_control.read();
 
         // TODO evil lifetime hack, please kill
         let g_ptr = &self.guard as *const Guard;
@@ -98,9 +175,10 @@ impl<'a> Iterator for Iter<'a> {
             match (self.going_forward, self.cached_node.take()) {
                 (true, Some((pid, node))) => (pid, node),
                 _ => {
+                    let seek = Seek::Key(self.low_key());
                     let view = iter_try!(self
                         .tree
-                        .node_for_key(self.low_key(), guard));
+                        .seek_node_with(seek, self.cache, guard));
                     (view.pid, view.node)
                 }
             };
@@ -110,41 +188,58 @@ impl<'a> Iterator for Iter<'a> {
                 return None;
             }
 
-            if !node.contains_upper_bound(&self.lo) {
+            if !node.contains_upper_bound(&self.lo, &self.tree.order) {
                 // view too low (maybe merged, maybe exhausted?)
                 let next_pid = node.next?;
                 assert_ne!(pid, next_pid);
-                let view = if let Some(view) =
-                    iter_try!(self.tree.view_for_pid(next_pid, guard))
+                let view = if let Some(view) = iter_try!(self
+                    .tree
+                    .view_for_pid_with(next_pid, self.cache, guard))
                 {
                     view
                 } else {
-                    iter_try!(self.tree.node_for_key(self.low_key(), guard))
+                    iter_try!(self.tree.seek_node_with(
+                        Seek::Key(self.low_key()),
+                        self.cache,
+                        guard
+                    ))
                 };
 
                 pid = view.pid;
                 node = view.node;
                 continue;
-            } else if !node.contains_lower_bound(&self.lo, true) {
+            } else if !node.contains_lower_bound(
+                &self.lo,
+                true,
+                &self.tree.order,
+            ) {
                 // view too high (maybe split, maybe exhausted?)
-                let seek_key = possible_predecessor(&node.lo)?;
-                let view = iter_try!(self.tree.node_for_key(seek_key, guard));
+                if node.lo.is_empty() {
+                    return None;
+                }
+                let seek = Seek::Before(&node.lo);
+                let view = iter_try!(self
+                    .tree
+                    .seek_node_with(seek, self.cache, guard));
                 pid = view.pid;
                 node = view.node;
                 continue;
             }
 
-            if let Some((key, value)) = node.successor(&self.lo) {
+            if let Some((key, value)) =
+                node.successor(&self.lo, &self.tree.order)
+            {
+                let order = &self.tree.order;
                 self.lo = Bound::Excluded(key.clone());
                 self.cached_node = Some((pid, node));
                 self.going_forward = true;
 
                 match self.hi {
                     Bound::Unbounded => return Some(Ok((key, value))),
-                    Bound::Included(ref h) if *h >= key => {
+                    Bound::Included(ref h) if order.cmp(h, &key) != Less => {
                         return Some(Ok((key, value)));
                     }
-                    Bound::Excluded(ref h) if *h > key => {
+                    Bound::Excluded(ref h) if order.cmp(h, &key) == Greater => {
                         return Some(Ok((key, value)));
                     }
                     _ => return None,
@@ -163,15 +258,11 @@ impl<'a> Iterator for Iter<'a> {
         );
     }
 
-    fn last(mut self) -> Option<Self::Item> {
-        self.next_back()
-    }
-}
-
-impl<'a> DoubleEndedIterator for Iter<'a> {
-    fn next_back(&mut self) -> Option<Self::Item> {
+    /// Returns the previous pair, including keys whose
+    /// time-to-live has already elapsed.
+    fn next_back_inner(&mut self) -> Option<Result<(IVec, IVec)>> {
         let _measure = Measure::new(&M.tree_reverse_scan);
-        let _ = self.tree.concurrency_control.read();
+        let _cc = self.tree.concurrency_control.read();
 
         // TODO evil lifetime hack, please kill
         let g_ptr = &self.guard as *const Guard;
@@ -181,9 +272,11 @@ impl<'a> DoubleEndedIterator for Iter<'a> {
             match (self.going_forward, self.cached_node.take()) {
                 (false, Some((pid, node))) => (pid, node),
                 _ => {
-                    let view = iter_try!(self
-                        .tree
-                        .node_for_key(self.high_key(), guard));
+                    let view = iter_try!(self.tree.seek_node_with(
+                        self.high_seek(),
+                        self.cache,
+                        guard
+                    ));
                     (view.pid, view.node)
                 }
             };
@@ -193,41 +286,58 @@ impl<'a> DoubleEndedIterator for Iter<'a> {
                 return None;
             }
 
-            if !node.contains_upper_bound(&self.hi) {
+            if !node.contains_upper_bound(&self.hi, &self.tree.order) {
                 // node too low (maybe merged, maybe exhausted?)
                 let next_pid = node.next?;
                 assert_ne!(pid, next_pid);
-                let view = if let Some(view) =
-                    iter_try!(self.tree.view_for_pid(next_pid, guard))
+                let view = if let Some(view) = iter_try!(self
+                    .tree
+                    .view_for_pid_with(next_pid, self.cache, guard))
                 {
                     view
                 } else {
-                    iter_try!(self.tree.node_for_key(self.high_key(), guard))
+                    iter_try!(self.tree.seek_node_with(
+                        self.high_seek(),
+                        self.cache,
+                        guard
+                    ))
                 };
 
                 pid = view.pid;
                 node = view.node;
                 continue;
-            } else if !node.contains_lower_bound(&self.hi, false) {
+            } else if !node.contains_lower_bound(
+                &self.hi,
+                false,
+                &self.tree.order,
+            ) {
                 // view too high (maybe split, maybe exhausted?)
-                let seek_key = possible_predecessor(&node.lo)?;
-                let view = iter_try!(self.tree.node_for_key(seek_key, guard));
+                if node.lo.is_empty() {
+                    return None;
+                }
+                let seek = Seek::Before(&node.lo);
+                let view = iter_try!(self
+                    .tree
+                    .seek_node_with(seek, self.cache, guard));
                 pid = view.pid;
                 node = view.node;
                 continue;
             }
 
-            if let Some((key, value)) = node.predecessor(&self.hi) {
+            if let Some((key, value)) =
+                node.predecessor(&self.hi, &self.tree.order)
+            {
+                let order = &self.tree.order;
                 self.hi = Bound::Excluded(key.clone());
                 self.cached_node = Some((pid, node));
                 self.going_forward = false;
 
                 match self.lo {
                     Bound::Unbounded => return Some(Ok((key, value))),
-                    Bound::Included(ref l) if *l <= key => {
+                    Bound::Included(ref l) if order.cmp(l, &key) != Greater => {
                         return Some(Ok((key, value)));
                     }
-                    Bound::Excluded(ref l) if *l < key => {
+                    Bound::Excluded(ref l) if order.cmp(l, &key) == Less => {
                         return Some(Ok((key, value)));
                     }
                     _ => return None,
@@ -246,27 +356,3 @@ impl<'a> DoubleEndedIterator for Iter<'a> {
         );
     }
 }
-
-#[test]
-fn test_possible_predecessor() {
-    assert_eq!(possible_predecessor(b""), None);
-    assert_eq!(possible_predecessor(&[0]), Some(vec![]));
-    assert_eq!(possible_predecessor(&[0, 0]), Some(vec![0]));
-    assert_eq!(
-        possible_predecessor(&[0, 1]),
-        Some(vec![0, 0, 255, 255, 255, 255])
-    );
-    assert_eq!(
-        possible_predecessor(&[0, 2]),
-        Some(vec![0, 1, 255, 255, 255, 255])
-    );
-    assert_eq!(possible_predecessor(&[1, 0]), Some(vec![1]));
-    assert_eq!(
-        possible_predecessor(&[1, 1]),
-        Some(vec![1, 0, 255, 255, 255, 255])
-    );
-    assert_eq!(
-        possible_predecessor(&[155]),
-        Some(vec![154, 255, 255, 255, 255])
-    );
-}
@@ -3,9 +3,10 @@
 //! # Examples
 //!
 //! ```
-//! use sled::{Db, IVec};
+//! use sled::{ConfigBuilder, Db, IVec};
 //!
-//! let t = Db::open("my_db").unwrap();
+//! let config = ConfigBuilder::new().temporary(true).build();
+//! let t = Db::start(config).unwrap();
 //! t.insert(b"yo!", b"v1".to_vec());
 //! assert_eq!(t.get(b"yo!"), Ok(Some(IVec::from(b"v1"))));
 //!
@@ -36,32 +37,73 @@
 
 mod batch;
 mod binary_search;
+mod bulk_load;
+mod changes;
+mod codec;
 mod context;
 mod data;
 mod db;
+mod dump;
 mod flusher;
 mod frag;
+mod indexes;
 mod iter;
 mod ivec;
 mod materializer;
 mod meta;
 mod node;
+mod order;
 mod prefix;
+mod repair;
+mod snapshot;
+mod stats;
 mod subscription;
+mod transaction;
 mod tree;
+mod tree_config;
+mod ttl;
+mod typed;
 
 const DEFAULT_TREE_ID: &[u8] = b"__sled__default";
 
+const EXPIRATIONS_TREE_ID: &[u8] = b"__sled__expirations";
+
+const CHANGES_TREE_ID: &[u8] = b"__sled__changes";
+
+const STATS_TREE_ID: &[u8] = b"__sled__stats";
+
+const INDEX_TREE_PREFIX: &[u8] = b"__sled__index";
+
 pub use {
     self::{
         batch::Batch,
+        changes::{Change, Changes},
+        codec::{Codec, OrderedCodec},
         db::Db,
+        dump::{
+            DumpCheckpoint, DumpHeader, DumpReader, DumpRecord,
+            DUMP_FORMAT_VERSION,
+        },
+        indexes::{Index, IndexExtractor},
         iter::Iter,
         ivec::IVec,
-        subscription::{Event, Subscriber},
+        order::{register_comparator, Comparator},
+        repair::{LostRange, RepairReport},
+        snapshot::{Snapshot, SnapshotIter},
+        stats::TreeStats,
+        subscription::{Backpressure, Event, Subscriber},
+        transaction::{
+            TransactionError, TransactionResult, Transactional,
+            TransactionalTree,
+        },
         tree::Tree,
+        tree_config::TreeConfig,
+        typed::{TypedEvent, TypedIter, TypedSubscriber, TypedTree},
+    },
+    pagecache::{
+        CachePolicy, CachePriority, Config, ConfigBuilder, EncryptionKey,
+        Error, IntegrityReport, IoMode, Result,
     },
-    pagecache::{Config, ConfigBuilder, Error, Result},
 };
 
 use {
@@ -69,18 +111,23 @@ use {
         binary_search::binary_search_lub,
         context::Context,
         data::Data,
+        dump::DumpWriter,
         frag::Frag,
+        indexes::Indexes,
+        meta::TreeOptions,
         node::Node,
+        order::{check_comparator, KeyOrder, Seek},
         prefix::{
             prefix_cmp, prefix_cmp_encoded, prefix_decode, prefix_encode,
-            prefix_reencode,
+            prefix_encode_unordered, prefix_reencode,
         },
+        stats::Stats,
         subscription::Subscriptions,
     },
-    log::{debug, error, trace},
+    log::{debug, error, trace, warn},
     pagecache::{
         debug_delay, pin, Materializer, Measure, PageCache, PageId,
-        RecoveryGuard, M,
+        PageSettings, RecoveryGuard, M,
     },
     serde::{Deserialize, Serialize},
 };
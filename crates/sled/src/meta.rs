@@ -1,4 +1,7 @@
-use std::sync::{atomic::AtomicU64, Arc};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64},
+    Arc,
+};
 
 use parking_lot::RwLock;
 
@@ -6,31 +9,87 @@ use pagecache::Guard;
 
 use super::*;
 
+/// How a `Tree` is opened by `open_tree`.
+#[derive(Default)]
+pub(crate) struct TreeOptions {
+    /// The order of the keys, which must match the order
+    /// that the tree was created with.
+    pub(crate) order: KeyOrder,
+    /// If set, replaces the overrides of the `Config` that
+    /// the tree was created with.
+    pub(crate) settings: Option<PageSettings>,
+    /// Keys may only be given a time-to-live if this is set.
+    pub(crate) expirations: Option<Arc<Tree>>,
+    /// Writes are only recorded if this is set.
+    pub(crate) changes: Option<Arc<Tree>>,
+    /// Statistics are only persisted if this is set.
+    pub(crate) stats_tree: Option<Arc<Tree>>,
+}
+
 /// Open or create a new disk-backed Tree with its own keyspace,
 /// accessible from the `Db` via the provided identifier.
 pub(crate) fn open_tree<'a>(
     context: Context,
     name: Vec<u8>,
+    options: TreeOptions,
     guard: &'a Guard,
 ) -> Result<Tree> {
+    let TreeOptions {
+        order,
+        settings,
+        expirations,
+        changes,
+        stats_tree,
+    } = options;
+
     // we loop because creating this Tree may race with
     // concurrent attempts to open the same one.
     loop {
         match context.pagecache.meta_pid_for_name(&name, guard) {
             Ok(root_id) => {
-                return Ok(Tree {
+                let meta = context.pagecache.meta(guard)?;
+                check_comparator(
+                    &name,
+                    meta.get_comparator(&name).map(str::as_bytes),
+                    order.name().map(|n| &**n),
+                )?;
+
+                let has_expirations = if let Some(ref index) = expirations {
+                    ttl::has_deadlines(index, &name)?
+                } else {
+                    false
+                };
+                let stats =
+                    stats::load(stats_tree.as_ref().map(|t| &**t), &name)?;
+                let indexes = Indexes::load(meta, &name);
+                let persisted = meta.get_settings(&name);
+                let tree = Tree {
                     tree_id: name,
                     context: context.clone(),
                     subscriptions: Arc::new(Subscriptions::default()),
                     root: Arc::new(AtomicU64::new(root_id)),
                     concurrency_control: Arc::new(RwLock::new(())),
                     merge_operator: Arc::new(RwLock::new(None)),
-                });
+                    expirations,
+                    has_expirations: Arc::new(AtomicBool::new(has_expirations)),
+                    changes,
+                    stats: Arc::new(stats),
+                    stats_tree,
+                    order,
+                    indexes: Arc::new(RwLock::new(indexes)),
+                    settings: Arc::new(RwLock::new(persisted)),
+                };
+                if let Some(settings) = settings {
+                    tree.set_settings(settings)?;
+                }
+                return Ok(tree);
             }
-            Err(Error::CollectionNotFound(_)) => {}
+            Err(Error::CollectionNotFound(_)) if !context.read_only => {}
             Err(other) => return Err(other),
         }
 
+        let settings = settings.unwrap_or_default();
+
         // set up empty leaf
         let leaf = Frag::Base(Node {
             data: Data::Leaf(vec![]),
@@ -41,7 +100,8 @@ pub(crate) fn open_tree<'a>(
             merging: false,
         });
 
-        let (leaf_id, leaf_ptr) = context.pagecache.allocate(leaf, guard)?;
+        let (leaf_id, leaf_ptr) =
+            context.pagecache.allocate_with(leaf, &settings, guard)?;
 
         trace!(
             "allocated pid {} for leaf in new_tree for namespace {:?}",
@@ -63,14 +123,20 @@ pub(crate) fn open_tree<'a>(
             merging: false,
         });
 
-        let (root_id, root_ptr) = context.pagecache.allocate(root, guard)?;
+        let (root_id, root_ptr) =
+            context.pagecache.allocate_with(root, &settings, guard)?;
 
         debug!("allocated pid {} for root of new_tree {:?}", root_id, name);
 
-        let res = context.pagecache.cas_root_in_meta(
+        let comparator = order
+            .name()
+            .map(|name| String::from_utf8_lossy(name).into_owned());
+
+        let res = context.pagecache.create_root_in_meta(
             name.clone(),
-            None,
-            Some(root_id),
+            root_id,
+            comparator,
+            settings,
             guard,
         )?;
 
@@ -95,6 +161,14 @@ pub(crate) fn open_tree<'a>(
             root: Arc::new(AtomicU64::new(root_id)),
             concurrency_control: Arc::new(RwLock::new(())),
             merge_operator: Arc::new(RwLock::new(None)),
+            expirations,
+            has_expirations: Arc::new(AtomicBool::new(false)),
+            changes,
+            stats: Arc::new(Stats::new_tree()),
+            stats_tree,
+            order,
+            indexes: Arc::new(RwLock::new(Indexes::default())),
+            settings: Arc::new(RwLock::new(settings)),
         });
     }
 }
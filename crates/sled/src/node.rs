@@ -1,6 +1,4 @@
-use std::{fmt, ops::Bound};
-
-use pagecache::Lazy;
+use std::{cmp::Ordering, fmt, ops::Bound};
 
 use super::*;
 
@@ -49,7 +47,7 @@ impl Node {
                 // (when hi is empty, it means it's unbounded)
                 if self.hi.is_empty()
                     || prefix_cmp_encoded(k, &self.hi, &self.lo)
-                        == std::cmp::Ordering::Less
+                        == Ordering::Less
                 {
                     self.set_leaf(k.clone(), v.clone());
                 } else {
@@ -64,13 +62,17 @@ impl Node {
                 // (when hi is empty, it means it's unbounded)
                 if self.hi.is_empty()
                     || prefix_cmp_encoded(k, &self.hi, &self.lo)
-                        == std::cmp::Ordering::Less
+                        == Ordering::Less
                 {
                     self.del_leaf(k);
                 } else {
                     panic!("tried to consolidate del at key <= hi")
                 }
             }
+            SetAt(ref k, ref v, idx) => {
+                self.set_leaf_at(k.clone(), v.clone(), idx)
+            }
+            DelAt(ref k, idx) => self.del_leaf_at(k, idx),
             Base(_) => panic!("trying to apply a Base to frag {:?}", self),
             ParentMergeIntention(pid) => {
                 assert!(
@@ -109,10 +111,30 @@ impl Node {
         }
     }
 
-    pub(crate) fn parent_split(&mut self, at: &[u8], to: PageId) -> bool {
+    pub(crate) fn set_leaf_at(&mut self, key: IVec, val: IVec, idx: usize) {
+        if let Data::Leaf(ref mut records) = self.data {
+            match records.get(idx) {
+                Some((k, _)) if *k == key => records[idx] = (key, val),
+                _ => records.insert(idx, (key, val)),
+            }
+        } else {
+            panic!("tried to Set a value to an index");
+        }
+    }
+
+    pub(crate) fn parent_split(
+        &mut self,
+        at: &[u8],
+        to: PageId,
+        order: &KeyOrder,
+    ) -> bool {
+        let lo = &self.lo;
         if let Data::Index(ref mut ptrs) = self.data {
-            let encoded_sep = prefix_encode(&self.lo, at);
-            match ptrs.binary_search_by(|a| prefix_cmp(&a.0, &encoded_sep)) {
+            let encoded_sep = order.encode(lo, at);
+            let search = ptrs.binary_search_by(|a| {
+                order.cmp_both_encoded(&a.0, &encoded_sep, lo)
+            });
+            match search {
                 Ok(_) => {
                     debug!(
                         "parent_split skipped because \
@@ -142,6 +164,58 @@ impl Node {
         }
     }
 
+    pub(crate) fn del_leaf_at(&mut self, key: &IVec, idx: usize) {
+        if let Data::Leaf(ref mut records) = self.data {
+            if records.get(idx).map_or(false, |(k, _)| k == key) {
+                records.remove(idx);
+            }
+        } else {
+            panic!("tried to attach a Del to an Index chain");
+        }
+    }
+
+    /// Returns the frag that sets the encoded `key` to `val`.
+    ///
+    /// The pagecache consolidates frags without knowing
+    /// which tree they belong to, so under a custom order
+    /// the frag carries the position of the key instead.
+    /// That position stays valid because a frag is only
+    /// ever linked onto the exact view it was computed on.
+    pub(crate) fn set_frag(
+        &self,
+        key: IVec,
+        val: IVec,
+        order: &KeyOrder,
+    ) -> Frag {
+        if order.is_lexicographic() {
+            Frag::Set(key, val)
+        } else {
+            let idx = self.leaf_position(&key, order);
+            Frag::SetAt(key, val, idx)
+        }
+    }
+
+    /// Returns the frag that removes the encoded `key`,
+    /// see `set_frag`.
+    pub(crate) fn del_frag(&self, key: IVec, order: &KeyOrder) -> Frag {
+        if order.is_lexicographic() {
+            Frag::Del(key)
+        } else {
+            let idx = self.leaf_position(&key, order);
+            Frag::DelAt(key, idx)
+        }
+    }
+
+    fn leaf_position(&self, key: &[u8], order: &KeyOrder) -> usize {
+        let records = self.data.leaf_ref().unwrap();
+        let search = records.binary_search_by(|&(ref k, ref _v)| {
+            order.cmp_both_encoded(k, key, &self.lo)
+        });
+        match search {
+            Ok(idx) | Err(idx) => idx,
+        }
+    }
+
     pub(crate) fn split(mut self) -> (Self, Self) {
         let (split, right_data) = self.data.split(&self.lo);
         let rhs = Self {
@@ -153,7 +227,8 @@ impl Node {
             merging: false,
         };
 
-        self.data.drop_gte(&rhs.lo, &self.lo);
+        let lhs_len = self.data.len() - rhs.data.len();
+        self.data.truncate(lhs_len);
         self.hi = rhs.lo.clone();
 
         // intentionally make this the end to make
@@ -176,10 +251,22 @@ impl Node {
         merged
     }
 
-    pub(crate) fn contains_upper_bound(&self, bound: &Bound<IVec>) -> bool {
+    pub(crate) fn contains_upper_bound(
+        &self,
+        bound: &Bound<IVec>,
+        order: &KeyOrder,
+    ) -> bool {
         match bound {
-            Bound::Excluded(bound) if self.hi >= *bound => true,
-            Bound::Included(bound) if self.hi > *bound => true,
+            Bound::Excluded(bound)
+                if order.cmp(&self.hi, bound) != Ordering::Less =>
+            {
+                true
+            }
+            Bound::Included(bound)
+                if order.cmp(&self.hi, bound) == Ordering::Greater =>
+            {
+                true
+            }
             _ => self.hi.is_empty(),
         }
     }
@@ -188,161 +275,135 @@ impl Node {
         &self,
         bound: &Bound<IVec>,
         is_forward: bool,
+        order: &KeyOrder,
     ) -> bool {
         match bound {
-            Bound::Excluded(bound)
-                if self.lo < *bound || (is_forward && *bound == self.lo) =>
+            Bound::Excluded(bound) => match order.cmp(&self.lo, bound) {
+                Ordering::Less => true,
+                Ordering::Equal if is_forward => true,
+                _ => self.lo.is_empty(),
+            },
+            Bound::Included(bound)
+                if order.cmp(&self.lo, bound) != Ordering::Greater =>
             {
                 true
             }
-            Bound::Included(bound) if self.lo <= *bound => true,
             Bound::Unbounded if !is_forward => self.hi.is_empty(),
             _ => self.lo.is_empty(),
         }
     }
 
+    /// Returns the first item that falls within `bound`,
+    /// if it is a lower bound.
     pub(crate) fn successor(
         &self,
         bound: &Bound<IVec>,
+        order: &KeyOrder,
     ) -> Option<(IVec, IVec)> {
         assert!(!self.data.is_index());
 
-        // This encoding happens this way because
-        // keys cannot be lower than the node's lo key.
-        let predecessor_key = match bound {
-            Bound::Unbounded => prefix_encode(&self.lo, &self.lo),
-            Bound::Included(b) => {
-                let max = std::cmp::max(b, &self.lo);
-                prefix_encode(&self.lo, max)
-            }
-            Bound::Excluded(b) => {
-                let max = std::cmp::max(b, &self.lo);
-                prefix_encode(&self.lo, max)
-            }
-        };
-
         let records = self.data.leaf_ref().unwrap();
-        let search =
-            records.binary_search_by(|(k, _)| prefix_cmp(k, &predecessor_key));
 
-        let idx = match search {
-            Ok(idx) | Err(idx) => idx,
+        // the closure never returns Equal, so the search
+        // returns the index of the first key that is not
+        // below the bound.
+        let idx = match bound {
+            Bound::Unbounded => 0,
+            Bound::Included(b) => records
+                .binary_search_by(|(k, _)| {
+                    match order.cmp_encoded(k, b, &self.lo) {
+                        Ordering::Less => Ordering::Less,
+                        _ => Ordering::Greater,
+                    }
+                })
+                .unwrap_err(),
+            Bound::Excluded(b) => records
+                .binary_search_by(|(k, _)| {
+                    match order.cmp_encoded(k, b, &self.lo) {
+                        Ordering::Greater => Ordering::Greater,
+                        _ => Ordering::Less,
+                    }
+                })
+                .unwrap_err(),
         };
 
-        for (k, v) in &records[idx..] {
-            match bound {
-                Bound::Excluded(b)
-                    if prefix_cmp_encoded(k, b, &self.lo)
-                        == std::cmp::Ordering::Equal =>
-                {
-                    // keep going because we wanted to exclude
-                    // this key.
-                    continue;
-                }
-                _ => {}
-            }
-            let decoded_key = prefix_decode(&self.lo, &k);
-            return Some((IVec::from(decoded_key), v.clone()));
-        }
-
-        None
+        let (k, v) = records.get(idx)?;
+        let decoded_key = prefix_decode(&self.lo, &k);
+        Some((IVec::from(decoded_key), v.clone()))
     }
 
+    /// Returns the last item that falls within `bound`,
+    /// if it is an upper bound.
     pub(crate) fn predecessor(
         &self,
         bound: &Bound<IVec>,
+        order: &KeyOrder,
     ) -> Option<(IVec, IVec)> {
-        static MAX_IVEC: Lazy<IVec, fn() -> IVec> = Lazy::new(init_max_ivec);
-
-        fn init_max_ivec() -> IVec {
-            let mut base = vec![255; 1024 * 1024];
-            base[0] = 0;
-            IVec::from(base)
-        }
-
         assert!(!self.data.is_index());
 
-        // This encoding happens this way because
-        // the rightmost (unbounded) node has
-        // a hi key represented by the empty slice
-        let successor_key = match bound {
-            Bound::Unbounded => {
-                if self.hi.is_empty() {
-                    MAX_IVEC.clone()
-                } else {
-                    prefix_encode(&self.lo, &self.hi)
-                }
-            }
-            Bound::Included(b) => {
-                let min = if self.hi.is_empty() {
-                    b
-                } else {
-                    std::cmp::min(b, &self.hi)
-                };
-                prefix_encode(&self.lo, min)
-            }
-            Bound::Excluded(b) => {
-                let min = if self.hi.is_empty() {
-                    b
-                } else {
-                    std::cmp::min(b, &self.hi)
-                };
-                prefix_encode(&self.lo, min)
-            }
-        };
-
         let records = self.data.leaf_ref().unwrap();
-        let search =
-            records.binary_search_by(|(k, _)| prefix_cmp(k, &successor_key));
 
-        let idx = match search {
-            Ok(idx) => idx,
-            Err(idx) if idx > 0 => idx - 1,
-            _ => return None,
+        // the closure never returns Equal, so the search
+        // returns the index right after the last key that
+        // is not above the bound.
+        let end = match bound {
+            Bound::Unbounded => records.len(),
+            Bound::Included(b) => records
+                .binary_search_by(|(k, _)| {
+                    match order.cmp_encoded(k, b, &self.lo) {
+                        Ordering::Greater => Ordering::Greater,
+                        _ => Ordering::Less,
+                    }
+                })
+                .unwrap_err(),
+            Bound::Excluded(b) => records
+                .binary_search_by(|(k, _)| {
+                    match order.cmp_encoded(k, b, &self.lo) {
+                        Ordering::Less => Ordering::Less,
+                        _ => Ordering::Greater,
+                    }
+                })
+                .unwrap_err(),
         };
 
-        for (k, v) in records[0..=idx].iter().rev() {
-            match bound {
-                Bound::Excluded(b)
-                    if prefix_cmp_encoded(k, b, &self.lo)
-                        == std::cmp::Ordering::Equal =>
-                {
-                    // keep going because we wanted to exclude
-                    // this key.
-                    continue;
-                }
-                _ => {}
-            }
-            let decoded_key = prefix_decode(&self.lo, &k);
-            return Some((IVec::from(decoded_key), v.clone()));
-        }
-        None
+        let (k, v) = records.get(end.checked_sub(1)?)?;
+        let decoded_key = prefix_decode(&self.lo, &k);
+        Some((IVec::from(decoded_key), v.clone()))
     }
 
     pub(crate) fn leaf_pair_for_key(
         &self,
         key: &[u8],
+        order: &KeyOrder,
     ) -> Option<(&IVec, &IVec)> {
         assert!(!self.data.is_index());
 
         let records = self.data.leaf_ref().unwrap();
         let search = records
             .binary_search_by(|&(ref k, ref _v)| {
-                prefix_cmp_encoded(k, key, &self.lo)
+                order.cmp_encoded(k, key, &self.lo)
             })
             .ok();
 
         search.map(|idx| (&records[idx].0, &records[idx].1))
     }
 
-    pub(crate) fn should_split(&self) -> bool {
-        let threshold = if cfg!(feature = "lock_free_delays") {
-            2
-        } else if self.data.is_index() {
+    /// The most children an index node, or items a leaf,
+    /// may have before it is split.
+    pub(crate) fn split_threshold(is_index: bool) -> usize {
+        if cfg!(feature = "lock_free_delays") {
+            // the smallest nodes that can be split into two
+            // halves that are not full
+            3
+        } else if is_index {
             256
         } else {
             16
-        };
+        }
+    }
+
+    pub(crate) fn should_split(&self) -> bool {
+        let threshold = Node::split_threshold(self.data.is_index());
 
         let size_checks = self.data.len() > threshold;
         let safety_checks = self.merging_child.is_none() && !self.merging;
@@ -369,14 +430,31 @@ impl Node {
         self.merging_child.is_none() && !self.merging
     }
 
-    pub(crate) fn index_next_node(&self, key: &[u8]) -> (usize, PageId) {
+    pub(crate) fn index_next_node(
+        &self,
+        seek: Seek<'_>,
+        order: &KeyOrder,
+    ) -> (usize, PageId) {
         assert!(self.data.is_index());
 
         let records = self.data.index_ref().unwrap();
 
-        let search = binary_search_lub(records, |&(ref k, ref _v)| {
-            prefix_cmp_encoded(k, key, &self.lo)
-        });
+        let search = match seek {
+            Seek::Key(key) => binary_search_lub(records, |&(ref k, ref _v)| {
+                order.cmp_encoded(k, key, &self.lo)
+            }),
+            Seek::Before(key) => {
+                // treat a child starting at the key as being
+                // above it, so that we land on the child before
+                binary_search_lub(records, |&(ref k, ref _v)| {
+                    match order.cmp_encoded(k, key, &self.lo) {
+                        Ordering::Equal => Ordering::Greater,
+                        other => other,
+                    }
+                })
+            }
+            Seek::Last => records.len().checked_sub(1),
+        };
 
         // This might be none if ord is Less and we're
         // searching for the empty key
@@ -0,0 +1,248 @@
+//! Custom key orders for trees opened with
+//! `Db::open_tree_with_comparator`.
+//!
+//! Comparators are plain functions, so they can not be
+//! persisted. Instead, their name is stored in the `Meta`
+//! entry of the tree, and looked up in a process-wide
+//! registry once, when the tree is opened. A `Db` refuses
+//! to open while any of its trees uses a comparator that
+//! has not been registered.
+//!
+//! The pagecache consolidates the frags of a page without
+//! knowing which tree it belongs to, so writes to these
+//! trees are logged as `Frag::SetAt` and `Frag::DelAt`,
+//! which carry the position of their key in the node
+//! instead of needing the comparator to find it.
+//!
+//! Keys are still prefix-encoded relative to the low key of
+//! their node. That low key only sorts before them in the
+//! comparator's order, not necessarily in byte order, so
+//! the encoding can only strip the bytes the two share,
+//! and keys are decoded before they are compared. The empty
+//! key is the low key of the leftmost node, so it always
+//! sorts first, whatever the comparator says.
+use std::{cmp::Ordering, collections::HashMap, fmt};
+
+use pagecache::Lazy;
+use parking_lot::RwLock;
+
+use super::*;
+
+/// Orders the keys of a tree opened with
+/// `Db::open_tree_with_comparator`. It must be a total
+/// order, and must never change for a given name.
+pub type Comparator = fn(&[u8], &[u8]) -> Ordering;
+
+static COMPARATORS: Lazy<
+    RwLock<HashMap<Vec<u8>, Comparator>>,
+    fn() -> RwLock<HashMap<Vec<u8>, Comparator>>,
+> = Lazy::new(init_comparators);
+
+fn init_comparators() -> RwLock<HashMap<Vec<u8>, Comparator>> {
+    RwLock::new(HashMap::new())
+}
+
+/// Registers `comparator` under `name` for this process, so
+/// that existing trees that are ordered by it can be opened.
+/// This must happen before `Db::start` is called on a
+/// database that contains such trees. Registering the same
+/// function again is a no-op, but a name can not be reused
+/// for a different function.
+///
+/// # Examples
+///
+/// ```
+/// use std::cmp::Ordering;
+///
+/// fn reverse(a: &[u8], b: &[u8]) -> Ordering {
+///     b.cmp(a)
+/// }
+///
+/// sled::register_comparator("reverse", reverse).unwrap();
+/// ```
+pub fn register_comparator(name: &str, comparator: Comparator) -> Result<()> {
+    let mut comparators = COMPARATORS.write();
+    match comparators.get(name.as_bytes()) {
+        Some(existing) if *existing as usize != comparator as usize => {
+            Err(Error::Unsupported(format!(
+                "a different comparator is already registered as {:?}",
+                name
+            )))
+        }
+        Some(_) => Ok(()),
+        None => {
+            comparators.insert(name.as_bytes().to_vec(), comparator);
+            Ok(())
+        }
+    }
+}
+
+/// Positions that a traversal can look for.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Seek<'a> {
+    /// The node responsible for this key.
+    Key(&'a [u8]),
+    /// The node responsible for the keys right before this one.
+    Before(&'a [u8]),
+    /// The rightmost node.
+    Last,
+}
+
+/// The order of the keys in a tree, along with the name
+/// that its `Meta` entry records it under.
+#[derive(Clone, Default)]
+pub(crate) struct KeyOrder {
+    name: Option<IVec>,
+    comparator: Option<Comparator>,
+}
+
+impl fmt::Debug for KeyOrder {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> std::result::Result<(), fmt::Error> {
+        match self.name {
+            Some(ref name) => {
+                write!(f, "KeyOrder({:?})", String::from_utf8_lossy(name))
+            }
+            None => write!(f, "KeyOrder(lexicographic)"),
+        }
+    }
+}
+
+impl KeyOrder {
+    /// Looks up the comparator registered under `name`,
+    /// or byte order if there is none.
+    pub(crate) fn named(name: Option<&[u8]>) -> Result<KeyOrder> {
+        let name = if let Some(name) = name {
+            name
+        } else {
+            return Ok(KeyOrder::default());
+        };
+
+        match COMPARATORS.read().get(name) {
+            Some(comparator) => Ok(KeyOrder {
+                name: Some(IVec::from(name)),
+                comparator: Some(*comparator),
+            }),
+            None => Err(Error::Unsupported(format!(
+                "the comparator {:?} is not registered, see \
+                 `sled::register_comparator`",
+                String::from_utf8_lossy(name)
+            ))),
+        }
+    }
+
+    pub(crate) fn name(&self) -> Option<&IVec> {
+        self.name.as_ref()
+    }
+
+    pub(crate) fn is_lexicographic(&self) -> bool {
+        self.comparator.is_none()
+    }
+
+    pub(crate) fn cmp(&self, a: &[u8], b: &[u8]) -> Ordering {
+        let comparator = if let Some(comparator) = self.comparator {
+            comparator
+        } else {
+            return a.cmp(b);
+        };
+
+        match (a.is_empty(), b.is_empty()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            (false, false) => comparator(a, b),
+        }
+    }
+
+    /// Encodes `key` relative to the low key of its node.
+    pub(crate) fn encode(&self, lo: &[u8], key: &[u8]) -> IVec {
+        if self.is_lexicographic() {
+            prefix_encode(lo, key)
+        } else {
+            prefix_encode_unordered(lo, key)
+        }
+    }
+
+    /// Compares the encoded key `a` with the plain key `b`.
+    pub(crate) fn cmp_encoded(
+        &self,
+        a: &[u8],
+        b: &[u8],
+        lo: &[u8],
+    ) -> Ordering {
+        if self.is_lexicographic() {
+            prefix_cmp_encoded(a, b, lo)
+        } else {
+            self.cmp(&prefix_decode(lo, a), b)
+        }
+    }
+
+    /// Compares two keys that are encoded relative to `lo`.
+    pub(crate) fn cmp_both_encoded(
+        &self,
+        a: &[u8],
+        b: &[u8],
+        lo: &[u8],
+    ) -> Ordering {
+        if self.is_lexicographic() {
+            prefix_cmp(a, b)
+        } else {
+            self.cmp(&prefix_decode(lo, a), &prefix_decode(lo, b))
+        }
+    }
+
+    /// Returns `true` if a node starting at `lo` only holds
+    /// keys that come after the sought position.
+    pub(crate) fn overshot(&self, seek: Seek<'_>, lo: &[u8]) -> bool {
+        match seek {
+            Seek::Key(key) => self.cmp(key, lo) == Ordering::Less,
+            Seek::Before(key) => self.cmp(key, lo) != Ordering::Greater,
+            Seek::Last => false,
+        }
+    }
+
+    /// Returns `true` if a node ending at `hi` only holds
+    /// keys that come before the sought position.
+    pub(crate) fn undershot(&self, seek: Seek<'_>, hi: &[u8]) -> bool {
+        if hi.is_empty() {
+            // the rightmost node is unbounded
+            return false;
+        }
+        match seek {
+            Seek::Key(key) => self.cmp(key, hi) != Ordering::Less,
+            Seek::Before(key) => self.cmp(key, hi) == Ordering::Greater,
+            Seek::Last => true,
+        }
+    }
+}
+
+/// Fails loudly if a tree is opened with a different order
+/// than the one it was created with, which would misplace
+/// every key written from now on.
+pub(crate) fn check_comparator(
+    tree_id: &[u8],
+    persisted: Option<&[u8]>,
+    requested: Option<&[u8]>,
+) -> Result<()> {
+    if persisted == requested {
+        return Ok(());
+    }
+
+    let describe = |name: Option<&[u8]>| {
+        name.map_or_else(
+            || "byte order".to_owned(),
+            |name| {
+                format!("the comparator {:?}", String::from_utf8_lossy(name))
+            },
+        )
+    };
+
+    Err(Error::Unsupported(format!(
+        "tree {:?} is ordered by {}, but was opened with {}",
+        String::from_utf8_lossy(tree_id),
+        describe(persisted),
+        describe(requested),
+    )))
+}
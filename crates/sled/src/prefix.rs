@@ -10,6 +10,16 @@ pub(crate) fn prefix_encode(prefix: &[u8], buf: &[u8]) -> IVec {
         buf
     );
 
+    prefix_encode_unordered(prefix, buf)
+}
+
+/// Encodes `buf` relative to `prefix` without requiring the
+/// prefix to sort before it, as is the case for the low key
+/// of a node in a tree with a custom comparator. Only the
+/// bytes that the two share are stripped, so the result
+/// decodes correctly, but may not be compared with
+/// `prefix_cmp` or `prefix_cmp_encoded`.
+pub(crate) fn prefix_encode_unordered(prefix: &[u8], buf: &[u8]) -> IVec {
     let max = u8::max_value() as usize;
     let zip = prefix.iter().zip(buf);
     let prefix_len = zip.take(max).take_while(|(a, b)| a == b).count();
@@ -144,6 +154,22 @@ fn test_prefix() {
     }
 }
 
+#[test]
+fn test_prefix_unordered() {
+    let prefix = b"cat";
+    assert_eq!(prefix_encode_unordered(prefix, b"cab"), vec![2, b'b']);
+    assert_eq!(prefix_encode_unordered(prefix, b"ca"), vec![2]);
+    assert_eq!(prefix_encode_unordered(prefix, b"a"), vec![0, b'a']);
+
+    for item in &[b"" as &[u8], b"c", b"cas", b"bat\x00"] {
+        let encoded = prefix_encode_unordered(prefix, item);
+        assert_eq!(prefix_decode(prefix, &encoded), item.to_vec());
+
+        let reencoded = prefix_reencode(prefix, b"bag", &encoded);
+        assert_eq!(prefix_decode(b"bag", &reencoded), item.to_vec());
+    }
+}
+
 #[test]
 fn test_prefix_cmp() {
     assert_eq!(prefix_cmp(&[], &[]), Ordering::Equal);
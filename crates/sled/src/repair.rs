@@ -0,0 +1,232 @@
+//! Offline repair of a database that can no longer be opened.
+//!
+//! The pages that survive in the damaged log are read back
+//! with `pagecache::salvage`, and every tree named in the
+//! meta page is walked from its root, through the index
+//! nodes, to its leaves. The items of every leaf that was
+//! recovered are written into a fresh database. When a node
+//! is missing, or lost updates, the range of keys that its
+//! parent assigned to it is reported, so the caller knows
+//! which keys to restore from elsewhere.
+//!
+//! Like `Db::export_to`, only the default tree and the trees
+//! opened with `Db::open_tree` are copied. The deadlines of
+//! keys written with a ttl, and the history of the change
+//! feed, are not.
+use std::path::Path;
+
+use pagecache::{FastSet8, LogId, Salvage};
+
+use super::*;
+
+/// A range of keys in a tree that may have lost writes while
+/// being repaired by `Db::repair`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LostRange {
+    /// The name of the tree.
+    pub tree_name: Vec<u8>,
+    /// The lowest key in the range.
+    pub lo: Vec<u8>,
+    /// The key right above the range, or `None` if the range
+    /// is unbounded.
+    pub hi: Option<Vec<u8>>,
+}
+
+impl LostRange {
+    /// Returns `true` if `key` falls within this range.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        key >= &*self.lo && self.hi.as_ref().map_or(true, |hi| key < &**hi)
+    }
+}
+
+/// What `Db::repair` recovered, and what it could not.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RepairReport {
+    /// The names of the trees that were written to the
+    /// repaired database.
+    pub trees: Vec<Vec<u8>>,
+    /// The number of keys that were written to the repaired
+    /// database.
+    pub keys_recovered: u64,
+    /// The number of log messages that were read.
+    pub messages_read: usize,
+    /// The number of log messages that were dropped because
+    /// they were corrupt or their blob was missing. They may
+    /// have been superseded by later writes, in which case
+    /// no data was lost.
+    pub messages_dropped: usize,
+    /// Key ranges that may have lost writes.
+    pub lost_ranges: Vec<LostRange>,
+    /// The offsets of log segments that could not be read to
+    /// their end. Writes to any tree may have been lost in
+    /// them, without that showing up in `lost_ranges`.
+    pub unreadable_segments: Vec<LogId>,
+    /// Whether the record of which trees exist lost an update,
+    /// in which case a tree that was created or dropped
+    /// shortly before the damage may be missing or present.
+    pub trees_may_be_missing: bool,
+}
+
+impl RepairReport {
+    /// Returns `true` if no data is known to have been lost.
+    pub fn is_complete(&self) -> bool {
+        self.lost_ranges.is_empty()
+            && self.unreadable_segments.is_empty()
+            && !self.trees_may_be_missing
+    }
+}
+
+pub(crate) fn repair(
+    from: &Path,
+    to: &Path,
+    encryption_key: Option<EncryptionKey>,
+) -> Result<RepairReport> {
+    if to.exists() && to.read_dir()?.next().is_some() {
+        return Err(Error::Unsupported(format!(
+            "refusing to repair into {:?}, which is not empty",
+            to
+        )));
+    }
+
+    let salvage: Salvage<Frag> =
+        pagecache::salvage(from, encryption_key.clone())?;
+
+    let meta = if let Some(ref meta) = salvage.meta {
+        meta.clone()
+    } else {
+        return Err(Error::Unsupported(
+            "the meta page, which records the root of every tree, \
+             could not be recovered"
+                .to_owned(),
+        ));
+    };
+
+    let config = ConfigBuilder::clone(&salvage.config)
+        .path(to)
+        .temporary(false)
+        .read_only(false)
+        .encryption_key(encryption_key)
+        .build();
+
+    let db = Db::start(config)?;
+
+    let mut report = RepairReport {
+        messages_read: salvage.messages_read,
+        messages_dropped: salvage.messages_dropped,
+        unreadable_segments: salvage.unreadable_segments.clone(),
+        trees_may_be_missing: salvage.meta_damaged,
+        ..RepairReport::default()
+    };
+
+    for (name, root) in meta.tenants() {
+        // indexes are rebuilt when they are added again
+        if name == EXPIRATIONS_TREE_ID
+            || name == CHANGES_TREE_ID
+            || name == STATS_TREE_ID
+            || indexes::is_index_tree(&name)
+        {
+            continue;
+        }
+
+        let order =
+            KeyOrder::named(meta.get_comparator(&name).map(str::as_bytes))?;
+        let settings = meta.get_settings(&name);
+        let tree = db.open_collection(&name, order, Some(settings))?;
+        repair_tree(&salvage, &name, root, &tree, &mut report)?;
+        report.trees.push(name);
+    }
+
+    db.flush()?;
+
+    Ok(report)
+}
+
+/// Copies the leaves reachable from `root` into `tree`.
+fn repair_tree(
+    salvage: &Salvage<Frag>,
+    name: &[u8],
+    root: PageId,
+    tree: &Tree,
+    report: &mut RepairReport,
+) -> Result<()> {
+    let mut visited = FastSet8::default();
+    let mut stack: Vec<(PageId, Vec<u8>, Option<Vec<u8>>)> =
+        vec![(root, vec![], None)];
+    let mut siblings = vec![];
+
+    let lost = |lo: Vec<u8>, hi: Option<Vec<u8>>| {
+        warn!(
+            "keys from {:?} to {:?} in tree {:?} may have been lost",
+            lo, hi, name
+        );
+        LostRange {
+            tree_name: name.to_vec(),
+            lo,
+            hi,
+        }
+    };
+
+    // follow the index first, because it knows the key range
+    // of every child, and then any right siblings that were
+    // split off, but not yet linked into their parent.
+    loop {
+        let (pid, lo, hi) = if let Some(next) = stack.pop() {
+            next
+        } else if let Some(next) = siblings.pop() {
+            next
+        } else {
+            break;
+        };
+
+        if !visited.insert(pid) {
+            continue;
+        }
+
+        let node = match salvage.pages.get(&pid) {
+            Some(Frag::Base(node)) => node,
+            _ => {
+                report.lost_ranges.push(lost(lo, hi));
+                continue;
+            }
+        };
+
+        let node_hi = if node.hi.is_empty() {
+            None
+        } else {
+            Some(node.hi.to_vec())
+        };
+
+        if salvage.damaged.contains(&pid) {
+            report
+                .lost_ranges
+                .push(lost(node.lo.to_vec(), node_hi.clone()));
+        }
+
+        if let (Some(next), Some(next_lo)) = (node.next, node_hi.clone()) {
+            siblings.push((next, next_lo, None));
+        }
+
+        match node.data {
+            Data::Index(ref children) => {
+                for (i, (sep, child)) in children.iter().enumerate() {
+                    let child_lo = prefix_decode(&node.lo, sep);
+                    let child_hi = match children.get(i + 1) {
+                        Some((next_sep, _)) => {
+                            Some(prefix_decode(&node.lo, next_sep))
+                        }
+                        None => node_hi.clone(),
+                    };
+                    stack.push((*child, child_lo, child_hi));
+                }
+            }
+            Data::Leaf(ref items) => {
+                for (k, v) in items {
+                    tree.insert(prefix_decode(&node.lo, k), v.clone())?;
+                    report.keys_recovered += 1;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
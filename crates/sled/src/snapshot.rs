@@ -0,0 +1,571 @@
+use std::{
+    cmp::Ordering::{self, Greater, Less},
+    ops::{self, Bound, Deref, RangeBounds},
+    sync::{atomic::Ordering::SeqCst, Arc},
+};
+
+use pagecache::{FastMap8, Lsn, PageVersions};
+
+use super::*;
+
+macro_rules! iter_try {
+    ($e:expr) => {
+        match $e {
+            Ok(item) => item,
+            Err(e) => return Some(Err(e)),
+        }
+    };
+}
+
+/// The state of a captured tree that its `Snapshot`
+/// handles are created from.
+#[derive(Clone)]
+struct CapturedTree {
+    root: PageId,
+    order: KeyOrder,
+    // whether any key of the tree had a deadline
+    has_expirations: bool,
+}
+
+struct SnapshotInner {
+    context: Context,
+    roots: FastMap8<Vec<u8>, CapturedTree>,
+    // the root and key order of the shared deadline index,
+    // if any captured tree had keys with a deadline.
+    expirations: Option<(PageId, KeyOrder)>,
+    // the state of the pages that changed after the
+    // snapshot was taken, as of when it was taken.
+    versions: Arc<PageVersions<Frag>>,
+    lsn: Lsn,
+}
+
+/// A node as of a `Snapshot`, shared with the pages that
+/// were preserved for it or copied out of the cache.
+#[derive(Clone)]
+struct SnapshotNode(Arc<Frag>);
+
+impl Deref for SnapshotNode {
+    type Target = Node;
+
+    fn deref(&self) -> &Node {
+        if let Frag::Base(ref node) = *self.0 {
+            node
+        } else {
+            panic!("materialized page should be a Base: {:?}", self.0)
+        }
+    }
+}
+
+/// A read-only view of the state of one or more `Tree`s
+/// at a single point in time, created by `Tree::snapshot`
+/// or `Db::snapshot`. Reads and scans over a `Snapshot`
+/// never observe writes that happened after it was taken.
+///
+/// Taking a snapshot only waits for the writes that are in
+/// progress on the captured trees, and copies nothing.
+/// While it is held, the state of a page is preserved just
+/// before it is first changed, so holding a snapshot costs
+/// memory proportional to the number of pages written to
+/// in the `Db` since it was taken. Snapshots are meant for
+/// reports, exports and consistent multi-key reads, not
+/// for long-term retention.
+///
+/// As with reads from a `Tree`, keys whose time-to-live
+/// has run out are not returned, even if they had not
+/// yet expired when the snapshot was taken.
+///
+/// # Examples
+///
+/// ```
+/// use sled::{ConfigBuilder, Db, IVec};
+/// let config = ConfigBuilder::new().temporary(true).build();
+/// let db = Db::start(config).unwrap();
+///
+/// db.insert(b"a", vec![1]).unwrap();
+///
+/// let snapshot = db.snapshot().unwrap();
+///
+/// db.insert(b"a", vec![2]).unwrap();
+/// db.insert(b"b", vec![2]).unwrap();
+///
+/// assert_eq!(snapshot.get(b"a"), Ok(Some(IVec::from(vec![1]))));
+/// assert_eq!(snapshot.get(b"b"), Ok(None));
+/// assert_eq!(snapshot.iter().count(), 1);
+/// ```
+#[derive(Clone)]
+pub struct Snapshot {
+    inner: Arc<SnapshotInner>,
+    tree_id: Vec<u8>,
+    root: PageId,
+    order: KeyOrder,
+    has_expirations: bool,
+}
+
+impl Snapshot {
+    /// Records the roots of the provided trees, after
+    /// waiting for the operations that are modifying them
+    /// to complete. `tree_id` selects which tree this
+    /// handle reads.
+    pub(crate) fn capture(
+        context: &Context,
+        trees: &[&Tree],
+        tree_id: &[u8],
+    ) -> Result<Self> {
+        // lock trees in the same order as transactions do, so
+        // that the two can not deadlock each other.
+        let mut trees = trees.to_vec();
+        trees.sort_by(|a, b| a.tree_id.cmp(&b.tree_id));
+
+        let ccs: Vec<_> = trees
+            .iter()
+            .map(|t| t.concurrency_control.write())
+            .collect();
+
+        // writers that start after this preserve the pages
+        // they change, so it must happen before the roots
+        // are read and the writers are let go.
+        let versions = context.pagecache.preserve_versions();
+
+        let roots: FastMap8<_, _> = trees
+            .iter()
+            .map(|t| {
+                let captured = CapturedTree {
+                    root: t.root.load(SeqCst),
+                    order: t.order.clone(),
+                    has_expirations: t.expirations().is_some(),
+                };
+                (t.tree_id.clone(), captured)
+            })
+            .collect();
+
+        // deadlines of the keys of a tree are only changed
+        // while holding its lock, so they are captured along
+        // with the keys themselves.
+        let expirations = trees
+            .iter()
+            .filter_map(|t| t.expirations())
+            .next()
+            .map(|index| (index.root.load(SeqCst), index.order.clone()));
+
+        let lsn = context.pagecache.max_reserved_lsn();
+
+        drop(ccs);
+
+        let inner = SnapshotInner {
+            context: context.clone(),
+            roots,
+            expirations,
+            versions,
+            lsn,
+        };
+
+        Self::for_tree(Arc::new(inner), tree_id)
+    }
+
+    fn for_tree(inner: Arc<SnapshotInner>, tree_id: &[u8]) -> Result<Self> {
+        let captured = if let Some(captured) = inner.roots.get(tree_id) {
+            captured.clone()
+        } else {
+            return Err(Error::CollectionNotFound(tree_id.to_vec()));
+        };
+
+        Ok(Self {
+            inner,
+            tree_id: tree_id.to_vec(),
+            root: captured.root,
+            order: captured.order,
+            has_expirations: captured.has_expirations,
+        })
+    }
+
+    /// Returns the highest LSN that had been reserved in
+    /// the log when this snapshot was taken.
+    pub fn lsn(&self) -> Lsn {
+        self.inner.lsn
+    }
+
+    /// Returns the name of the tree that this
+    /// `Snapshot` reads from.
+    pub fn name(&self) -> Vec<u8> {
+        self.tree_id.clone()
+    }
+
+    /// Returns the names of the trees that
+    /// were captured in this snapshot.
+    pub fn tree_names(&self) -> Vec<Vec<u8>> {
+        self.inner.roots.keys().cloned().collect()
+    }
+
+    /// Open the state of another tree, as of the same
+    /// point in time. Only trees that existed when a
+    /// `Db::snapshot` was taken are available.
+    pub fn open_tree<V: AsRef<[u8]>>(&self, name: V) -> Result<Self> {
+        Self::for_tree(self.inner.clone(), name.as_ref())
+    }
+
+    /// Retrieve a value as of this snapshot, if it existed.
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<IVec>> {
+        let _measure = Measure::new(&M.tree_get);
+
+        let node = self.leaf_for(Seek::Key(key.as_ref()))?;
+
+        let v_opt = node
+            .leaf_pair_for_key(key.as_ref(), &self.order)
+            .map(|(_, v)| v.clone());
+
+        if v_opt.is_some() && self.is_expired(key.as_ref())? {
+            return Ok(None);
+        }
+
+        Ok(v_opt)
+    }
+
+    /// Returns `true` if the snapshot contains a value
+    /// for the specified key.
+    pub fn contains_key<K: AsRef<[u8]>>(&self, key: K) -> Result<bool> {
+        self.get(key).map(|v| v.is_some())
+    }
+
+    /// Create a double-ended iterator over the tuples of
+    /// keys and values in this snapshot.
+    pub fn iter(&self) -> SnapshotIter<'_> {
+        self.range::<Vec<u8>, _>(..)
+    }
+
+    /// Create a double-ended iterator over tuples of keys
+    /// and values in this snapshot, where the keys fall
+    /// within the specified range.
+    pub fn range<K, R>(&self, range: R) -> SnapshotIter<'_>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        let lo = match range.start_bound() {
+            ops::Bound::Included(ref start) => {
+                ops::Bound::Included(IVec::from(start.as_ref()))
+            }
+            ops::Bound::Excluded(ref start) => {
+                ops::Bound::Excluded(IVec::from(start.as_ref()))
+            }
+            ops::Bound::Unbounded => ops::Bound::Included(IVec::from(&[])),
+        };
+
+        let hi = match range.end_bound() {
+            ops::Bound::Included(ref end) => {
+                ops::Bound::Included(IVec::from(end.as_ref()))
+            }
+            ops::Bound::Excluded(ref end) => {
+                ops::Bound::Excluded(IVec::from(end.as_ref()))
+            }
+            ops::Bound::Unbounded => ops::Bound::Unbounded,
+        };
+
+        SnapshotIter {
+            snapshot: self,
+            hi,
+            lo,
+            cached_node: None,
+            going_forward: true,
+        }
+    }
+
+    /// Create an iterator over tuples of keys and values
+    /// in this snapshot, where all the keys start with
+    /// the given prefix.
+    pub fn scan_prefix<P>(&self, prefix: P) -> SnapshotIter<'_>
+    where
+        P: AsRef<[u8]>,
+    {
+        let prefix = prefix.as_ref();
+        let mut upper = prefix.to_vec();
+
+        while let Some(last) = upper.pop() {
+            if last < u8::max_value() {
+                upper.push(last + 1);
+                return self.range(prefix..&upper);
+            }
+        }
+
+        self.range(prefix..)
+    }
+
+    /// Returns `true` if the key had a deadline when the
+    /// snapshot was taken, and that deadline has passed.
+    fn is_expired(&self, key: &[u8]) -> Result<bool> {
+        let (root, order) = match self.inner.expirations {
+            Some((root, ref order)) if self.has_expirations => {
+                (root, order.clone())
+            }
+            _ => return Ok(false),
+        };
+
+        let index = Self {
+            inner: self.inner.clone(),
+            tree_id: EXPIRATIONS_TREE_ID.to_vec(),
+            root,
+            order,
+            has_expirations: false,
+        };
+
+        let deadline = ttl::snapshot_deadline(&index, &self.tree_id, key)?;
+        Ok(deadline.map_or(false, |d| d <= ttl::now_millis()))
+    }
+
+    fn node_for_pid(&self, pid: PageId) -> Result<Option<SnapshotNode>> {
+        let current = {
+            let guard = pin();
+            self.inner
+                .context
+                .pagecache
+                .get(pid, &guard)?
+                .map(|(_, frag, _)| Arc::new(frag.clone()))
+        };
+
+        // a page is preserved before it is changed, so only
+        // looking for it after reading the current state
+        // guarantees that the current state was not changed
+        // after the snapshot was taken.
+        let frag = match self.inner.versions.get(pid) {
+            Some(preserved) => preserved,
+            None => current,
+        };
+
+        Ok(frag.map(SnapshotNode))
+    }
+
+    /// Returns the leaf responsible for the sought position.
+    /// Because nothing can change a snapshot, we only need
+    /// to follow right siblings across splits that had not
+    /// yet been installed in a parent.
+    fn leaf_for(&self, seek: Seek<'_>) -> Result<SnapshotNode> {
+        let _measure = Measure::new(&M.tree_traverse);
+
+        if self.root == u64::max_value() {
+            // this collection had been explicitly removed
+            return Err(Error::CollectionNotFound(self.tree_id.clone()));
+        }
+
+        let mut cursor = self.root;
+
+        loop {
+            let node = if let Some(node) = self.node_for_pid(cursor)? {
+                node
+            } else {
+                return Err(Error::ReportableBug(format!(
+                    "snapshot of tree {:?} references missing pid {}",
+                    self.tree_id, cursor
+                )));
+            };
+
+            if self.order.undershot(seek, &node.hi) {
+                cursor = node.next.expect(
+                    "if our hi bound is not Inf (inity), \
+                     we should have a right sibling",
+                );
+            } else if node.data.is_index() {
+                cursor = node.index_next_node(seek, &self.order).1;
+            } else {
+                return Ok(node);
+            }
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Snapshot {
+    type Item = Result<(IVec, IVec)>;
+    type IntoIter = SnapshotIter<'a>;
+
+    fn into_iter(self) -> SnapshotIter<'a> {
+        self.iter()
+    }
+}
+
+/// An iterator over keys and values in a `Snapshot`.
+pub struct SnapshotIter<'a> {
+    snapshot: &'a Snapshot,
+    hi: Bound<IVec>,
+    lo: Bound<IVec>,
+    cached_node: Option<SnapshotNode>,
+    going_forward: bool,
+}
+
+impl<'a> SnapshotIter<'a> {
+    /// Iterate over the keys of this snapshot
+    pub fn keys(self) -> impl 'a + DoubleEndedIterator<Item = Result<IVec>> {
+        self.map(|r| r.map(|(k, _v)| k))
+    }
+
+    /// Iterate over the values of this snapshot
+    pub fn values(self) -> impl 'a + DoubleEndedIterator<Item = Result<IVec>> {
+        self.map(|r| r.map(|(_k, v)| v))
+    }
+
+    fn bounds_collapsed(&self, order: &KeyOrder) -> bool {
+        match (&self.lo, &self.hi) {
+            (Bound::Included(ref start), Bound::Included(ref end))
+            | (Bound::Included(ref start), Bound::Excluded(ref end))
+            | (Bound::Excluded(ref start), Bound::Included(ref end))
+            | (Bound::Excluded(ref start), Bound::Excluded(ref end)) => {
+                order.cmp(start, end) == Ordering::Greater
+            }
+            _ => false,
+        }
+    }
+
+    fn low_seek(&self) -> Seek<'_> {
+        match self.lo {
+            Bound::Unbounded => Seek::Key(&[]),
+            Bound::Excluded(ref lo) | Bound::Included(ref lo) => {
+                Seek::Key(lo.as_ref())
+            }
+        }
+    }
+
+    fn high_seek(&self) -> Seek<'_> {
+        match self.hi {
+            Bound::Unbounded => Seek::Last,
+            Bound::Excluded(ref hi) | Bound::Included(ref hi) => {
+                Seek::Key(hi.as_ref())
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for SnapshotIter<'a> {
+    type Item = Result<(IVec, IVec)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, value) = iter_try!(self.next_inner()?);
+            if !iter_try!(self.snapshot.is_expired(&key)) {
+                return Some(Ok((key, value)));
+            }
+        }
+    }
+
+    fn last(mut self) -> Option<Self::Item> {
+        self.next_back()
+    }
+}
+
+impl<'a> DoubleEndedIterator for SnapshotIter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, value) = iter_try!(self.next_back_inner()?);
+            if !iter_try!(self.snapshot.is_expired(&key)) {
+                return Some(Ok((key, value)));
+            }
+        }
+    }
+}
+
+impl<'a> SnapshotIter<'a> {
+    /// Returns the next pair, including keys whose
+    /// time-to-live has already elapsed.
+    fn next_inner(&mut self) -> Option<Result<(IVec, IVec)>> {
+        let _measure = Measure::new(&M.tree_scan);
+
+        let mut node = match (self.going_forward, self.cached_node.take()) {
+            (true, Some(node)) => node,
+            _ => iter_try!(self.snapshot.leaf_for(self.low_seek())),
+        };
+
+        loop {
+            let order = &self.snapshot.order;
+            if self.bounds_collapsed(order) {
+                return None;
+            }
+
+            if let Some((key, value)) = node.successor(&self.lo, order) {
+                self.lo = Bound::Excluded(key.clone());
+                self.cached_node = Some(node);
+                self.going_forward = true;
+
+                match self.hi {
+                    Bound::Unbounded => return Some(Ok((key, value))),
+                    Bound::Included(ref h) if order.cmp(h, &key) != Less => {
+                        return Some(Ok((key, value)));
+                    }
+                    Bound::Excluded(ref h) if order.cmp(h, &key) == Greater => {
+                        return Some(Ok((key, value)));
+                    }
+                    _ => return None,
+                }
+            }
+
+            if node.hi.is_empty() {
+                return None;
+            }
+
+            self.lo = Bound::Included(node.hi.clone());
+
+            let next = match node.next {
+                Some(next) => iter_try!(self.snapshot.node_for_pid(next)),
+                None => None,
+            };
+
+            node = if let Some(next) = next {
+                next
+            } else {
+                iter_try!(self.snapshot.leaf_for(self.low_seek()))
+            };
+        }
+    }
+
+    /// Returns the previous pair, including keys whose
+    /// time-to-live has already elapsed.
+    fn next_back_inner(&mut self) -> Option<Result<(IVec, IVec)>> {
+        let _measure = Measure::new(&M.tree_reverse_scan);
+
+        let mut node = match (self.going_forward, self.cached_node.take()) {
+            (false, Some(node)) => node,
+            _ => iter_try!(self.snapshot.leaf_for(self.high_seek())),
+        };
+
+        loop {
+            let order = &self.snapshot.order;
+            if self.bounds_collapsed(order) {
+                return None;
+            }
+
+            if !node.contains_upper_bound(&self.hi, order) {
+                // we sought a predecessor that was not
+                // the closest one, so move right.
+                let next = node.next?;
+                node = if let Some(next) =
+                    iter_try!(self.snapshot.node_for_pid(next))
+                {
+                    next
+                } else {
+                    iter_try!(self.snapshot.leaf_for(self.high_seek()))
+                };
+                continue;
+            }
+
+            if let Some((key, value)) = node.predecessor(&self.hi, order) {
+                self.hi = Bound::Excluded(key.clone());
+                self.cached_node = Some(node);
+                self.going_forward = false;
+
+                match self.lo {
+                    Bound::Unbounded => return Some(Ok((key, value))),
+                    Bound::Included(ref l) if order.cmp(l, &key) != Greater => {
+                        return Some(Ok((key, value)));
+                    }
+                    Bound::Excluded(ref l) if order.cmp(l, &key) == Less => {
+                        return Some(Ok((key, value)));
+                    }
+                    _ => return None,
+                }
+            }
+
+            if node.lo.is_empty() {
+                return None;
+            }
+
+            self.hi = Bound::Excluded(node.lo.clone());
+
+            node = iter_try!(self.snapshot.leaf_for(Seek::Before(&node.lo)));
+        }
+    }
+}
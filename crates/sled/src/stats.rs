@@ -0,0 +1,317 @@
+//! Counters that back `Tree::len` and `Tree::stats`.
+//!
+//! Each `Tree` keeps its counters in memory, and every write
+//! adjusts them by the difference between the value it
+//! replaced and the one it wrote. When the last handle to a
+//! `Db` is dropped, the counters of each tree are written to
+//! an internal tree:
+//!
+//! `tree id -> len ++ key bytes ++ value bytes ++ nodes ++ depth`
+//!
+//! where all integers are big-endian u64s. An entry is only
+//! valid until the next write to its tree, so that write
+//! first removes it. Because the removal is logged before
+//! the write itself, recovery can never restore the write
+//! without also restoring the removal. Trees without an
+//! entry after a crash are recounted with a full scan the
+//! first time their statistics are requested.
+use std::{
+    convert::TryInto,
+    sync::atomic::{AtomicBool, AtomicI64, Ordering::SeqCst},
+};
+
+use parking_lot::Mutex;
+
+use super::*;
+
+/// Statistics about the contents and shape of a `Tree`,
+/// returned by `Tree::stats`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct TreeStats {
+    /// The number of keys in the tree. Keys whose
+    /// time-to-live has passed are counted until they
+    /// are removed in the background.
+    pub len: u64,
+    /// The total size of all keys.
+    pub key_bytes: u64,
+    /// The total size of all values.
+    pub value_bytes: u64,
+    /// The number of nodes in the tree, which may briefly
+    /// be off while nodes are being split or merged.
+    pub nodes: u64,
+    /// The number of levels in the tree, including
+    /// the level of leaves.
+    pub depth: u64,
+}
+
+pub(crate) struct Stats {
+    len: AtomicI64,
+    key_bytes: AtomicI64,
+    value_bytes: AtomicI64,
+    nodes: AtomicI64,
+    depth: AtomicI64,
+    /// Unset until the counters are known to be exact.
+    known: AtomicBool,
+    /// Set while the stats tree holds an entry for
+    /// these counters that must be removed before
+    /// the next write.
+    persisted: AtomicBool,
+    persist_mu: Mutex<()>,
+}
+
+impl Stats {
+    fn new(stats: Option<TreeStats>, persisted: bool) -> Stats {
+        let known = stats.is_some();
+        let stats = stats.unwrap_or_default();
+        Stats {
+            len: AtomicI64::new(stats.len as i64),
+            key_bytes: AtomicI64::new(stats.key_bytes as i64),
+            value_bytes: AtomicI64::new(stats.value_bytes as i64),
+            nodes: AtomicI64::new(stats.nodes as i64),
+            depth: AtomicI64::new(stats.depth as i64),
+            known: AtomicBool::new(known),
+            persisted: AtomicBool::new(persisted),
+            persist_mu: Mutex::new(()),
+        }
+    }
+
+    /// Counters for a tree that was just created with
+    /// an empty leaf below its root index.
+    pub(crate) fn new_tree() -> Stats {
+        Stats::new(
+            Some(TreeStats {
+                nodes: 2,
+                depth: 2,
+                ..TreeStats::default()
+            }),
+            false,
+        )
+    }
+
+    /// Counters that are recounted before they are used.
+    pub(crate) fn unknown() -> Stats {
+        Stats::new(None, false)
+    }
+
+    pub(crate) fn is_known(&self) -> bool {
+        self.known.load(SeqCst)
+    }
+
+    /// Forces a recount, for when the tree was changed
+    /// without going through this `Stats`.
+    pub(crate) fn forget(&self) {
+        self.known.store(false, SeqCst);
+    }
+
+    pub(crate) fn current(&self) -> TreeStats {
+        let load = |counter: &AtomicI64| counter.load(SeqCst).max(0) as u64;
+        TreeStats {
+            len: load(&self.len),
+            key_bytes: load(&self.key_bytes),
+            value_bytes: load(&self.value_bytes),
+            nodes: load(&self.nodes),
+            depth: load(&self.depth),
+        }
+    }
+
+    /// Replaces the counters with ones that are known
+    /// to be exact.
+    pub(crate) fn store(&self, stats: TreeStats) {
+        self.len.store(stats.len as i64, SeqCst);
+        self.key_bytes.store(stats.key_bytes as i64, SeqCst);
+        self.value_bytes.store(stats.value_bytes as i64, SeqCst);
+        self.nodes.store(stats.nodes as i64, SeqCst);
+        self.depth.store(stats.depth as i64, SeqCst);
+        self.known.store(true, SeqCst);
+    }
+
+    /// Accounts for a successful write of a key, given the
+    /// sizes of the value it replaced and the value it wrote.
+    pub(crate) fn wrote(
+        &self,
+        key_len: usize,
+        old: Option<usize>,
+        new: Option<usize>,
+    ) {
+        let key_len = key_len as i64;
+        match (old, new) {
+            (None, Some(_)) => {
+                self.len.fetch_add(1, SeqCst);
+                self.key_bytes.fetch_add(key_len, SeqCst);
+            }
+            (Some(_), None) => {
+                self.len.fetch_sub(1, SeqCst);
+                self.key_bytes.fetch_sub(key_len, SeqCst);
+            }
+            _ => {}
+        }
+        let old = old.unwrap_or(0) as i64;
+        let new = new.unwrap_or(0) as i64;
+        self.value_bytes.fetch_add(new - old, SeqCst);
+    }
+
+    pub(crate) fn split(&self) {
+        self.nodes.fetch_add(1, SeqCst);
+    }
+
+    pub(crate) fn root_hoisted(&self) {
+        self.nodes.fetch_add(1, SeqCst);
+        self.depth.fetch_add(1, SeqCst);
+    }
+
+    pub(crate) fn merged(&self) {
+        self.nodes.fetch_sub(1, SeqCst);
+    }
+}
+
+fn parse_u64(buf: &[u8]) -> Option<u64> {
+    let array: [u8; 8] = buf.get(..8)?.try_into().ok()?;
+    Some(u64::from_be_bytes(array))
+}
+
+fn encode(stats: TreeStats) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(40);
+    for field in &[
+        stats.len,
+        stats.key_bytes,
+        stats.value_bytes,
+        stats.nodes,
+        stats.depth,
+    ] {
+        buf.extend_from_slice(&field.to_be_bytes());
+    }
+    buf
+}
+
+fn decode(buf: &[u8]) -> Option<TreeStats> {
+    if buf.len() != 40 {
+        return None;
+    }
+    Some(TreeStats {
+        len: parse_u64(&buf[0..])?,
+        key_bytes: parse_u64(&buf[8..])?,
+        value_bytes: parse_u64(&buf[16..])?,
+        nodes: parse_u64(&buf[24..])?,
+        depth: parse_u64(&buf[32..])?,
+    })
+}
+
+/// Loads the counters of an existing tree, which are
+/// unknown if they were not persisted at shutdown.
+pub(crate) fn load(stats_tree: Option<&Tree>, tree_id: &[u8]) -> Result<Stats> {
+    let stats_tree = if let Some(stats_tree) = stats_tree {
+        stats_tree
+    } else {
+        return Ok(Stats::unknown());
+    };
+
+    match stats_tree.get_inner(tree_id)? {
+        Some(raw) => {
+            let stats = decode(&raw).ok_or_else(|| {
+                Error::ReportableBug(format!(
+                    "malformed statistics for tree {:?}",
+                    tree_id
+                ))
+            })?;
+            Ok(Stats::new(Some(stats), true))
+        }
+        None => Ok(Stats::unknown()),
+    }
+}
+
+/// Removes the persisted entry of a tree before it is
+/// written to, if it has one.
+pub(crate) fn mark_dirty(
+    stats_tree: &Tree,
+    tree_id: &[u8],
+    stats: &Stats,
+) -> Result<()> {
+    if !stats.persisted.load(SeqCst) {
+        return Ok(());
+    }
+
+    let _mu = stats.persist_mu.lock();
+    if stats.persisted.load(SeqCst) {
+        stats_tree.remove_inner(tree_id)?;
+        stats.persisted.store(false, SeqCst);
+    }
+
+    Ok(())
+}
+
+/// Writes the counters of a tree to the stats tree,
+/// if they are known.
+pub(crate) fn persist(stats_tree: &Tree, tree: &Tree) -> Result<()> {
+    let _cc = tree.concurrency_control.write();
+
+    let stats = &tree.stats;
+    if !stats.is_known() {
+        return Ok(());
+    }
+
+    let _mu = stats.persist_mu.lock();
+    stats_tree.insert_inner(&tree.tree_id, encode(stats.current()))?;
+    stats.persisted.store(true, SeqCst);
+
+    Ok(())
+}
+
+/// Forgets the persisted counters of a tree that is
+/// about to be dropped.
+pub(crate) fn remove_tree(stats_tree: &Tree, tree_id: &[u8]) -> Result<()> {
+    stats_tree.remove_inner(tree_id)?;
+    Ok(())
+}
+
+/// Recounts everything by walking each level of the
+/// tree from left to right. Must be called while holding
+/// the write side of the tree's `concurrency_control`.
+pub(crate) fn recount(tree: &Tree) -> Result<()> {
+    let guard = pin();
+
+    let mut stats = TreeStats::default();
+    let mut leftmost = tree.root.load(SeqCst);
+
+    loop {
+        stats.depth += 1;
+
+        let mut next_level = None;
+        let mut cursor = Some(leftmost);
+
+        while let Some(pid) = cursor {
+            let view = if let Some(view) = tree.view_for_pid(pid, &guard)? {
+                view
+            } else {
+                // merged away while we were walking
+                break;
+            };
+
+            stats.nodes += 1;
+
+            if let Some(items) = view.data.leaf_ref() {
+                for (k, v) in items {
+                    let key = prefix_decode(&view.lo, k);
+                    stats.len += 1;
+                    stats.key_bytes += key.len() as u64;
+                    stats.value_bytes += v.len() as u64;
+                }
+            } else if let Some(index) = view.data.index_ref() {
+                if next_level.is_none() {
+                    next_level = index.first().map(|(_, child)| *child);
+                }
+            }
+
+            cursor = view.next;
+        }
+
+        if let Some(child) = next_level {
+            leftmost = child;
+        } else {
+            break;
+        }
+    }
+
+    tree.stats.store(stats);
+
+    Ok(())
+}
@@ -1,13 +1,19 @@
 use std::{
-    collections::BTreeMap,
+    cell::RefCell,
+    collections::{BTreeMap, VecDeque},
+    future::Future,
+    pin::Pin,
     sync::{
         atomic::{AtomicUsize, Ordering::Relaxed},
-        mpsc::{sync_channel, Receiver, SyncSender},
+        mpsc::RecvTimeoutError,
         Arc,
     },
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
 };
 
-use parking_lot::RwLock;
+use futures_core::stream::Stream;
+use parking_lot::{Condvar, Mutex, RwLock};
 
 use crate::ivec::IVec;
 
@@ -15,8 +21,28 @@ use pagecache::{Promise, PromiseFiller};
 
 static ID_GEN: AtomicUsize = AtomicUsize::new(0);
 
+thread_local! {
+    static BACKLOG: RefCell<Backlog> = RefCell::new(Backlog::default());
+}
+
+/// The `DeferBackpressure` guards held by a thread, and the
+/// queues of the `Backpressure::Block` subscribers that it
+/// has to wait for once it holds none of them.
+#[derive(Default)]
+struct Backlog {
+    pegs: usize,
+    queues: Vec<Arc<Queue>>,
+}
+
+/// The number of `Event`s buffered for each `Subscriber`.
+const BUFFER_LEN: usize = 1024;
+
 /// An event that happened to a key that a subscriber is interested in.
+///
+/// New kinds of events may be added in later versions, so
+/// matches on an `Event` must include a wildcard arm.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
 pub enum Event {
     /// A new complete (key, value) pair
     Set(Vec<u8>, IVec),
@@ -24,13 +50,31 @@ pub enum Event {
     Merge(Vec<u8>, IVec),
     /// A deleted key
     Del(Vec<u8>),
+    /// A key that was removed because its time-to-live elapsed
+    Expired(Vec<u8>),
+    /// All keys from the first (inclusive) up to the second
+    /// (exclusive) were removed, or up to the end of the
+    /// `Tree` if there is no second key. The range follows
+    /// the comparator of the `Tree`, if it has one
+    DelRange(Vec<u8>, Option<Vec<u8>>),
+    /// The number of `Event`s that were discarded because the
+    /// `Subscriber` fell behind with `Backpressure::Lag`. The
+    /// watched keys should be scanned again to catch up.
+    Lagged(u64),
 }
 
 impl Event {
-    /// Return a reference to the key that this `Event` refers to
+    /// Return a reference to the key that this `Event` refers to,
+    /// which is the first removed key for `Event::DelRange`,
+    /// and empty for `Event::Lagged`.
     pub fn key(&self) -> &[u8] {
         match self {
-            Event::Set(k, ..) | Event::Merge(k, ..) | Event::Del(k) => &*k,
+            Event::Set(k, ..)
+            | Event::Merge(k, ..)
+            | Event::Del(k)
+            | Event::Expired(k)
+            | Event::DelRange(k, _) => &*k,
+            Event::Lagged(_) => &[],
         }
     }
 }
@@ -43,23 +87,233 @@ impl Clone for Event {
             Set(k, v) => Set(k.clone(), v.clone()),
             Merge(k, v) => Merge(k.clone(), v.clone()),
             Del(k) => Del(k.clone()),
+            Expired(k) => Expired(k.clone()),
+            DelRange(lo, hi) => DelRange(lo.clone(), hi.clone()),
+            Lagged(n) => Lagged(*n),
         }
     }
 }
 
-type Senders = Vec<(usize, SyncSender<Promise<Event>>)>;
+/// What happens to new `Event`s for a `Subscriber`
+/// that has 1024 `Event`s buffered already.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Writers to watched keys block until the
+    /// `Subscriber` catches up, once their write is
+    /// done and they do not hold any log reservation.
+    Block,
+    /// The oldest buffered `Event` is discarded
+    /// to make room for the new one.
+    DropOldest,
+    /// New `Event`s are discarded until the `Subscriber`
+    /// has received everything that was buffered, after
+    /// which it receives a single `Event::Lagged` with the
+    /// number of `Event`s that it missed.
+    Lag,
+}
+
+impl Default for Backpressure {
+    fn default() -> Self {
+        Backpressure::Block
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    items: VecDeque<Promise<Event>>,
+    lagged: u64,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+/// The buffer between writers and a single `Subscriber`.
+#[derive(Default)]
+struct Queue {
+    mu: Mutex<QueueState>,
+    /// Signalled whenever an item is pushed or popped,
+    /// or the queue is closed.
+    cv: Condvar,
+}
+
+impl Queue {
+    fn close(&self) {
+        let mut state = self.mu.lock();
+        state.closed = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        self.cv.notify_all();
+    }
+
+    /// Blocks while more than `BUFFER_LEN` items are buffered,
+    /// unless the queue is closed.
+    fn wait_for_room(&self) {
+        let mut state = self.mu.lock();
+        while state.items.len() > BUFFER_LEN && !state.closed {
+            self.cv.wait(&mut state);
+        }
+    }
+
+    /// Buffers a reservation for an `Event`, following the
+    /// `Backpressure` policy if the buffer is full. Never
+    /// blocks: a full buffer of a `Backpressure::Block`
+    /// subscriber is waited for with `wait_for_room` once the
+    /// write that completes the `Event` has released its
+    /// locks. Returns `None` if the `Event` will not be
+    /// delivered.
+    fn push(&self, policy: Backpressure) -> Option<PromiseFiller<Event>> {
+        let mut state = self.mu.lock();
+
+        match policy {
+            Backpressure::Block => {}
+            Backpressure::DropOldest => {
+                if state.items.len() >= BUFFER_LEN {
+                    state.items.pop_front();
+                }
+            }
+            Backpressure::Lag => {
+                // keep discarding until the lag has been reported,
+                // so that nothing is delivered out of order.
+                if state.lagged > 0 || state.items.len() >= BUFFER_LEN {
+                    state.lagged += 1;
+                    return None;
+                }
+            }
+        }
 
-/// A subscriber listening on a specified prefix
+        if state.closed {
+            return None;
+        }
+
+        let (tx, rx) = Promise::pair();
+        state.items.push_back(rx);
+
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        self.cv.notify_all();
+
+        Some(tx)
+    }
+}
+
+/// The result of checking a `Queue` for a `Subscriber`.
+enum Pop {
+    Item(Promise<Event>),
+    Lagged(u64),
+    Closed,
+}
+
+impl QueueState {
+    fn pop(&mut self) -> Option<Pop> {
+        if let Some(item) = self.items.pop_front() {
+            Some(Pop::Item(item))
+        } else if self.lagged > 0 {
+            let lagged = self.lagged;
+            self.lagged = 0;
+            Some(Pop::Lagged(lagged))
+        } else if self.closed {
+            Some(Pop::Closed)
+        } else {
+            None
+        }
+    }
+}
+
+type Senders = Vec<(usize, Backpressure, Arc<Queue>)>;
+
+/// A subscriber listening on a specified prefix.
+///
+/// `Event`s may be received by blocking on it as an
+/// `Iterator`, with a timeout using `next_timeout`, or
+/// without blocking by polling it as a `Stream`. Dropping
+/// it stops the subscription, and all of them end once
+/// the `Tree` they watch is dropped. What happens when
+/// it falls behind is decided by its `Backpressure`.
 pub struct Subscriber {
     id: usize,
-    rx: Receiver<Promise<Event>>,
+    queue: Arc<Queue>,
     home: Arc<RwLock<Senders>>,
+    /// A reserved `Event` that was not completed yet
+    /// when we last stopped waiting for it.
+    existing: Option<Promise<Event>>,
 }
 
 impl Drop for Subscriber {
     fn drop(&mut self) {
+        // unblock writers waiting for us to make room
+        // before we wait for them to release `home`.
+        self.queue.close();
+
         let mut w_senders = self.home.write();
-        w_senders.retain(|(id, _)| *id != self.id);
+        w_senders.retain(|(id, ..)| *id != self.id);
+    }
+}
+
+impl Subscriber {
+    /// Block for up to `timeout` until the next `Event`
+    /// arrives. Returns `RecvTimeoutError::Disconnected`
+    /// once the watched `Tree` has been dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::{sync::mpsc::RecvTimeoutError, time::Duration};
+    /// let config = sled::ConfigBuilder::new().temporary(true).build();
+    /// let tree = sled::Db::start(config).unwrap();
+    ///
+    /// let mut events = tree.watch_prefix(vec![]);
+    ///
+    /// let timeout = Duration::from_millis(10);
+    /// assert_eq!(events.next_timeout(timeout), Err(RecvTimeoutError::Timeout));
+    ///
+    /// tree.insert(vec![0], vec![1]).unwrap();
+    /// assert_eq!(events.next_timeout(timeout).unwrap().key(), &[0]);
+    /// ```
+    pub fn next_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> std::result::Result<Event, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let future_rx = if let Some(future_rx) = self.existing.take() {
+                future_rx
+            } else {
+                let mut state = self.queue.mu.lock();
+                loop {
+                    match state.pop() {
+                        Some(Pop::Item(future_rx)) => break future_rx,
+                        Some(Pop::Lagged(n)) => return Ok(Event::Lagged(n)),
+                        Some(Pop::Closed) => {
+                            return Err(RecvTimeoutError::Disconnected)
+                        }
+                        None => {}
+                    }
+                    if self
+                        .queue
+                        .cv
+                        .wait_until(&mut state, deadline)
+                        .timed_out()
+                    {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                }
+            };
+            self.queue.cv.notify_all();
+
+            let remaining = deadline
+                .checked_duration_since(Instant::now())
+                .unwrap_or_default();
+
+            match future_rx.wait_timeout(remaining) {
+                Ok(Some(event)) => return Ok(event),
+                Ok(None) => continue,
+                Err(future_rx) => {
+                    self.existing = Some(future_rx);
+                    return Err(RecvTimeoutError::Timeout);
+                }
+            }
+        }
     }
 }
 
@@ -68,7 +322,21 @@ impl Iterator for Subscriber {
 
     fn next(&mut self) -> Option<Event> {
         loop {
-            let future_rx = self.rx.recv().ok()?;
+            let future_rx = if let Some(future_rx) = self.existing.take() {
+                future_rx
+            } else {
+                let mut state = self.queue.mu.lock();
+                loop {
+                    match state.pop() {
+                        Some(Pop::Item(future_rx)) => break future_rx,
+                        Some(Pop::Lagged(n)) => return Some(Event::Lagged(n)),
+                        Some(Pop::Closed) => return None,
+                        None => self.queue.cv.wait(&mut state),
+                    }
+                }
+            };
+            self.queue.cv.notify_all();
+
             match future_rx.wait() {
                 Some(event) => return Some(event),
                 None => continue,
@@ -77,13 +345,70 @@ impl Iterator for Subscriber {
     }
 }
 
+impl Stream for Subscriber {
+    type Item = Event;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Event>> {
+        loop {
+            if self.existing.is_none() {
+                let mut state = self.queue.mu.lock();
+                match state.pop() {
+                    Some(Pop::Item(future_rx)) => {
+                        drop(state);
+                        self.queue.cv.notify_all();
+                        self.existing = Some(future_rx);
+                    }
+                    Some(Pop::Lagged(n)) => {
+                        return Poll::Ready(Some(Event::Lagged(n)))
+                    }
+                    Some(Pop::Closed) => return Poll::Ready(None),
+                    None => {
+                        state.waker = Some(cx.waker().clone());
+                        return Poll::Pending;
+                    }
+                }
+            }
+
+            let future_rx = self.existing.as_mut().unwrap();
+            match Pin::new(future_rx).poll(cx) {
+                Poll::Ready(Some(event)) => {
+                    self.existing = None;
+                    return Poll::Ready(Some(event));
+                }
+                Poll::Ready(None) => {
+                    // the write was aborted
+                    self.existing = None;
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
 #[derive(Default)]
 pub(crate) struct Subscriptions {
     watched: RwLock<BTreeMap<Vec<u8>, Arc<RwLock<Senders>>>>,
 }
 
+impl Drop for Subscriptions {
+    fn drop(&mut self) {
+        for senders in self.watched.read().values() {
+            for (_, _, queue) in senders.read().iter() {
+                queue.close();
+            }
+        }
+    }
+}
+
 impl Subscriptions {
-    pub(crate) fn register(&self, prefix: Vec<u8>) -> Subscriber {
+    pub(crate) fn register(
+        &self,
+        prefix: Vec<u8>,
+        backpressure: Backpressure,
+    ) -> Subscriber {
         let r_mu = {
             let r_mu = self.watched.read();
             if r_mu.contains_key(&prefix) {
@@ -99,19 +424,20 @@ impl Subscriptions {
             }
         };
 
-        let (tx, rx) = sync_channel(1024);
+        let queue = Arc::new(Queue::default());
 
         let arc_senders = &r_mu[&prefix];
         let mut w_senders = arc_senders.write();
 
         let id = ID_GEN.fetch_add(1, Relaxed);
 
-        w_senders.push((id, tx));
+        w_senders.push((id, backpressure, queue.clone()));
 
         Subscriber {
             id,
-            rx,
+            queue,
             home: arc_senders.clone(),
+            existing: None,
         }
     }
 
@@ -119,39 +445,135 @@ impl Subscriptions {
         &self,
         key: R,
     ) -> Option<ReservedBroadcast> {
-        let r_mu = self.watched.read();
-        let prefixes = r_mu.iter().filter(|(k, _)| key.as_ref().starts_with(k));
+        self.reserve_matching(|prefix| key.as_ref().starts_with(prefix))
+    }
 
-        let mut subscribers = vec![];
+    /// Reserves an `Event` for every subscriber whose prefix
+    /// may match a key in `lo..hi`, or `lo..` if `hi` is `None`.
+    pub(crate) fn reserve_range(
+        &self,
+        lo: &[u8],
+        hi: Option<&[u8]>,
+    ) -> Option<ReservedBroadcast> {
+        // the keys starting with a prefix are the ones between
+        // the prefix itself and the first key after all of them,
+        // which is beyond `lo` unless `lo` sorts after the prefix
+        // without starting with it.
+        self.reserve_matching(|prefix| {
+            hi.map_or(true, |hi| prefix < hi)
+                && (prefix >= lo || lo.starts_with(prefix))
+        })
+    }
 
-        for (_, subs_rwl) in prefixes {
-            let subs = subs_rwl.read();
+    /// Reserves a slot for every subscriber, for ranges that
+    /// can not be matched against prefixes because they do
+    /// not follow byte order.
+    pub(crate) fn reserve_all(&self) -> Option<ReservedBroadcast> {
+        self.reserve_matching(|_| true)
+    }
+
+    fn reserve_matching<F>(&self, matches: F) -> Option<ReservedBroadcast>
+    where
+        F: Fn(&[u8]) -> bool,
+    {
+        // the senders are collected first, so that subscribers
+        // may register and unregister while we push.
+        let senders: Vec<(Backpressure, Arc<Queue>)> = {
+            let r_mu = self.watched.read();
+            let prefixes = r_mu.iter().filter(|(k, _)| matches(k.as_slice()));
 
-            for (_id, sender) in subs.iter() {
-                let (tx, rx) = Promise::pair();
-                if sender.send(rx).is_err() {
-                    continue;
+            let mut senders = vec![];
+            for (_, subs_rwl) in prefixes {
+                let subs = subs_rwl.read();
+                for (_id, backpressure, queue) in subs.iter() {
+                    senders.push((*backpressure, queue.clone()));
                 }
+            }
+            senders
+        };
+
+        let mut subscribers = vec![];
+        let mut blocking = vec![];
+
+        for (backpressure, queue) in senders {
+            if let Some(tx) = queue.push(backpressure) {
                 subscribers.push(tx);
+                if backpressure == Backpressure::Block {
+                    blocking.push(queue);
+                }
             }
         }
 
         if subscribers.is_empty() {
             None
         } else {
-            Some(ReservedBroadcast { subscribers })
+            Some(ReservedBroadcast {
+                subscribers,
+                blocking,
+            })
         }
     }
 }
 
+/// Keeps this thread from waiting for the `Backpressure::Block`
+/// subscribers that its writes fall behind until it is dropped.
+/// Public write paths take one before any lock or log
+/// reservation, so that it is dropped after all of them.
+pub(crate) struct DeferBackpressure(());
+
+/// Called before this thread takes locks or a log reservation,
+/// under which it must not wait for subscribers.
+pub(crate) fn defer_backpressure() -> DeferBackpressure {
+    BACKLOG.with(|backlog| backlog.borrow_mut().pegs += 1);
+    DeferBackpressure(())
+}
+
+impl Drop for DeferBackpressure {
+    fn drop(&mut self) {
+        resume_backpressure();
+    }
+}
+
+/// Once this thread holds no `DeferBackpressure`, waits for
+/// the `Backpressure::Block` subscribers that fell behind in
+/// the mean time.
+fn resume_backpressure() {
+    let queues = BACKLOG.with(|backlog| {
+        let mut backlog = backlog.borrow_mut();
+        backlog.pegs -= 1;
+        if backlog.pegs == 0 {
+            std::mem::replace(&mut backlog.queues, vec![])
+        } else {
+            vec![]
+        }
+    });
+
+    if std::thread::panicking() {
+        return;
+    }
+
+    for queue in queues {
+        queue.wait_for_room();
+    }
+}
+
 pub(crate) struct ReservedBroadcast {
     subscribers: Vec<PromiseFiller<Event>>,
+    /// The queues of the `Backpressure::Block` subscribers,
+    /// which are waited for after the write that completes
+    /// the `Event` releases its locks.
+    blocking: Vec<Arc<Queue>>,
 }
 
 impl ReservedBroadcast {
     pub fn complete(self, event: Event) {
-        let len = self.subscribers.len();
-        let mut iter = self.subscribers.into_iter();
+        let ReservedBroadcast {
+            subscribers,
+            blocking,
+        } = self;
+
+        let len = subscribers.len();
+        let mut iter = subscribers.into_iter();
 
         let mut sent = 0;
 
@@ -165,6 +587,19 @@ impl ReservedBroadcast {
             let tx = iter.next().unwrap();
             tx.fill(event);
         }
+
+        // the writer may still hold locks that the subscribers
+        // need to make progress, so the subscribers that fell
+        // behind are waited for when its `DeferBackpressure`
+        // is dropped.
+        BACKLOG.with(|backlog| {
+            let mut backlog = backlog.borrow_mut();
+            for queue in blocking {
+                if !backlog.queues.iter().any(|q| Arc::ptr_eq(q, &queue)) {
+                    backlog.queues.push(queue);
+                }
+            }
+        });
     }
 }
 
@@ -172,14 +607,14 @@ impl ReservedBroadcast {
 fn basic_subscription() {
     let subs = Subscriptions::default();
 
-    let mut s2 = subs.register([0].to_vec());
-    let mut s3 = subs.register([0, 1].to_vec());
-    let mut s4 = subs.register([1, 2].to_vec());
+    let mut s2 = subs.register([0].to_vec(), Backpressure::Block);
+    let mut s3 = subs.register([0, 1].to_vec(), Backpressure::Block);
+    let mut s4 = subs.register([1, 2].to_vec(), Backpressure::Block);
 
     let r1 = subs.reserve(b"awft");
     assert!(r1.is_none());
 
-    let mut s1 = subs.register([].to_vec());
+    let mut s1 = subs.register([].to_vec(), Backpressure::Block);
 
     let k2 = vec![];
     let r2 = subs.reserve(&k2).unwrap();
@@ -0,0 +1,318 @@
+use std::{
+    borrow::Borrow, cell::RefCell, collections::HashMap, fmt, sync::Arc,
+};
+
+use super::*;
+
+/// A transaction that will be applied
+/// atomically to the `Tree`, passed to
+/// the closure given to `Tree::transaction`.
+///
+/// Reads are tracked, and writes are buffered
+/// until the closure returns. At that point,
+/// every read is validated against the current
+/// state of the `Tree`. If any of them changed,
+/// the closure is executed again. Otherwise, the
+/// buffered writes are applied as a single
+/// crash-atomic batch.
+pub struct TransactionalTree<'a> {
+    tree: &'a Tree,
+    reads: RefCell<HashMap<IVec, Option<IVec>>>,
+    writes: RefCell<HashMap<IVec, Option<IVec>>>,
+}
+
+/// An error returned from a transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransactionError {
+    /// The transaction was aborted by the caller,
+    /// and none of its writes were applied.
+    Abort,
+    /// An error was encountered by the
+    /// underlying storage.
+    Storage(Error),
+}
+
+/// A transaction-related `Result` which is used for
+/// returning from transactional closures.
+pub type TransactionResult<T> = std::result::Result<T, TransactionError>;
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use TransactionError::*;
+        match self {
+            Abort => write!(f, "Transaction was aborted"),
+            Storage(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TransactionError::Storage(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for TransactionError {
+    fn from(error: Error) -> Self {
+        TransactionError::Storage(error)
+    }
+}
+
+impl<'a> TransactionalTree<'a> {
+    pub(crate) fn new(tree: &'a Tree) -> TransactionalTree<'a> {
+        TransactionalTree {
+            tree,
+            reads: RefCell::new(HashMap::default()),
+            writes: RefCell::new(HashMap::default()),
+        }
+    }
+
+    /// Retrieve a value from the `Tree` if it exists,
+    /// observing any writes made earlier in this
+    /// transaction.
+    pub fn get<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+    ) -> TransactionResult<Option<IVec>> {
+        let key = key.as_ref();
+
+        if let Some(pending) = self.writes.borrow().get(key) {
+            return Ok(pending.clone());
+        }
+
+        if let Some(observed) = self.reads.borrow().get(key) {
+            return Ok(observed.clone());
+        }
+
+        let current = self.tree.get(key)?;
+
+        self.reads
+            .borrow_mut()
+            .insert(IVec::from(key), current.clone());
+
+        Ok(current)
+    }
+
+    /// Set a key to a new value, returning the last value
+    /// if it was set.
+    pub fn insert<K, V>(
+        &self,
+        key: K,
+        value: V,
+    ) -> TransactionResult<Option<IVec>>
+    where
+        K: AsRef<[u8]>,
+        IVec: From<V>,
+    {
+        let last = self.get(key.as_ref())?;
+        self.writes
+            .borrow_mut()
+            .insert(key.as_ref().into(), Some(IVec::from(value)));
+        Ok(last)
+    }
+
+    /// Remove a key, returning the last value if it was set.
+    pub fn remove<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+    ) -> TransactionResult<Option<IVec>> {
+        let last = self.get(key.as_ref())?;
+        self.writes
+            .borrow_mut()
+            .insert(IVec::from(key.as_ref()), None);
+        Ok(last)
+    }
+
+    /// Returns `false` if any key read by this transaction
+    /// has been changed since. Must be called while holding
+    /// the write side of the tree's `concurrency_control`.
+    fn validate(&self) -> Result<bool> {
+        for (key, observed) in self.reads.borrow().iter() {
+            if self.tree.get_inner(key)? != *observed {
+                trace!("transaction conflict on key {:?}, retrying", key);
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Installs the buffered writes. Must be called while holding
+    /// the write side of the tree's `concurrency_control`, and
+    /// within a pinned log batch.
+    fn apply(&self) -> Result<()> {
+        for (k, v_opt) in self.writes.borrow().iter() {
+            self.tree.clear_ttl(k)?;
+            if let Some(v) = v_opt {
+                self.tree.insert_inner(k, v.clone())?;
+            } else {
+                self.tree.remove_inner(k)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Validates the read sets of the provided transactions and,
+/// if nothing that was read has changed since, applies all
+/// of their buffered writes atomically. Returns `false` if
+/// a conflict was detected and the transaction needs to be
+/// retried.
+pub(crate) fn commit(txs: &[&TransactionalTree<'_>]) -> Result<bool> {
+    let first = if let Some(first) = txs.first() {
+        first.tree
+    } else {
+        return Ok(true);
+    };
+
+    if txs.iter().any(|tx| {
+        !Arc::ptr_eq(&tx.tree.context.pagecache, &first.context.pagecache)
+    }) {
+        return Err(Error::Unsupported(
+            "transactions may only span Trees that \
+             belong to the same Db"
+                .to_owned(),
+        ));
+    }
+
+    // lock trees in a globally consistent order, so that
+    // transactions over overlapping sets of trees can not
+    // deadlock each other.
+    let mut trees: Vec<&Tree> = txs.iter().map(|tx| tx.tree).collect();
+    trees.sort_by(|a, b| a.tree_id.cmp(&b.tree_id));
+    trees.dedup_by(|a, b| {
+        Arc::ptr_eq(&a.concurrency_control, &b.concurrency_control)
+    });
+
+    // the log is pinned before the trees are locked, in the
+    // same order as `Batch::apply`, so that writers taking
+    // both can not deadlock each other.
+    let peg = first.context.pin_log()?;
+
+    // excludes every other writer on these trees until
+    // the buffered writes are installed.
+    let ccs: Vec<_> = trees
+        .iter()
+        .map(|t| t.concurrency_control.write())
+        .collect();
+
+    for tx in txs {
+        if !tx.validate()? {
+            return Ok(false);
+        }
+    }
+
+    if txs.iter().all(|tx| tx.writes.borrow().is_empty()) {
+        return Ok(true);
+    }
+
+    // fail before anything is written if one of the trees
+    // has an index that would fall out of date.
+    for tree in &trees {
+        tree.indexes.read().check_writable(&tree.tree_id)?;
+    }
+
+    for tx in txs {
+        tx.apply()?;
+    }
+
+    drop(ccs);
+
+    // when the peg drops, it ensures all updates
+    // written to the log since its creation are
+    // recovered atomically
+    peg.seal_batch()?;
+
+    Ok(true)
+}
+
+/// A collection of `Tree`s that may be
+/// atomically updated together in a single
+/// serializable transaction.
+///
+/// This is implemented for tuples of references
+/// to anything that borrows as a `Tree`, such as
+/// the `Arc<Tree>` returned by `Db::open_tree`.
+/// All `Tree`s must belong to the same `Db`.
+///
+/// # Examples
+///
+/// ```
+/// use sled::{ConfigBuilder, Db, Transactional};
+/// let config = ConfigBuilder::new().temporary(true).build();
+/// let db = Db::start(config).unwrap();
+///
+/// let inbox = db.open_tree(b"inbox").unwrap();
+/// let archive = db.open_tree(b"archive").unwrap();
+///
+/// inbox.insert(b"message", b"hello".to_vec()).unwrap();
+///
+/// // atomically move the message from the inbox to the archive
+/// (&inbox, &archive)
+///     .transaction(|(inbox, archive)| {
+///         if let Some(message) = inbox.remove(b"message")? {
+///             archive.insert(b"message", message)?;
+///         }
+///         Ok(())
+///     })
+///     .unwrap();
+///
+/// assert_eq!(inbox.get(b"message"), Ok(None));
+/// assert!(archive.get(b"message").unwrap().is_some());
+/// ```
+pub trait Transactional {
+    /// The view of the `Tree`s that is passed
+    /// to the transactional closure.
+    type View;
+
+    /// Perform a multi-key serializable transaction
+    /// across all of these `Tree`s. See
+    /// `Tree::transaction` for the retry and
+    /// abort semantics.
+    fn transaction<F, R>(&self, f: F) -> TransactionResult<R>
+    where
+        F: Fn(&Self::View) -> TransactionResult<R>;
+}
+
+macro_rules! transactional_view {
+    ($_name:ident) => {
+        TransactionalTree<'a>
+    };
+}
+
+macro_rules! impl_transactional_tuple {
+    ($($name:ident: $idx:tt),+) => {
+        impl<'a, $($name),+> Transactional for ($(&'a $name,)+)
+        where
+            $($name: Borrow<Tree>),+
+        {
+            type View = ($(transactional_view!($name),)+);
+
+            fn transaction<F, R>(&self, f: F) -> TransactionResult<R>
+            where
+                F: Fn(&Self::View) -> TransactionResult<R>,
+            {
+                loop {
+                    let view = ($(
+                        TransactionalTree::new(
+                            <$name as Borrow<Tree>>::borrow(self.$idx),
+                        ),
+                    )+);
+                    let ret = f(&view)?;
+                    if commit(&[$(&view.$idx),+])? {
+                        return Ok(ret);
+                    }
+                    M.tree_looped();
+                }
+            }
+        }
+    };
+}
+
+impl_transactional_tuple!(T0: 0, T1: 1);
+impl_transactional_tuple!(T0: 0, T1: 1, T2: 2);
+impl_transactional_tuple!(T0: 0, T1: 1, T2: 2, T3: 3);
+impl_transactional_tuple!(T0: 0, T1: 1, T2: 2, T3: 3, T4: 4);
+impl_transactional_tuple!(T0: 0, T1: 1, T2: 2, T3: 3, T4: 4, T5: 5);
@@ -1,15 +1,18 @@
 use std::{
+    borrow::Cow,
     fmt::{self, Debug},
+    future::Future,
     ops::{self, RangeBounds},
     sync::{
-        atomic::{AtomicU64, Ordering::SeqCst},
+        atomic::{AtomicBool, AtomicU64, Ordering::SeqCst},
         Arc,
     },
+    time::Duration,
 };
 
-use parking_lot::RwLock;
+use parking_lot::{RwLock, RwLockReadGuard};
 
-use pagecache::Guard;
+use pagecache::{Guard, Lsn};
 
 use super::*;
 
@@ -17,7 +20,8 @@ use super::*;
 pub(crate) struct View<'g> {
     pub ptr: TreePtr<'g>,
     pub pid: PageId,
-    pub node: &'g Node,
+    // owned if it was read without being cached
+    pub node: Cow<'g, Node>,
     pub size: u64,
 }
 
@@ -43,9 +47,10 @@ impl<'a> IntoIterator for &'a Tree {
 /// # Examples
 ///
 /// ```
-/// use sled::{Db, IVec};
+/// use sled::{ConfigBuilder, Db, IVec};
 ///
-/// let t = Db::open("db").unwrap();
+/// let config = ConfigBuilder::new().temporary(true).build();
+/// let t = Db::start(config).unwrap();
 /// t.insert(b"yo!", b"v1".to_vec());
 /// assert_eq!(t.get(b"yo!"), Ok(Some(IVec::from(b"v1"))));
 ///
@@ -73,6 +78,28 @@ pub struct Tree {
     pub(crate) root: Arc<AtomicU64>,
     pub(crate) concurrency_control: Arc<RwLock<()>>,
     pub(crate) merge_operator: Arc<RwLock<Option<MergeOperator>>>,
+    /// Deadlines of keys written with a time-to-live,
+    /// shared by every `Tree` of the `Db`.
+    pub(crate) expirations: Option<Arc<Tree>>,
+    /// Set if any key of this `Tree` may have a deadline,
+    /// so that others can skip checking `expirations`.
+    pub(crate) has_expirations: Arc<AtomicBool>,
+    /// The feed that writes are recorded in, if
+    /// `ConfigBuilder::change_feed` is enabled.
+    pub(crate) changes: Option<Arc<Tree>>,
+    /// The counters behind `len` and `stats`.
+    pub(crate) stats: Arc<Stats>,
+    /// Where the counters are persisted at shutdown,
+    /// unset for internal trees.
+    pub(crate) stats_tree: Option<Arc<Tree>>,
+    /// The order of the keys, which is byte order unless
+    /// the tree was opened with a comparator.
+    pub(crate) order: KeyOrder,
+    /// Secondary indexes that every write keeps up to date.
+    pub(crate) indexes: Arc<RwLock<Indexes>>,
+    /// The overrides of the `Config` that new nodes are
+    /// created with, set by `Db::open_tree_with`.
+    pub(crate) settings: Arc<RwLock<PageSettings>>,
 }
 
 unsafe impl Send for Tree {}
@@ -120,8 +147,202 @@ impl Tree {
         K: AsRef<[u8]>,
         IVec: From<V>,
     {
-        let _ = self.concurrency_control.read();
-        self.insert_inner(key, value)
+        let key = key.as_ref();
+        self.write_clearing_ttl(key, || self.insert_inner(key, value))
+    }
+
+    /// Insert a key to a new value that expires after the provided
+    /// time-to-live, returning the last value if it was set and had
+    /// not expired yet. Expired keys are invisible to reads right
+    /// away, and are physically removed by the background flush
+    /// thread if `flush_every_ms` is configured, which emits
+    /// `Event::Expired` for them. Writing the
+    /// key again with any other method removes its time-to-live.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use sled::{ConfigBuilder, Db, IVec};
+    /// let config = ConfigBuilder::new().temporary(true).build();
+    /// let t = Db::start(config).unwrap();
+    ///
+    /// t.insert_with_ttl(b"session", vec![1], Duration::from_millis(10)).unwrap();
+    /// assert_eq!(t.get(b"session"), Ok(Some(IVec::from(vec![1]))));
+    ///
+    /// std::thread::sleep(Duration::from_millis(20));
+    /// assert_eq!(t.get(b"session"), Ok(None));
+    /// ```
+    pub fn insert_with_ttl<K, V>(
+        &self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> Result<Option<IVec>>
+    where
+        K: AsRef<[u8]>,
+        IVec: From<V>,
+    {
+        let index = if let Some(ref index) = self.expirations {
+            index
+        } else {
+            return Err(Error::Unsupported(
+                "keys of internal trees can not expire".to_owned(),
+            ));
+        };
+
+        let key = key.as_ref();
+
+        let peg = self.context.pin_log()?;
+        let cc = self.concurrency_control.write();
+
+        self.clear_ttl(key)?;
+        let last_value = self.insert_inner(key, value)?;
+
+        self.has_expirations.store(true, SeqCst);
+        let deadline = ttl::deadline_after(ttl);
+        ttl::set_deadline(index, &self.tree_id, key, deadline)?;
+
+        drop(cc);
+
+        // when the peg drops, it ensures all updates
+        // written to the log since its creation are
+        // recovered atomically
+        peg.seal_batch()?;
+
+        Ok(last_value)
+    }
+
+    /// Runs a write to a key, first removing its time-to-live
+    /// if it has one. Keys without a deadline in trees without
+    /// indexes are written under the read side of the
+    /// `concurrency_control` as usual. Otherwise, the write
+    /// is exclusive, and recovered atomically along with the
+    /// writes to the deadlines and indexes that it causes.
+    /// If the change feed is enabled, a write is always
+    /// recovered atomically along with its record in the feed.
+    fn write_clearing_ttl<R, F>(&self, key: &[u8], f: F) -> Result<R>
+    where
+        F: FnOnce() -> Result<R>,
+    {
+        // dropped last, so that blocking subscribers are only
+        // waited for once the locks below are released
+        let _backpressure = subscription::defer_backpressure();
+
+        let peg = if self.changes.is_some() {
+            Some(self.context.pin_log()?)
+        } else {
+            None
+        };
+
+        let cc = self.concurrency_control.read();
+
+        // deadlines and indexes are only added under the write
+        // side, so this can not change until we release the
+        // read side.
+        let has_deadline = if let Some(index) = self.expirations() {
+            ttl::deadline(index, &self.tree_id, key)?.is_some()
+        } else {
+            false
+        };
+
+        if !has_deadline && self.indexes.read().is_empty() {
+            let ret = f()?;
+            drop(cc);
+            if let Some(peg) = peg {
+                peg.seal_batch()?;
+            }
+            return Ok(ret);
+        }
+
+        drop(cc);
+
+        let peg = if let Some(peg) = peg {
+            peg
+        } else {
+            self.context.pin_log()?
+        };
+        let cc = self.concurrency_control.write();
+
+        self.clear_ttl(key)?;
+        let ret = f()?;
+
+        drop(cc);
+
+        peg.seal_batch()?;
+
+        Ok(ret)
+    }
+
+    /// Returns the shared deadline index if any key of this
+    /// `Tree` may have a deadline.
+    pub(crate) fn expirations(&self) -> Option<&Tree> {
+        match self.expirations {
+            Some(ref index) if self.has_expirations.load(SeqCst) => Some(index),
+            _ => None,
+        }
+    }
+
+    /// Removes the deadline of a key, first removing the key
+    /// itself if it has already expired. Must be called while
+    /// holding the write side of the `concurrency_control`,
+    /// and within a pinned log batch.
+    pub(crate) fn clear_ttl(&self, key: &[u8]) -> Result<()> {
+        let index = if let Some(index) = self.expirations() {
+            index
+        } else {
+            return Ok(());
+        };
+
+        if let Some(deadline) = ttl::deadline(index, &self.tree_id, key)? {
+            if deadline <= ttl::now_millis() {
+                self.remove_with_event(key, subscription::Event::Expired)?;
+            }
+            ttl::remove_deadline(index, &self.tree_id, key, deadline)?;
+        }
+
+        Ok(())
+    }
+
+    /// Keeps `Db::changes_since` from reading past the Lsn
+    /// of a write until that write is recorded in the feed.
+    fn pin_changes(&self) -> Option<RwLockReadGuard<'_, ()>> {
+        self.changes
+            .as_ref()
+            .map(|feed| feed.concurrency_control.read())
+    }
+
+    /// Records a successful write in the change feed, if
+    /// it is enabled, under the Lsn of its log message.
+    fn record_change(
+        &self,
+        lsn: Lsn,
+        event: &subscription::Event,
+    ) -> Result<()> {
+        if let Some(ref feed) = self.changes {
+            changes::record(feed, lsn, &self.tree_id, event)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the persisted counters of this `Tree`, if
+    /// any, so that they are not trusted after a crash
+    /// that recovers the write that follows.
+    pub(crate) fn mark_stats_dirty(&self) -> Result<()> {
+        if let Some(ref stats_tree) = self.stats_tree {
+            stats::mark_dirty(stats_tree, &self.tree_id, &self.stats)?;
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the key has a deadline that has passed.
+    pub(crate) fn is_expired(&self, key: &[u8]) -> Result<bool> {
+        if let Some(index) = self.expirations() {
+            let deadline = ttl::deadline(index, &self.tree_id, key)?;
+            Ok(deadline.map_or(false, |d| d <= ttl::now_millis()))
+        } else {
+            Ok(false)
+        }
     }
 
     pub(crate) fn insert_inner<K, V>(
@@ -144,6 +365,13 @@ impl Tree {
 
         let value = IVec::from(value);
 
+        let indexes = self.indexes.read();
+        indexes.check_writable(&self.tree_id)?;
+
+        let _changes = self.pin_changes();
+
+        self.mark_stats_dirty()?;
+
         loop {
             let guard = pin();
             let View { ptr, pid, node, .. } =
@@ -151,28 +379,46 @@ impl Tree {
 
             let mut subscriber_reservation = self.subscriptions.reserve(&key);
 
-            let (encoded_key, last_value) =
-                if let Some((k, v)) = node.leaf_pair_for_key(key.as_ref()) {
-                    (k.clone(), Some(v.clone()))
-                } else {
-                    let k = prefix_encode(&node.lo, key.as_ref());
-                    let old_v = None;
-                    (k, old_v)
-                };
-            let frag = Frag::Set(encoded_key, value.clone());
-            let link = self.context.pagecache.link(
+            let (encoded_key, last_value) = if let Some((k, v)) =
+                node.leaf_pair_for_key(key.as_ref(), &self.order)
+            {
+                (k.clone(), Some(v.clone()))
+            } else {
+                let k = self.order.encode(&node.lo, key.as_ref());
+                let old_v = None;
+                (k, old_v)
+            };
+            let frag = node.set_frag(encoded_key, value.clone(), &self.order);
+            let link = self.context.pagecache.link_with(
                 pid,
                 ptr.clone(),
                 frag.clone(),
+                &self.page_settings(),
                 &guard,
             )?;
-            if let Ok(_new_cas_key) = link {
+            if let Ok(new_cas_key) = link {
                 // success
-                if let Some(res) = subscriber_reservation.take() {
+                self.stats.wrote(
+                    key.as_ref().len(),
+                    last_value.as_ref().map(|v| v.len()),
+                    Some(value.len()),
+                );
+
+                indexes.update(
+                    key.as_ref(),
+                    last_value.as_ref().map(AsRef::as_ref),
+                    Some(&*value),
+                )?;
+
+                if self.changes.is_some() || subscriber_reservation.is_some() {
                     let event =
                         subscription::Event::Set(key.as_ref().to_vec(), value);
 
-                    res.complete(event);
+                    self.record_change(new_cas_key.last_lsn(), &event)?;
+
+                    if let Some(res) = subscriber_reservation.take() {
+                        res.complete(event);
+                    }
                 }
 
                 return Ok(last_value);
@@ -187,9 +433,10 @@ impl Tree {
     /// # Examples
     ///
     /// ```
-    /// use sled::Db;
+    /// use sled::{ConfigBuilder, Db};
     ///
-    /// let db = Db::open("batch_db").unwrap();
+    /// let config = ConfigBuilder::new().temporary(true).build();
+    /// let db = Db::start(config).unwrap();
     /// db.insert("key_0", "val_0").unwrap();
     /// let mut batch = db.batch();
     /// batch.insert("key_a", "val_a");
@@ -207,6 +454,82 @@ impl Tree {
         }
     }
 
+    /// Perform a multi-key serializable transaction.
+    ///
+    /// The closure may be executed several times: every
+    /// key read through the `TransactionalTree` is validated
+    /// before the buffered writes are applied, and if any of
+    /// them was changed by a concurrent writer in the meantime,
+    /// the closure is retried against the new state. Writes are
+    /// recovered atomically after a crash. Returning
+    /// `Err(TransactionError::Abort)` from the closure discards
+    /// all of its writes. To atomically update several `Tree`s
+    /// of the same `Db`, see `Transactional`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::{ConfigBuilder, Db, IVec, TransactionError};
+    /// let config = ConfigBuilder::new().temporary(true).build();
+    /// let db = Db::start(config).unwrap();
+    ///
+    /// db.insert(b"alice", vec![10]).unwrap();
+    ///
+    /// // atomically move one unit from alice to bob
+    /// db.transaction(|tx| {
+    ///     let alice = tx.get(b"alice")?.map_or(0, |v| v[0]);
+    ///     if alice == 0 {
+    ///         return Err(TransactionError::Abort);
+    ///     }
+    ///     let bob = tx.get(b"bob")?.map_or(0, |v| v[0]);
+    ///     tx.insert(b"alice", vec![alice - 1])?;
+    ///     tx.insert(b"bob", vec![bob + 1])?;
+    ///     Ok(())
+    /// }).unwrap();
+    ///
+    /// assert_eq!(db.get(b"alice"), Ok(Some(IVec::from(vec![9]))));
+    /// assert_eq!(db.get(b"bob"), Ok(Some(IVec::from(vec![1]))));
+    /// ```
+    pub fn transaction<F, R>(&self, f: F) -> TransactionResult<R>
+    where
+        F: Fn(&TransactionalTree<'_>) -> TransactionResult<R>,
+    {
+        loop {
+            let tx = TransactionalTree::new(self);
+            let ret = f(&tx)?;
+            if transaction::commit(&[&tx])? {
+                return Ok(ret);
+            }
+            M.tree_looped();
+        }
+    }
+
+    /// Take a read-only, point-in-time view of this `Tree`.
+    /// Writes that happen after this call returns are not
+    /// visible through the returned `Snapshot`. See the
+    /// `Snapshot` docs for the memory cost of holding one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::{ConfigBuilder, Db, IVec};
+    /// let config = ConfigBuilder::new().temporary(true).build();
+    /// let db = Db::start(config).unwrap();
+    /// let tree = db.open_tree(b"balances").unwrap();
+    ///
+    /// tree.insert(b"alice", vec![10]).unwrap();
+    ///
+    /// let snapshot = tree.snapshot().unwrap();
+    ///
+    /// tree.insert(b"alice", vec![9]).unwrap();
+    ///
+    /// assert_eq!(snapshot.get(b"alice"), Ok(Some(IVec::from(vec![10]))));
+    /// assert_eq!(tree.get(b"alice"), Ok(Some(IVec::from(vec![9]))));
+    /// ```
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        Snapshot::capture(&self.context, &[self], &self.tree_id)
+    }
+
     /// Retrieve a value from the `Tree` if it exists.
     ///
     /// # Examples
@@ -221,7 +544,107 @@ impl Tree {
     /// assert_eq!(t.get(&[1]), Ok(None));
     /// ```
     pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<IVec>> {
-        let _ = self.concurrency_control.read();
+        let _cc = self.concurrency_control.read();
+        self.get_inner(key)
+    }
+
+    /// Retrieve a value from the `Tree` without blocking the
+    /// calling thread. The value is looked up in the cache
+    /// first. Reading a page that is not cached may hit the
+    /// disk, so then the read happens on a background thread if
+    /// one is idle, and the returned `Future` may be awaited on
+    /// any executor.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let config = sled::ConfigBuilder::new().temporary(true).build();
+    /// # let t = sled::Db::start(config).unwrap();
+    /// async fn read(t: &sled::Tree) -> sled::Result<Option<sled::IVec>> {
+    ///     t.get_async(b"k").await
+    /// }
+    /// ```
+    pub fn get_async<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+    ) -> impl Future<Output = Result<Option<IVec>>> {
+        let cached = self.get_cached(key.as_ref());
+        let tree = self.clone();
+        let key = key.as_ref().to_vec();
+        async move {
+            if let Some(res) = cached.transpose() {
+                return res;
+            }
+            let promise = pagecache::spawn_or_run(move || tree.get(key));
+            promise.await.unwrap_or_else(|| {
+                Err(Error::ReportableBug(
+                    "get_async background read panicked".to_owned(),
+                ))
+            })
+        }
+    }
+
+    /// Looks a key up without reading from disk, waiting for a
+    /// lock, or helping with splits and merges. `None` means
+    /// that `get` has to be used instead.
+    fn get_cached(&self, key: &[u8]) -> Result<Option<Option<IVec>>> {
+        let _cc = if let Some(cc) = self.concurrency_control.try_read() {
+            cc
+        } else {
+            return Ok(None);
+        };
+
+        if self.expirations().is_some() {
+            // the deadline of the key may not be cached
+            return Ok(None);
+        }
+
+        let seek = Seek::Key(key);
+        let guard = pin();
+        let mut cursor = self.root.load(SeqCst);
+
+        while cursor != u64::max_value() {
+            let frag = self.context.pagecache.get_cached(
+                cursor,
+                &self.page_settings(),
+                &guard,
+            )?;
+            let node = match frag {
+                Some((_, Cow::Borrowed(Frag::Base(node)), _)) => {
+                    Cow::Borrowed(node)
+                }
+                Some((_, Cow::Owned(Frag::Base(node)), _)) => Cow::Owned(node),
+                _ => return Ok(None),
+            };
+
+            if node.merging_child.is_some()
+                || node.merging
+                || self.order.overshot(seek, &node.lo)
+            {
+                return Ok(None);
+            }
+
+            if self.order.undershot(seek, &node.hi) {
+                // follow a split that its parent does not know of
+                cursor = match node.next {
+                    Some(next) => next,
+                    None => return Ok(None),
+                };
+            } else if node.data.is_index() {
+                cursor = node.index_next_node(seek, &self.order).1;
+            } else {
+                let kv_opt = node.leaf_pair_for_key(key, &self.order);
+                return Ok(Some(kv_opt.map(|kv| kv.1.clone())));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub(crate) fn get_inner<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+    ) -> Result<Option<IVec>> {
         let _measure = Measure::new(&M.tree_get);
         trace!("getting key {:?}", key.as_ref());
 
@@ -229,9 +652,13 @@ impl Tree {
 
         let View { node, .. } = self.node_for_key(key.as_ref(), &guard)?;
 
-        let kv_opt = node.leaf_pair_for_key(key.as_ref());
+        let kv_opt = node.leaf_pair_for_key(key.as_ref(), &self.order);
         let v_opt = kv_opt.map(|kv| kv.1.clone());
 
+        if v_opt.is_some() && self.is_expired(key.as_ref())? {
+            return Ok(None);
+        }
+
         Ok(v_opt)
     }
 
@@ -263,13 +690,23 @@ impl Tree {
     /// assert_eq!(t.remove(&[1]), Ok(None));
     /// ```
     pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<IVec>> {
-        let _ = self.concurrency_control.read();
-        self.remove_inner(key)
+        let key = key.as_ref();
+        self.write_clearing_ttl(key, || self.remove_inner(key))
     }
 
     pub(crate) fn remove_inner<K: AsRef<[u8]>>(
         &self,
         key: K,
+    ) -> Result<Option<IVec>> {
+        self.remove_with_event(key, subscription::Event::Del)
+    }
+
+    /// Removes a key, notifying subscribers with
+    /// the `Event` built by `make_event`.
+    pub(crate) fn remove_with_event<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        make_event: fn(Vec<u8>) -> subscription::Event,
     ) -> Result<Option<IVec>> {
         let _measure = Measure::new(&M.tree_del);
 
@@ -277,6 +714,13 @@ impl Tree {
             return Ok(None);
         }
 
+        let indexes = self.indexes.read();
+        indexes.check_writable(&self.tree_id)?;
+
+        let _changes = self.pin_changes();
+
+        self.mark_stats_dirty()?;
+
         loop {
             let guard = pin();
 
@@ -285,28 +729,48 @@ impl Tree {
 
             let mut subscriber_reservation = self.subscriptions.reserve(&key);
 
-            let (encoded_key, existing_val) =
-                if let Some((k, v)) = node.leaf_pair_for_key(key.as_ref()) {
-                    (k.clone(), Some(v.clone()))
-                } else {
-                    let encoded_key = prefix_encode(&node.lo, key.as_ref());
-                    let encoded_val = None;
-                    (encoded_key, encoded_val)
-                };
+            let (encoded_key, existing_val) = if let Some((k, v)) =
+                node.leaf_pair_for_key(key.as_ref(), &self.order)
+            {
+                (k.clone(), Some(v.clone()))
+            } else {
+                let encoded_key = self.order.encode(&node.lo, key.as_ref());
+                let encoded_val = None;
+                (encoded_key, encoded_val)
+            };
 
-            let frag = Frag::Del(encoded_key);
+            let frag = node.del_frag(encoded_key, &self.order);
 
-            let link =
-                self.context
-                    .pagecache
-                    .link(pid, ptr.clone(), frag, &guard)?;
+            let link = self.context.pagecache.link_with(
+                pid,
+                ptr.clone(),
+                frag,
+                &self.page_settings(),
+                &guard,
+            )?;
 
-            if link.is_ok() {
+            if let Ok(new_cas_key) = link {
                 // success
-                if let Some(res) = subscriber_reservation.take() {
-                    let event = subscription::Event::Del(key.as_ref().to_vec());
+                self.stats.wrote(
+                    key.as_ref().len(),
+                    existing_val.as_ref().map(|v| v.len()),
+                    None,
+                );
 
-                    res.complete(event);
+                indexes.update(
+                    key.as_ref(),
+                    existing_val.as_ref().map(AsRef::as_ref),
+                    None,
+                )?;
+
+                if self.changes.is_some() || subscriber_reservation.is_some() {
+                    let event = make_event(key.as_ref().to_vec());
+
+                    self.record_change(new_cas_key.last_lsn(), &event)?;
+
+                    if let Some(res) = subscriber_reservation.take() {
+                        res.complete(event);
+                    }
                 }
 
                 return Ok(existing_val);
@@ -342,6 +806,21 @@ impl Tree {
         old: Option<OV>,
         new: Option<NV>,
     ) -> Result<std::result::Result<(), Option<IVec>>>
+    where
+        K: AsRef<[u8]>,
+        OV: AsRef<[u8]>,
+        IVec: From<NV>,
+    {
+        let key = key.as_ref();
+        self.write_clearing_ttl(key, || self.cas_inner(key, old, new))
+    }
+
+    pub(crate) fn cas_inner<K, OV, NV>(
+        &self,
+        key: K,
+        old: Option<OV>,
+        new: Option<NV>,
+    ) -> Result<std::result::Result<(), Option<IVec>>>
     where
         K: AsRef<[u8]>,
         OV: AsRef<[u8]>,
@@ -350,8 +829,6 @@ impl Tree {
         trace!("casing key {:?}", key.as_ref());
         let _measure = Measure::new(&M.tree_cas);
 
-        let _ = self.concurrency_control.read();
-
         if self.context.read_only {
             return Err(Error::Unsupported(
                 "can not perform a cas on a read-only Tree".into(),
@@ -360,6 +837,13 @@ impl Tree {
 
         let new = new.map(IVec::from);
 
+        let indexes = self.indexes.read();
+        indexes.check_writable(&self.tree_id)?;
+
+        let _changes = self.pin_changes();
+
+        self.mark_stats_dirty()?;
+
         // we need to retry caps until old != cur, since just because
         // cap fails it doesn't mean our value was changed.
         loop {
@@ -367,14 +851,15 @@ impl Tree {
             let View { ptr, pid, node, .. } =
                 self.node_for_key(key.as_ref(), &guard)?;
 
-            let (encoded_key, current_value) =
-                if let Some((k, v)) = node.leaf_pair_for_key(key.as_ref()) {
-                    (k.clone(), Some(v.clone()))
-                } else {
-                    let k = prefix_encode(&node.lo, key.as_ref());
-                    let old_v = None;
-                    (k, old_v)
-                };
+            let (encoded_key, current_value) = if let Some((k, v)) =
+                node.leaf_pair_for_key(key.as_ref(), &self.order)
+            {
+                (k.clone(), Some(v.clone()))
+            } else {
+                let k = self.order.encode(&node.lo, key.as_ref());
+                let old_v = None;
+                (k, old_v)
+            };
 
             let matches = match (&old, &current_value) {
                 (None, None) => true,
@@ -389,21 +874,43 @@ impl Tree {
             let mut subscriber_reservation = self.subscriptions.reserve(&key);
 
             let frag = if let Some(ref new) = new {
-                Frag::Set(encoded_key, new.clone())
+                node.set_frag(encoded_key, new.clone(), &self.order)
             } else {
-                Frag::Del(encoded_key)
+                node.del_frag(encoded_key, &self.order)
             };
-            let link = self.context.pagecache.link(pid, ptr, frag, &guard)?;
+            let link = self.context.pagecache.link_with(
+                pid,
+                ptr,
+                frag,
+                &self.page_settings(),
+                &guard,
+            )?;
 
-            if link.is_ok() {
-                if let Some(res) = subscriber_reservation.take() {
+            if let Ok(new_cas_key) = link {
+                self.stats.wrote(
+                    key.as_ref().len(),
+                    current_value.as_ref().map(|v| v.len()),
+                    new.as_ref().map(|v| v.len()),
+                );
+
+                indexes.update(
+                    key.as_ref(),
+                    current_value.as_ref().map(AsRef::as_ref),
+                    new.as_ref().map(AsRef::as_ref),
+                )?;
+
+                if self.changes.is_some() || subscriber_reservation.is_some() {
                     let event = if let Some(new) = new {
                         subscription::Event::Set(key.as_ref().to_vec(), new)
                     } else {
                         subscription::Event::Del(key.as_ref().to_vec())
                     };
 
-                    res.complete(event);
+                    self.record_change(new_cas_key.last_lsn(), &event)?;
+
+                    if let Some(res) = subscriber_reservation.take() {
+                        res.complete(event);
+                    }
                 }
 
                 return Ok(Ok(()));
@@ -550,8 +1057,9 @@ impl Tree {
     /// of `Event`s across different keys. If subscribers don't
     /// keep up with new writes, they will cause new writes
     /// to block. There is a buffer of 1024 items per
-    /// `Subscriber`. This can be used to build reactive
-    /// and replicated systems.
+    /// `Subscriber`, and `watch_prefix_with` allows other
+    /// behavior when it fills up. This can be used to build
+    /// reactive and replicated systems.
     ///
     /// # Examples
     /// ```
@@ -574,13 +1082,46 @@ impl Tree {
     ///         Event::Set(key, value) => assert_eq!(key, vec![0]),
     ///         Event::Merge(key, partial_value) => {}
     ///         Event::Del(key) => {}
+    ///         Event::Expired(key) => {}
+    ///         Event::DelRange(start, end) => {}
+    ///         Event::Lagged(missed) => {}
+    ///         _ => {}
     ///     }
     /// }
     ///
     /// thread.join().unwrap();
     /// ```
     pub fn watch_prefix(&self, prefix: Vec<u8>) -> Subscriber {
-        self.subscriptions.register(prefix)
+        self.subscriptions.register(prefix, Backpressure::Block)
+    }
+
+    /// Subscribe to `Event`s that happen to keys that have
+    /// the specified prefix, like `watch_prefix`, choosing
+    /// what happens when the `Subscriber` falls more than
+    /// 1024 `Event`s behind.
+    ///
+    /// # Examples
+    /// ```
+    /// use sled::{Backpressure, ConfigBuilder, Event};
+    /// let config = ConfigBuilder::new().temporary(true).build();
+    ///
+    /// let tree = sled::Db::start(config).unwrap();
+    ///
+    /// let mut events = tree.watch_prefix_with(vec![], Backpressure::Lag);
+    ///
+    /// // writers never block on a slow subscriber
+    /// for i in 0..2000_u32 {
+    ///     tree.insert(i.to_be_bytes(), vec![]).unwrap();
+    /// }
+    ///
+    /// assert_eq!(events.nth(1024), Some(Event::Lagged(976)));
+    /// ```
+    pub fn watch_prefix_with(
+        &self,
+        prefix: Vec<u8>,
+        backpressure: Backpressure,
+    ) -> Subscriber {
+        self.subscriptions.register(prefix, backpressure)
     }
 
     /// Flushes all dirty IO buffers and calls fsync.
@@ -592,6 +1133,37 @@ impl Tree {
         self.context.pagecache.flush()
     }
 
+    /// Asynchronously flushes all dirty IO buffers and calls
+    /// fsync. The returned `Future` resolves once every write
+    /// that happened before this call is durable, with the
+    /// number of bytes flushed while waiting. The flushing
+    /// happens on a background thread, so the returned
+    /// `Future` may be awaited on any executor without
+    /// blocking it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # let config = sled::ConfigBuilder::new().temporary(true).build();
+    /// # let t = sled::Db::start(config).unwrap();
+    /// async fn durable_insert(t: &sled::Tree) -> sled::Result<()> {
+    ///     t.insert(b"k", vec![1])?;
+    ///     t.flush_async().await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn flush_async(&self) -> impl Future<Output = Result<usize>> {
+        let lsn = self.context.pagecache.max_reserved_lsn();
+        let promise = self.context.pagecache.make_stable_async(lsn);
+        async move {
+            promise.await.unwrap_or_else(|| {
+                Err(Error::ReportableBug(
+                    "flush_async background flush panicked".to_owned(),
+                ))
+            })
+        }
+    }
+
     /// Returns `true` if the `Tree` contains a value for
     /// the specified key.
     ///
@@ -635,7 +1207,6 @@ impl Tree {
         K: AsRef<[u8]>,
     {
         let _measure = Measure::new(&M.tree_get);
-        let _ = self.concurrency_control.read();
         self.range(..key).next_back().transpose()
     }
 
@@ -647,7 +1218,8 @@ impl Tree {
     ///
     /// `[] < [0] < [255] < [255, 0] < [255, 255] ...`
     ///
-    /// To retain the ordering of numerical types use big endian reprensentation
+    /// To retain the ordering of numerical types use big endian reprensentation,
+    /// or open the tree with `Db::open_tree_with_comparator`.
     ///
     /// # Examples
     ///
@@ -675,7 +1247,6 @@ impl Tree {
         K: AsRef<[u8]>,
     {
         let _measure = Measure::new(&M.tree_get);
-        let _ = self.concurrency_control.read();
         self.range((ops::Bound::Excluded(key), ops::Bound::Unbounded))
             .next()
             .transpose()
@@ -741,8 +1312,8 @@ impl Tree {
         K: AsRef<[u8]>,
         V: AsRef<[u8]>,
     {
-        let _ = self.concurrency_control.read();
-        self.merge_inner(key, value)
+        let key = key.as_ref();
+        self.write_clearing_ttl(key, || self.merge_inner(key, value))
     }
 
     pub(crate) fn merge_inner<K, V>(
@@ -777,12 +1348,12 @@ impl Tree {
         let merge_operator = merge_operator_opt.unwrap();
 
         let key = key.as_ref();
-        let mut current = self.get(key)?;
+        let mut current = self.get_inner(key)?;
 
         loop {
             let tmp = current.as_ref().map(AsRef::as_ref);
             let next = merge_operator(key, tmp, value.as_ref()).map(IVec::from);
-            match self.cas::<_, _, IVec>(key, tmp, next.clone())? {
+            match self.cas_inner::<_, _, IVec>(key, tmp, next.clone())? {
                 Ok(()) => return Ok(next),
                 Err(new_current) => current = new_current,
             }
@@ -937,11 +1508,16 @@ impl Tree {
             cached_node: None,
             guard: pin(),
             going_forward: true,
+            cache: true,
+            expiry_leaf: None,
+            any_due: false,
         }
     }
 
     /// Create an iterator over tuples of keys and values,
     /// where the all the keys starts with the given prefix.
+    /// See `Db::open_tree_with_comparator` for how this
+    /// behaves in trees with a custom key order.
     ///
     /// # Examples
     ///
@@ -1066,7 +1642,11 @@ impl Tree {
 
     /// Returns the number of elements in this tree.
     ///
-    /// Beware: performs a full O(n) scan under the hood.
+    /// This is read from counters that are kept up to date by
+    /// every write, so it does not scan the tree, unless the
+    /// counters were lost in a crash. Keys whose time-to-live
+    /// has passed are counted until they are removed in the
+    /// background.
     ///
     /// # Examples
     ///
@@ -1078,7 +1658,42 @@ impl Tree {
     /// assert_eq!(t.len(), 2);
     /// ```
     pub fn len(&self) -> usize {
-        self.iter().count()
+        match self.stats() {
+            Ok(stats) => stats.len as usize,
+            Err(_) => self.iter().count(),
+        }
+    }
+
+    /// Returns the number of keys in this tree, their total
+    /// size and the total size of their values, along with
+    /// the number of nodes and levels of the tree.
+    ///
+    /// The counters are kept up to date by every write, and
+    /// persisted when the last handle to the `Db` is dropped.
+    /// After a crash, they are recounted with a full scan the
+    /// first time this is called.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let config = sled::ConfigBuilder::new().temporary(true).build();
+    /// let t = sled::Db::start(config).unwrap();
+    /// t.insert(b"a", vec![0, 0]);
+    /// t.insert(b"bb", vec![1]);
+    ///
+    /// let stats = t.stats().unwrap();
+    /// assert_eq!(stats.len, 2);
+    /// assert_eq!(stats.key_bytes, 3);
+    /// assert_eq!(stats.value_bytes, 3);
+    /// ```
+    pub fn stats(&self) -> Result<TreeStats> {
+        if !self.stats.is_known() {
+            let _cc = self.concurrency_control.write();
+            if !self.stats.is_known() {
+                stats::recount(self)?;
+            }
+        }
+        Ok(self.stats.current())
     }
 
     /// Returns `true` if the `Tree` contains no elements.
@@ -1086,22 +1701,489 @@ impl Tree {
         self.iter().next().is_none()
     }
 
-    /// Clears the `Tree`, removing all values.
+    /// Fills an empty `Tree` with key-value pairs that are sorted
+    /// in the order of its keys, returning how many there were.
+    /// This is much faster than inserting them one by one: nodes
+    /// are packed as full as they may be and written once each,
+    /// from the leaves up, and the new root is swapped in at the
+    /// end, so that either all of the pairs become visible at
+    /// once, or, after a crash, none of them do.
     ///
-    /// Note that this is not atomic.
-    pub fn clear(&self) -> Result<()> {
-        for k in self.iter().keys() {
-            let key = k?;
-            self.remove(key)?;
+    /// Fails without writing anything if the `Tree` is not empty,
+    /// or if the keys are not strictly ascending. Other reads and
+    /// writes to the `Tree` wait until loading is done. Subscribers
+    /// are not notified of the loaded pairs, and trees with
+    /// indexes or a change feed can not be bulk loaded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::{ConfigBuilder, Db, IVec};
+    /// let config = ConfigBuilder::new().temporary(true).build();
+    /// let t = Db::start(config).unwrap();
+    ///
+    /// let pairs = (0..1000_u32).map(|i| (i.to_be_bytes(), vec![0; 8]));
+    /// assert_eq!(t.bulk_load(pairs), Ok(1000));
+    /// assert_eq!(t.len(), 1000);
+    /// assert_eq!(t.get(500_u32.to_be_bytes()), Ok(Some(IVec::from(vec![0; 8]))));
+    ///
+    /// // only an empty tree can be bulk loaded
+    /// assert!(t.bulk_load(vec![(b"a", b"1")]).is_err());
+    /// ```
+    pub fn bulk_load<I, K, V>(&self, iter: I) -> Result<usize>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<[u8]>,
+        IVec: From<V>,
+    {
+        if self.context.read_only {
+            return Err(Error::Unsupported(
+                "the database is in read-only mode".to_owned(),
+            ));
+        }
+
+        let _cc = self.concurrency_control.write();
+
+        if !self.indexes.read().is_empty() {
+            return Err(Error::Unsupported(
+                "bulk_load can not keep the indexes of a tree up to date"
+                    .to_owned(),
+            ));
+        }
+
+        if self.changes.is_some() {
+            return Err(Error::Unsupported(
+                "bulk_load can not record the loaded pairs in the change feed"
+                    .to_owned(),
+            ));
         }
+
+        bulk_load::load(self, iter)
+    }
+
+    /// Clears the `Tree`, atomically removing all values.
+    /// See `remove_range` for details.
+    pub fn clear(&self) -> Result<()> {
+        self.remove_range::<&[u8], _>(..)?;
         Ok(())
     }
 
+    /// Atomically removes every key in the range, returning
+    /// how many were removed. After a crash, either all or
+    /// none of them are gone. Each leaf that overlaps the
+    /// range is rewritten once without the removed keys,
+    /// instead of logging a deletion for every key, and
+    /// subscribers to prefixes that overlap the range receive
+    /// a single `Event::DelRange`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::{ConfigBuilder, Db, IVec};
+    /// let config = ConfigBuilder::new().temporary(true).build();
+    /// let t = Db::start(config).unwrap();
+    ///
+    /// for i in 0..10 {
+    ///     t.insert(&[i], vec![i]).unwrap();
+    /// }
+    ///
+    /// let start: &[u8] = &[2];
+    /// let end: &[u8] = &[8];
+    /// assert_eq!(t.remove_range(start..end), Ok(6));
+    /// assert_eq!(t.len(), 4);
+    /// assert_eq!(t.get(&[7]), Ok(None));
+    /// assert_eq!(t.get(&[8]), Ok(Some(IVec::from(vec![8]))));
+    /// ```
+    pub fn remove_range<K, R>(&self, range: R) -> Result<usize>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        let _measure = Measure::new(&M.tree_del);
+
+        if self.context.read_only {
+            return Err(Error::Unsupported(
+                "the database is in read-only mode".to_owned(),
+            ));
+        }
+
+        let peg = self.context.pin_log()?;
+        let cc = self.concurrency_control.write();
+
+        let (lo, hi) = if let Some(bounds) = self.range_bounds(range)? {
+            bounds
+        } else {
+            return Ok(0);
+        };
+
+        if hi.as_ref().map_or(false, |hi| {
+            self.order.cmp(hi, &lo) != std::cmp::Ordering::Greater
+        }) {
+            return Ok(0);
+        }
+
+        let removed =
+            self.remove_range_inner(&lo, hi.as_ref().map(Vec::as_slice))?;
+
+        drop(cc);
+
+        // all leaves that were rewritten since the peg was
+        // created are recovered atomically
+        peg.seal_batch()?;
+
+        Ok(removed)
+    }
+
+    /// Expresses a range as `lo..hi`, or `lo..` if `hi` is
+    /// `None`, returning `None` if it can not hold any keys.
+    /// In byte order, appending a zero byte to a key produces
+    /// the first key that sorts after it. Other orders have
+    /// no such key, so the nearest present keys are used
+    /// instead, which requires holding the write side of the
+    /// `concurrency_control`.
+    fn range_bounds<K, R>(
+        &self,
+        range: R,
+    ) -> Result<Option<(Vec<u8>, Option<Vec<u8>>)>>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        let after = |key: &K| -> Result<Option<Vec<u8>>> {
+            if self.order.is_lexicographic() {
+                let mut after = key.as_ref().to_vec();
+                after.push(0);
+                Ok(Some(after))
+            } else {
+                self.first_key_after(key.as_ref())
+            }
+        };
+
+        let lo = match range.start_bound() {
+            ops::Bound::Included(start) => start.as_ref().to_vec(),
+            ops::Bound::Excluded(start) => {
+                if let Some(lo) = after(start)? {
+                    lo
+                } else {
+                    return Ok(None);
+                }
+            }
+            ops::Bound::Unbounded => vec![],
+        };
+        let hi = match range.end_bound() {
+            // with no key after the end, the range is unbounded
+            ops::Bound::Included(end) => after(end)?,
+            ops::Bound::Excluded(end) => Some(end.as_ref().to_vec()),
+            ops::Bound::Unbounded => None,
+        };
+
+        Ok(Some((lo, hi)))
+    }
+
+    /// Returns the first key after `key`, including keys whose
+    /// time-to-live has passed, without taking the
+    /// `concurrency_control`.
+    fn first_key_after(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let guard = pin();
+        let mut bound = ops::Bound::Excluded(IVec::from(key));
+        let mut cursor = key.to_vec();
+
+        loop {
+            let view = self.node_for_key(&cursor, &guard)?;
+            if let Some((k, _v)) = view.successor(&bound, &self.order) {
+                return Ok(Some(k.to_vec()));
+            }
+            if view.hi.is_empty() {
+                return Ok(None);
+            }
+            cursor = view.hi.to_vec();
+            bound = ops::Bound::Included(view.hi.clone());
+        }
+    }
+
+    /// Removes the keys in `lo..hi`, or `lo..` if `hi` is `None`.
+    /// Must be called while holding the write side of the
+    /// `concurrency_control`, and within a pinned log batch.
+    fn remove_range_inner(
+        &self,
+        lo: &[u8],
+        hi: Option<&[u8]>,
+    ) -> Result<usize> {
+        use std::cmp::Ordering::{Greater, Less};
+
+        let order = &self.order;
+        let in_range = |key: &[u8]| {
+            order.cmp(key, lo) != Less
+                && hi.map_or(true, |hi| order.cmp(key, hi) == Less)
+        };
+
+        let indexes = self.indexes.read();
+        indexes.check_writable(&self.tree_id)?;
+
+        let _changes = self.pin_changes();
+
+        self.mark_stats_dirty()?;
+
+        let mut subscriber_reservation = if order.is_lexicographic() {
+            self.subscriptions.reserve_range(lo, hi)
+        } else {
+            self.subscriptions.reserve_all()
+        };
+
+        let mut removed = 0;
+        let mut last_lsn = None;
+        let mut cursor = lo.to_vec();
+
+        loop {
+            let guard = pin();
+            let view = self.node_for_key(&cursor, &guard)?;
+
+            let doomed: Vec<(Vec<u8>, IVec)> = view
+                .data
+                .leaf_ref()
+                .expect("node_for_key should always return a leaf")
+                .iter()
+                .map(|(k, v)| (prefix_decode(&view.lo, k), v.clone()))
+                .filter(|(k, _)| in_range(&k[..]))
+                .collect();
+
+            if !doomed.is_empty() {
+                let mut node = view.node.clone().into_owned();
+                if let Data::Leaf(ref mut items) = node.data {
+                    items.retain(|(k, _)| {
+                        !in_range(&prefix_decode(&view.lo, k)[..])
+                    });
+                }
+
+                let replace = self.context.pagecache.replace_with(
+                    view.pid,
+                    view.ptr.clone(),
+                    Frag::Base(node),
+                    &self.page_settings(),
+                    &guard,
+                )?;
+                match replace {
+                    Ok(new_ptr) => last_lsn = Some(new_ptr.last_lsn()),
+                    Err(_) => {
+                        // the leaf was split or merged by a
+                        // concurrent reader, so look it up again.
+                        M.tree_looped();
+                        continue;
+                    }
+                }
+
+                for (key, value) in &doomed {
+                    self.stats.wrote(key.len(), Some(value.len()), None);
+                    indexes.update(key, Some(&**value), None)?;
+                    if let Some(index) = self.expirations() {
+                        let deadline =
+                            ttl::deadline(index, &self.tree_id, key)?;
+                        if let Some(deadline) = deadline {
+                            ttl::remove_deadline(
+                                index,
+                                &self.tree_id,
+                                key,
+                                deadline,
+                            )?;
+                        }
+                    }
+                }
+                removed += doomed.len();
+            }
+
+            if view.hi.is_empty()
+                || hi.map_or(false, |hi| order.cmp(hi, &view.hi) != Greater)
+            {
+                break;
+            }
+            cursor = view.hi.to_vec();
+        }
+
+        if let Some(lsn) = last_lsn {
+            if self.changes.is_some() || subscriber_reservation.is_some() {
+                let event = subscription::Event::DelRange(
+                    lo.to_vec(),
+                    hi.map(|hi| hi.to_vec()),
+                );
+
+                self.record_change(lsn, &event)?;
+
+                if let Some(res) = subscriber_reservation.take() {
+                    res.complete(event);
+                }
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Adds a secondary index to this `Tree`, which maps each
+    /// index key that `extractor` returns for a key and its value
+    /// back to that key. Every later write to the `Tree`,
+    /// including batches, transactions and merges, updates the
+    /// index atomically with itself. Writes to a `Tree` with
+    /// indexes are exclusive, so they do not run concurrently
+    /// with each other.
+    ///
+    /// If the index does not exist yet, it is filled from the
+    /// existing keys first. Extractors are not persisted, so
+    /// existing indexes must be added again with the same
+    /// extractor after the `Db` is reopened, before the `Tree`
+    /// is written to. Until then, writes fail, because they
+    /// would leave the index out of date. Indexes that are no
+    /// longer needed can be removed with `drop_index`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::{ConfigBuilder, Db, IVec};
+    ///
+    /// // index email addresses by their domain
+    /// fn domain(_key: &[u8], email: &[u8]) -> Vec<Vec<u8>> {
+    ///     email
+    ///         .iter()
+    ///         .position(|b| *b == b'@')
+    ///         .map(|at| email[at + 1..].to_vec())
+    ///         .into_iter()
+    ///         .collect()
+    /// }
+    ///
+    /// let config = ConfigBuilder::new().temporary(true).build();
+    /// let db = Db::start(config).unwrap();
+    /// let users = db.open_tree(b"users").unwrap();
+    ///
+    /// users.insert(b"alice", b"alice@a.com".to_vec()).unwrap();
+    ///
+    /// let by_domain = users.add_index(b"by domain", domain).unwrap();
+    ///
+    /// users.insert(b"bob", b"bob@a.com".to_vec()).unwrap();
+    /// users.insert(b"carol", b"carol@b.com".to_vec()).unwrap();
+    ///
+    /// assert_eq!(
+    ///     by_domain.get(b"a.com").unwrap(),
+    ///     vec![IVec::from(b"alice"), IVec::from(b"bob")]
+    /// );
+    ///
+    /// users.insert(b"bob", b"bob@b.com".to_vec()).unwrap();
+    /// let records = by_domain.get_records(b"b.com").unwrap();
+    /// assert_eq!(records[0], (IVec::from(b"bob"), IVec::from(b"bob@b.com")));
+    /// assert_eq!(records.len(), 2);
+    /// ```
+    pub fn add_index<N: AsRef<[u8]>>(
+        &self,
+        name: N,
+        extractor: IndexExtractor,
+    ) -> Result<Index> {
+        indexes::add(self, name.as_ref(), extractor)
+    }
+
+    /// Removes a secondary index of this `Tree`, whether or not
+    /// it was added in this process, returning `false` if there
+    /// was no index with that name.
+    pub fn drop_index<N: AsRef<[u8]>>(&self, name: N) -> Result<bool> {
+        if self.context.read_only {
+            return Err(Error::Unsupported(
+                "the database is in read-only mode".to_owned(),
+            ));
+        }
+        indexes::remove(self, name.as_ref())
+    }
+
+    /// Removes this `Tree` from the `Meta`, returning the
+    /// leftmost page of each of its levels, which may be
+    /// passed to `gc_pages` once no other thread can
+    /// reach the tree anymore.
+    pub(crate) fn detach(&self, guard: &Guard) -> Result<Vec<PageId>> {
+        let mut root_id = Some(
+            self.context
+                .pagecache
+                .meta_pid_for_name(&self.tree_id, guard)?,
+        );
+
+        let mut leftmost_chain: Vec<PageId> = vec![root_id.unwrap()];
+        let mut cursor = root_id.unwrap();
+        while let Some(view) = self.view_for_pid(cursor, guard)? {
+            if let Some(index) = view.data.index_ref() {
+                let leftmost_child = index[0].1;
+                leftmost_chain.push(leftmost_child);
+                cursor = leftmost_child;
+            } else {
+                break;
+            }
+        }
+
+        loop {
+            let res = self.context.pagecache.cas_root_in_meta(
+                self.tree_id.clone(),
+                root_id,
+                None,
+                guard,
+            )?;
+
+            if let Err(actual_root) = res {
+                root_id = actual_root;
+            } else {
+                break;
+            }
+        }
+
+        self.root.store(u64::max_value(), SeqCst);
+
+        Ok(leftmost_chain)
+    }
+
     /// Returns the name of the tree.
     pub fn name(&self) -> Vec<u8> {
         self.tree_id.clone()
     }
 
+    /// Returns the overrides of the global `Config` that
+    /// apply to this tree, which were persisted by the last
+    /// call to `Db::open_tree_with` that opened it.
+    pub fn config(&self) -> TreeConfig {
+        TreeConfig {
+            settings: *self.settings.read(),
+        }
+    }
+
+    /// Persists new overrides of the `Config` for this tree.
+    /// They are passed to the `PageCache` along with each page
+    /// that is written or read, so no node needs to be rewritten.
+    pub(crate) fn set_settings(&self, settings: PageSettings) -> Result<()> {
+        // exclude writers, so that the settings in memory and
+        // in the `Meta` change together
+        let _cc = self.concurrency_control.write();
+
+        if *self.settings.read() == settings {
+            return Ok(());
+        }
+        if self.context.read_only {
+            return Err(Error::Unsupported(
+                "cannot change the configuration of a tree \
+                 in read-only mode"
+                    .into(),
+            ));
+        }
+
+        let guard = pin();
+
+        self.context.pagecache.set_settings_in_meta(
+            self.tree_id.clone(),
+            settings,
+            &guard,
+        )?;
+
+        *self.settings.write() = settings;
+
+        Ok(())
+    }
+
+    /// Returns the overrides of the `Config` that the pages of
+    /// this tree are written and cached with.
+    pub(crate) fn page_settings(&self) -> PageSettings {
+        *self.settings.read()
+    }
+
     fn split_node<'g>(
         &self,
         node_view: View<'g>,
@@ -1111,19 +2193,23 @@ impl Tree {
     ) -> Result<()> {
         trace!("splitting node {}", node_view.pid);
         // split node
-        let (mut lhs, rhs) = node_view.node.clone().split();
+        let (mut lhs, rhs) = node_view.node.clone().into_owned().split();
         let rhs_lo = rhs.lo.clone();
 
         // install right side
-        let (rhs_pid, rhs_ptr) =
-            self.context.pagecache.allocate(Frag::Base(rhs), guard)?;
+        let (rhs_pid, rhs_ptr) = self.context.pagecache.allocate_with(
+            Frag::Base(rhs),
+            &self.page_settings(),
+            guard,
+        )?;
 
         // replace node, pointing next to installed right
         lhs.next = Some(rhs_pid);
-        let replace = self.context.pagecache.replace(
+        let replace = self.context.pagecache.replace_with(
             node_view.pid,
             node_view.ptr.clone(),
             Frag::Base(lhs),
+            &self.page_settings(),
             guard,
         )?;
         M.tree_child_split_attempt();
@@ -1137,12 +2223,14 @@ impl Tree {
             return Ok(());
         }
         M.tree_child_split_success();
+        self.stats.split();
 
         // either install parent split or hoist root
         if let Some(parent_view) = parent_view {
             M.tree_parent_split_attempt();
-            let mut parent = parent_view.node.clone();
-            let split_applied = parent.parent_split(&rhs_lo, rhs_pid);
+            let mut parent = parent_view.node.clone().into_owned();
+            let split_applied =
+                parent.parent_split(&rhs_lo, rhs_pid, &self.order);
 
             if !split_applied {
                 // due to deep races, it's possible for the
@@ -1152,10 +2240,11 @@ impl Tree {
                 return Ok(());
             }
 
-            let replace = self.context.pagecache.replace(
+            let replace = self.context.pagecache.replace_with(
                 parent_view.pid,
                 parent_view.ptr.clone(),
                 Frag::Base(parent),
+                &self.page_settings(),
                 guard,
             )?;
             if replace.is_ok() {
@@ -1199,8 +2288,10 @@ impl Tree {
             merging: false,
         });
 
-        let (new_root_pid, new_root_ptr) =
-            self.context.pagecache.allocate(new_root, guard)?;
+        let (new_root_pid, new_root_ptr) = self
+            .context
+            .pagecache
+            .allocate_with(new_root, &self.page_settings(), guard)?;
         debug!("allocated pid {} in root_hoist", new_root_pid);
 
         debug_delay();
@@ -1213,6 +2304,7 @@ impl Tree {
         )?;
         if cas.is_ok() {
             debug!("root hoist from {} to {} successful", from, new_root_pid);
+            self.stats.root_hoisted();
 
             // we spin in a cas loop because it's possible
             // 2 threads are at this point, and we don't want
@@ -1241,23 +2333,49 @@ impl Tree {
         &self,
         pid: PageId,
         guard: &'g Guard,
+    ) -> Result<Option<View<'g>>> {
+        self.view_for_pid_with(pid, true, guard)
+    }
+
+    /// Like `view_for_pid`, but if `cache` is false, a page
+    /// that has been paged out is read from disk without
+    /// being added to the cache.
+    pub(crate) fn view_for_pid_with<'g>(
+        &self,
+        pid: PageId,
+        cache: bool,
+        guard: &'g Guard,
     ) -> Result<Option<View<'g>>> {
         loop {
-            let frag_opt = self.context.pagecache.get(pid, guard)?;
-            if let Some((tree_ptr, Frag::Base(ref leaf), size)) = &frag_opt {
-                let view = View {
-                    node: leaf,
-                    ptr: tree_ptr.clone(),
-                    pid,
-                    size: *size,
-                };
-                if leaf.merging_child.is_some() {
-                    self.merge_node(view, leaf.merging_child.unwrap(), guard)?;
-                } else {
-                    return Ok(Some(view));
-                }
+            let frag_opt = if cache {
+                self.context
+                    .pagecache
+                    .get_with(pid, &self.page_settings(), guard)?
+                    .map(|(ptr, frag, size)| (ptr, Cow::Borrowed(frag), size))
+            } else {
+                self.context.pagecache.get_uncached(pid, guard)?
+            };
+            let (tree_ptr, frag, size) = if let Some(got) = frag_opt {
+                got
             } else {
                 return Ok(None);
+            };
+            let node = match frag {
+                Cow::Borrowed(Frag::Base(leaf)) => Cow::Borrowed(leaf),
+                Cow::Owned(Frag::Base(leaf)) => Cow::Owned(leaf),
+                _ => return Ok(None),
+            };
+            let view = View {
+                node,
+                ptr: tree_ptr,
+                pid,
+                size,
+            };
+            match view.merging_child {
+                Some(child) if !self.context.read_only => {
+                    self.merge_node(view, child, guard)?;
+                }
+                _ => return Ok(Some(view)),
             }
         }
     }
@@ -1272,6 +2390,28 @@ impl Tree {
     where
         K: AsRef<[u8]>,
     {
+        self.seek_node(Seek::Key(key.as_ref()), guard)
+    }
+
+    /// Like `node_for_key`, but may also look for the leaf
+    /// that precedes a key, or for the rightmost leaf.
+    pub(crate) fn seek_node<'g>(
+        &self,
+        seek: Seek<'_>,
+        guard: &'g Guard,
+    ) -> Result<View<'g>> {
+        self.seek_node_with(seek, true, guard)
+    }
+
+    /// Like `seek_node`, but if `cache` is false, the pages
+    /// that have been paged out are read from disk without
+    /// being added to the cache.
+    pub(crate) fn seek_node_with<'g>(
+        &self,
+        seek: Seek<'_>,
+        cache: bool,
+        guard: &'g Guard,
+    ) -> Result<View<'g>> {
         #[cfg(feature = "lock_free_delays")]
         const MAX_LOOPS: usize = usize::max_value();
 
@@ -1280,6 +2420,12 @@ impl Tree {
 
         let _measure = Measure::new(&M.tree_traverse);
 
+        // a read-only process may not finish the splits and
+        // merges it comes across, and leaves them to the
+        // process that writes. until then, the old structure
+        // still leads to every key.
+        let read_only = self.context.read_only;
+
         let mut cursor = self.root.load(SeqCst);
         let mut root_pid = cursor;
         let mut parent_view = None;
@@ -1308,7 +2454,7 @@ impl Tree {
                 return Err(Error::CollectionNotFound(self.tree_id.clone()));
             }
 
-            let node_opt = self.view_for_pid(cursor, guard)?;
+            let node_opt = self.view_for_pid_with(cursor, cache, guard)?;
 
             let view = if let Some(view) = node_opt {
                 view
@@ -1317,7 +2463,9 @@ impl Tree {
             };
 
             // When we encounter a merge intention, we collaboratively help out
-            if view.merging_child.is_some() {
+            if read_only {
+                // the merging child still holds all of its items
+            } else if view.merging_child.is_some() {
                 self.merge_node(
                     view.clone(),
                     view.node.merging_child.unwrap(),
@@ -1330,16 +2478,15 @@ impl Tree {
                 retry!();
             }
 
-            let overshot = key.as_ref() < view.lo.as_ref();
-            let undershot =
-                key.as_ref() >= view.hi.as_ref() && !view.hi.is_empty();
+            let overshot = self.order.overshot(seek, &view.lo);
+            let undershot = self.order.undershot(seek, &view.hi);
 
             if overshot {
                 // merge interfered, reload root and retry
                 retry!();
             }
 
-            if view.should_split() {
+            if view.should_split() && !read_only {
                 self.split_node(view.clone(), &parent_view, root_pid, guard)?;
                 retry!();
             }
@@ -1350,7 +2497,9 @@ impl Tree {
                     "if our hi bound is not Inf (inity), \
                      we should have a right sibling",
                 );
-                if unsplit_parent.is_none() && parent_view.is_some() {
+                if read_only {
+                    // follow the right sibling without fixing its parent
+                } else if unsplit_parent.is_none() && parent_view.is_some() {
                     unsplit_parent = parent_view.clone();
                 } else if parent_view.is_none() && view.lo.is_empty() {
                     assert_eq!(view.pid, root_pid);
@@ -1369,9 +2518,9 @@ impl Tree {
             } else if let Some(unsplit_parent) = unsplit_parent.take() {
                 // we have found the proper page for
                 // our cooperative parent split
-                let mut parent = unsplit_parent.node.clone();
+                let mut parent = unsplit_parent.node.clone().into_owned();
                 let split_applied =
-                    parent.parent_split(view.lo.as_ref(), cursor);
+                    parent.parent_split(view.lo.as_ref(), cursor, &self.order);
 
                 if !split_applied {
                     // due to deep races, it's possible for the
@@ -1382,10 +2531,11 @@ impl Tree {
                 }
 
                 M.tree_parent_split_attempt();
-                let replace = self.context.pagecache.replace(
+                let replace = self.context.pagecache.replace_with(
                     unsplit_parent.pid,
                     unsplit_parent.ptr.clone(),
                     Frag::Base(parent),
+                    &self.page_settings(),
                     guard,
                 )?;
                 if replace.is_ok() {
@@ -1400,16 +2550,17 @@ impl Tree {
             // would be merged into a different index, which
             // would add considerable complexity to this already
             // fairly complex implementation.
-            if view.should_merge() && !took_leftmost_branch {
+            if view.should_merge() && !took_leftmost_branch && !read_only {
                 if let Some(ref mut parent) = parent_view {
                     assert!(parent.node.merging_child.is_none());
                     if parent.node.can_merge_child() {
                         let frag = Frag::ParentMergeIntention(cursor);
 
-                        let link = self.context.pagecache.link(
+                        let link = self.context.pagecache.link_with(
                             parent.pid,
                             parent.ptr.clone(),
                             frag,
+                            &self.page_settings(),
                             guard,
                         )?;
 
@@ -1423,7 +2574,7 @@ impl Tree {
             }
 
             if view.data.is_index() {
-                let next = view.index_next_node(key.as_ref());
+                let next = view.index_next_node(seek, &self.order);
                 took_leftmost_branch = next.0 == 0;
                 parent_view = Some(view);
                 cursor = next.1;
@@ -1433,9 +2584,8 @@ impl Tree {
             }
         }
         panic!(
-            "cannot find pid {} in view_for_key, looking for key {:?} in tree",
-            cursor,
-            key.as_ref(),
+            "cannot find pid {} in view_for_key, looking for {:?} in tree",
+            cursor, seek,
         );
     }
 
@@ -1463,10 +2613,11 @@ impl Tree {
                 break child_view;
             }
 
-            let install_frag = self.context.pagecache.link(
+            let install_frag = self.context.pagecache.link_with(
                 child_pid,
                 child_view.ptr.clone(),
                 Frag::ChildMergeCap,
+                &self.page_settings(),
                 guard,
             )?;
             match install_frag {
@@ -1558,11 +2709,12 @@ impl Tree {
                 let cursor_node = cursor_view.node;
                 let cursor_cas_key = cursor_view.ptr;
 
-                let replacement = cursor_node.receive_merge(child_view.node);
-                let replace = self.context.pagecache.replace(
+                let replacement = cursor_node.receive_merge(&child_view.node);
+                let replace = self.context.pagecache.replace_with(
                     cursor_pid,
                     cursor_cas_key,
                     Frag::Base(replacement),
+                    &self.page_settings(),
                     guard,
                 )?;
                 match replace {
@@ -1594,7 +2746,9 @@ impl Tree {
                         continue;
                     }
                 }
-            } else if cursor_view.hi >= child_view.lo {
+            } else if self.order.cmp(&cursor_view.hi, &child_view.lo)
+                != std::cmp::Ordering::Less
+            {
                 // we overshot the node being merged,
                 trace!(
                     "cursor pid {} has hi key {:?}, which is \
@@ -1634,10 +2788,11 @@ impl Tree {
             parent_view.pid
         );
         loop {
-            let linked = self.context.pagecache.link(
+            let linked = self.context.pagecache.link_with(
                 parent_view.pid,
                 parent_cas_key,
                 Frag::ParentMergeConfirm,
+                &self.page_settings(),
                 guard,
             )?;
             match linked {
@@ -1701,6 +2856,7 @@ impl Tree {
             Ok(_) => {
                 // we freed it
                 trace!("freed merged pid {}", child_pid);
+                self.stats.merged();
             }
             Err(None) => {
                 // someone else freed it
@@ -1784,7 +2940,7 @@ impl Debug for Tree {
         loop {
             let get_res = self.view_for_pid(pid, &guard);
             let node = match get_res {
-                Ok(Some(ref view)) => view.node,
+                Ok(Some(view)) => view.node,
                 broken => {
                     error!(
                         "Tree::fmt failed to read node {} \
@@ -1805,7 +2961,7 @@ impl Debug for Tree {
                 // we've traversed our level, time to bump down
                 let left_get_res = self.view_for_pid(left_most, &guard);
                 let left_node = match left_get_res {
-                    Ok(Some(ref view)) => view.node,
+                    Ok(Some(view)) => view.node,
                     broken => {
                         panic!("pagecache returned non-base node: {:?}", broken)
                     }
@@ -0,0 +1,91 @@
+use super::*;
+
+/// Overrides of the global `Config` for a single `Tree`,
+/// used with `Db::open_tree_with`. They are persisted
+/// along with the tree, so later calls to `Db::open_tree`
+/// keep using them. Settings that are not set fall back
+/// to the `Config` that the `Db` was started with.
+///
+/// # Examples
+///
+/// ```
+/// use sled::{CachePriority, ConfigBuilder, Db, TreeConfig};
+///
+/// let config = ConfigBuilder::new().temporary(true).build();
+/// let db = Db::start(config).unwrap();
+///
+/// let counters = db
+///     .open_tree_with(
+///         b"counters",
+///         TreeConfig::new()
+///             .page_consolidation_threshold(2)
+///             .cache_priority(CachePriority::High),
+///     )
+///     .unwrap();
+///
+/// counters.insert(b"hits", vec![1]).unwrap();
+/// assert_eq!(
+///     counters.config().get_cache_priority(),
+///     CachePriority::High
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TreeConfig {
+    pub(crate) settings: PageSettings,
+}
+
+impl TreeConfig {
+    /// Returns a `TreeConfig` that overrides nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether to use zstd compression for the pages of
+    /// this tree, overriding `ConfigBuilder::use_compression`.
+    pub fn use_compression(mut self, to: bool) -> Self {
+        self.settings.use_compression = Some(to);
+        self
+    }
+
+    /// The zstd compression factor for the pages of this tree,
+    /// overriding `ConfigBuilder::compression_factor`.
+    pub fn compression_factor(mut self, to: i32) -> Self {
+        self.settings.compression_factor = Some(to);
+        self
+    }
+
+    /// The number of updates after which the pages of this
+    /// tree are consolidated, overriding
+    /// `ConfigBuilder::page_consolidation_threshold`.
+    pub fn page_consolidation_threshold(mut self, to: usize) -> Self {
+        self.settings.page_consolidation_threshold = Some(to);
+        self
+    }
+
+    /// How reluctantly the pages of this tree are evicted
+    /// from the page cache.
+    pub fn cache_priority(mut self, to: CachePriority) -> Self {
+        self.settings.cache_priority = to;
+        self
+    }
+
+    /// Returns the compression override, if any.
+    pub fn get_use_compression(&self) -> Option<bool> {
+        self.settings.use_compression
+    }
+
+    /// Returns the compression factor override, if any.
+    pub fn get_compression_factor(&self) -> Option<i32> {
+        self.settings.compression_factor
+    }
+
+    /// Returns the consolidation threshold override, if any.
+    pub fn get_page_consolidation_threshold(&self) -> Option<usize> {
+        self.settings.page_consolidation_threshold
+    }
+
+    /// Returns the cache eviction priority.
+    pub fn get_cache_priority(&self) -> CachePriority {
+        self.settings.cache_priority
+    }
+}
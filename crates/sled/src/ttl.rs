@@ -0,0 +1,270 @@
+//! Bookkeeping for keys that were written with a time-to-live.
+//!
+//! Deadlines are stored in an internal tree that is shared by
+//! every `Tree` of a `Db`, using two kinds of keys:
+//!
+//! 1. `0 ++ len(tree id) ++ tree id ++ key` maps a key to its
+//!    deadline, so that reads can tell if it has expired.
+//! 2. `1 ++ deadline ++ len(tree id) ++ tree id ++ key` orders
+//!    keys by deadline, so that the reaper can find the ones
+//!    that are due without scanning everything.
+//!
+//! Deadlines are milliseconds since the unix epoch, and all
+//! integers are big-endian so that they sort numerically.
+//! Both entries of a key are only changed while holding the
+//! write side of its tree's `concurrency_control`, or the
+//! read side while the key has no deadline.
+use std::{
+    convert::TryInto,
+    sync::{Arc, Weak},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::{Mutex, RwLock};
+
+use pagecache::FastMap8;
+
+use super::*;
+
+const BY_KEY: u8 = 0;
+const BY_DEADLINE: u8 = 1;
+
+/// The most expired keys that are removed in a single
+/// pass of the reaper, so that it does not starve the
+/// flusher that it runs next to.
+const REAP_BATCH: usize = 1024;
+
+pub(crate) fn now_millis() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_else(|_| Duration::from_millis(0));
+    since_epoch
+        .as_millis()
+        .try_into()
+        .unwrap_or(u64::max_value())
+}
+
+/// The deadline of a key that is written now with
+/// the provided time-to-live.
+pub(crate) fn deadline_after(ttl: Duration) -> u64 {
+    let ttl_ms = ttl.as_millis().try_into().unwrap_or(u64::max_value());
+    now_millis().saturating_add(ttl_ms)
+}
+
+fn push_tree_id(buf: &mut Vec<u8>, tree_id: &[u8]) {
+    buf.extend_from_slice(&(tree_id.len() as u64).to_be_bytes());
+    buf.extend_from_slice(tree_id);
+}
+
+fn tree_prefix(tree_id: &[u8]) -> Vec<u8> {
+    let mut ret = vec![BY_KEY];
+    push_tree_id(&mut ret, tree_id);
+    ret
+}
+
+fn by_key(tree_id: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut ret = tree_prefix(tree_id);
+    ret.extend_from_slice(key);
+    ret
+}
+
+fn by_deadline(deadline: u64, tree_id: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut ret = vec![BY_DEADLINE];
+    ret.extend_from_slice(&deadline.to_be_bytes());
+    push_tree_id(&mut ret, tree_id);
+    ret.extend_from_slice(key);
+    ret
+}
+
+fn parse_u64(buf: &[u8]) -> Option<u64> {
+    let array: [u8; 8] = buf.get(..8)?.try_into().ok()?;
+    Some(u64::from_be_bytes(array))
+}
+
+/// Splits a by-deadline key into its deadline, tree id and key.
+fn parse_by_deadline(buf: &[u8]) -> Option<(u64, &[u8], &[u8])> {
+    if buf.first() != Some(&BY_DEADLINE) {
+        return None;
+    }
+    let deadline = parse_u64(&buf[1..])?;
+    let id_len: usize = parse_u64(buf.get(9..)?)?.try_into().ok()?;
+    let rest = buf.get(17..)?;
+    if rest.len() < id_len {
+        return None;
+    }
+    let (tree_id, key) = rest.split_at(id_len);
+    Some((deadline, tree_id, key))
+}
+
+/// Returns the deadline of a key, if it has one.
+pub(crate) fn deadline(
+    index: &Tree,
+    tree_id: &[u8],
+    key: &[u8],
+) -> Result<Option<u64>> {
+    let raw = index.get_inner(by_key(tree_id, key))?;
+    Ok(raw.and_then(|raw| parse_u64(&raw)))
+}
+
+/// Returns the deadline that a key had when the snapshot
+/// of the deadline index was taken, if it had one.
+pub(crate) fn snapshot_deadline(
+    index: &Snapshot,
+    tree_id: &[u8],
+    key: &[u8],
+) -> Result<Option<u64>> {
+    let raw = index.get(by_key(tree_id, key))?;
+    Ok(raw.and_then(|raw| parse_u64(&raw)))
+}
+
+/// Records the deadline of a key that has none yet.
+pub(crate) fn set_deadline(
+    index: &Tree,
+    tree_id: &[u8],
+    key: &[u8],
+    deadline: u64,
+) -> Result<()> {
+    index
+        .insert_inner(by_key(tree_id, key), deadline.to_be_bytes().to_vec())?;
+    index.insert_inner(by_deadline(deadline, tree_id, key), vec![])?;
+    Ok(())
+}
+
+/// Forgets the deadline of a key.
+pub(crate) fn remove_deadline(
+    index: &Tree,
+    tree_id: &[u8],
+    key: &[u8],
+    deadline: u64,
+) -> Result<()> {
+    index.remove_inner(by_key(tree_id, key))?;
+    index.remove_inner(by_deadline(deadline, tree_id, key))?;
+    Ok(())
+}
+
+/// Returns `true` if any key of the tree has a deadline.
+pub(crate) fn has_deadlines(index: &Tree, tree_id: &[u8]) -> Result<bool> {
+    match index.scan_prefix(tree_prefix(tree_id)).next() {
+        Some(Ok(_)) => Ok(true),
+        Some(Err(e)) => Err(e),
+        None => Ok(false),
+    }
+}
+
+/// Returns `true` if the deadline of any key, in any tree,
+/// has passed without the key having been reaped yet.
+pub(crate) fn any_due(index: &Tree) -> Result<bool> {
+    let earliest = index.scan_prefix(vec![BY_DEADLINE]).keys().next();
+    match earliest {
+        Some(Ok(raw)) => Ok(parse_by_deadline(&raw)
+            .map_or(true, |(deadline, _, _)| deadline <= now_millis())),
+        Some(Err(e)) => Err(e),
+        None => Ok(false),
+    }
+}
+
+/// Forgets the deadlines of every key of a dropped tree.
+pub(crate) fn remove_tree(index: &Tree, tree_id: &[u8]) -> Result<()> {
+    let prefix = tree_prefix(tree_id);
+    for res in index.scan_prefix(&prefix) {
+        let (raw_key, raw_deadline) = res?;
+        if let Some(deadline) = parse_u64(&raw_deadline) {
+            let key = &raw_key[prefix.len()..];
+            index.remove_inner(by_deadline(deadline, tree_id, key))?;
+        }
+        index.remove_inner(raw_key)?;
+    }
+    Ok(())
+}
+
+/// Physically removes keys whose deadline has passed. It
+/// only holds weak references, so that the background
+/// thread it runs on does not keep the `Db` alive.
+pub(crate) struct Reaper {
+    pub(crate) index: Weak<Tree>,
+    pub(crate) default: Weak<Tree>,
+    pub(crate) tenants: Weak<RwLock<FastMap8<Vec<u8>, Arc<Tree>>>>,
+    /// Held while the weak references are upgraded, and set
+    /// by the last `Db` handle, so that the last reference is
+    /// always released by the thread that drops it.
+    pub(crate) closed: Arc<Mutex<bool>>,
+}
+
+impl Reaper {
+    fn tree(&self, tree_id: &[u8]) -> Option<Arc<Tree>> {
+        // the default tree is also tracked in the tenants
+        // map, but its writers only use the `Db`'s own copy.
+        if tree_id == DEFAULT_TREE_ID {
+            self.default.upgrade()
+        } else {
+            self.tenants.upgrade()?.read().get(tree_id).cloned()
+        }
+    }
+
+    /// Removes up to `REAP_BATCH` expired keys, returning
+    /// the number of keys that were removed.
+    pub(crate) fn reap(&self) -> Result<usize> {
+        // declared first so that it is released after
+        // every reference upgraded below.
+        let closed = self.closed.lock();
+        if *closed {
+            return Ok(0);
+        }
+
+        let index = if let Some(index) = self.index.upgrade() {
+            index
+        } else {
+            return Ok(0);
+        };
+
+        let lo = vec![BY_DEADLINE];
+        let mut hi = vec![BY_DEADLINE];
+        hi.extend_from_slice(&now_millis().saturating_add(1).to_be_bytes());
+
+        let due: Vec<IVec> = index
+            .range(lo..hi)
+            .keys()
+            .take(REAP_BATCH)
+            .collect::<Result<_>>()?;
+
+        let mut reaped = 0;
+
+        for raw in due {
+            let (deadline, tree_id, key) =
+                if let Some(parsed) = parse_by_deadline(&raw) {
+                    parsed
+                } else {
+                    warn!("removing malformed expiration entry {:?}", raw);
+                    index.remove_inner(&raw)?;
+                    continue;
+                };
+
+            let tree = if let Some(tree) = self.tree(tree_id) {
+                tree
+            } else {
+                // the tree was dropped before it was cleaned up
+                remove_deadline(&index, tree_id, key, deadline)?;
+                continue;
+            };
+
+            let peg = tree.context.pin_log()?;
+            let cc = tree.concurrency_control.write();
+
+            if self::deadline(&index, tree_id, key)? == Some(deadline) {
+                trace!("reaping expired key {:?} of {:?}", key, tree_id);
+                tree.remove_with_event(key, subscription::Event::Expired)?;
+                remove_deadline(&index, tree_id, key, deadline)?;
+                reaped += 1;
+            } else {
+                // the key was written again after this deadline
+                // was set, which replaced or cleared it.
+                index.remove_inner(&raw)?;
+            }
+
+            drop(cc);
+            peg.seal_batch()?;
+        }
+
+        Ok(reaped)
+    }
+}
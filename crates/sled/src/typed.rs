@@ -0,0 +1,340 @@
+use std::{
+    fmt,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+};
+
+use serde::de::DeserializeOwned;
+
+use super::*;
+
+/// Ties a typed handle to its types without owning any
+/// values of them.
+type Marker<K, V, C> = PhantomData<fn() -> (K, V, C)>;
+
+/// A `Tree` whose keys and values are Rust types, which are
+/// turned into bytes by a `Codec`. With the default
+/// `OrderedCodec`, ranges follow the natural order of the
+/// keys, including integers and tuples of them.
+///
+/// Other handles to the same `Tree` see the encoded bytes,
+/// so every typed handle to a tree should use the same
+/// key type, value type and `Codec`.
+///
+/// # Examples
+///
+/// ```
+/// use sled::{ConfigBuilder, Db, TypedTree};
+///
+/// let config = ConfigBuilder::new().temporary(true).build();
+/// let db = Db::start(config).unwrap();
+///
+/// let tree: TypedTree<u64, String> =
+///     TypedTree::new((*db.open_tree("by id").unwrap()).clone());
+///
+/// for id in 0..30 {
+///     tree.insert(&id, &format!("item {}", id)).unwrap();
+/// }
+///
+/// assert_eq!(tree.get(&12).unwrap(), Some("item 12".to_owned()));
+///
+/// let ids: Vec<u64> = tree
+///     .range(10..20)
+///     .map(|res| res.map(|(id, _)| id))
+///     .collect::<sled::Result<_>>()
+///     .unwrap();
+/// assert_eq!(ids, (10..20).collect::<Vec<_>>());
+/// ```
+pub struct TypedTree<K, V, C = OrderedCodec> {
+    tree: Tree,
+    _marker: Marker<K, V, C>,
+}
+
+impl<K, V, C> Clone for TypedTree<K, V, C> {
+    fn clone(&self) -> TypedTree<K, V, C> {
+        TypedTree {
+            tree: self.tree.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<K, V, C> fmt::Debug for TypedTree<K, V, C> {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> std::result::Result<(), fmt::Error> {
+        write!(
+            f,
+            "TypedTree({:?})",
+            String::from_utf8_lossy(&self.tree.tree_id)
+        )
+    }
+}
+
+impl<K, V, C> TypedTree<K, V, C>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    /// Wraps `tree`, which may be the default `Tree` of a
+    /// `Db`, or one returned by `Db::open_tree`.
+    pub fn new(tree: Tree) -> TypedTree<K, V, C> {
+        TypedTree {
+            tree,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the underlying `Tree`, which works with the
+    /// encoded keys and values.
+    pub fn tree(&self) -> &Tree {
+        &self.tree
+    }
+
+    /// Retrieve the value for a key, if it exists.
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        let raw = self.tree.get(C::encode(key)?)?;
+        decode_opt::<C, V>(raw)
+    }
+
+    /// Returns `true` if the tree contains a value for the key.
+    pub fn contains_key(&self, key: &K) -> Result<bool> {
+        self.tree.contains_key(C::encode(key)?)
+    }
+
+    /// Set a key to a new value, returning the last value if it
+    /// was set.
+    pub fn insert(&self, key: &K, value: &V) -> Result<Option<V>> {
+        let old = self.tree.insert(C::encode(key)?, C::encode(value)?)?;
+        decode_opt::<C, V>(old)
+    }
+
+    /// Delete a value, returning the old value if it existed.
+    pub fn remove(&self, key: &K) -> Result<Option<V>> {
+        let old = self.tree.remove(C::encode(key)?)?;
+        decode_opt::<C, V>(old)
+    }
+
+    /// Compare and swap, like `Tree::cas`. Values are compared
+    /// by their encodings, so their types should always encode
+    /// the same way, which is not the case for `HashMap`s.
+    pub fn cas(
+        &self,
+        key: &K,
+        old: Option<&V>,
+        new: Option<&V>,
+    ) -> Result<std::result::Result<(), Option<V>>> {
+        let key = C::encode(key)?;
+        let old = old.map(C::encode).transpose()?;
+        let new = new.map(C::encode).transpose()?;
+
+        match self.tree.cas(key, old, new)? {
+            Ok(()) => Ok(Ok(())),
+            Err(current) => Ok(Err(decode_opt::<C, V>(current)?)),
+        }
+    }
+
+    /// Fetch the value, apply a function to it and return the
+    /// result, like `Tree::update_and_fetch`.
+    ///
+    /// # Note
+    ///
+    /// This may call the function multiple times if the value has been
+    /// changed from other threads in the meantime.
+    pub fn update_and_fetch<F>(&self, key: &K, mut f: F) -> Result<Option<V>>
+    where
+        F: FnMut(Option<V>) -> Option<V>,
+    {
+        let key = C::encode(key)?;
+        let mut current = self.tree.get(&key)?;
+
+        loop {
+            let decoded = decode_opt::<C, V>(current.clone())?;
+            let next = f(decoded);
+            let encoded = next.as_ref().map(C::encode).transpose()?;
+
+            match self.tree.cas(&key, current.as_ref(), encoded)? {
+                Ok(()) => return Ok(next),
+                Err(new_current) => current = new_current,
+            }
+        }
+    }
+
+    /// Create a double-ended iterator over all keys and values,
+    /// in the order of their encoded keys.
+    pub fn iter(&self) -> TypedIter<'_, K, V, C> {
+        TypedIter {
+            iter: Ok(self.tree.iter()),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Create a double-ended iterator over the keys and values
+    /// whose keys fall within the specified range. If a bound
+    /// can not be encoded, the iterator only returns that error.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> TypedIter<'_, K, V, C> {
+        let encode_bound = |bound: Bound<&K>| -> Result<Bound<Vec<u8>>> {
+            Ok(match bound {
+                Bound::Included(key) => Bound::Included(C::encode(key)?),
+                Bound::Excluded(key) => Bound::Excluded(C::encode(key)?),
+                Bound::Unbounded => Bound::Unbounded,
+            })
+        };
+
+        let bounds = encode_bound(range.start_bound())
+            .and_then(|lo| encode_bound(range.end_bound()).map(|hi| (lo, hi)));
+
+        TypedIter {
+            iter: bounds.map(|bounds| self.tree.range(bounds)).map_err(Some),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Subscribe to `TypedEvent`s that happen to keys whose
+    /// encoding starts with the encoding of `prefix`. With the
+    /// `OrderedCodec`, tuple keys may be watched by a tuple of
+    /// their first fields, and `&()` watches every key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::{ConfigBuilder, Db, TypedEvent, TypedTree};
+    ///
+    /// let config = ConfigBuilder::new().temporary(true).build();
+    /// let db = Db::start(config).unwrap();
+    /// let tree: TypedTree<(u32, u32), bool> = TypedTree::new((*db).clone());
+    ///
+    /// let mut events = tree.watch_prefix(&(7_u32,)).unwrap();
+    ///
+    /// tree.insert(&(6, 1), &true).unwrap();
+    /// tree.insert(&(7, 2), &false).unwrap();
+    ///
+    /// assert_eq!(events.next().unwrap().unwrap(), TypedEvent::Set((7, 2), false));
+    /// ```
+    pub fn watch_prefix<P: Serialize + ?Sized>(
+        &self,
+        prefix: &P,
+    ) -> Result<TypedSubscriber<K, V, C>> {
+        Ok(TypedSubscriber {
+            subscriber: self.tree.watch_prefix(C::encode(prefix)?),
+            _marker: PhantomData,
+        })
+    }
+}
+
+fn decode_opt<C: Codec, T: DeserializeOwned>(
+    raw: Option<IVec>,
+) -> Result<Option<T>> {
+    raw.map(|raw| C::decode(&raw)).transpose()
+}
+
+/// An iterator over the keys and values of a `TypedTree`.
+pub struct TypedIter<'a, K, V, C = OrderedCodec> {
+    /// The error of a bound that could not be encoded,
+    /// until it is returned.
+    iter: std::result::Result<Iter<'a>, Option<Error>>,
+    _marker: Marker<K, V, C>,
+}
+
+impl<'a, K, V, C> TypedIter<'a, K, V, C>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+    C: Codec,
+{
+    fn decode(item: Option<Result<(IVec, IVec)>>) -> Option<Result<(K, V)>> {
+        item.map(|res| {
+            let (k, v) = res?;
+            Ok((C::decode(&k)?, C::decode(&v)?))
+        })
+    }
+}
+
+impl<'a, K, V, C> Iterator for TypedIter<'a, K, V, C>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+    C: Codec,
+{
+    type Item = Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.iter {
+            Ok(ref mut iter) => Self::decode(iter.next()),
+            Err(ref mut error) => error.take().map(Err),
+        }
+    }
+}
+
+impl<'a, K, V, C> DoubleEndedIterator for TypedIter<'a, K, V, C>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+    C: Codec,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self.iter {
+            Ok(ref mut iter) => Self::decode(iter.next_back()),
+            Err(ref mut error) => error.take().map(Err),
+        }
+    }
+}
+
+/// An `Event` with decoded keys and values, returned by a
+/// `TypedSubscriber`. Like `Event`, it may gain new kinds
+/// of events in later versions.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum TypedEvent<K, V> {
+    /// A new complete (key, value) pair
+    Set(K, V),
+    /// A new partial (key, merged value) pair, whose value
+    /// is left encoded because merge operators may write
+    /// values of other types
+    Merge(K, IVec),
+    /// A deleted key
+    Del(K),
+    /// A key that was removed because its time-to-live elapsed
+    Expired(K),
+    /// The encoded bounds of a removed range, as in
+    /// `Event::DelRange`. The first bound may not be the
+    /// encoding of a key
+    DelRange(Vec<u8>, Option<Vec<u8>>),
+    /// The number of `Event`s that were discarded because the
+    /// `Subscriber` fell behind
+    Lagged(u64),
+}
+
+/// A `Subscriber` to a `TypedTree`, which blocks on each
+/// `TypedEvent`, and fails on events whose keys or values
+/// can not be decoded.
+pub struct TypedSubscriber<K, V, C = OrderedCodec> {
+    subscriber: Subscriber,
+    _marker: Marker<K, V, C>,
+}
+
+impl<K, V, C> Iterator for TypedSubscriber<K, V, C>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+    C: Codec,
+{
+    type Item = Result<TypedEvent<K, V>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let event = self.subscriber.next()?;
+        Some(match event {
+            Event::Set(k, v) => C::decode(&k)
+                .and_then(|k| Ok(TypedEvent::Set(k, C::decode(&v)?))),
+            Event::Merge(k, v) => {
+                C::decode(&k).map(|k| TypedEvent::Merge(k, v))
+            }
+            Event::Del(k) => C::decode(&k).map(TypedEvent::Del),
+            Event::Expired(k) => C::decode(&k).map(TypedEvent::Expired),
+            Event::DelRange(lo, hi) => Ok(TypedEvent::DelRange(lo, hi)),
+            Event::Lagged(n) => Ok(TypedEvent::Lagged(n)),
+        })
+    }
+}
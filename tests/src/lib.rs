@@ -41,3 +41,52 @@ pub fn setup_logger() {
 
     let _r = builder.try_init();
 }
+
+/// Drives a future to completion on the current thread,
+/// parking it while the future is not ready. This is all
+/// that is needed to test async APIs without a runtime.
+pub fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    use std::{
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+        thread::{self, Thread},
+    };
+
+    unsafe fn clone(data: *const ()) -> RawWaker {
+        let thread = Arc::from_raw(data as *const Thread);
+        let cloned = thread.clone();
+        std::mem::forget(thread);
+        RawWaker::new(Arc::into_raw(cloned) as *const (), &VTABLE)
+    }
+
+    unsafe fn wake(data: *const ()) {
+        Arc::from_raw(data as *const Thread).unpark();
+    }
+
+    unsafe fn wake_by_ref(data: *const ()) {
+        (*(data as *const Thread)).unpark();
+    }
+
+    unsafe fn drop(data: *const ()) {
+        std::mem::drop(Arc::from_raw(data as *const Thread));
+    }
+
+    static VTABLE: RawWakerVTable =
+        RawWakerVTable::new(clone, wake, wake_by_ref, drop);
+
+    let thread = Arc::new(thread::current());
+    let raw = RawWaker::new(Arc::into_raw(thread) as *const (), &VTABLE);
+    let waker = unsafe { Waker::from_raw(raw) };
+    let mut cx = Context::from_waker(&waker);
+
+    let mut future = future;
+    let mut future = unsafe { Pin::new_unchecked(&mut future) };
+
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
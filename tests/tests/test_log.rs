@@ -4,8 +4,8 @@ use std::mem::size_of;
 use {
     lazy_static::lazy_static,
     pagecache::{
-        ConfigBuilder, DiskPtr, Log, LogKind, LogRead, PageId, SegmentMode,
-        MINIMUM_ITEMS_PER_SEGMENT, MSG_HEADER_LEN, SEG_HEADER_LEN,
+        ConfigBuilder, DiskPtr, IoMode, Log, LogKind, LogRead, PageId,
+        SegmentMode, MINIMUM_ITEMS_PER_SEGMENT, MSG_HEADER_LEN, SEG_HEADER_LEN,
     },
     quickcheck::{Arbitrary, Gen, QuickCheck, StdGen},
     rand::{thread_rng, Rng},
@@ -376,6 +376,90 @@ fn log_iterator() {
     assert_eq!(iter.next(), None);
 }
 
+fn log_direct_io(io_mode: IoMode) {
+    tests::setup_logger();
+    let path =
+        std::env::temp_dir().join(format!("pagecache_log_{:?}", io_mode));
+    let _ = fs::remove_dir_all(&path);
+
+    let config = |io_mode| {
+        ConfigBuilder::new()
+            .path(&path)
+            .segment_mode(SegmentMode::Linear)
+            .io_buf_size(1 << 16)
+            .io_mode(io_mode)
+            .build()
+    };
+
+    // leave the tip of the log unaligned for the first direct write
+    let log = Log::start_raw_log(config(IoMode::Buffered)).unwrap();
+    let (lsn, _) = log.reserve(KIND, 0, b"odd").unwrap().complete().unwrap();
+    log.make_stable(lsn).unwrap();
+    drop(log);
+
+    let mut reference = vec![(0, b"odd".to_vec())];
+
+    for pass in 0..2_u64 {
+        let log = Log::start_raw_log(config(io_mode)).unwrap();
+        for i in 1..300 {
+            let pid = pass * 1000 + i;
+            let buf = vec![i as u8; (i as usize * 97) % 5000];
+            if i % 7 == 0 {
+                log.reserve(KIND, pid, &buf).unwrap().abort().unwrap();
+                continue;
+            }
+            let (lsn, _) =
+                log.reserve(KIND, pid, &buf).unwrap().complete().unwrap();
+            if i % 3 == 0 {
+                log.make_stable(lsn).unwrap();
+            }
+            reference.push((pid, buf));
+        }
+        log.flush().unwrap();
+    }
+
+    let log = Log::start_raw_log(config(io_mode)).unwrap();
+    let mut iter = log.iter_from(SEG_HEADER_LEN as Lsn);
+    for (pid, buf) in reference {
+        let (_, read_pid, lsn, ptr, _) =
+            iter.next().expect("expected to read another message");
+        assert_eq!(read_pid, pid);
+        let read = log.read(pid, lsn, ptr).unwrap().into_data().unwrap();
+        assert_eq!(read, buf, "wrong data for pid {}", pid);
+    }
+    assert_eq!(iter.next(), None);
+
+    drop(iter);
+    drop(log);
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn log_direct_io_buffered_fallback() {
+    // tmpfs rejects O_DIRECT before linux 6.6, in which case the
+    // log is written through the page cache instead
+    let config = ConfigBuilder::new()
+        .temporary(true)
+        .segment_mode(SegmentMode::Linear)
+        .io_buf_size(1 << 16)
+        .io_mode(IoMode::Direct)
+        .build();
+    let log = Log::start_raw_log(config).unwrap();
+    write(&log);
+    abort(&log);
+    write(&log);
+}
+
+#[test]
+fn log_direct() {
+    log_direct_io(IoMode::Direct);
+}
+
+#[test]
+fn log_direct_sync() {
+    log_direct_io(IoMode::DirectSync);
+}
+
 #[test]
 #[cfg(not(target_os = "fuchsia"))]
 fn log_chunky_iterator() {
@@ -0,0 +1,63 @@
+// Resident memory is measured for the whole process, so this
+// test lives in its own binary, away from the other tests.
+#![cfg(target_os = "linux")]
+
+use pagecache::ConfigBuilder;
+use sled::*;
+
+const N: usize = 8 * 1024;
+const VALUE_LEN: usize = 4 * 1024;
+
+fn resident_bytes() -> usize {
+    let statm = std::fs::read_to_string("/proc/self/statm").unwrap();
+    let pages: usize =
+        statm.split_whitespace().nth(1).unwrap().parse().unwrap();
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    pages * page_size
+}
+
+#[test]
+fn no_cache_scan_memory_is_bounded() -> Result<()> {
+    tests::setup_logger();
+
+    let config = ConfigBuilder::new()
+        .temporary(true)
+        .cache_capacity(1024 * 1024)
+        .flush_every_ms(None)
+        .build();
+
+    let db = sled::Db::start(config.clone())?;
+    for i in 0..N {
+        db.insert((i as u32).to_be_bytes(), vec![i as u8; VALUE_LEN])?;
+    }
+    drop(db);
+
+    // nothing is cached after a restart, so every leaf is
+    // read from disk by the scan below.
+    let db = sled::Db::start(config)?;
+    let before = resident_bytes();
+
+    let mut iter = db.iter().no_cache();
+    let mut scanned = 0;
+    for kv_res in &mut iter {
+        let (_, v) = kv_res?;
+        assert_eq!(v.len(), VALUE_LEN);
+        scanned += 1;
+    }
+    assert_eq!(scanned, N);
+
+    // the leaves that were read are not kept until the
+    // iterator is dropped, so the scan used far less memory
+    // than the values that it went through.
+    let grown = resident_bytes().saturating_sub(before);
+    assert!(
+        grown < N * VALUE_LEN / 4,
+        "resident memory grew by {} bytes during a scan over {} bytes",
+        grown,
+        N * VALUE_LEN
+    );
+
+    drop(iter);
+
+    Ok(())
+}
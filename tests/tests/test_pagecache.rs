@@ -14,7 +14,8 @@ use {
 };
 
 use pagecache::{
-    pin, ConfigBuilder, Materializer, PageCache, MAX_SPACE_AMPLIFICATION,
+    pin, ConfigBuilder, Materializer, PageCache, PageSettings,
+    MAX_SPACE_AMPLIFICATION,
 };
 
 type PageId = u64;
@@ -102,6 +103,47 @@ fn pagecache_caching() {
     }
 }
 
+#[test]
+fn pagecache_reserved_pids() {
+    let config = ConfigBuilder::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .snapshot_after_ops(1_000_000)
+        .io_buf_size(20000)
+        .build();
+
+    let pc: PageCache<TestMaterializer> =
+        PageCache::start(config.clone()).unwrap();
+
+    let guard = pin();
+
+    let first = pc.reserve_pid(&guard);
+    let second = pc.reserve_pid(&guard);
+    assert_ne!(first, second);
+
+    // reserved pages read as missing until they are written,
+    // and can be written in any order
+    assert!(pc.get(second, &guard).unwrap().is_none());
+    let settings = PageSettings::default();
+    pc.allocate_reserved(second, vec![2].into(), &settings, &guard)
+        .unwrap();
+    assert!(pc.get(first, &guard).unwrap().is_none());
+    pc.allocate_reserved(first, vec![1].into(), &settings, &guard)
+        .unwrap();
+
+    drop(guard);
+    drop(pc);
+
+    let pc: PageCache<TestMaterializer> =
+        PageCache::start(config.clone()).unwrap();
+    let guard = pin();
+
+    for (pid, expected) in vec![(first, vec![1]), (second, vec![2])] {
+        let (_, page, _) = pc.get(pid, &guard).unwrap().unwrap();
+        assert_eq!(page, &TestMaterializer(expected));
+    }
+}
+
 #[test]
 fn concurrent_pagecache() -> sled::Result<()> {
     tests::setup_logger();
@@ -1,4 +1,7 @@
-use std::sync::{Arc, Barrier};
+use std::sync::{
+    atomic::{AtomicBool, Ordering::SeqCst},
+    Arc, Barrier,
+};
 use std::thread;
 
 use pagecache::ConfigBuilder;
@@ -640,6 +643,2073 @@ fn tree_import_export() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn tree_dump_and_restore() -> Result<()> {
+    tests::setup_logger();
+
+    let config = ConfigBuilder::new().temporary(true).build();
+    let db = sled::Db::start(config)?;
+
+    for db_id in 0..N_THREADS {
+        let tree_id = format!("tree_{}", db_id);
+        let tree = db.open_tree(tree_id.as_bytes())?;
+        for i in 0..N_PER_THREAD {
+            tree.insert(kv(i), kv(i))?;
+        }
+    }
+
+    let mut dump = vec![];
+    let written = db.export_to(&mut dump)?;
+    assert_eq!(written, (N_THREADS * N_PER_THREAD) as u64);
+
+    let reader = DumpReader::new(&*dump)?;
+    assert_eq!(reader.header().format_version, DUMP_FORMAT_VERSION);
+    assert_eq!(DumpCheckpoint::scan(&*dump)?.complete, true);
+
+    // simulate an interrupted dump, then resume it
+    let mut interrupted = dump[..dump.len() / 2].to_vec();
+    let checkpoint = DumpCheckpoint::scan(&*interrupted)?;
+    assert!(!checkpoint.complete);
+    interrupted.truncate(checkpoint.offset as usize);
+
+    let config = ConfigBuilder::new().temporary(true).build();
+    let partial = sled::Db::start(config)?;
+    assert!(partial.import_from(&*interrupted).is_err());
+
+    db.resume_export_to(&mut interrupted, &checkpoint)?;
+    assert_eq!(interrupted, dump);
+
+    // corruption is detected by the checksums
+    let mut corrupted = dump.clone();
+    let last = corrupted.len() - 20;
+    corrupted[last] ^= 1;
+    let config = ConfigBuilder::new().temporary(true).build();
+    let restored = sled::Db::start(config)?;
+    assert!(restored.import_from(&*corrupted).is_err());
+
+    // a corrupted length fails cleanly instead of allocating
+    // as much memory as it asks for.
+    let header_len = 24 + reader.header().sled_version.len();
+    let mut truncated = dump[..header_len].to_vec();
+    truncated.extend_from_slice(&[2, 255, 255, 255, 255, 0, 0]);
+    assert!(DumpReader::new(&*truncated)?.next().unwrap().is_err());
+
+    let config = ConfigBuilder::new().temporary(true).build();
+    let restored = sled::Db::start(config)?;
+    assert_eq!(restored.import_from(&*dump)?, written);
+
+    for db_id in 0..N_THREADS {
+        let tree_id = format!("tree_{}", db_id);
+        let tree = restored.open_tree(tree_id.as_bytes())?;
+        assert_eq!(tree.len(), N_PER_THREAD);
+        for i in 0..N_PER_THREAD {
+            assert_eq!(tree.get(kv(i))?, Some(IVec::from(kv(i))));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn tree_dump_keeps_comparators_and_configs() -> Result<()> {
+    use std::cmp::Ordering;
+
+    fn reverse(a: &[u8], b: &[u8]) -> Ordering {
+        b.cmp(a)
+    }
+
+    tests::setup_logger();
+
+    let config = ConfigBuilder::new().temporary(true).build();
+    let db = sled::Db::start(config)?;
+
+    let tree_config = TreeConfig::new()
+        .compression_factor(3)
+        .page_consolidation_threshold(2)
+        .cache_priority(CachePriority::High);
+    let configured = db.open_tree_with(b"configured", tree_config)?;
+    let reversed =
+        db.open_tree_with_comparator(b"reversed", "dump_reverse", reverse)?;
+
+    for i in 0..N_PER_THREAD {
+        configured.insert(kv(i), kv(i))?;
+        reversed.insert(kv(i), kv(i))?;
+    }
+
+    let mut dump = vec![];
+    db.export_to(&mut dump)?;
+
+    let config = ConfigBuilder::new().temporary(true).build();
+    let restored = sled::Db::start(config)?;
+    restored.import_from(&*dump)?;
+
+    let configured = restored.open_tree(b"configured")?;
+    assert_eq!(configured.config(), tree_config);
+    assert_eq!(configured.len(), N_PER_THREAD);
+
+    // the tree is only known under its comparator
+    assert!(restored.open_tree(b"reversed").is_err());
+    let reversed = restored.open_tree_with_comparator(
+        b"reversed",
+        "dump_reverse",
+        reverse,
+    )?;
+    let expected: Vec<IVec> =
+        (0..N_PER_THREAD).rev().map(|i| IVec::from(kv(i))).collect();
+    let keys: Vec<IVec> = reversed.iter().keys().collect::<Result<_>>()?;
+    assert_eq!(keys, expected);
+
+    // rename the comparator in the dump to one that was
+    // never registered, fixing up the record checksum.
+    let header_len = 20 + DumpReader::new(&*dump)?.header().sled_version.len();
+    let mut offset = header_len;
+    let mut renamed = false;
+    while offset < dump.len() {
+        let mut len = [0; 4];
+        len.copy_from_slice(&dump[offset + 1..offset + 5]);
+        let end = offset + 5 + u32::from_le_bytes(len) as usize;
+        let record = &mut dump[offset..end];
+        if let Some(at) = record
+            .windows(12)
+            .position(|window| window == b"dump_reverse")
+        {
+            record[at..at + 12].copy_from_slice(b"dump_missing");
+            let crc = pagecache::crc32(record);
+            dump[end..end + 4].copy_from_slice(&crc.to_le_bytes());
+            renamed = true;
+        }
+        offset = end + 4;
+    }
+    assert!(renamed);
+
+    let config = ConfigBuilder::new().temporary(true).build();
+    let restored = sled::Db::start(config)?;
+    assert!(restored.import_from(&*dump).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn tree_transactions() -> Result<()> {
+    tests::setup_logger();
+
+    let config = ConfigBuilder::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .build();
+    let db = Arc::new(sled::Db::start(config)?);
+
+    db.insert(b"a", 100_u64.to_be_bytes().to_vec())?;
+    db.insert(b"b", 0_u64.to_be_bytes().to_vec())?;
+
+    fn read(v: Option<IVec>) -> u64 {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(&v.unwrap());
+        u64::from_be_bytes(buf)
+    }
+
+    let threads: Vec<_> = (0..N_THREADS)
+        .map(|_| {
+            let db = db.clone();
+            thread::spawn(move || {
+                for _ in 0..N_PER_THREAD / 10 {
+                    db.transaction(|tx| {
+                        let a = read(tx.get(b"a")?);
+                        let b = read(tx.get(b"b")?);
+                        if a == 0 {
+                            return Err(TransactionError::Abort);
+                        }
+                        tx.insert(b"a", (a - 1).to_be_bytes().to_vec())?;
+                        tx.insert(b"b", (b + 1).to_be_bytes().to_vec())?;
+                        Ok(())
+                    })
+                    .unwrap();
+                }
+            })
+        })
+        .collect();
+
+    for t in threads.into_iter() {
+        t.join().unwrap();
+    }
+
+    assert_eq!(read(db.get(b"a")?), 0);
+    assert_eq!(read(db.get(b"b")?), 100);
+
+    // aborted transactions leave no trace
+    let res = db.transaction(|tx| {
+        tx.remove(b"b")?;
+        assert_eq!(tx.get(b"b")?, None);
+        Err(TransactionError::Abort) as TransactionResult<()>
+    });
+    assert_eq!(res, Err(TransactionError::Abort));
+    assert_eq!(read(db.get(b"b")?), 100);
+
+    Ok(())
+}
+
+#[test]
+fn tree_cross_tree_transactions() -> Result<()> {
+    tests::setup_logger();
+
+    let config = ConfigBuilder::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .build();
+
+    let db = sled::Db::start(config.clone())?;
+    let inbox = db.open_tree(b"inbox")?;
+    let archive = db.open_tree(b"archive")?;
+
+    for i in 0..N_PER_THREAD {
+        inbox.insert(kv(i), kv(i))?;
+    }
+
+    let threads: Vec<_> = (0..N_THREADS)
+        .map(|t| {
+            let inbox = inbox.clone();
+            let archive = archive.clone();
+            thread::spawn(move || {
+                for i in (t..N_PER_THREAD).step_by(N_THREADS) {
+                    (&inbox, &archive)
+                        .transaction(|(inbox, archive)| {
+                            let v = inbox.remove(kv(i))?.unwrap();
+                            archive.insert(kv(i), v)?;
+                            Ok(())
+                        })
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+
+    for t in threads.into_iter() {
+        t.join().unwrap();
+    }
+
+    assert!(inbox.is_empty());
+    assert_eq!(archive.len(), N_PER_THREAD);
+
+    db.flush()?;
+    drop(inbox);
+    drop(archive);
+    drop(db);
+
+    let db = sled::Db::start(config)?;
+    let inbox = db.open_tree(b"inbox")?;
+    let archive = db.open_tree(b"archive")?;
+
+    assert!(inbox.is_empty());
+    for i in 0..N_PER_THREAD {
+        assert_eq!(archive.get(kv(i))?, Some(IVec::from(kv(i))));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn tree_snapshots() -> Result<()> {
+    tests::setup_logger();
+
+    let config = ConfigBuilder::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .build();
+
+    let db = sled::Db::start(config)?;
+    let inbox = db.open_tree(b"inbox")?;
+    let archive = db.open_tree(b"archive")?;
+
+    for i in 0..N_PER_THREAD {
+        inbox.insert(kv(i), kv(i))?;
+    }
+
+    let snapshot = db.snapshot()?;
+
+    // move everything, overwriting and adding enough
+    // keys along the way to cause splits in both trees.
+    for i in 0..N_PER_THREAD {
+        (&inbox, &archive)
+            .transaction(|(inbox, archive)| {
+                let v = inbox.remove(kv(i))?.unwrap();
+                archive.insert(kv(i), v)?;
+                Ok(())
+            })
+            .unwrap();
+    }
+    for i in N_PER_THREAD..N_PER_THREAD * 10 {
+        archive.insert(kv(i), kv(i))?;
+        db.insert(kv(i), kv(i))?;
+    }
+
+    let old_inbox = snapshot.open_tree(b"inbox")?;
+    let old_archive = snapshot.open_tree(b"archive")?;
+
+    assert_eq!(snapshot.iter().count(), 0);
+    assert_eq!(old_archive.iter().count(), 0);
+    assert_eq!(old_inbox.iter().count(), N_PER_THREAD);
+
+    for i in 0..N_PER_THREAD {
+        assert_eq!(old_inbox.get(kv(i))?, Some(IVec::from(kv(i))));
+        assert_eq!(old_archive.get(kv(i))?, None);
+    }
+
+    let forward: Vec<_> = old_inbox
+        .range(kv(10)..kv(20))
+        .keys()
+        .collect::<Result<_>>()?;
+    let mut backward: Vec<_> = old_inbox
+        .range(kv(10)..kv(20))
+        .keys()
+        .rev()
+        .collect::<Result<_>>()?;
+    backward.reverse();
+    assert_eq!(forward.len(), 10);
+    assert_eq!(forward, backward);
+
+    assert!(snapshot.open_tree(b"created later").is_err());
+
+    // the live trees are unaffected by the snapshot
+    assert!(inbox.is_empty());
+    assert_eq!(archive.len(), N_PER_THREAD * 10);
+
+    let fresh = archive.snapshot()?;
+    assert_eq!(fresh.iter().count(), N_PER_THREAD * 10);
+
+    // snapshots may be read from other threads
+    let reader = thread::spawn(move || old_inbox.iter().count());
+    assert_eq!(reader.join().unwrap(), N_PER_THREAD);
+
+    Ok(())
+}
+
+#[test]
+fn tree_snapshots_during_writes() -> Result<()> {
+    tests::setup_logger();
+
+    // a small cache, so that preserved pages are also
+    // read back from disk
+    let config = ConfigBuilder::new()
+        .temporary(true)
+        .cache_capacity(4096)
+        .build();
+
+    let db = sled::Db::start(config)?;
+
+    // (key, inserted) in the order that the writer applies them
+    let ops: Vec<(usize, bool)> = (0..N)
+        .flat_map(|i| {
+            let remove = if i % 3 == 0 {
+                Some((i / 3, false))
+            } else {
+                None
+            };
+            std::iter::once((i, true)).chain(remove)
+        })
+        .collect();
+
+    let done = Arc::new(AtomicBool::new(false));
+
+    let writer = {
+        let db = db.clone();
+        let ops = ops.clone();
+        let done = done.clone();
+        thread::spawn(move || -> Result<()> {
+            for (k, inserted) in ops {
+                if inserted {
+                    db.insert(kv(k), kv(k))?;
+                } else {
+                    db.remove(kv(k))?;
+                }
+            }
+            done.store(true, SeqCst);
+            Ok(())
+        })
+    };
+
+    // every snapshot must hold the state after some
+    // number of the writer's operations.
+    while !done.load(SeqCst) {
+        let snapshot = db.snapshot()?;
+        let keys: Vec<IVec> = snapshot.iter().keys().collect::<Result<_>>()?;
+
+        let mut state = std::collections::BTreeSet::new();
+        let mut matched = keys.is_empty();
+        for &(k, inserted) in &ops {
+            if matched {
+                break;
+            }
+            if inserted {
+                state.insert(IVec::from(kv(k)));
+            } else {
+                state.remove(&IVec::from(kv(k)));
+            }
+            matched = state.iter().eq(keys.iter());
+        }
+        assert!(matched, "snapshot does not match any prefix of writes");
+
+        for key in &keys {
+            assert_eq!(snapshot.get(key)?, Some(key.clone()));
+        }
+    }
+
+    writer.join().unwrap()?;
+
+    Ok(())
+}
+
+#[test]
+fn tree_opens_legacy_config() -> Result<()> {
+    tests::setup_logger();
+
+    let path = std::env::temp_dir().join("sled_legacy_config_test");
+    let _ = std::fs::remove_dir_all(&path);
+
+    let db = sled::Db::open(&path)?;
+    db.insert(b"k", b"v")?;
+    drop(db);
+
+    // write the configuration the way pagecache 0.18 did,
+    // without a version header
+    let config = ConfigBuilder::new();
+    let raw_path = path.to_str().unwrap().as_bytes();
+    let mut bytes = vec![];
+    bytes.extend_from_slice(&config.cache_capacity.to_le_bytes());
+    bytes.push(1);
+    bytes.extend_from_slice(&500_u64.to_le_bytes());
+    bytes.extend_from_slice(&(config.io_buf_size as u64).to_le_bytes());
+    bytes.extend_from_slice(
+        &(config.page_consolidation_threshold as u64).to_le_bytes(),
+    );
+    bytes.extend_from_slice(&(raw_path.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(raw_path);
+    bytes.push(0);
+    bytes.extend_from_slice(&config.segment_cleanup_threshold.to_le_bytes());
+    bytes
+        .extend_from_slice(&(config.segment_cleanup_skew as u64).to_le_bytes());
+    bytes.extend_from_slice(&0_u32.to_le_bytes());
+    bytes.extend_from_slice(&config.snapshot_after_ops.to_le_bytes());
+    bytes.push(0);
+    bytes.push(0);
+    bytes.push(0);
+    bytes.extend_from_slice(&config.compression_factor.to_le_bytes());
+    bytes.push(0);
+    bytes.extend_from_slice(&config.idgen_persist_interval.to_le_bytes());
+    bytes.extend_from_slice(&(config.version.0 as u64).to_le_bytes());
+    bytes.extend_from_slice(&(config.version.1 as u64).to_le_bytes());
+    let crc = pagecache::crc32(&bytes);
+    bytes.extend_from_slice(&crc.to_le_bytes());
+
+    let conf = path.join("conf");
+    std::fs::write(&conf, &bytes)?;
+
+    let db = sled::Db::open(&path)?;
+    assert_eq!(db.get(b"k")?, Some(IVec::from(b"v")));
+    drop(db);
+
+    // the configuration is rewritten in the current format
+    assert!(std::fs::read(&conf)?.starts_with(b"pcconfig"));
+    assert!(sled::Db::open(&path)?.contains_key(b"k")?);
+
+    std::fs::remove_dir_all(&path)?;
+    Ok(())
+}
+
+#[test]
+fn tree_backup() -> Result<()> {
+    tests::setup_logger();
+
+    let config = ConfigBuilder::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .io_buf_size(10000)
+        .build();
+
+    let backup_path = std::env::temp_dir().join("sled_tree_backup_test");
+    let _ = std::fs::remove_dir_all(&backup_path);
+
+    let db = sled::Db::start(config)?;
+    let tree = db.open_tree(b"tree")?;
+
+    for i in 0..N_PER_THREAD {
+        tree.insert(kv(i), kv(i))?;
+    }
+
+    // keep writing while the backup is taken
+    let writer = {
+        let tree = tree.clone();
+        thread::spawn(move || {
+            for i in N_PER_THREAD..N_PER_THREAD * N_THREADS {
+                tree.insert(kv(i), kv(i)).unwrap();
+            }
+        })
+    };
+
+    let lsn = db.backup_to(&backup_path)?;
+
+    writer.join().unwrap();
+
+    assert!(db.backup_to(&backup_path).is_err());
+
+    {
+        let copy = sled::Db::open(&backup_path)?;
+        let copy_tree = copy.open_tree(b"tree")?;
+        for i in 0..N_PER_THREAD {
+            assert_eq!(copy_tree.get(kv(i))?, Some(IVec::from(kv(i))));
+        }
+        // anything that made it into the copy must be intact
+        for item in copy_tree.iter() {
+            let (k, v) = item?;
+            assert_eq!(k, v);
+        }
+    }
+
+    db.backup_incremental(&backup_path, lsn)?;
+
+    {
+        let copy = sled::Db::open(&backup_path)?;
+        let copy_tree = copy.open_tree(b"tree")?;
+        assert_eq!(copy_tree.len(), N_PER_THREAD * N_THREADS);
+        for i in 0..N_PER_THREAD * N_THREADS {
+            assert_eq!(copy_tree.get(kv(i))?, Some(IVec::from(kv(i))));
+        }
+    }
+
+    std::fs::remove_dir_all(&backup_path)?;
+
+    Ok(())
+}
+
+#[test]
+fn tree_ttl() -> Result<()> {
+    use std::time::Duration;
+
+    tests::setup_logger();
+
+    let config = ConfigBuilder::new()
+        .temporary(true)
+        .flush_every_ms(Some(10))
+        .build();
+    let db = sled::Db::start(config)?;
+    let tree = db.open_tree(b"ttl")?;
+    let mut events = tree.watch_prefix(vec![]);
+
+    let short = Duration::from_millis(200);
+    let long = Duration::from_secs(3600);
+
+    assert_eq!(tree.insert_with_ttl(b"a", vec![1], short)?, None);
+    tree.insert(b"b", vec![2])?;
+    tree.insert_with_ttl(b"c", vec![3], long)?;
+    tree.insert_with_ttl(b"d", vec![4], short)?;
+
+    // a plain write removes the time-to-live
+    tree.insert(b"d", vec![5])?;
+
+    assert_eq!(tree.get(b"a")?, Some(IVec::from(vec![1])));
+
+    thread::sleep(Duration::from_millis(300));
+
+    // expired keys are invisible, even if not reaped yet
+    assert_eq!(tree.get(b"a")?, None);
+    let keys: Vec<IVec> = tree.iter().keys().collect::<Result<_>>()?;
+    assert_eq!(
+        keys,
+        vec![IVec::from(b"b"), IVec::from(b"c"), IVec::from(b"d")]
+    );
+    assert_eq!(tree.get(b"d")?, Some(IVec::from(vec![5])));
+
+    // five writes, then the reaper's expiry
+    let last = events.nth(5).unwrap();
+    assert_eq!(last, Event::Expired(b"a".to_vec()));
+
+    // an expired value is not returned when overwritten
+    tree.insert_with_ttl(b"e", vec![6], Duration::from_millis(0))?;
+    assert_eq!(tree.insert(b"e", vec![7])?, None);
+    assert_eq!(tree.get(b"e")?, Some(IVec::from(vec![7])));
+
+    Ok(())
+}
+
+#[test]
+fn tree_snapshot_ttl() -> Result<()> {
+    use std::time::Duration;
+
+    tests::setup_logger();
+
+    // without a flusher, nothing reaps the expired keys
+    let config = ConfigBuilder::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .build();
+    let db = sled::Db::start(config)?;
+    let tree = db.open_tree(b"ttl")?;
+
+    tree.insert_with_ttl(b"a", vec![1], Duration::from_millis(200))?;
+    tree.insert(b"b", vec![2])?;
+    tree.insert_with_ttl(b"c", vec![3], Duration::from_secs(3600))?;
+
+    let snapshot = tree.snapshot()?;
+    let db_snapshot = db.snapshot()?.open_tree(b"ttl")?;
+
+    // the deadlines as of the snapshot still apply
+    tree.insert(b"a", vec![4])?;
+
+    thread::sleep(Duration::from_millis(300));
+
+    for snapshot in &[snapshot, db_snapshot] {
+        assert_eq!(snapshot.get(b"a")?, None);
+        assert_eq!(snapshot.get(b"c")?, Some(IVec::from(vec![3])));
+        let keys: Vec<IVec> = snapshot.iter().keys().collect::<Result<_>>()?;
+        assert_eq!(keys, vec![IVec::from(b"b"), IVec::from(b"c")]);
+        let keys: Vec<IVec> =
+            snapshot.iter().keys().rev().collect::<Result<_>>()?;
+        assert_eq!(keys, vec![IVec::from(b"c"), IVec::from(b"b")]);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn tree_async() -> Result<()> {
+    tests::setup_logger();
+
+    let config = ConfigBuilder::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .build();
+    let db = sled::Db::start(config)?;
+    let tree = db.open_tree(b"async")?;
+
+    for i in 0..N_PER_THREAD {
+        tree.insert(kv(i), kv(i))?;
+    }
+
+    let flushed = tests::block_on(tree.flush_async())?;
+    assert!(flushed > 0);
+    assert_eq!(tests::block_on(tree.flush_async())?, 0);
+
+    let reads: Vec<_> =
+        (0..N_PER_THREAD).map(|i| tree.get_async(kv(i))).collect();
+    for (i, read) in reads.into_iter().enumerate() {
+        assert_eq!(tests::block_on(read)?, Some(IVec::from(kv(i))));
+    }
+    assert_eq!(tests::block_on(tree.get_async(kv(N_PER_THREAD)))?, None);
+
+    // with a cache too small for the tree, most reads miss
+    // the cache and go to the disk
+    let config = ConfigBuilder::new()
+        .temporary(true)
+        .cache_capacity(1024)
+        .flush_every_ms(None)
+        .build();
+    let db = sled::Db::start(config)?;
+    for i in 0..N {
+        db.insert(kv(i), vec![i as u8; 64])?;
+    }
+    let reads: Vec<_> = (0..N).map(|i| db.get_async(kv(i))).collect();
+    for (i, read) in reads.into_iter().enumerate() {
+        assert_eq!(tests::block_on(read)?, Some(IVec::from(vec![i as u8; 64])));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn tree_subscriber_stream() -> Result<()> {
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::mpsc::RecvTimeoutError,
+        task::{Context, Poll},
+        time::Duration,
+    };
+
+    use futures_core::Stream;
+
+    struct Next<'a, S>(&'a mut S);
+
+    impl<'a, S: Stream + Unpin> Future for Next<'a, S> {
+        type Output = Option<S::Item>;
+
+        fn poll(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Self::Output> {
+            Pin::new(&mut *self.0).poll_next(cx)
+        }
+    }
+
+    tests::setup_logger();
+
+    let config = ConfigBuilder::new().temporary(true).build();
+    let db = sled::Db::start(config)?;
+    let tree = db.open_tree(b"stream")?;
+
+    let mut events = tree.watch_prefix(b"k".to_vec());
+
+    let timeout = Duration::from_millis(10);
+    assert_eq!(events.next_timeout(timeout), Err(RecvTimeoutError::Timeout));
+
+    // the stream is woken up by writes from other threads
+    let writer = {
+        let tree = tree.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            for i in 0..N_PER_THREAD {
+                tree.insert(format!("k{}", i), kv(i)).unwrap();
+            }
+        })
+    };
+
+    for i in 0..N_PER_THREAD / 2 {
+        let event = tests::block_on(Next(&mut events)).unwrap();
+        assert_eq!(event.key(), format!("k{}", i).as_bytes());
+    }
+
+    writer.join().unwrap();
+
+    // both interfaces share the same queue of events
+    for i in N_PER_THREAD / 2..N_PER_THREAD {
+        let event = events.next_timeout(timeout).unwrap();
+        assert_eq!(event.key(), format!("k{}", i).as_bytes());
+    }
+    assert_eq!(events.next_timeout(timeout), Err(RecvTimeoutError::Timeout));
+
+    Ok(())
+}
+
+#[test]
+fn tree_subscriber_backpressure() -> Result<()> {
+    use std::{sync::mpsc::RecvTimeoutError, time::Duration};
+
+    tests::setup_logger();
+
+    let config = ConfigBuilder::new().temporary(true).build();
+    let db = sled::Db::start(config)?;
+
+    let mut dropping = db.watch_prefix_with(vec![], Backpressure::DropOldest);
+    let mut lagging = db.watch_prefix_with(vec![], Backpressure::Lag);
+
+    // far more writes than either subscriber can buffer
+    let n_writes = 3000;
+    for i in 0..n_writes {
+        db.insert(kv(i), kv(i))?;
+    }
+
+    // only the newest events are kept
+    let first = dropping.next().unwrap();
+    assert_eq!(first.key(), &*kv(n_writes - 1024));
+
+    // the oldest events are kept, then the lag is reported
+    for i in 0..1024 {
+        assert_eq!(lagging.next().unwrap().key(), &*kv(i));
+    }
+    assert_eq!(
+        lagging.next(),
+        Some(Event::Lagged((n_writes - 1024) as u64))
+    );
+
+    // after catching up, new events are delivered again
+    db.insert(b"after", vec![])?;
+    assert_eq!(lagging.next().unwrap().key(), b"after");
+
+    let timeout = Duration::from_millis(10);
+    assert_eq!(
+        lagging.next_timeout(timeout),
+        Err(RecvTimeoutError::Timeout)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn tree_subscriber_block_releases_log() -> Result<()> {
+    use std::{sync::mpsc, time::Duration};
+
+    tests::setup_logger();
+
+    let config = ConfigBuilder::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .build();
+    let db = sled::Db::start(config)?;
+
+    let mut events = db.watch_prefix(vec![]);
+
+    // fill the buffer of the subscriber
+    for i in 0..1024 {
+        db.insert(kv(i), kv(i))?;
+    }
+
+    // a batch is recovered atomically, so it pins the log
+    let done = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let db = db.clone();
+        let done = done.clone();
+        thread::spawn(move || -> Result<()> {
+            let mut batch = db.batch();
+            batch.insert(b"blocked".to_vec(), vec![]);
+            batch.apply()?;
+            done.store(true, SeqCst);
+            Ok(())
+        })
+    };
+
+    // the writer waits for the subscriber without holding the
+    // log, so that the log can still be flushed meanwhile
+    let (tx, rx) = mpsc::channel();
+    let flusher = {
+        let db = db.clone();
+        thread::spawn(move || tx.send(db.flush()).unwrap())
+    };
+    let flushed = rx.recv_timeout(Duration::from_secs(10));
+    assert!(flushed.expect("flush blocked on the subscriber").is_ok());
+    flusher.join().unwrap();
+    assert!(!done.load(SeqCst));
+
+    assert_eq!(events.next().unwrap().key(), &*kv(0));
+    writer.join().unwrap()?;
+    assert!(done.load(SeqCst));
+
+    Ok(())
+}
+
+#[test]
+fn tree_subscriber_block_releases_locks() -> Result<()> {
+    use std::time::{Duration, Instant};
+
+    tests::setup_logger();
+
+    let config = ConfigBuilder::new().temporary(true).build();
+    let db = sled::Db::start(config)?;
+
+    let mut events = db.watch_prefix(vec![]);
+
+    // fill the buffer of the subscriber
+    for i in 0..1024 {
+        db.insert(kv(i), kv(i))?;
+    }
+
+    // this write waits for the subscriber
+    let writer = {
+        let db = db.clone();
+        thread::spawn(move || db.insert(b"blocked", vec![]).map(drop))
+    };
+
+    // a transaction needs the exclusive lock of the tree, which
+    // the blocked writer must have released before waiting
+    let transactor = {
+        let db = db.clone();
+        thread::spawn(move || {
+            db.transaction(|tx| {
+                tx.insert(b"transaction", vec![])?;
+                Ok(())
+            })
+        })
+    };
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while db.get(b"transaction")?.is_none() {
+        assert!(
+            Instant::now() < deadline,
+            "the transaction blocked on the subscriber"
+        );
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    // catching up releases both writers
+    for i in 0..1024 {
+        assert_eq!(events.next().unwrap().key(), &*kv(i));
+    }
+    writer.join().unwrap()?;
+    transactor.join().unwrap().unwrap();
+
+    let mut keys: Vec<Vec<u8>> = (0..2)
+        .map(|_| events.next().unwrap().key().to_vec())
+        .collect();
+    keys.sort();
+    assert_eq!(keys, vec![b"blocked".to_vec(), b"transaction".to_vec()]);
+
+    Ok(())
+}
+
+#[test]
+fn tree_change_feed() -> Result<()> {
+    tests::setup_logger();
+
+    let path = std::env::temp_dir().join("sled_tree_change_feed_test");
+    let _ = std::fs::remove_dir_all(&path);
+    let config = |change_feed| {
+        ConfigBuilder::new()
+            .path(&path)
+            .change_feed(change_feed)
+            .build()
+    };
+
+    let db = sled::Db::start(config(true))?;
+    let start = db.change_feed_start()?;
+    let tree = db.open_tree(b"cdc")?;
+
+    db.insert(b"a", vec![1])?;
+    tree.insert(b"b", vec![2])?;
+    tree.cas(b"b", Some(vec![2]), None as Option<&[u8]>)?
+        .unwrap();
+    tree.remove(b"missing")?;
+
+    let changes: Vec<Change> =
+        db.changes_since(start)?.collect::<Result<_>>()?;
+    let events: Vec<(&[u8], &Event)> =
+        changes.iter().map(|c| (&*c.tree_name, &c.event)).collect();
+    assert_eq!(
+        events,
+        vec![
+            (
+                &b"__sled__default"[..],
+                &Event::Set(b"a".to_vec(), vec![1].into())
+            ),
+            (&b"cdc"[..], &Event::Set(b"b".to_vec(), vec![2].into())),
+            (&b"cdc"[..], &Event::Del(b"b".to_vec())),
+            (&b"cdc"[..], &Event::Del(b"missing".to_vec())),
+        ]
+    );
+    assert!(changes.windows(2).all(|w| w[0].lsn < w[1].lsn));
+
+    // a consumer resumes after the last change it processed
+    let checkpoint = changes[1].lsn;
+    drop(tree);
+    drop(db);
+
+    let db = sled::Db::start(config(true))?;
+    let resumed: Vec<Change> =
+        db.changes_since(checkpoint + 1)?.collect::<Result<_>>()?;
+    assert_eq!(resumed, changes[2..].to_vec());
+
+    // retention drops history that no consumer needs
+    assert_eq!(db.truncate_changes(checkpoint + 1)?, 2);
+    assert!(db.changes_since(checkpoint).is_err());
+    assert_eq!(db.change_feed_start()?, checkpoint + 1);
+
+    // writes made while the feed is disabled are missed,
+    // so its history starts over when it is enabled again
+    drop(db);
+    let db = sled::Db::start(config(false))?;
+    assert!(db.changes_since(checkpoint + 1).is_err());
+    db.insert(b"unrecorded", vec![])?;
+    drop(db);
+
+    let db = sled::Db::start(config(true))?;
+    assert!(db.changes_since(checkpoint + 1).is_err());
+    let start = db.change_feed_start()?;
+    assert_eq!(db.changes_since(start)?.count(), 0);
+
+    drop(db);
+    std::fs::remove_dir_all(&path)?;
+
+    Ok(())
+}
+
+#[test]
+fn tree_encryption() -> Result<()> {
+    tests::setup_logger();
+
+    let path = std::env::temp_dir().join("sled_tree_encryption_test");
+    let _ = std::fs::remove_dir_all(&path);
+    let config = |key: Option<[u8; 32]>| {
+        ConfigBuilder::new()
+            .path(&path)
+            .io_buf_size(1 << 16)
+            .snapshot_after_ops(10)
+            .encryption_key(key.map(EncryptionKey::new))
+            .build()
+    };
+
+    let secret = b"a very secret value".to_vec();
+    // big enough to be written to a blob
+    let big_secret: Vec<u8> =
+        secret.iter().cycle().take(1 << 15).cloned().collect();
+
+    let db = sled::Db::start(config(Some([7; 32])))?;
+    for i in 0..100 {
+        db.insert(kv(i), secret.clone())?;
+    }
+    db.insert(b"big", big_secret.clone())?;
+    db.flush()?;
+    drop(db);
+
+    // nothing on disk reveals the values
+    fn contains_secret(dir: &std::path::Path, secret: &[u8]) -> bool {
+        std::fs::read_dir(dir).unwrap().any(|entry| {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                contains_secret(&path, secret)
+            } else {
+                let contents = std::fs::read(&path).unwrap();
+                contents.windows(secret.len()).any(|w| w == secret)
+            }
+        })
+    }
+    assert!(!contains_secret(&path, &secret));
+
+    // the wrong key, or no key, is rejected with an error
+    match sled::Db::start(config(Some([8; 32]))) {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected a wrong key to be rejected: {:?}", other),
+    }
+    match sled::Db::start(config(None)) {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected a missing key to be rejected: {:?}", other),
+    }
+
+    let db = sled::Db::start(config(Some([7; 32])))?;
+    for i in 0..100 {
+        assert_eq!(db.get(kv(i))?, Some(IVec::from(secret.clone())));
+    }
+    assert_eq!(db.get(b"big")?, Some(IVec::from(big_secret.clone())));
+
+    // each of these values is written to its own blob
+    let a = db.open_tree(b"a")?;
+    let b = db.open_tree(b"b")?;
+    a.insert(b"big", big_secret[1..].to_vec())?;
+    b.insert(b"big", big_secret[2..].to_vec())?;
+    db.flush()?;
+    assert!(db.verify_integrity()?.is_ok());
+
+    // a blob is sealed with a subkey of its own lsn, and bound
+    // to its page, so the blob of tree a fails authentication
+    // in place of tree b's
+    let mut blobs: Vec<(u64, std::path::PathBuf)> =
+        std::fs::read_dir(path.join("blobs"))?
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let lsn = path.file_name().unwrap().to_str().unwrap();
+                (lsn.parse().unwrap(), path)
+            })
+            .collect();
+    blobs.sort();
+    let (_, newest) = blobs.pop().unwrap();
+    let (_, older) = blobs.pop().unwrap();
+    std::fs::copy(&older, &newest)?;
+
+    let report = db.verify_integrity()?;
+    assert_eq!(report.corrupt_messages.len(), 1, "{:?}", report);
+
+    drop((a, b, db));
+    std::fs::remove_dir_all(&path)?;
+
+    Ok(())
+}
+
+#[test]
+fn tree_integrity() -> Result<()> {
+    tests::setup_logger();
+
+    let path = std::env::temp_dir().join("sled_tree_integrity_test");
+    let _ = std::fs::remove_dir_all(&path);
+    let config = ConfigBuilder::new()
+        .path(&path)
+        .io_buf_size(1 << 16)
+        .build();
+
+    let db = sled::Db::start(config)?;
+    for i in 0..100 {
+        db.insert(kv(i), kv(i))?;
+    }
+    // big enough to be written to a blob
+    db.insert(b"big", vec![1; 1 << 15])?;
+    db.flush()?;
+
+    let report = db.verify_integrity()?;
+    assert!(report.is_ok(), "found problems: {:?}", report);
+    assert!(report.pages_checked > 0);
+    assert!(report.fragments_checked >= report.pages_checked);
+
+    let blobs = path.join("blobs");
+    let real_blobs: Vec<_> = std::fs::read_dir(&blobs)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    assert!(!real_blobs.is_empty());
+
+    // a blob file that nothing refers to
+    std::fs::write(blobs.join("1"), b"stray")?;
+    let report = db.verify_integrity()?;
+    assert_eq!(report.orphaned_blobs, vec![1]);
+    assert!(report.missing_blobs.is_empty());
+
+    // blob files that pages refer to
+    for blob in &real_blobs {
+        std::fs::remove_file(blob)?;
+    }
+    let report = db
+        .verify_integrity_in_background(std::time::Duration::from_millis(1))?
+        .join()
+        .expect("integrity check thread panicked")?;
+    assert!(!report.missing_blobs.is_empty());
+    assert!(report.corrupt_messages.is_empty());
+    assert_eq!(report.orphaned_blobs, vec![1]);
+
+    drop(db);
+    std::fs::remove_dir_all(&path)?;
+
+    Ok(())
+}
+
+#[test]
+fn tree_repair() -> Result<()> {
+    tests::setup_logger();
+
+    let dir = std::env::temp_dir().join("sled_tree_repair_test");
+    let _ = std::fs::remove_dir_all(&dir);
+    let damaged = dir.join("damaged");
+
+    let config = ConfigBuilder::new()
+        .path(&damaged)
+        .io_buf_size(1 << 16)
+        .build();
+    let db = sled::Db::start(config)?;
+    let tree = db.open_tree(b"a")?;
+    for i in 0..100 {
+        db.insert(kv(i), kv(i))?;
+        tree.insert(kv(i), kv(i))?;
+    }
+    // big enough to be written to a blob
+    db.insert(b"big", vec![1; 1 << 15])?;
+    db.flush()?;
+    drop(tree);
+    drop(db);
+
+    // the repaired databases keep the damaged one's settings
+    let repaired_config = |path| {
+        ConfigBuilder::new()
+            .path(dir.join(path))
+            .io_buf_size(1 << 16)
+            .build()
+    };
+
+    // nothing is lost when the database is intact
+    let report = sled::Db::repair(&damaged, dir.join("intact"), None)?;
+    assert!(report.is_complete(), "lost data: {:?}", report);
+    assert_eq!(report.keys_recovered, 201);
+
+    let repaired = sled::Db::start(repaired_config("intact"))?;
+    assert_eq!(repaired.get(b"big")?, Some(IVec::from(vec![1; 1 << 15])));
+    let tree = repaired.open_tree(b"a")?;
+    for i in 0..100 {
+        assert_eq!(tree.get(kv(i))?, Some(IVec::from(kv(i))));
+    }
+    drop(tree);
+    drop(repaired);
+
+    // the repaired database must start out empty
+    match sled::Db::repair(&damaged, dir.join("intact"), None) {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected to refuse a non-empty target: {:?}", other),
+    }
+
+    for entry in std::fs::read_dir(damaged.join("blobs"))? {
+        std::fs::remove_file(entry?.path())?;
+    }
+
+    let report = sled::Db::repair(&damaged, dir.join("salvaged"), None)?;
+    assert!(!report.is_complete());
+    assert!(report.messages_dropped > 0);
+    assert!(report
+        .lost_ranges
+        .iter()
+        .any(|range| range.tree_name == b"__sled__default"
+            && range.contains(b"big")));
+    assert!(report
+        .lost_ranges
+        .iter()
+        .all(|range| range.tree_name != b"a"));
+
+    let repaired = sled::Db::start(repaired_config("salvaged"))?;
+    let tree = repaired.open_tree(b"a")?;
+    for i in 0..100 {
+        assert_eq!(tree.get(kv(i))?, Some(IVec::from(kv(i))));
+    }
+    drop(tree);
+    drop(repaired);
+
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn tree_follow_writer() -> Result<()> {
+    tests::setup_logger();
+
+    let dir = std::env::temp_dir().join("sled_tree_follow_writer_test");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let config = ConfigBuilder::new().path(&dir).io_buf_size(1 << 16).build();
+    let writer = sled::Db::start(config.clone())?;
+    for i in 0..100 {
+        writer.insert(kv(i), kv(i))?;
+    }
+    writer.flush()?;
+
+    // readers follow a running writer without waiting for its lock
+    let reader =
+        sled::Db::start(ConfigBuilder::clone(&config).read_only(true).build())?;
+    for i in 0..100 {
+        assert_eq!(reader.get(kv(i))?, Some(IVec::from(kv(i))));
+    }
+
+    match reader.insert(b"k", b"v") {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected the reader to refuse writes: {:?}", other),
+    }
+    match writer.refresh() {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected the writer to refuse refresh: {:?}", other),
+    }
+
+    // enough writes to split pages and fill several segments
+    let tree = writer.open_tree(b"a")?;
+    for i in 0..N {
+        writer.insert(kv(i), kv(i + 1))?;
+        tree.insert(kv(i), kv(i))?;
+    }
+    for i in 0..50 {
+        writer.remove(kv(i))?;
+    }
+    writer.flush()?;
+
+    // nothing changes until the reader refreshes
+    assert_eq!(reader.get(kv(0))?, Some(IVec::from(kv(0))));
+    assert!(!reader.tree_names().contains(&b"a".to_vec()));
+
+    assert!(reader.refresh()? > 0);
+
+    for i in 0..50 {
+        assert_eq!(reader.get(kv(i))?, None);
+    }
+    for i in 50..N {
+        assert_eq!(reader.get(kv(i))?, Some(IVec::from(kv(i + 1))));
+    }
+    let followed = reader.open_tree(b"a")?;
+    assert_eq!(followed.iter().count(), N);
+
+    writer.drop_tree(b"a")?;
+    writer.flush()?;
+    reader.refresh()?;
+    assert!(!reader.tree_names().contains(&b"a".to_vec()));
+    match followed.get(kv(0)) {
+        Err(Error::CollectionNotFound(_)) => {}
+        other => panic!("expected the tree to be dropped: {:?}", other),
+    }
+
+    drop(followed);
+    drop(tree);
+    drop(reader);
+    drop(writer);
+
+    drop(config);
+
+    // readers that start first share their lock, which keeps
+    // a writer from recovering the log underneath them
+    let builder = ConfigBuilder::new().path(&dir).io_buf_size(1 << 16);
+    let reader = sled::Db::start(builder.clone().read_only(true).build())?;
+    let other_reader =
+        sled::Db::start(builder.clone().read_only(true).build())?;
+    assert_eq!(other_reader.get(kv(50))?, Some(IVec::from(kv(51))));
+    let writer_builder = builder.clone();
+    assert!(std::panic::catch_unwind(move || writer_builder.build()).is_err());
+
+    drop(other_reader);
+    drop(reader);
+
+    let writer = sled::Db::start(builder.build())?;
+    assert_eq!(writer.get(kv(50))?, Some(IVec::from(kv(51))));
+    drop(writer);
+
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn tree_stats() -> Result<()> {
+    tests::setup_logger();
+
+    let dir = std::env::temp_dir().join("sled_tree_stats_test");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let config = ConfigBuilder::new().path(&dir).io_buf_size(1 << 16).build();
+    let db = sled::Db::start(config.clone())?;
+    let tree = db.open_tree(b"a")?;
+    assert_eq!(tree.len(), 0);
+
+    for i in 0..N {
+        tree.insert(kv(i), kv(i))?;
+    }
+    for i in 0..100 {
+        tree.insert(kv(i), vec![0; 10])?;
+    }
+    for i in N - 100..N {
+        tree.remove(kv(i))?;
+    }
+    tree.cas(kv(N - 1), None as Option<&[u8]>, Some(vec![1]))?
+        .unwrap();
+
+    let expected = TreeStats {
+        len: N as u64 - 99,
+        key_bytes: 3 * (N as u64 - 99),
+        value_bytes: 100 * 10 + 3 * (N as u64 - 200) + 1,
+        ..tree.stats()?
+    };
+    assert_eq!(tree.stats()?, expected);
+    assert_eq!(tree.len(), tree.iter().count());
+    assert!(expected.nodes > 2);
+    assert!(expected.depth >= 2);
+
+    // persisted by the last handle to the Db
+    drop(tree);
+    drop(db);
+
+    let db = sled::Db::start(config.clone())?;
+    let tree = db.open_tree(b"a")?;
+    assert_eq!(tree.stats()?, expected);
+
+    tree.remove(kv(0))?;
+    assert_eq!(tree.len(), N - 100);
+
+    // a tree created with the name of a dropped
+    // one does not inherit its counters.
+    drop(tree);
+    db.drop_tree(b"a")?;
+    let tree = db.open_tree(b"a")?;
+    assert_eq!(tree.len(), 0);
+
+    drop(tree);
+    drop(db);
+
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn tree_remove_range() -> Result<()> {
+    tests::setup_logger();
+
+    let dir = std::env::temp_dir().join("sled_tree_remove_range_test");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let config = ConfigBuilder::new()
+        .path(&dir)
+        .io_buf_size(1 << 16)
+        .change_feed(true)
+        .build();
+    let db = sled::Db::start(config.clone())?;
+    let start = db.change_feed_start()?;
+
+    // enough keys to span many leaves
+    for i in 0..N {
+        db.insert(kv(i), kv(i))?;
+    }
+
+    let mut overlapping = db.watch_prefix(kv(500)[..2].to_vec());
+    let mut disjoint = db.watch_prefix(kv(N - 1).to_vec());
+
+    assert_eq!(db.remove_range(kv(100)..kv(900)), Ok(800));
+    assert_eq!(db.remove_range(kv(100)..kv(900)), Ok(0));
+    assert_eq!(db.len(), N - 800);
+    assert_eq!(db.get(kv(99))?, Some(IVec::from(kv(99))));
+    assert_eq!(db.get(kv(100))?, None);
+    assert_eq!(db.get(kv(899))?, None);
+    assert_eq!(db.get(kv(900))?, Some(IVec::from(kv(900))));
+
+    // subscribers get one event for the whole range
+    let expected = Event::DelRange(kv(100), Some(kv(900)));
+    assert_eq!(overlapping.next(), Some(expected.clone()));
+    assert_eq!(
+        disjoint.next_timeout(std::time::Duration::from_millis(10)),
+        Err(std::sync::mpsc::RecvTimeoutError::Timeout)
+    );
+
+    let last = db.changes_since(start)?.last().unwrap()?;
+    assert_eq!(last.event, expected);
+
+    // inclusive ends cover the end key itself
+    assert_eq!(db.remove_range(kv(0)..=kv(0)), Ok(1));
+    assert_eq!(db.get(kv(0))?, None);
+    assert_eq!(db.get(kv(1))?, Some(IVec::from(kv(1))));
+
+    drop(overlapping);
+    drop(disjoint);
+    drop(db);
+
+    let db = sled::Db::start(config.clone())?;
+    assert_eq!(db.iter().count(), N - 801);
+    assert_eq!(db.get(kv(500))?, None);
+
+    db.clear()?;
+    assert!(db.is_empty());
+    assert_eq!(db.len(), 0);
+
+    drop(db);
+
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn tree_comparator() -> Result<()> {
+    use std::cmp::Ordering;
+
+    fn reverse(a: &[u8], b: &[u8]) -> Ordering {
+        b.cmp(a)
+    }
+
+    fn forward(a: &[u8], b: &[u8]) -> Ordering {
+        a.cmp(b)
+    }
+
+    tests::setup_logger();
+
+    let dir = std::env::temp_dir().join("sled_tree_comparator_test");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let config = ConfigBuilder::new().path(&dir).io_buf_size(1 << 16).build();
+    let db = sled::Db::start(config.clone())?;
+    let tree = db.open_tree_with_comparator(b"reversed", "reverse", reverse)?;
+
+    // enough keys to split many leaves
+    for i in 0..N {
+        tree.insert(kv(i), kv(i))?;
+    }
+
+    let expected: Vec<IVec> = (0..N).rev().map(|i| IVec::from(kv(i))).collect();
+    let keys: Vec<IVec> = tree.iter().keys().collect::<Result<_>>()?;
+    assert_eq!(keys, expected);
+    let keys: Vec<IVec> = tree.iter().keys().rev().collect::<Result<_>>()?;
+    assert_eq!(keys.len(), N);
+    assert_eq!(keys[0], IVec::from(kv(0)));
+
+    // ranges and neighbors follow the comparator
+    let mut r = tree.range(kv(600)..kv(400));
+    assert_eq!(r.next().unwrap()?.0, IVec::from(kv(600)));
+    assert_eq!(r.next_back().unwrap()?.0, IVec::from(kv(401)));
+    assert_eq!(tree.range(kv(600)..kv(400)).count(), 200);
+    assert_eq!(tree.get_gt(kv(500))?.unwrap().0, IVec::from(kv(499)));
+    assert_eq!(tree.get_lt(kv(500))?.unwrap().0, IVec::from(kv(501)));
+
+    assert_eq!(tree.remove_range(kv(300)..=kv(200)), Ok(101));
+    assert_eq!(tree.get(kv(200))?, None);
+    assert_eq!(tree.get(kv(199))?, Some(IVec::from(kv(199))));
+
+    // mismatched comparators fail loudly
+    assert!(db.open_tree(b"reversed").is_err());
+    assert!(db
+        .open_tree_with_comparator(b"reversed", "forward", forward)
+        .is_err());
+    assert!(db
+        .open_tree_with_comparator(b"other", "reverse", forward)
+        .is_err());
+
+    drop(tree);
+    drop(db);
+
+    let db = sled::Db::start(config.clone())?;
+    let tree = db.open_tree_with_comparator(b"reversed", "reverse", reverse)?;
+    let first = tree.iter().next().unwrap()?;
+    assert_eq!(first.0, IVec::from(kv(N - 1)));
+    assert_eq!(tree.iter().count(), N - 101);
+    assert_eq!(tree.len(), N - 101);
+
+    drop(tree);
+    drop(db);
+
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn tree_opens_sled_0_25_format() -> Result<()> {
+    use std::cmp::Ordering;
+
+    fn reverse(a: &[u8], b: &[u8]) -> Ordering {
+        b.cmp(a)
+    }
+
+    tests::setup_logger();
+
+    // written by sled 0.25, before trees had comparators or
+    // settings: keys 0 to 99 of the default tree, without the
+    // multiples of 10, and keys 0 to 49 of the tree "other".
+    let dir = std::env::temp_dir().join("sled_0_25_format_test");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(
+        dir.join("conf"),
+        &include_bytes!("../fixtures/sled-0.25/conf")[..],
+    )?;
+    std::fs::write(
+        dir.join("db"),
+        &include_bytes!("../fixtures/sled-0.25/db")[..],
+    )?;
+
+    let key = |i: u32| i.to_be_bytes().to_vec();
+
+    let config = ConfigBuilder::new().path(&dir).io_buf_size(8192).build();
+    let db = sled::Db::start(config.clone())?;
+    let other = db.open_tree(b"other")?;
+    for i in 0..100 {
+        let expected = if i % 10 == 0 {
+            None
+        } else {
+            Some(IVec::from(vec![i as u8; 8]))
+        };
+        assert_eq!(db.get(key(i))?, expected);
+    }
+    for i in 0..50 {
+        assert_eq!(other.get(key(i))?, Some(IVec::from(vec![!i as u8; 8])));
+    }
+    assert_eq!(db.iter().count(), 90);
+    assert_eq!(other.iter().count(), 50);
+
+    // the first comparator or settings change the format of the `Meta`
+    db.insert(key(0), vec![0; 8])?;
+    other.remove(key(49))?;
+    let reversed =
+        db.open_tree_with_comparator(b"reversed", "old_reverse", reverse)?;
+    reversed.insert(key(1), key(1))?;
+    let hot = db.open_tree_with(
+        b"hot",
+        TreeConfig::new().page_consolidation_threshold(2),
+    )?;
+    hot.insert(key(2), key(2))?;
+
+    drop(reversed);
+    drop(hot);
+    drop(other);
+    drop(db);
+
+    let db = sled::Db::start(config.clone())?;
+    assert_eq!(db.get(key(0))?, Some(IVec::from(vec![0; 8])));
+    assert_eq!(db.iter().count(), 91);
+    assert_eq!(db.open_tree(b"other")?.iter().count(), 49);
+    assert!(db.open_tree(b"reversed").is_err());
+    let reversed =
+        db.open_tree_with_comparator(b"reversed", "old_reverse", reverse)?;
+    assert_eq!(reversed.get(key(1))?, Some(IVec::from(key(1))));
+    let hot = db.open_tree(b"hot")?;
+    assert_eq!(hot.config().get_page_consolidation_threshold(), Some(2));
+    assert_eq!(hot.get(key(2))?, Some(IVec::from(key(2))));
+
+    drop(reversed);
+    drop(hot);
+    drop(db);
+
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn tree_typed() -> Result<()> {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Account {
+        owner: String,
+        balance: i64,
+    }
+
+    tests::setup_logger();
+
+    let config = ConfigBuilder::new().temporary(true).build();
+    let db = sled::Db::start(config)?;
+
+    // signed keys sort numerically, not by their bytes
+    let ints: TypedTree<i64, u64> =
+        TypedTree::new((*db.open_tree("ints")?).clone());
+    for i in -500..500 {
+        ints.insert(&i, &((i * i) as u64))?;
+    }
+    let keys: Vec<i64> = ints
+        .range(-10..10)
+        .map(|res| res.map(|(k, _)| k))
+        .collect::<Result<_>>()?;
+    assert_eq!(keys, (-10..10).collect::<Vec<_>>());
+    assert_eq!(ints.iter().next().unwrap()?, (-500, 250_000));
+    assert_eq!(ints.iter().next_back().unwrap()?, (499, 249_001));
+    assert_eq!(ints.range(..=-499).count(), 2);
+
+    let accounts: TypedTree<(u32, String), Account> =
+        TypedTree::new((*db.open_tree("accounts")?).clone());
+    let mut events = accounts.watch_prefix(&(2_u32,))?;
+
+    let alice = Account {
+        owner: "alice".to_owned(),
+        balance: 10,
+    };
+    let key = (2, "a".to_owned());
+    assert_eq!(accounts.insert(&(1, "z".to_owned()), &alice)?, None);
+    assert_eq!(accounts.insert(&key, &alice)?, None);
+    assert_eq!(accounts.get(&key)?, Some(alice.clone()));
+
+    let poor = Account {
+        balance: 0,
+        ..alice.clone()
+    };
+    assert_eq!(
+        accounts.cas(&key, Some(&poor), None)?,
+        Err(Some(alice.clone()))
+    );
+    assert_eq!(accounts.cas(&key, Some(&alice), Some(&poor))?, Ok(()));
+
+    let richer = accounts.update_and_fetch(&key, |account| {
+        account.map(|mut account| {
+            account.balance += 5;
+            account
+        })
+    })?;
+    assert_eq!(richer.map(|account| account.balance), Some(5));
+
+    // tuple keys sort field by field
+    accounts.insert(&(2, String::new()), &alice)?;
+    accounts.insert(&(3, String::new()), &alice)?;
+    let keys: Vec<(u32, String)> = accounts
+        .range((2, String::new())..(3, String::new()))
+        .map(|res| res.map(|(k, _)| k))
+        .collect::<Result<_>>()?;
+    assert_eq!(keys, vec![(2, String::new()), (2, "a".to_owned())]);
+
+    // only the keys under the watched prefix are seen
+    assert_eq!(
+        events.next().unwrap()?,
+        TypedEvent::Set(key.clone(), alice.clone())
+    );
+    assert_eq!(
+        events.next().unwrap()?,
+        TypedEvent::Set(key.clone(), poor.clone())
+    );
+    assert_eq!(
+        events.next().unwrap()?,
+        TypedEvent::Set(key.clone(), Account { balance: 5, ..poor })
+    );
+    assert_eq!(
+        events.next().unwrap()?,
+        TypedEvent::Set((2, String::new()), alice)
+    );
+
+    // values of the wrong type fail to decode
+    let wrong: TypedTree<(u32, String), u8> =
+        TypedTree::new(accounts.tree().clone());
+    assert!(wrong.get(&key).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn tree_indexes() -> Result<()> {
+    // indexes keys by the parity of the last byte of their value
+    fn parity(_key: &[u8], value: &[u8]) -> Vec<Vec<u8>> {
+        value.last().map(|b| vec![b % 2]).into_iter().collect()
+    }
+
+    fn other(_key: &[u8], _value: &[u8]) -> Vec<Vec<u8>> {
+        vec![]
+    }
+
+    fn concatenate(
+        _key: &[u8],
+        old: Option<&[u8]>,
+        new: &[u8],
+    ) -> Option<Vec<u8>> {
+        let mut ret = old.map(|o| o.to_vec()).unwrap_or_else(|| vec![]);
+        ret.extend_from_slice(new);
+        Some(ret)
+    }
+
+    tests::setup_logger();
+
+    let dir = std::env::temp_dir().join("sled_tree_indexes_test");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let config = ConfigBuilder::new().path(&dir).io_buf_size(1 << 16).build();
+    let db = sled::Db::start(config.clone())?;
+    let tree = db.open_tree(b"indexed")?;
+    tree.set_merge_operator(concatenate);
+
+    for i in 0..N {
+        tree.insert(kv(i), kv(i))?;
+    }
+
+    // existing keys are backfilled
+    let odd = tree.add_index(b"parity", parity)?;
+    assert_eq!(odd.get([1])?.len(), N / 2);
+    assert_eq!(odd.get([0])?[0], IVec::from(kv(0)));
+    assert!(tree.add_index(b"parity", other).is_err());
+    assert!(db
+        .tree_names()
+        .iter()
+        .all(|name| !name.starts_with(b"__sled__index")));
+
+    tree.insert(kv(0), vec![1])?;
+    tree.remove(kv(1))?;
+    assert_eq!(tree.cas(kv(2), Some(kv(2)), Some(vec![3]))?, Ok(()));
+    tree.merge(kv(4), vec![5])?;
+
+    let mut batch = tree.batch();
+    batch.insert(kv(6), vec![7]);
+    batch.remove(kv(3));
+    batch.apply()?;
+
+    tree.transaction(|tx| {
+        tx.insert(kv(8).as_slice(), vec![9])?;
+        Ok(())
+    })
+    .unwrap();
+
+    assert_eq!(tree.remove_range(kv(100)..kv(N - 100)), Ok(N - 200));
+
+    let expected = |parity: usize| -> Vec<IVec> {
+        (0..N)
+            .filter(|i| *i < 100 || *i >= N - 100)
+            .filter(|i| *i != 1 && *i != 3)
+            .filter(|i| {
+                if *i <= 8 {
+                    parity == 1
+                } else {
+                    *i % 2 == parity
+                }
+            })
+            .map(|i| IVec::from(kv(i)))
+            .collect()
+    };
+    assert_eq!(odd.get([1])?, expected(1));
+    assert_eq!(odd.get([0])?, expected(0));
+
+    let records = odd.get_records([1])?;
+    assert_eq!(records[0], (IVec::from(kv(0)), IVec::from(vec![1])));
+    assert_eq!(
+        records[2],
+        (IVec::from(kv(4)), IVec::from(vec![0, 0, 4, 5]))
+    );
+
+    drop(odd);
+    drop(tree);
+    drop(db);
+
+    // writes fail until the index is added again
+    let db = sled::Db::start(config.clone())?;
+    let tree = db.open_tree(b"indexed")?;
+    assert!(tree.insert(kv(1), kv(1)).is_err());
+    assert_eq!(tree.get(kv(1))?, None);
+
+    let odd = tree.add_index(b"parity", parity)?;
+    assert_eq!(odd.get([1])?, expected(1));
+    tree.insert(kv(1), kv(1))?;
+    assert_eq!(odd.get([1])?.len(), expected(1).len() + 1);
+
+    // dropped indexes are forgotten
+    assert_eq!(tree.drop_index(b"parity"), Ok(true));
+    assert_eq!(tree.drop_index(b"parity"), Ok(false));
+
+    drop(odd);
+    drop(tree);
+    drop(db);
+
+    let db = sled::Db::start(config.clone())?;
+    let tree = db.open_tree(b"indexed")?;
+    tree.insert(kv(3), kv(3))?;
+
+    drop(tree);
+    drop(db);
+
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn tree_config() -> Result<()> {
+    tests::setup_logger();
+
+    let dir = std::env::temp_dir().join("sled_tree_config_test");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let config = ConfigBuilder::new()
+        .path(&dir)
+        .io_buf_size(1 << 16)
+        .use_compression(false)
+        .build();
+    let db = sled::Db::start(config.clone())?;
+
+    let hot_config = TreeConfig::new()
+        .use_compression(true)
+        .compression_factor(3)
+        .page_consolidation_threshold(2)
+        .cache_priority(CachePriority::High);
+    let hot = db.open_tree_with(b"hot", hot_config)?;
+    let plain = db.open_tree(b"plain")?;
+    assert_eq!(hot.config(), hot_config);
+    assert_eq!(plain.config(), TreeConfig::new());
+
+    // enough writes to split and consolidate many pages
+    for i in 0..N {
+        hot.insert(kv(i), vec![i as u8; 64])?;
+        plain.insert(kv(i), vec![i as u8; 64])?;
+    }
+
+    // the overrides are kept by later calls to open_tree
+    assert_eq!(db.open_tree(b"hot")?.config(), hot_config);
+
+    assert!(db
+        .open_tree_with(b"plain", TreeConfig::new().compression_factor(0))
+        .is_err());
+    assert!(db
+        .open_tree_with(
+            b"plain",
+            TreeConfig::new().page_consolidation_threshold(0)
+        )
+        .is_err());
+
+    drop(hot);
+    drop(plain);
+    drop(db);
+
+    let db = sled::Db::start(config.clone())?;
+    let hot = db.open_tree(b"hot")?;
+    assert_eq!(hot.config(), hot_config);
+    for i in 0..N {
+        assert_eq!(hot.get(kv(i))?, Some(IVec::from(vec![i as u8; 64])));
+    }
+
+    // opening with another config replaces the overrides,
+    // and pages written under both can still be read
+    drop(hot);
+    let cold_config = TreeConfig::new().cache_priority(CachePriority::Low);
+    let hot = db.open_tree_with(b"hot", cold_config)?;
+    assert_eq!(hot.config(), cold_config);
+    for i in 0..N / 2 {
+        hot.insert(kv(i), vec![i as u8; 32])?;
+    }
+
+    drop(hot);
+    drop(db);
+
+    let db = sled::Db::start(config.clone())?;
+    let hot = db.open_tree(b"hot")?;
+    assert_eq!(hot.config(), cold_config);
+    for i in 0..N {
+        let len = if i < N / 2 { 32 } else { 64 };
+        assert_eq!(hot.get(kv(i))?, Some(IVec::from(vec![i as u8; len])));
+    }
+    let plain = db.open_tree(b"plain")?;
+    assert_eq!(plain.config(), TreeConfig::new());
+    assert_eq!(plain.len(), N);
+
+    drop(hot);
+    drop(plain);
+    drop(db);
+
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn tree_config_changes_during_writes() -> Result<()> {
+    tests::setup_logger();
+
+    let config = ConfigBuilder::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .build();
+    let db = sled::Db::start(config)?;
+    let tree = db.open_tree(b"changing")?;
+
+    let writer = {
+        let tree = tree.clone();
+        thread::spawn(move || -> Result<()> {
+            for i in 0..N {
+                tree.insert(kv(i), vec![i as u8; 64])?;
+            }
+            Ok(())
+        })
+    };
+
+    let configs = [
+        TreeConfig::new().page_consolidation_threshold(2),
+        TreeConfig::new().cache_priority(CachePriority::High),
+        TreeConfig::new()
+            .use_compression(true)
+            .cache_priority(CachePriority::Low),
+    ];
+    let mut last = TreeConfig::new();
+    for config in configs.iter().cycle().take(30) {
+        let reopened = db.open_tree_with(b"changing", *config)?;
+        assert_eq!(reopened.config(), *config);
+        last = *config;
+    }
+
+    writer.join().unwrap()?;
+
+    // nodes do not record the overrides that they were written
+    // under, so all of them are read with the latest ones
+    assert_eq!(tree.config(), last);
+    for i in 0..N {
+        assert_eq!(tree.get(kv(i))?, Some(IVec::from(vec![i as u8; 64])));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn tree_no_cache_scan() -> Result<()> {
+    tests::setup_logger();
+
+    let config = ConfigBuilder::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .build();
+
+    let db = sled::Db::start(config.clone())?;
+    for i in 0..N {
+        db.insert(kv(i), kv(i))?;
+    }
+
+    // finish the splits that were left for later traversals,
+    // so that the scans below never have to write
+    for _ in 0..2 {
+        for i in 0..N {
+            assert_eq!(db.get(kv(i))?, Some(IVec::from(kv(i))));
+        }
+    }
+    let expected = db.iter().collect::<Result<Vec<_>>>()?;
+    assert_eq!(expected.len(), N);
+    drop(db);
+
+    // nothing is cached after a restart
+    let db = sled::Db::start(config.clone())?;
+    let before = db.cache_hits_and_misses();
+
+    let scanned = db.iter().no_cache().collect::<Result<Vec<_>>>()?;
+    assert_eq!(scanned, expected);
+    let mut reversed =
+        db.iter().no_cache().rev().collect::<Result<Vec<_>>>()?;
+    reversed.reverse();
+    assert_eq!(reversed, expected);
+
+    // the scans neither added pages to the cache nor
+    // marked any as accessed
+    assert_eq!(db.cache_hits_and_misses(), before);
+
+    // so a regular scan still reads them from disk
+    let scanned = db.iter().collect::<Result<Vec<_>>>()?;
+    assert_eq!(scanned, expected);
+    let (hits, misses) = db.cache_hits_and_misses();
+    assert!(misses > before.1);
+
+    // and later scans skip the pages that are now cached
+    let scanned = db.iter().no_cache().collect::<Result<Vec<_>>>()?;
+    assert_eq!(scanned, expected);
+    assert_eq!(db.cache_hits_and_misses(), (hits, misses));
+
+    Ok(())
+}
+
+#[test]
+fn tree_bulk_load() -> Result<()> {
+    use std::cmp::Ordering;
+
+    fn reverse(a: &[u8], b: &[u8]) -> Ordering {
+        b.cmp(a)
+    }
+
+    // unlike kv, these keys do not wrap around
+    fn kv(i: usize) -> Vec<u8> {
+        (i as u32).to_be_bytes().to_vec()
+    }
+
+    tests::setup_logger();
+
+    let dir = std::env::temp_dir().join("sled_tree_bulk_load_test");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let config = ConfigBuilder::new().path(&dir).io_buf_size(1 << 16).build();
+    let db = sled::Db::start(config.clone())?;
+
+    // enough keys for several levels of index nodes
+    let n = 10 * N;
+    let pairs = (0..n).map(|i| (kv(i), kv(i)));
+    assert_eq!(db.bulk_load(pairs.clone()), Ok(n));
+
+    let expected: Vec<(IVec, IVec)> =
+        pairs.map(|(k, v)| (IVec::from(k), IVec::from(v))).collect();
+    assert_eq!(db.iter().collect::<Result<Vec<_>>>()?, expected);
+    let mut reversed = db.iter().rev().collect::<Result<Vec<_>>>()?;
+    reversed.reverse();
+    assert_eq!(reversed, expected);
+    for i in 0..n {
+        assert_eq!(db.get(kv(i))?, Some(IVec::from(kv(i))));
+    }
+
+    let stats = db.stats()?;
+    assert_eq!(stats.len, n as u64);
+    assert_eq!(stats.key_bytes, 4 * n as u64);
+    assert_eq!(stats.value_bytes, 4 * n as u64);
+    assert!(stats.depth > 2);
+
+    // only empty trees can be bulk loaded
+    assert!(db.bulk_load(vec![(b"a", b"a")]).is_err());
+
+    // unsorted input leaves the tree empty
+    let tree = db.open_tree(b"unsorted")?;
+    let unsorted = vec![(kv(1), kv(1)), (kv(3), kv(3)), (kv(2), kv(2))];
+    assert!(tree.bulk_load(unsorted).is_err());
+    assert!(tree.is_empty());
+    assert_eq!(tree.bulk_load(Vec::<(Vec<u8>, Vec<u8>)>::new()), Ok(0));
+    assert!(tree.is_empty());
+    drop(tree);
+
+    // the comparator of a tree decides what sorted means
+    let tree =
+        db.open_tree_with_comparator(b"reversed", "bulk_reverse", reverse)?;
+    assert_eq!(tree.bulk_load((0..N).rev().map(|i| (kv(i), kv(i)))), Ok(N));
+    let first = tree.iter().next().unwrap()?;
+    assert_eq!(first.0, IVec::from(kv(N - 1)));
+
+    // the loaded trees can be written to as usual
+    for i in 0..N {
+        db.remove(kv(2 * i))?;
+        db.insert(kv(n + i), kv(n + i))?;
+        tree.remove(kv(2 * i + 1))?;
+    }
+
+    drop(tree);
+    drop(db);
+
+    let db = sled::Db::start(config)?;
+    assert_eq!(db.len(), n);
+    assert_eq!(db.get(kv(2))?, None);
+    assert_eq!(db.get(kv(3))?, Some(IVec::from(kv(3))));
+    assert_eq!(db.get(kv(n))?, Some(IVec::from(kv(n))));
+    assert_eq!(db.iter().count(), n);
+
+    let tree =
+        db.open_tree_with_comparator(b"reversed", "bulk_reverse", reverse)?;
+    assert_eq!(tree.len(), N / 2);
+    let keys: Vec<IVec> = tree.iter().keys().collect::<Result<_>>()?;
+    let expected: Vec<IVec> =
+        (0..N / 2).rev().map(|i| IVec::from(kv(2 * i))).collect();
+    assert_eq!(keys, expected);
+
+    drop(tree);
+    drop(db);
+
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
 #[test]
 #[cfg(not(target_os = "fuchsia"))]
 fn quickcheck_tree_matches_btreemap() {
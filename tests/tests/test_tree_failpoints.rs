@@ -74,6 +74,12 @@ fn v(b: &[u8]) -> u16 {
     (u16::from(b[0]) << 8) + u16::from(b[1])
 }
 
+lazy_static! {
+    // failpoints are global, so this forces tests that
+    // use them to run one thread at a time
+    static ref FAILPOINT_TEST_LOCK: Mutex<()> = Mutex::new(());
+}
+
 fn tear_down_failpoints() {
     for (name, _) in fail::list() {
         fail::remove(name);
@@ -81,12 +87,9 @@ fn tear_down_failpoints() {
 }
 
 fn prop_tree_crashes_nicely(ops: Vec<Op>, flusher: bool) -> bool {
-    lazy_static! {
-        // forces quickcheck to run one thread at a time
-        static ref M: Mutex<()> = Mutex::new(());
-    }
-
-    let _lock = M.lock().expect("our test lock should not be poisoned");
+    let _lock = FAILPOINT_TEST_LOCK
+        .lock()
+        .expect("our test lock should not be poisoned");
 
     // clear all failpoints that may be left over from the last run
     tear_down_failpoints();
@@ -1293,3 +1296,61 @@ fn failpoints_bug_28() {
         true,
     ))
 }
+
+#[test]
+fn failpoints_change_feed_atomic() {
+    // a write that is recovered after a crash must also
+    // have its record in the change feed recovered.
+    for crash_after in &[1_u8, 3, 7, 20, 50] {
+        let _lock = FAILPOINT_TEST_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        tear_down_failpoints();
+
+        let config = ConfigBuilder::new()
+            .temporary(true)
+            .flush_every_ms(None)
+            .io_buf_size(300)
+            .change_feed(true)
+            .build();
+
+        let db = sled::Db::start(config.clone()).unwrap();
+        let start = db.change_feed_start().unwrap();
+
+        for i in 0..100_u8 {
+            if i == *crash_after {
+                fail::cfg("buffer write", "return").unwrap();
+            }
+            match db.insert(&[i], vec![i; 20]) {
+                Ok(_) => {}
+                Err(Error::FailPoint) => break,
+                Err(other) => panic!("unexpected error: {:?}", other),
+            }
+        }
+
+        tear_down_failpoints();
+        drop(db);
+
+        let db = sled::Db::start(config).unwrap();
+
+        let mut recorded = BTreeMap::new();
+        for change in db.changes_since(start).unwrap() {
+            let change = change.unwrap();
+            if let Event::Set(k, v) = change.event {
+                recorded.insert(k, v);
+            }
+        }
+
+        let recovered: BTreeMap<_, _> = db
+            .iter()
+            .map(|res| res.map(|(k, v)| (k.to_vec(), v)))
+            .collect::<Result<_>>()
+            .unwrap();
+
+        assert_eq!(
+            recovered, recorded,
+            "the change feed lost writes after crashing after {} writes",
+            crash_after
+        );
+    }
+}